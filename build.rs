@@ -0,0 +1,21 @@
+//! Records the commit the crate is built from as `WASM_IC_GIT_HASH`, for
+//! the metadata stamped into artifacts. A build outside a git checkout, or
+//! without git, goes without.
+
+use std::process::Command;
+
+fn main() {
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+    println!("cargo:rerun-if-changed=.git/packed-refs");
+    let Ok(out) = Command::new("git")
+        .args(["rev-parse", "--short=12", "HEAD"])
+        .output()
+    else {
+        return;
+    };
+    let hash = String::from_utf8_lossy(&out.stdout);
+    if out.status.success() && !hash.trim().is_empty() {
+        println!("cargo:rustc-env=WASM_IC_GIT_HASH={}", hash.trim());
+    }
+}
@@ -8,7 +8,8 @@ use std::{env::current_dir, ffi::OsString, fs, process::Command};
 
 use camino::Utf8PathBuf;
 use marlin_verilator::{
-    eprintln_nocapture, AsVerilatedModel, VerilatorRuntime, VerilatorRuntimeOptions,
+    eprintln_nocapture, AsVerilatedModel, VerilatedModelConfig, VerilatorRuntime,
+    VerilatorRuntimeOptions,
 };
 use owo_colors::OwoColorize;
 use snafu::{whatever, OptionExt, ResultExt, Whatever};
@@ -205,4 +206,14 @@ impl VerylRuntime {
     pub fn create_model<'ctx, M: AsVerilatedModel<'ctx>>(&'ctx self) -> Result<M, Whatever> {
         self.verilator_runtime.create_model_simple()
     }
+
+    /// Instantiates a new Veryl module with an explicit model configuration,
+    /// e.g. to build it with tracing support. This function simply wraps
+    /// [`VerilatorRuntime::create_model`].
+    pub fn create_model_with_config<'ctx, M: AsVerilatedModel<'ctx>>(
+        &'ctx self,
+        config: &VerilatedModelConfig,
+    ) -> Result<M, Whatever> {
+        self.verilator_runtime.create_model(config)
+    }
 }
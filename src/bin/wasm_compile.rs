@@ -1,107 +1,2825 @@
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use clap::Parser;
 use std::fs;
-use std::path::PathBuf;
+use std::io::{IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::Instant;
+use wasm_ic::emit::{
+    diff_artifact_dirs, generate_sv_branch_rom, generate_sv_rom, generate_veryl_branch_rom,
+    generate_veryl_prog_rom, link_module, render_artifact, verify_artifacts, write_boot_image,
+    ArtifactKind, BranchHexLayout, BusMap, ChecksumAlgo, DiffLine, Endian, LookupStyle, OpcodeMap,
+    Radix, RomOptions, RomStyle, DIFF_LIMIT,
+};
+use wasm_ic::parse::{
+    disassembly_listing, listing_file, parse_wasm_features, program_stats, program_stats_text,
+    size_breakdown, size_breakdown_text, source_snippet, PcSourceMap, SourceMap,
+};
+use wasm_ic::svgen::{
+    generate_branch_scoreboard, generate_defines_svh, generate_depths_svh, generate_sv_package,
+    generate_sva_with, generate_svh_includes, generate_uvm_svh, generate_veryl_depths_package,
+    generate_veryl_testbench, test_svh_file_name, write_svh_io, SvhLoad, DPI_HEADER,
+};
 use wasm_ic::*;
 
 #[derive(Parser)]
-#[command(name = "wasm-compile", about = "Compile WAT to hex files for wasm-ic")]
+#[command(
+    name = "wasm-compile",
+    about = "Compile WAT to hex files for wasm-ic",
+    after_help = EXIT_CODES_HELP
+)]
 struct Cli {
     #[command(subcommand)]
     command: Command,
+    /// Take default options from this file instead of the nearest
+    /// wasm-ic.toml
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+    /// Don't look for a wasm-ic.toml
+    #[arg(long, global = true, conflicts_with = "config")]
+    no_config: bool,
+    /// Log more: -v adds stage timings, -vv everything
+    #[arg(long, short, global = true, action = clap::ArgAction::Count)]
+    verbose: u8,
+    /// Log only errors, leaving out the progress lines
+    #[arg(long, short, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+    /// Keep the modules the reference runs compile in this directory, so
+    /// runs over an unchanged corpus load them instead of compiling again
+    #[arg(long, global = true)]
+    module_cache: Option<PathBuf>,
 }
 
+// Parsed once per run; boxing the big variants would buy nothing.
+#[allow(clippy::large_enum_variant)]
 #[derive(clap::Subcommand)]
 enum Command {
     /// Compile a WAT file to hex files for the hardware core
     Compile {
-        /// Input WAT file
-        input: PathBuf,
+        /// Input WAT file or precompiled .wasm, or `-` for stdin. Several
+        /// inputs, or a directory of them, compile each test into its own
+        /// subdirectory of the output directory. With `--emit`, the single input is
+        /// followed by TARGET
+        #[arg(required = true, num_args = 1..)]
+        inputs: Vec<PathBuf>,
         /// Output directory for hex files
         #[arg(long, default_value = ".")]
         out_dir: PathBuf,
+        #[command(flatten)]
+        emit_args: EmitArgs,
+        /// Emit only this artifact, to TARGET: a file path or `-` for
+        /// stdout
+        #[arg(long, value_enum)]
+        emit: Option<EmitArg>,
+        /// Keep running, compiling again whenever an input changes
+        #[arg(long)]
+        watch: bool,
+        /// Print the summary as JSON instead of text
+        #[arg(long, conflicts_with = "emit")]
+        summary_json: bool,
+        /// Take the branch table of a module `link` wrote from its
+        /// `wasm-ic` section instead of computing it
+        #[arg(long)]
+        trust_embedded: bool,
+        /// Compute the branch table of a linked module and fail if its
+        /// `wasm-ic` section disagrees
+        #[arg(long, conflicts_with = "trust_embedded")]
+        verify_embedded: bool,
+        /// Replace instructions the core lacks with sequences of ones it
+        /// has where possible, e.g. `i32.extend8_s` with two shifts or a
+        /// `memory.fill` of constant size with stores
+        #[arg(long)]
+        expand_unsupported: bool,
+        /// Re-encode padded LEB128 immediates in their shortest form,
+        /// moving the PCs after them
+        #[arg(long)]
+        normalize_leb: bool,
+        /// Wasm proposals inputs may use, comma-separated: a preset
+        /// (wasm1, wasm2, wasm3, all, none, default) replaces the set,
+        /// `name` or `+name` enables a proposal and `-name` disables it,
+        /// e.g. `wasm1,+sign-extension` or `-simd,-threads`
+        #[arg(long, value_name = "LIST")]
+        wasm_features: Option<String>,
+        /// Also write <name>.srcmap.json, the WAT line and expression of
+        /// every PC, for reporting failures against the source
+        #[arg(long)]
+        source_map: bool,
+        /// Also write <name>.lst: PC, raw bytes, mnemonic, branch target,
+        /// and WAT source line of every instruction
+        #[arg(long)]
+        listing: bool,
+        /// Compile every function of each input into its own
+        /// subdirectory, named <input>_<export> or <input>_func<index>;
+        /// only functions exported as `() -> i32` get an expected value
+        #[arg(long, conflicts_with = "emit")]
+        all_functions: bool,
+    },
+    /// Check artifacts written by `compile` against a fresh compile of
+    /// their source, to catch stale or hand-edited files
+    Verify {
+        /// Sources the artifacts were compiled from, laid out as
+        /// `compile` would for the same inputs
+        #[arg(required = true, num_args = 1..)]
+        inputs: Vec<PathBuf>,
+        /// Directory holding the artifacts
+        #[arg(long, default_value = ".")]
+        out_dir: PathBuf,
+        /// The options the artifacts were compiled with
+        #[command(flatten)]
+        emit_args: EmitArgs,
+        /// Color the differences: expected in red, actual in green
+        #[arg(long, value_enum, default_value_t = ColorArg::Auto)]
+        color: ColorArg,
+    },
+    /// Report every instruction or construct the core can't run, failing
+    /// if there are any
+    Check {
+        /// WAT or .wasm files, directories of them, or `-` for stdin
+        #[arg(required = true, num_args = 1..)]
+        inputs: Vec<PathBuf>,
+        /// Treat this instruction as supported, e.g. `i32.wrap_i64` on a
+        /// core built with it; repeatable
+        #[arg(long)]
+        allow: Vec<String>,
+        /// TOML description of the core to check against instead of the
+        /// default build
+        #[arg(long)]
+        hardware: Option<PathBuf>,
+    },
+    /// Cross the instructions a whole corpus uses against the ones the
+    /// core implements: which implemented ones no test covers, and which
+    /// missing ones keep tests off the core
+    Coverage {
+        /// WAT or .wasm files, directories of them, or `-` for stdin
+        #[arg(required = true, num_args = 1..)]
+        inputs: Vec<PathBuf>,
+        /// Treat this instruction as supported; repeatable
+        #[arg(long)]
+        allow: Vec<String>,
+        /// TOML description of the core to check against instead of the
+        /// default build
+        #[arg(long)]
+        hardware: Option<PathBuf>,
+        /// Also rank each missing instruction and feature by the tests
+        /// gaining it alone would unlock
+        #[arg(long)]
+        what_if: bool,
+        /// List the tests gaining all of these together would unlock: a
+        /// mnemonic or a feature key such as `sign_extension`; repeatable
+        #[arg(long)]
+        gain: Vec<String>,
+    },
+    /// Compare two artifact directories, e.g. from two tool versions, and
+    /// list what changed in each file
+    Diff {
+        /// Directory of the old artifacts
+        old: PathBuf,
+        /// Directory of the new artifacts
+        new: PathBuf,
+        /// Differences to list per file before just counting them
+        #[arg(long, default_value_t = DIFF_LIMIT)]
+        limit: usize,
+        /// Color the differences: old in red, new in green
+        #[arg(long, value_enum, default_value_t = ColorArg::Auto)]
+        color: ColorArg,
+    },
+    /// Disassemble the program exactly as the ROM holds it: PC, raw bytes,
+    /// and mnemonic per instruction, with branch table targets
+    Disasm {
+        /// Input WAT file or precompiled .wasm, or `-` for stdin
+        input: PathBuf,
+        /// Write the listing here instead of stdout
+        #[arg(long)]
+        output: Option<PathBuf>,
+        /// End each line with the WAT line and expression it came from
+        #[arg(long)]
+        source: bool,
+    },
+    /// Report body size, opcode histogram, nesting, branches, and stack
+    /// depth per program, to judge what the core can run
+    Stats {
+        /// WAT, .wasm, or .wast files, directories of them, or `-` for
+        /// stdin
+        #[arg(required = true, num_args = 1..)]
+        inputs: Vec<PathBuf>,
+    },
+    /// Break each program's ROM bytes down by construct (constants,
+    /// arithmetic, control flow, locals, memory ops) and by the WAT lines
+    /// they were written as, largest first
+    Size {
+        /// WAT, .wasm, or .wast files, directories of them, or `-` for
+        /// stdin
+        #[arg(required = true, num_args = 1..)]
+        inputs: Vec<PathBuf>,
+        /// Source lines to list per program
+        #[arg(long, default_value_t = 10)]
+        top: usize,
+    },
+    /// Report each test's ROM bytes, branch entries, stack and block
+    /// depth, and memory footprint against the core's limits, then the
+    /// peaks across all of them, to see the headroom left
+    Usage {
+        /// WAT, .wasm, or .wast files, directories of them, or `-` for
+        /// stdin
+        #[arg(required = true, num_args = 1..)]
+        inputs: Vec<PathBuf>,
+        /// TOML description of the core to measure against instead of the
+        /// default build
+        #[arg(long)]
+        hardware: Option<PathBuf>,
+    },
+    /// Pack a WAT file into a single boot image for loaders and simulators
+    Pack {
+        /// Input WAT file or precompiled .wasm
+        input: PathBuf,
+        /// Output boot image path
+        #[arg(long, short)]
+        output: PathBuf,
+    },
+    /// Embed the program image, branch table, and core numbers in the
+    /// module as a `wasm-ic` custom section, for one self-describing file
+    Link {
+        /// Input WAT file or precompiled .wasm
+        input: PathBuf,
+        /// Output .wasm path
+        #[arg(long, short)]
+        output: PathBuf,
+        /// TOML description of the core to check against and record
+        /// instead of the default build
+        #[arg(long)]
+        hardware: Option<PathBuf>,
+    },
+    /// Generate a synthesizable SystemVerilog ROM module holding the program
+    GenRom {
+        /// Input WAT file or precompiled .wasm
+        input: PathBuf,
+        /// Output .sv file path
+        #[arg(long, short)]
+        output: PathBuf,
+        /// Output language
+        #[arg(long, value_enum, default_value_t = HdlArg::Sv)]
+        lang: HdlArg,
+        /// Which table to generate
+        #[arg(long, value_enum, default_value_t = RomTableArg::Prog)]
+        table: RomTableArg,
+        /// Module name (defaults to prog_rom/branch_rom for SV and
+        /// ProgRom/BranchRom for Veryl)
+        #[arg(long)]
+        module: Option<String>,
+        /// Storage style for the program ROM
+        #[arg(long, value_enum, default_value_t = RomStyleArg::Case)]
+        style: RomStyleArg,
+        /// Lookup structure for the branch table ROM
+        #[arg(long, value_enum, default_value_t = LookupArg::Case)]
+        lookup: LookupArg,
+        /// Word width in bits
+        #[arg(long, default_value_t = 8)]
+        data_width: u32,
+        /// Byte order when packing program bytes into wider words
+        #[arg(long, value_enum, default_value_t = EndianArg::Little)]
+        endian: EndianArg,
+        /// ROM depth in words (defaults to the program size)
+        #[arg(long)]
+        depth: Option<usize>,
+    },
+    /// Generate a cocotb test module for all WAT files
+    GenCocotb {
+        /// Directory containing WAT files
+        #[arg(long)]
+        wat_dir: PathBuf,
+        #[command(flatten)]
+        filter: FilterArgs,
+        /// Output .py file path
+        #[arg(long)]
+        output: PathBuf,
+        /// Directory for the hex files the tests load (defaults to the
+        /// output's directory)
+        #[arg(long)]
+        hex_dir: Option<PathBuf>,
+    },
+    /// Generate a standalone Verilator C++ harness for all WAT files
+    GenVerilator {
+        /// Directory containing WAT files
+        #[arg(long)]
+        wat_dir: PathBuf,
+        #[command(flatten)]
+        filter: FilterArgs,
+        /// Directory for sim_main.cpp and its support header
+        #[arg(long, default_value = ".")]
+        out_dir: PathBuf,
+        /// Verilated top class the harness instantiates
+        #[arg(long, default_value = "Vhello_WasmCoreTb")]
+        top: String,
+    },
+    /// Write the `import "DPI-C"` header for the wasm-ic-dpi library
+    GenDpi {
+        /// Output .svh file path
+        #[arg(long, default_value = DPI_HEADER)]
+        output: PathBuf,
+    },
+    /// Write a Veryl file with a testbench for a core variant, wired to its
+    /// ports and running the tests of a gen-tests header, to start
+    /// bring-up from
+    GenVerylTb {
+        /// Output .veryl file path
+        #[arg(long)]
+        output: PathBuf,
+        /// Name of the testbench module and its test
+        #[arg(long, default_value = "test_wasm_core")]
+        module: String,
+        /// Core module the testbench instantiates, with the WasmCoreTb ports
+        #[arg(long, default_value = "hello_WasmCoreTb")]
+        core: String,
+        /// Header of test tasks the testbench includes (see gen-tests)
+        #[arg(long, default_value = "wat_tests.svh")]
+        include: String,
+        /// JSON file overriding testbench names, as given to gen-tests
+        #[arg(long)]
+        svh_config: Option<PathBuf>,
+        /// TOML port map renaming the core's ports and setting their widths
+        /// and active levels, for a core without the WasmCoreTb interface
+        #[arg(long)]
+        port_map: Option<PathBuf>,
+        /// TOML description of the core, for its ROM depth
+        #[arg(long)]
+        hardware: Option<PathBuf>,
+        /// Overwrite OUTPUT if it exists
+        #[arg(long)]
+        force: bool,
+    },
+    /// Generate a Rust marlin test module with one test per WAT file
+    GenMarlin {
+        /// Directory containing WAT files
+        #[arg(long)]
+        wat_dir: PathBuf,
+        #[command(flatten)]
+        filter: FilterArgs,
+        /// Output .rs file path
+        #[arg(long)]
+        output: PathBuf,
     },
     /// Generate a SystemVerilog header with test tasks for all WAT files
     GenTests {
         /// Directory containing WAT files
         #[arg(long)]
         wat_dir: PathBuf,
+        #[command(flatten)]
+        filter: FilterArgs,
         /// Output .svh file path
         #[arg(long)]
         output: PathBuf,
+        /// Write per-test hex files and load them with $readmemh instead of
+        /// inlining every program byte into the header
+        #[arg(long)]
+        readmemh: bool,
+        /// Directory for the per-test hex files, also used as the
+        /// $readmemh path prefix (defaults to the output's directory)
+        #[arg(long, requires = "readmemh")]
+        hex_dir: Option<PathBuf>,
+        /// Write one include-guarded <test>.svh per test next to OUTPUT,
+        /// which then only includes them
+        #[arg(long)]
+        split: bool,
+        /// Prefix for the generated per-test task names
+        #[arg(long, default_value = "run_wat_")]
+        task_prefix: String,
+        /// Have run_all_wat_tests run the tests in a random order, seeded
+        /// by +WAT_SEED=<n> or else by the simulator, and print the seed
+        #[arg(long)]
+        shuffle: bool,
+        /// JSON file overriding testbench names and boilerplate, e.g.
+        /// {"names": {"prog_rom": "i_code_mem"}}
+        #[arg(long)]
+        svh_config: Option<PathBuf>,
+        /// TOML port map renaming the core's ports and setting their widths
+        /// and active levels, for a core without the WasmCoreTb interface
+        #[arg(long)]
+        port_map: Option<PathBuf>,
+        /// Also write a header of per-test localparams (length, branch
+        /// count, expected value, max PC) to this path
+        #[arg(long)]
+        defines: Option<PathBuf>,
+        /// Also write a SystemVerilog package with a test enum and arrays
+        /// indexed by it; the package is named after the file stem
+        #[arg(long)]
+        package: Option<PathBuf>,
+        /// Also write the label-stack depth, operand-stack depth, and branch
+        /// table size the corpus needs to this path: localparams, or a
+        /// Veryl package named after the file stem if it ends in .veryl
+        #[arg(long)]
+        depths: Option<PathBuf>,
+        /// Also write SVA checkers of each program's control flow to this
+        /// path, bound into --bind-target
+        #[arg(long)]
+        sva: Option<PathBuf>,
+        /// Module the SVA checkers are bound into
+        #[arg(long, default_value = "hello_WasmCoreTb")]
+        bind_target: String,
+        /// Also write a scoreboard checking each branch table lookup against
+        /// the computed table to this path, bound into --scoreboard-target
+        #[arg(long)]
+        scoreboard: Option<PathBuf>,
+        /// Module the scoreboards are bound into: the fetch unit, whose
+        /// lookup ports they watch
+        #[arg(long, default_value = "hello_WasmFetch")]
+        scoreboard_target: String,
+        /// Also write a Markdown report of the corpus to this path: each
+        /// test's size, branch entries, expected value, and instructions,
+        /// the skipped tests with their reasons, and opcode coverage
+        #[arg(long)]
+        report: Option<PathBuf>,
+        /// Kind of test code to generate
+        #[arg(long, value_enum, default_value_t = FlavorArg::Tasks)]
+        flavor: FlavorArg,
+        /// UVM flavor: base test class the generated tests extend
+        #[arg(long, default_value = "wat_base_test")]
+        uvm_base: String,
+        /// Keep running, regenerating whenever a corpus file, the
+        /// --svh-config file, or the --port-map file changes
+        #[arg(long)]
+        watch: bool,
+        /// Keep each corpus file's compile and golden run here and reuse
+        /// it while the file is unchanged
+        #[arg(long)]
+        cache_dir: Option<PathBuf>,
+        /// Files to compile at once; 0 for one per CPU
+        #[arg(long, short, default_value_t = 0)]
+        jobs: usize,
+        /// TOML description of the core the tests run on instead of the
+        /// default build; tests it can't run are skipped
+        #[arg(long)]
+        hardware: Option<PathBuf>,
+        /// Print the summary as JSON instead of text
+        #[arg(long)]
+        summary_json: bool,
+        /// Skip writing manifest.json, the record of every test and its
+        /// artifacts, and manifest.toml, the corpus layout and per-test
+        /// options, next to OUTPUT
+        #[arg(long)]
+        no_manifest: bool,
+    },
+    /// Convert the results file written by the generated SV tests into
+    /// JUnit XML and/or JSON
+    ParseResults {
+        /// Results file (wat_results.tsv unless +WAT_RESULTS was given)
+        input: PathBuf,
+        /// Write JUnit XML to this path
+        #[arg(long)]
+        junit: Option<PathBuf>,
+        /// Write JSON to this path
+        #[arg(long)]
+        json: Option<PathBuf>,
+        /// Test suite name in the JUnit XML
+        #[arg(long, default_value = "wat")]
+        suite: String,
+    },
+    /// Predict each test's cycles on the core model and write them as
+    /// CSV, next to the cycles measured on the core if given its results
+    Bench {
+        /// Directory containing WAT files
+        #[arg(long)]
+        wat_dir: PathBuf,
+        #[command(flatten)]
+        filter: FilterArgs,
+        /// Results file from a run on the core (see `parse-results`)
+        /// giving the measured cycles
+        #[arg(long)]
+        results: Option<PathBuf>,
+        /// Write the CSV here instead of stdout
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+        /// Cycles the model runs a test for before calling it a timeout
+        #[arg(long, default_value_t = 1_000_000)]
+        max_cycles: u64,
+    },
+    /// Reconstruct the executed instructions from a simulation VCD, with
+    /// disassembly from the WAT file that was run
+    AnalyzeVcd {
+        /// VCD file from the simulation
+        vcd: PathBuf,
+        /// WAT file the simulation ran
+        #[arg(long)]
+        wat: PathBuf,
+        /// Clock signal name
+        #[arg(long, default_value = "i_clk")]
+        clock: String,
+        /// Program counter signal name
+        #[arg(long, default_value = "o_pc")]
+        pc: String,
+        /// Stack top signal name
+        #[arg(long, default_value = "o_stack_top")]
+        stack_top: String,
+        /// Only cycles where this signal is high count; empty for all
+        #[arg(long, default_value = "o_running")]
+        running: String,
+        /// Write the listing here instead of stdout
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+    /// Serve compile requests as line-delimited JSON-RPC over TCP
+    Serve {
+        /// Port to listen on
+        #[arg(long, default_value_t = SERVE_PORT)]
+        port: u16,
+        /// Address to bind; the default only accepts local clients
+        #[arg(long, default_value = "127.0.0.1")]
+        host: String,
+        /// Clients to serve at once, later ones waiting their turn; 0 for
+        /// one per CPU
+        #[arg(long, short, default_value_t = 0)]
+        jobs: usize,
+    },
+    /// Curate the checks of the official spec testsuite the core can run
+    /// into a `.wast` corpus, with a report of what was left out and why
+    ImportSpec {
+        /// Checkout of the testsuite; every `.wast` below it is read
+        testsuite: PathBuf,
+        /// Directory for the curated scripts
+        #[arg(long, default_value = "tests/spec")]
+        out_dir: PathBuf,
+        /// Markdown report of kept and excluded checks
+        #[arg(long, default_value = "tests/spec/IMPORT.md")]
+        report: PathBuf,
+    },
+    /// Generate WAT stress programs from templates, a sweep of sizes each,
+    /// to probe the core's limits
+    GenCorpus {
+        /// Directory for the programs, named `<template>_<size>.wat`
+        #[arg(long, default_value = "tests/stress")]
+        out_dir: PathBuf,
+        /// Templates to generate, all by default; repeatable
+        #[arg(long, value_enum)]
+        template: Vec<TemplateArg>,
+        /// Sizes to generate each template at, instead of a sweep up to
+        /// the core's limits
+        #[arg(long, value_delimiter = ',')]
+        sizes: Vec<usize>,
+        /// Seed for the arithmetic template's operands
+        #[arg(long, default_value_t = 1)]
+        seed: u64,
+        /// TOML description of the core whose limits the sweeps go up to
+        #[arg(long)]
+        hardware: Option<PathBuf>,
+    },
+    /// Generate random modules with wasm-smith, kept to what the core
+    /// runs, and put them through the pipeline, saving the cases that
+    /// reach new instruction pairs and every one the pipeline fails on
+    Fuzz {
+        /// Directory for saved cases: `case_<n>.wasm` for new coverage,
+        /// `fail_<n>.wasm` with a `fail_<n>.txt` error for failures
+        #[arg(long, default_value = "tests/fuzz")]
+        out_dir: PathBuf,
+        /// Cases to generate; most are rejected, and cheaply
+        #[arg(long, default_value_t = 100_000)]
+        iterations: usize,
+        /// Seed for the random input; the same seed gives the same cases
+        #[arg(long, default_value_t = 1)]
+        seed: u64,
+        /// Most instructions in a generated function
+        #[arg(long, default_value_t = 12)]
+        max_instructions: usize,
+        /// Deepest block nesting in a generated function
+        #[arg(long, default_value_t = 6)]
+        max_nesting: usize,
+        /// TOML description of the core the cases must fit
+        #[arg(long)]
+        hardware: Option<PathBuf>,
+    },
+    /// Shrink a WAT or wasm file the pipeline fails on to a minimal
+    /// module failing the same way, written out as WAT for a bug report
+    Shrink {
+        /// The failing WAT or wasm file
+        input: PathBuf,
+        /// Where to write the reproducer; `<input>.min.wat` beside the
+        /// input by default
+        #[arg(long)]
+        out: Option<PathBuf>,
+    },
+    /// Compare a DUT signal trace against a golden trace (both JSONL) and
+    /// report the first divergence
+    #[cfg(feature = "sim")]
+    DiffTrace {
+        /// Trace recorded from the DUT
+        actual: PathBuf,
+        /// Golden trace to compare against
+        golden: PathBuf,
+        /// Samples to show either side of the divergence
+        #[arg(long, default_value_t = wasm_ic::sim::DIVERGENCE_CONTEXT)]
+        context: usize,
+        /// The program's <name>.srcmap.json from `compile --source-map`,
+        /// to name the WAT at the divergence
+        #[arg(long)]
+        source_map: Option<PathBuf>,
     },
 }
 
-fn main() -> Result<()> {
-    let cli = Cli::parse();
+/// Which corpus files a command takes, on top of the corpus directory's
+/// own skip list.
+#[derive(clap::Args)]
+struct FilterArgs {
+    /// Leave out tests whose file name (without extension) matches this
+    /// glob; repeatable
+    #[arg(long)]
+    skip: Vec<String>,
+    /// Take only tests whose file name matches one of these globs;
+    /// repeatable
+    #[arg(long)]
+    only: Vec<String>,
+    /// Also leave out the tests listed in this file, one glob per line
+    #[arg(long)]
+    skip_list: Option<PathBuf>,
+    /// Also take the tests in subdirectories, each directory a category
+    /// of its own (e.g. `arith`, `control/loops`)
+    #[arg(long, short)]
+    recursive: bool,
+    /// manifest.toml (as gen-tests writes) whose per-test options,
+    /// categories, and skips override the sources' and golden runs'
+    #[arg(long)]
+    manifest: Option<PathBuf>,
+}
 
-    match &cli.command {
-        Command::Compile { input, out_dir } => {
-            let wat_source = fs::read_to_string(input)
-                .with_context(|| format!("reading {}", input.display()))?;
-            let wasm_bytes = wat::parse_str(&wat_source)
-                .with_context(|| format!("compiling WAT from {}", input.display()))?;
+impl FilterArgs {
+    fn filter(&self) -> Result<CorpusFilter> {
+        let mut filter = CorpusFilter {
+            skip: self.skip.clone(),
+            only: self.only.clone(),
+        };
+        if let Some(path) = &self.skip_list {
+            filter.add_skip_list(path)?;
+        }
+        Ok(filter)
+    }
+}
 
-            let body_bytes =
-                extract_function_body(&wasm_bytes).context("extracting function body")?;
-            let branch_table =
-                compute_branch_table(&body_bytes).context("computing branch table")?;
-            let expected = run_with_wasmtime(&wasm_bytes).context("running with wasmtime")?;
+/// How `compile` lays out artifacts, shared with `verify` so it can
+/// expect the same files.
+#[derive(clap::Args)]
+struct EmitArgs {
+    /// Artifact format for the program and branch table images
+    #[arg(long, value_enum, default_value_t = OutputFormat::Hex)]
+    format: OutputFormat,
+    /// Word width in bits for the program image (hex and mif formats)
+    #[arg(long, default_value_t = 8)]
+    data_width: u32,
+    /// Byte order when packing program bytes into wider words
+    #[arg(long, value_enum, default_value_t = EndianArg::Little)]
+    endian: EndianArg,
+    /// Append a disassembly comment to every prog.hex line
+    #[arg(long)]
+    annotate: bool,
+    /// Number base for the branch.hex columns
+    #[arg(long, value_enum, default_value_t = RadixArg::Hex)]
+    branch_radix: RadixArg,
+    /// Zero-padded digit count for each branch.hex column (0 = no padding)
+    #[arg(long, default_value_t = 8)]
+    branch_width: usize,
+    /// Separator between the branch.hex source and target columns
+    #[arg(long, default_value = " ")]
+    branch_sep: String,
+    /// Pad the MIF program image to this many words
+    #[arg(long)]
+    depth: Option<usize>,
+    /// Bus format: program ROM base address
+    #[arg(long, value_parser = parse_u32, default_value = "0x00000000")]
+    prog_base: u32,
+    /// Bus format: branch table base address
+    #[arg(long, value_parser = parse_u32, default_value = "0x00010000")]
+    branch_base: u32,
+    /// Bus format: data memory base address
+    #[arg(long, value_parser = parse_u32, default_value = "0x00020000")]
+    mem_base: u32,
+    /// Bus format: control register written with 1 to start the core
+    #[arg(long, value_parser = parse_u32)]
+    start_addr: Option<u32>,
+    /// Also write checksums.json and checksums.svh for load-integrity checks
+    #[arg(long, value_enum)]
+    checksums: Option<ChecksumArg>,
+    /// Output file name prefix (defaults to the input file stem; pass
+    /// an empty string for bare prog.hex/branch.hex/... names)
+    #[arg(long)]
+    prefix: Option<String>,
+    /// TOML description of the core the artifacts are for instead of the
+    /// default build
+    #[arg(long)]
+    hardware: Option<PathBuf>,
+    /// TOML table of mnemonic to hardware op code, for a core decoding a
+    /// compacted encoding; the program is written remapped
+    #[arg(long)]
+    opcode_map: Option<PathBuf>,
+    /// Also write predecode.hex: the PC, length, and decoded immediate of
+    /// every instruction carrying one (hex format)
+    #[arg(long)]
+    predecode: bool,
+    /// Write a program longer than the hardware ROM with a warning
+    /// instead of failing
+    #[arg(long)]
+    force: bool,
+    /// Also write expected.json: the return values with their types, the
+    /// trap kind, the final memory words, and the cycle budget
+    #[arg(long)]
+    expected_spec: bool,
+}
 
-            fs::create_dir_all(out_dir)?;
-            write_prog_hex(&out_dir.join("prog.hex"), &body_bytes)?;
-            write_branch_hex(&out_dir.join("branch.hex"), &branch_table)?;
-            write_expected(&out_dir.join("expected.txt"), expected)?;
+impl EmitArgs {
+    fn options(&self) -> Result<EmitOptions> {
+        Ok(EmitOptions {
+            format: self.format.into(),
+            data_width: self.data_width,
+            endian: self.endian.into(),
+            annotate: self.annotate,
+            branch_layout: BranchHexLayout {
+                radix: self.branch_radix.into(),
+                width: self.branch_width,
+                separator: self.branch_sep.clone(),
+            },
+            depth: self.depth,
+            prefix: self.prefix.clone(),
+            bus_map: BusMap {
+                prog_base: self.prog_base,
+                branch_base: self.branch_base,
+                mem_base: self.mem_base,
+                start_addr: self.start_addr,
+            },
+            checksums: self.checksums.map(Into::into),
+            hardware: hardware_spec(self.hardware.as_deref())?,
+            opcode_map: self
+                .opcode_map
+                .as_deref()
+                .map(OpcodeMap::load)
+                .transpose()?,
+            predecode: self.predecode,
+            force: self.force,
+            expected_spec: self.expected_spec,
+        })
+    }
+}
 
-            let name = input.file_stem().unwrap_or_default().to_string_lossy();
-            println!(
-                "{}: {} bytes, {} branch entries, expected={}",
-                name,
-                body_bytes.len(),
-                branch_table.len(),
-                expected
-            );
+/// The core described by the file at `path`, or the default build.
+fn hardware_spec(path: Option<&Path>) -> Result<HardwareSpec> {
+    match path {
+        Some(path) => Ok(HardwareSpec::load(path)?),
+        None => Ok(HardwareSpec::default()),
+    }
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum OutputFormat {
+    /// prog.hex / branch.hex for $readmemh and the marlin testbench
+    Hex,
+    /// prog.coe / branch.coe for the Vivado block memory generator
+    Coe,
+    /// prog.mif / branch.mif for Quartus memory initialization
+    Mif,
+    /// prog.bin / branch.bin / mem_init.bin as raw little-endian binary
+    Bin,
+    /// program.json holding every artifact plus metadata in one document
+    Json,
+    /// program.h with const arrays for embedding in loader firmware
+    C,
+    /// program.rs with `pub const` items for `include!` in Rust loaders
+    Rust,
+    /// stimulus.txt with labeled PROG/BRANCH/MEM/EXPECTED sections
+    Stimulus,
+    /// bus.txt with the load sequence as 32-bit bus writes
+    Bus,
+    /// insn.hex (one 32-bit word per instruction), const.hex, and
+    /// branch.hex for the pipelined core without variable-length decode
+    Fixed,
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum EndianArg {
+    Little,
+    Big,
+}
+
+impl From<EndianArg> for Endian {
+    fn from(e: EndianArg) -> Self {
+        match e {
+            EndianArg::Little => Endian::Little,
+            EndianArg::Big => Endian::Big,
         }
-        Command::GenTests { wat_dir, output } => {
-            let mut wat_files: Vec<PathBuf> = fs::read_dir(wat_dir)
-                .with_context(|| format!("reading directory {}", wat_dir.display()))?
-                .filter_map(|e| e.ok())
-                .map(|e| e.path())
-                .filter(|p| p.extension().map_or(false, |ext| ext == "wat"))
-                .filter(|p| {
-                    let stem = p.file_stem().unwrap_or_default().to_string_lossy();
-                    !SKIP_FILES.contains(&stem.as_ref())
-                })
-                .collect();
-            wat_files.sort();
+    }
+}
 
-            let mut tests = Vec::new();
-            for path in &wat_files {
-                let info = compile_wat_file(path)
-                    .with_context(|| format!("compiling {}", path.display()))?;
-                println!(
-                    "  {}: {} bytes, {} branches, expected={}",
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum RadixArg {
+    Hex,
+    Dec,
+}
+
+impl From<RadixArg> for Radix {
+    fn from(r: RadixArg) -> Self {
+        match r {
+            RadixArg::Hex => Radix::Hex,
+            RadixArg::Dec => Radix::Dec,
+        }
+    }
+}
+
+impl From<OutputFormat> for ArtifactFormat {
+    fn from(f: OutputFormat) -> Self {
+        match f {
+            OutputFormat::Hex => ArtifactFormat::Hex,
+            OutputFormat::Coe => ArtifactFormat::Coe,
+            OutputFormat::Mif => ArtifactFormat::Mif,
+            OutputFormat::Bin => ArtifactFormat::Bin,
+            OutputFormat::Json => ArtifactFormat::Json,
+            OutputFormat::C => ArtifactFormat::C,
+            OutputFormat::Rust => ArtifactFormat::Rust,
+            OutputFormat::Stimulus => ArtifactFormat::Stimulus,
+            OutputFormat::Bus => ArtifactFormat::Bus,
+            OutputFormat::Fixed => ArtifactFormat::Fixed,
+        }
+    }
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum EmitArg {
+    /// Program image
+    Prog,
+    /// Branch table
+    Branch,
+    /// Memory init image
+    Mem,
+    /// Expected final memory words (hex format)
+    MemExpected,
+    /// Whole-program document (json, c, rust, and stimulus formats)
+    Program,
+    /// Expected return value
+    Expected,
+    /// Constant ROM (fixed format)
+    Const,
+    /// LEB128 pre-decode sideband (hex format)
+    Predecode,
+    /// Expected outcome as JSON: returns, trap kind, memory, cycle budget
+    ExpectedSpec,
+}
+
+impl From<EmitArg> for ArtifactKind {
+    fn from(e: EmitArg) -> Self {
+        match e {
+            EmitArg::Prog => ArtifactKind::Prog,
+            EmitArg::Branch => ArtifactKind::Branch,
+            EmitArg::Mem => ArtifactKind::Mem,
+            EmitArg::MemExpected => ArtifactKind::MemExpected,
+            EmitArg::Program => ArtifactKind::Program,
+            EmitArg::Expected => ArtifactKind::Expected,
+            EmitArg::Const => ArtifactKind::Const,
+            EmitArg::Predecode => ArtifactKind::Predecode,
+            EmitArg::ExpectedSpec => ArtifactKind::ExpectedSpec,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, clap::ValueEnum)]
+enum HdlArg {
+    /// SystemVerilog
+    Sv,
+    /// Veryl
+    Veryl,
+}
+
+#[derive(Clone, Copy, PartialEq, clap::ValueEnum)]
+enum RomTableArg {
+    /// Program ROM
+    Prog,
+    /// Branch table ROM
+    Branch,
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum LookupArg {
+    /// case statement on the source PC
+    Case,
+    /// balanced comparator tree over sorted source PCs
+    Tree,
+}
+
+impl From<LookupArg> for LookupStyle {
+    fn from(l: LookupArg) -> Self {
+        match l {
+            LookupArg::Case => LookupStyle::Case,
+            LookupArg::Tree => LookupStyle::Tree,
+        }
+    }
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum RomStyleArg {
+    /// case statement on the address
+    Case,
+    /// initialized constant array
+    Array,
+}
+
+impl From<RomStyleArg> for RomStyle {
+    fn from(s: RomStyleArg) -> Self {
+        match s {
+            RomStyleArg::Case => RomStyle::Case,
+            RomStyleArg::Array => RomStyle::Array,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, clap::ValueEnum)]
+enum FlavorArg {
+    /// Plain SystemVerilog tasks called from the testbench
+    Tasks,
+    /// UVM sequence items, sequences, and tests
+    Uvm,
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum ColorArg {
+    /// Color when stdout is a terminal
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorArg {
+    fn enabled(self) -> bool {
+        match self {
+            ColorArg::Auto => std::io::stdout().is_terminal(),
+            ColorArg::Always => true,
+            ColorArg::Never => false,
+        }
+    }
+}
+
+/// Print a detailed diff indented under its summary line, expected lines
+/// marked `-` and actual ones `+`.
+fn print_diff_detail(detail: &[DiffLine], color: bool) {
+    let paint = |code: &str, line: String| {
+        if color {
+            format!("\x1b[{}m{}\x1b[0m", code, line)
+        } else {
+            line
+        }
+    };
+    for line in detail {
+        let line = match line {
+            DiffLine::Same(text) => format!("      {}", text),
+            DiffLine::Expected(text) => paint("31", format!("    - {}", text)),
+            DiffLine::Actual(text) => paint("32", format!("    + {}", text)),
+            DiffLine::Skipped(n) => paint("2", format!("      ... {} unchanged", n)),
+        };
+        println!("{}", line);
+    }
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum TemplateArg {
+    /// Deeply nested blocks and ifs
+    Nesting,
+    /// A long chain of arithmetic
+    Arithmetic,
+    /// Many branch table entries
+    Branches,
+    /// Straight-line code of an exact byte size
+    Size,
+}
+
+impl From<TemplateArg> for StressTemplate {
+    fn from(t: TemplateArg) -> Self {
+        match t {
+            TemplateArg::Nesting => StressTemplate::Nesting,
+            TemplateArg::Arithmetic => StressTemplate::Arithmetic,
+            TemplateArg::Branches => StressTemplate::Branches,
+            TemplateArg::Size => StressTemplate::Size,
+        }
+    }
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum ChecksumArg {
+    Crc32,
+    Crc32c,
+}
+
+impl From<ChecksumArg> for ChecksumAlgo {
+    fn from(c: ChecksumArg) -> Self {
+        match c {
+            ChecksumArg::Crc32 => ChecksumAlgo::Crc32,
+            ChecksumArg::Crc32c => ChecksumAlgo::Crc32c,
+        }
+    }
+}
+
+/// Parse a decimal or `0x`-prefixed hex address.
+fn parse_u32(s: &str) -> Result<u32, String> {
+    let parsed = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(&hex.replace('_', ""), 16),
+        None => s.replace('_', "").parse(),
+    };
+    parsed.map_err(|e| format!("invalid address `{}`: {}", s, e))
+}
+
+/// Compile the corpus files in `wat_dir` that `filter` and the skip list
+/// let through on `jobs` threads (0 for one per CPU), reusing `cache`
+/// entries for unchanged files. Tests and errors come out in file order.
+/// Tests a `hardware` spec can't run are left out, and with `skipped`
+/// collected with their reasons; with `json`, each test's record (see
+/// [`test_json`]) is collected too.
+fn compile_corpus(
+    wat_dir: &PathBuf,
+    filter: &FilterArgs,
+    cache: Option<&Path>,
+    jobs: usize,
+    hardware: Option<&HardwareSpec>,
+    mut json: Option<&mut Vec<serde_json::Value>>,
+    mut skipped_tests: Option<&mut Vec<(CompiledProgram, Vec<String>)>>,
+) -> Result<Vec<CompiledProgram>> {
+    let manifest = filter
+        .manifest
+        .as_deref()
+        .map(CorpusManifest::load)
+        .transpose()?;
+    let manifest = manifest.as_ref();
+    let wat_files = if filter.recursive {
+        corpus_files_recursive(wat_dir, &filter.filter()?)?
+    } else {
+        corpus_files_filtered(wat_dir, &filter.filter()?)?
+    };
+    let jobs = match jobs {
+        0 => std::thread::available_parallelism().map_or(1, |n| n.get()),
+        n => n,
+    }
+    .clamp(1, wat_files.len().max(1));
+    let next = AtomicUsize::new(0);
+    let done = Mutex::new(Vec::new());
+    std::thread::scope(|scope| {
+        for _ in 0..jobs {
+            scope.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                let Some(path) = wat_files.get(index) else {
+                    return;
+                };
+                let started = Instant::now();
+                let compiled = match cache {
+                    Some(dir) => compile_corpus_file_cached(path, dir),
+                    None => compile_corpus_file(path).map(|infos| (infos, false)),
+                }
+                .with_context(|| format!("compiling {}", path.display()));
+                let took = started.elapsed();
+                done.lock().unwrap().push((index, compiled, took));
+            });
+        }
+    });
+    let mut done = done.into_inner().unwrap();
+    done.sort_by_key(|(index, _, _)| *index);
+
+    let mut tests = Vec::new();
+    let mut sources = std::collections::HashMap::new();
+    for (index, compiled, took) in done {
+        let (infos, cached) = compiled?;
+        for mut info in infos {
+            if let Some(other) = sources.insert(info.name.clone(), index) {
+                bail!(
+                    "{} and {} both make a test named `{}`",
+                    wat_files[other].display(),
+                    wat_files[index].display(),
+                    info.name
+                );
+            }
+            let entry = manifest.and_then(|m| m.test(&info.name));
+            if let Some(entry) = entry {
+                entry.apply(&mut info);
+            }
+            let mut reasons = match hardware {
+                Some(hardware) => hardware.skip_reasons(&info)?,
+                None => Vec::new(),
+            };
+            if let Some(reason) = entry.and_then(|e| e.skip.clone()) {
+                // A manifest written by gen-tests repeats the hardware's.
+                if reasons.join("; ") != reason {
+                    reasons.push(reason);
+                }
+            }
+            let skipped = !reasons.is_empty();
+            if skipped {
+                log::info!("  {}: skipped: {}", info.name, reasons.join("; "));
+            } else {
+                log::info!(
+                    "  {}: {} bytes, {} branches, expected={}{}",
                     info.name,
                     info.body_bytes.len(),
                     info.branch_table.len(),
-                    info.expected
+                    expected_label(&info),
+                    if cached { " (cached)" } else { "" }
                 );
+            }
+            if let Some(records) = json.as_deref_mut() {
+                let mut record = test_json(&info);
+                record["cached"] = cached.into();
+                record["compile_ms"] = millis(took).into();
+                record["source"] = wat_files[index].display().to_string().into();
+                let category = entry.and_then(|e| e.category.clone());
+                if let Some(category) =
+                    category.or_else(|| corpus_category(wat_dir, &wat_files[index]))
+                {
+                    record["category"] = category.into();
+                }
+                record["skipped"] = skipped.into();
+                if skipped {
+                    record["skip_reasons"] = reasons.clone().into();
+                }
+                records.push(record);
+            }
+            if !skipped {
                 tests.push(info);
+            } else if let Some(out) = skipped_tests.as_deref_mut() {
+                out.push((info, reasons));
+            }
+        }
+    }
+    for test in manifest.iter().flat_map(|m| &m.tests) {
+        if !sources.contains_key(&test.name) {
+            log::warn!("manifest test `{}` is not in the corpus", test.name);
+        }
+    }
+    Ok(tests)
+}
+
+/// Write `<name>.srcmap.json` beside the artifacts of `info`, mapping
+/// each PC to the WAT in `input` it came from. Inputs with no WAT text to
+/// map, like wasm binaries and scripts, get none.
+fn write_source_map(
+    input: &Path,
+    info: &CompiledProgram,
+    out_dir: &Path,
+    opts: &EmitOptions,
+) -> Result<Option<PathBuf>> {
+    let Some(map) = pc_source_map(input, info) else {
+        return Ok(None);
+    };
+    let path = out_dir.join(prefixed_name(info, opts, "srcmap.json"));
+    fs::create_dir_all(out_dir)?;
+    fs::write(&path, map.to_json()).with_context(|| format!("writing {}", path.display()))?;
+    Ok(Some(path))
+}
+
+/// Write `<name>.lst`, the [`listing_file`] of `info`, beside its
+/// artifacts, with the WAT of each instruction when `input` is WAT text.
+fn write_listing(
+    input: &Path,
+    info: &CompiledProgram,
+    out_dir: &Path,
+    opts: &EmitOptions,
+) -> Result<PathBuf> {
+    let map = pc_source_map(input, info);
+    let text = listing_file(
+        &info.name,
+        &info.body_bytes,
+        &info.branch_table,
+        map.as_ref(),
+    )?;
+    let path = out_dir.join(prefixed_name(info, opts, "lst"));
+    fs::create_dir_all(out_dir)?;
+    fs::write(&path, text).with_context(|| format!("writing {}", path.display()))?;
+    Ok(path)
+}
+
+/// The PCs of the WAT file `input` mapped to its source, or `None` for
+/// wasm binaries, scripts, and stdin, and when compiling moved the PCs of
+/// `info` away from the source's.
+fn pc_source_map(input: &Path, info: &CompiledProgram) -> Option<PcSourceMap> {
+    let source = fs::read_to_string(input).ok()?;
+    let map = SourceMap::from_wat(&source)?.pc_map(&input.display().to_string(), &source);
+    let pcs = map.locations.iter().map(|l| l.pc);
+    if !pcs.eq(disassemble(&info.body_bytes).iter().map(|insn| insn.offset)) {
+        log::warn!(
+            "{}: the program no longer matches its source; not mapping it",
+            info.name
+        );
+        return None;
+    }
+    Some(map)
+}
+
+/// `<prefix>.<base>`, with the artifacts' prefix.
+fn prefixed_name(info: &CompiledProgram, opts: &EmitOptions, base: &str) -> String {
+    match opts.prefix.as_deref().unwrap_or(&info.name) {
+        "" => base.to_string(),
+        prefix => format!("{}.{}", prefix, base),
+    }
+}
+
+/// A compiled test's summary in `--summary-json` output.
+fn test_json(info: &CompiledProgram) -> serde_json::Value {
+    serde_json::json!({
+        "name": info.name,
+        "bytes": info.body_bytes.len(),
+        "branch_entries": info.branch_table.len(),
+        "expected": info.expected,
+        "expect_trap": info.expect_trap,
+        "cycle_budget": info.cycle_budget,
+    })
+}
+
+/// The [`CorpusManifest`] of the tests `compile_corpus` recorded, skipped
+/// ones included with their reasons.
+fn corpus_manifest(records: &[serde_json::Value]) -> CorpusManifest {
+    let string = |v: &serde_json::Value| v.as_str().map(str::to_string);
+    let tests = records
+        .iter()
+        .map(|record| ManifestTest {
+            name: string(&record["name"]).unwrap_or_default(),
+            source: string(&record["source"]),
+            category: string(&record["category"]),
+            max_cycles: record["cycle_budget"].as_u64().map(|b| b as u32),
+            expect_trap: record["expect_trap"].as_bool(),
+            skip: record["skip_reasons"].as_array().map(|reasons| {
+                let reasons: Vec<&str> = reasons.iter().filter_map(|r| r.as_str()).collect();
+                reasons.join("; ")
+            }),
+        })
+        .collect();
+    CorpusManifest { tests }
+}
+
+/// A duration in milliseconds, as `--summary-json` output reports timings.
+fn millis(duration: std::time::Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+/// The wasm binary of a WAT or wasm file, or of stdin for `-`.
+fn read_wasm(input: &PathBuf) -> Result<Vec<u8>> {
+    let bytes = if input.as_os_str() == "-" {
+        let mut bytes = Vec::new();
+        std::io::stdin()
+            .read_to_end(&mut bytes)
+            .context("reading stdin")?;
+        bytes
+    } else {
+        fs::read(input).with_context(|| format!("reading {}", input.display()))?
+    };
+    if bytes.starts_with(WASM_MAGIC) {
+        return Ok(bytes);
+    }
+    let source = String::from_utf8(bytes)
+        .with_context(|| format!("reading {} as WAT text", input.display()))?;
+    compile_wat(&source).with_context(|| format!("parsing {}", input.display()))
+}
+
+/// Compile a WAT or wasm file, or, for `-`, whichever of the two stdin
+/// holds as a test named `stdin`.
+fn compile_input(input: &Path) -> Result<CompiledProgram> {
+    compile_input_with(input, &CompileOptions::new())
+}
+
+fn compile_input_with(input: &Path, options: &CompileOptions) -> Result<CompiledProgram> {
+    if input.as_os_str() != "-" {
+        return Ok(compile(Source::File(input), options)?);
+    }
+    let mut bytes = Vec::new();
+    std::io::stdin()
+        .read_to_end(&mut bytes)
+        .context("reading stdin")?;
+    let options = options.clone().name("stdin");
+    if bytes.starts_with(WASM_MAGIC) {
+        return compile(Source::Wasm(&bytes), &options).context("compiling wasm from stdin");
+    }
+    let source = String::from_utf8(bytes).context("reading stdin as WAT text")?;
+    compile(Source::Wat(&source), &options).context("compiling WAT from stdin")
+}
+
+/// How `compile` treats the `wasm-ic` section of linked inputs.
+fn embedded_options(trust: bool, verify: bool) -> CompileOptions {
+    CompileOptions::new().embedded(match (trust, verify) {
+        (true, _) => Embedded::Trust,
+        (_, true) => Embedded::Verify,
+        _ => Embedded::Ignore,
+    })
+}
+
+/// Compile every input, expanding directories to their corpus files,
+/// into `<out_dir>/<test name>/`, carrying on past failures and ending
+/// with a summary of them all, as JSON with `summary_json`. With
+/// `all_functions`, every function of a module is a test of its own (see
+/// [`compile_functions`]), and one without a golden run gets no
+/// expected-value file.
+#[allow(clippy::too_many_arguments)]
+fn compile_batch(
+    inputs: &[PathBuf],
+    out_dir: &Path,
+    options: &CompileOptions,
+    opts: &EmitOptions,
+    summary_json: bool,
+    source_map: bool,
+    listing: bool,
+    all_functions: bool,
+) -> Result<()> {
+    let started = Instant::now();
+    let mut files = Vec::new();
+    for input in inputs {
+        if input.is_dir() {
+            files.extend(corpus_files(input)?);
+        } else {
+            files.push(input.clone());
+        }
+    }
+    let mut rows = Vec::new();
+    let mut categories = Vec::new();
+    let mut names = std::collections::HashSet::new();
+    // Tests of functions the golden run can't call.
+    let mut no_golden = std::collections::HashSet::new();
+    for file in &files {
+        let stem = if file.as_os_str() == "-" {
+            "stdin".to_string()
+        } else {
+            file.file_stem()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned()
+        };
+        let compiled = if file.extension().is_some_and(|ext| ext == "wast") {
+            compile_corpus_file(file)
+                .map(|infos| infos.into_iter().map(Ok).collect())
+                .map_err(Into::into)
+        } else if all_functions {
+            read_wasm(file).and_then(|wasm| {
+                let functions = compile_functions(&stem, &wasm, options)?;
+                Ok(functions
+                    .into_iter()
+                    .map(|f| {
+                        if !f.golden {
+                            no_golden.insert(f.name.clone());
+                        }
+                        f.test.map_err(|e| (f.name, e.into()))
+                    })
+                    .collect())
+            })
+        } else {
+            compile_input_with(file, options).map(|info| vec![Ok(info)])
+        };
+        let infos: Vec<Result<CompiledProgram, (String, anyhow::Error)>> = match compiled {
+            Ok(infos) => infos,
+            Err(e) => {
+                log::error!("{}: {:#}", file.display(), e);
+                categories.push(ErrorCategory::of(&e));
+                rows.push((stem, Err(format!("{:#}", e))));
+                continue;
+            }
+        };
+        for info in infos {
+            let info = match info {
+                Ok(info) => info,
+                Err((name, e)) => {
+                    log::error!("{}: {}: {:#}", file.display(), name, e);
+                    categories.push(ErrorCategory::of(&e));
+                    rows.push((name, Err(format!("{:#}", e))));
+                    continue;
+                }
+            };
+            let written = if names.insert(info.name.clone()) {
+                let dir = out_dir.join(&info.name);
+                emit_artifacts(&info, &dir, opts)
+                    .map_err(Into::into)
+                    .and_then(|mut paths| {
+                        if no_golden.contains(&info.name) {
+                            // Whatever they hold wasn't checked against a run.
+                            for kind in [ArtifactKind::Expected, ArtifactKind::ExpectedSpec] {
+                                let base = opts.format.file_name(kind);
+                                let expected =
+                                    base.map(|b| dir.join(prefixed_name(&info, opts, b)));
+                                if let Some(path) = expected.filter(|p| paths.contains(p)) {
+                                    fs::remove_file(&path)
+                                        .with_context(|| format!("removing {}", path.display()))?;
+                                    paths.retain(|p| *p != path);
+                                }
+                            }
+                        }
+                        if source_map {
+                            paths.extend(write_source_map(file, &info, &dir, opts)?);
+                        }
+                        if listing {
+                            paths.push(write_listing(file, &info, &dir, opts)?);
+                        }
+                        Ok(paths)
+                    })
+            } else {
+                Err(anyhow::anyhow!(
+                    "another input already compiled a test named {}",
+                    info.name
+                ))
+            };
+            match written {
+                Ok(paths) => {
+                    log::info!("{}: wrote {} files", info.name, paths.len());
+                    rows.push((info.name.clone(), Ok(info)));
+                }
+                Err(e) => {
+                    log::error!("{}: {:#}", file.display(), e);
+                    categories.push(ErrorCategory::of(&e));
+                    rows.push((info.name, Err(format!("{:#}", e))));
+                }
+            }
+        }
+    }
+
+    let failed = rows.iter().filter(|(_, info)| info.is_err()).count();
+    let bytes: usize = rows
+        .iter()
+        .filter_map(|(_, info)| info.as_ref().ok())
+        .map(|info| info.body_bytes.len())
+        .sum();
+    if summary_json {
+        let tests: Vec<_> = rows
+            .iter()
+            .map(|(name, info)| match info {
+                Ok(info) if no_golden.contains(name) => {
+                    let mut record = test_json(info);
+                    record["expected"] = serde_json::Value::Null;
+                    record["expect_trap"] = serde_json::Value::Null;
+                    record
+                }
+                Ok(info) => test_json(info),
+                Err(e) => serde_json::json!({ "name": name, "error": e }),
+            })
+            .collect();
+        println!(
+            "{}",
+            serde_json::json!({
+                "out_dir": out_dir.display().to_string(),
+                "tests": tests,
+                "compiled": rows.len() - failed,
+                "failed": failed,
+                "program_bytes": bytes,
+                "elapsed_ms": millis(started.elapsed()),
+            })
+        );
+    } else {
+        println!();
+        println!(
+            "{:<24} {:>6} {:>8} {:>10}",
+            "test", "bytes", "branches", "expected"
+        );
+        for (name, info) in &rows {
+            match info {
+                Ok(info) => println!(
+                    "{:<24} {:>6} {:>8} {:>10}",
+                    name,
+                    info.body_bytes.len(),
+                    info.branch_table.len(),
+                    if no_golden.contains(name) {
+                        "-".to_string()
+                    } else {
+                        expected_label(info)
+                    }
+                ),
+                Err(_) => println!("{:<24} FAILED", name),
+            }
+        }
+        println!(
+            "{} compiled ({} program bytes) into {}, {} failed",
+            rows.len() - failed,
+            bytes,
+            out_dir.display(),
+            failed
+        );
+    }
+    if failed > 0 {
+        let message = format!("{} of {} tests failed to compile", failed, rows.len());
+        // Exit as the failures did when they all failed the same way.
+        return Err(match categories[0] {
+            Some(category) if categories.iter().all(|c| *c == Some(category)) => {
+                category.error(message)
             }
+            _ => anyhow::anyhow!(message),
+        });
+    }
+    Ok(())
+}
 
-            let svh = generate_svh(&tests);
+/// The golden result as printed in progress lines.
+fn expected_label(info: &CompiledProgram) -> String {
+    if info.expect_trap {
+        "trap".to_string()
+    } else {
+        info.expected.to_string()
+    }
+}
+
+/// `to` relative to the directory `from`, so generated sources can refer
+/// to it wherever the tree is checked out.
+fn relative_path(from: &Path, to: &Path) -> Result<PathBuf> {
+    let resolve = |p: &Path| {
+        let p = if p.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            p
+        };
+        p.canonicalize()
+            .with_context(|| format!("resolving {}", p.display()))
+    };
+    let from = resolve(from)?;
+    let to = resolve(to)?;
+    let common = from
+        .components()
+        .zip(to.components())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let mut rel = PathBuf::new();
+    for _ in from.components().skip(common) {
+        rel.push("..");
+    }
+    rel.extend(to.components().skip(common));
+    Ok(rel)
+}
+
+fn main() -> std::process::ExitCode {
+    exit_status(parse_with_project_config().and_then(|cli: Cli| {
+        init_logging(log_level(cli.verbose, cli.quiet));
+        set_module_cache(cli.module_cache.clone());
+        match watched_paths(&cli.command)? {
+            Some(paths) => watch(&paths, || run(&cli)),
+            None => run(&cli),
+        }
+    }))
+}
+
+/// What a `--watch` run of `command` reruns on, or `None` without
+/// `--watch`.
+fn watched_paths(command: &Command) -> Result<Option<Vec<PathBuf>>> {
+    Ok(match command {
+        Command::Compile {
+            inputs,
+            watch: true,
+            ..
+        } => {
+            if inputs.iter().any(|i| i.as_os_str() == "-") {
+                bail!("--watch can't watch stdin");
+            }
+            Some(inputs.clone())
+        }
+        Command::GenTests {
+            wat_dir,
+            svh_config,
+            port_map,
+            watch: true,
+            ..
+        } => Some(
+            std::iter::once(wat_dir)
+                .chain(svh_config)
+                .chain(port_map)
+                .cloned()
+                .collect(),
+        ),
+        _ => None,
+    })
+}
+
+/// How often `--watch` looks for changed inputs.
+const WATCH_INTERVAL: std::time::Duration = std::time::Duration::from_millis(300);
+
+/// Run `run` now and again each time a file in `paths` changes, appears,
+/// or goes away, until interrupted. Directories are watched for corpus
+/// files, subdirectories included, so artifacts written into them don't
+/// retrigger the run. Errors
+/// are reported and the watch goes on.
+fn watch(paths: &[PathBuf], mut run: impl FnMut() -> Result<()>) -> Result<()> {
+    let snapshot = || -> Vec<(PathBuf, Option<std::time::SystemTime>)> {
+        let mut files = Vec::new();
+        for path in paths {
+            if path.is_dir() {
+                files.extend(
+                    corpus_files_recursive(path, &CorpusFilter::default()).unwrap_or_default(),
+                );
+            } else {
+                files.push(path.clone());
+            }
+        }
+        files
+            .into_iter()
+            .map(|f| {
+                let modified = fs::metadata(&f).and_then(|m| m.modified()).ok();
+                (f, modified)
+            })
+            .collect()
+    };
+    loop {
+        let before = snapshot();
+        if let Err(e) = run() {
+            log::error!("{:#}", e);
+        }
+        log::info!("Watching {} files for changes", before.len());
+        while snapshot() == before {
+            std::thread::sleep(WATCH_INTERVAL);
+        }
+    }
+}
+
+fn run(cli: &Cli) -> Result<()> {
+    match &cli.command {
+        Command::Compile {
+            inputs,
+            out_dir,
+            emit_args,
+            emit,
+            summary_json,
+            trust_embedded,
+            verify_embedded,
+            expand_unsupported,
+            normalize_leb,
+            wasm_features,
+            source_map,
+            listing,
+            all_functions,
+            ..
+        } => {
+            let opts = emit_args.options()?;
+            let mut options = embedded_options(*trust_embedded, *verify_embedded)
+                .hardware(opts.hardware.clone())
+                .expand_unsupported(*expand_unsupported)
+                .normalize_leb(*normalize_leb);
+            if let Some(spec) = wasm_features {
+                options = options.features(parse_wasm_features(spec).context("--wasm-features")?);
+            }
+            if let Some(kind) = emit {
+                let [input, target] = inputs.as_slice() else {
+                    bail!("--emit takes one input followed by TARGET");
+                };
+                let info = compile_input_with(input, &options)?;
+                opts.check_fits(&info)?;
+                let bytes = render_artifact(&info, (*kind).into(), &opts)?;
+                if target.as_os_str() == "-" {
+                    std::io::stdout()
+                        .write_all(&bytes)
+                        .context("writing to stdout")?;
+                } else {
+                    fs::write(target, &bytes)
+                        .with_context(|| format!("writing {}", target.display()))?;
+                }
+                return Ok(());
+            }
+            if inputs.len() > 1 || inputs[0].is_dir() || *all_functions {
+                return compile_batch(
+                    inputs,
+                    out_dir,
+                    &options,
+                    &opts,
+                    *summary_json,
+                    *source_map,
+                    *listing,
+                    *all_functions,
+                );
+            }
+
+            let started = Instant::now();
+            let info = compile_input_with(&inputs[0], &options)?;
+            let mut paths = emit_artifacts(&info, out_dir, &opts)?;
+            if *source_map {
+                paths.extend(write_source_map(&inputs[0], &info, out_dir, &opts)?);
+            }
+            if *listing {
+                paths.push(write_listing(&inputs[0], &info, out_dir, &opts)?);
+            }
+
+            if *summary_json {
+                let mut record = test_json(&info);
+                record["files"] = paths
+                    .iter()
+                    .map(|p| p.display().to_string())
+                    .collect::<Vec<_>>()
+                    .into();
+                record["elapsed_ms"] = millis(started.elapsed()).into();
+                println!("{}", record);
+                return Ok(());
+            }
+            println!(
+                "{}: {} bytes, {} branch entries, expected={}",
+                info.name,
+                info.body_bytes.len(),
+                info.branch_table.len(),
+                expected_label(&info)
+            );
+            for path in &paths {
+                log::info!("  wrote {}", path.display());
+            }
+        }
+        Command::Verify {
+            inputs,
+            out_dir,
+            emit_args,
+            color,
+        } => {
+            let opts = emit_args.options()?;
+            let color = color.enabled();
+            let batch = inputs.len() > 1 || inputs[0].is_dir();
+            let mut files = Vec::new();
+            for input in inputs {
+                if input.is_dir() {
+                    files.extend(corpus_files(input)?);
+                } else {
+                    files.push(input.clone());
+                }
+            }
+            let mut checked = 0;
+            let mut stale = 0;
+            for file in &files {
+                let infos = if batch {
+                    compile_corpus_file(file)?
+                } else {
+                    vec![compile_input(file)?]
+                };
+                for info in infos {
+                    let dir = if batch {
+                        out_dir.join(&info.name)
+                    } else {
+                        out_dir.clone()
+                    };
+                    let problems = verify_artifacts(&info, &dir, &opts)?;
+                    checked += 1;
+                    if !problems.is_empty() {
+                        stale += 1;
+                    }
+                    for problem in problems {
+                        println!("{}: {}", dir.display(), problem);
+                        print_diff_detail(&problem.detail, color);
+                    }
+                }
+            }
+            if stale > 0 {
+                return Err(ErrorCategory::Mismatch.error(format!(
+                    "{} of {} tests have stale artifacts",
+                    stale, checked
+                )));
+            }
+            println!("{} tests' artifacts match their sources", checked);
+        }
+        Command::Check {
+            inputs,
+            allow,
+            hardware,
+        } => {
+            let mut spec = hardware_spec(hardware.as_deref())?;
+            spec.opcodes.extend(allow.iter().cloned());
+            let mut files = Vec::new();
+            for input in inputs {
+                if input.is_dir() {
+                    // Scripts hold several modules; check those one by one.
+                    files.extend(
+                        corpus_files(input)?
+                            .into_iter()
+                            .filter(|f| f.extension().is_none_or(|ext| ext != "wast")),
+                    );
+                } else {
+                    files.push(input.clone());
+                }
+            }
+            let mut failing = 0;
+            for file in &files {
+                let wasm = read_wasm(file)?;
+                let issues = compatibility_issues(&wasm, &spec)
+                    .with_context(|| format!("checking {}", file.display()))?;
+                // Only instructions the core lacks; `--allow` may cover some.
+                let value_types: Vec<_> = value_type_uses(&wasm)
+                    .with_context(|| format!("checking {}", file.display()))?
+                    .into_iter()
+                    .filter(|used| used.pc.is_none() || issues.iter().any(|i| i.pc == used.pc))
+                    .collect();
+                if !issues.is_empty() {
+                    failing += 1;
+                }
+                // Point at the WAT line when there is source to map.
+                let source = fs::read_to_string(file).ok();
+                let map = source.as_deref().and_then(SourceMap::from_wat);
+                for issue in &issues {
+                    let located = issue.pc.zip(source.as_deref()).and_then(|(pc, source)| {
+                        Some((pc, source, map.as_ref()?.source_offset(pc)?))
+                    });
+                    match (issue.pc, located) {
+                        (_, Some((pc, source, offset))) => {
+                            let message = format!("pc {:#06X}: {}", pc, issue.issue);
+                            let label = file.display().to_string();
+                            println!("{}", source_snippet(&label, source, offset, &message));
+                        }
+                        (Some(pc), None) => {
+                            println!("{}: pc {:#06X}: {}", file.display(), pc, issue.issue);
+                            println!("    {}", issue.context);
+                        }
+                        (None, None) => println!("{}: {}", file.display(), issue.issue),
+                    }
+                }
+                if !issues.is_empty() && !value_types.is_empty() {
+                    println!("{}: {}", file.display(), value_type_summary(&value_types));
+                }
+            }
+            if failing > 0 {
+                return Err(ErrorCategory::Unsupported.error(format!(
+                    "{} of {} files use what the core can't run",
+                    failing,
+                    files.len()
+                )));
+            }
+            println!("{} files are compatible", files.len());
+        }
+        Command::Coverage {
+            inputs,
+            allow,
+            hardware,
+            what_if,
+            gain,
+        } => {
+            let mut spec = hardware_spec(hardware.as_deref())?;
+            spec.opcodes.extend(allow.iter().cloned());
+            let mut tests = Vec::new();
+            for input in inputs {
+                let files = if input.is_dir() {
+                    corpus_files(input)?
+                        .into_iter()
+                        .filter(|f| f.extension().is_none_or(|ext| ext != "wast"))
+                        .collect()
+                } else {
+                    vec![input.clone()]
+                };
+                for file in files {
+                    tests.push((file.display().to_string(), read_wasm(&file)?));
+                }
+            }
+            let coverage = spec.opcode_coverage(&tests)?;
+            print!("{}", coverage);
+            if *what_if {
+                print!("{}", what_if_text(&coverage));
+            }
+            if !gain.is_empty() {
+                let unlocked = coverage.unlocked_by(gain);
+                println!(
+                    "gaining {} would unlock {} tests",
+                    gain.join(" and "),
+                    unlocked.len()
+                );
+                for test in unlocked {
+                    println!("  {}", test);
+                }
+            }
+        }
+        Command::Diff {
+            old,
+            new,
+            limit,
+            color,
+        } => {
+            let diffs = diff_artifact_dirs(old, new, *limit)?;
+            let color = color.enabled();
+            for diff in &diffs {
+                println!("{}", diff.file.display());
+                for change in &diff.changes {
+                    println!("  {}", change);
+                }
+                print_diff_detail(&diff.detail, color);
+            }
+            if !diffs.is_empty() {
+                return Err(ErrorCategory::Mismatch.error(format!("{} files differ", diffs.len())));
+            }
+            println!("No differences");
+        }
+        Command::Disasm {
+            input,
+            output,
+            source,
+        } => {
+            let info = compile_input(input)?;
+            let listing = if *source {
+                let map = pc_source_map(input, &info)
+                    .with_context(|| format!("mapping {} to its WAT", input.display()))?;
+                listing_file(&info.name, &info.body_bytes, &info.branch_table, Some(&map))?
+            } else {
+                disassembly_listing(&info.body_bytes, &info.branch_table)?
+            };
+            match output {
+                Some(path) => fs::write(path, listing)
+                    .with_context(|| format!("writing {}", path.display()))?,
+                None => print!("{}", listing),
+            }
+        }
+        Command::Stats { inputs } => {
+            let mut files = Vec::new();
+            for input in inputs {
+                if input.is_dir() {
+                    files.extend(corpus_files(input)?);
+                } else {
+                    files.push(input.clone());
+                }
+            }
+            for file in &files {
+                // Scripts only yield bodies through their checks; other
+                // inputs are measured straight from the module, so
+                // programs the golden run rejects still get stats.
+                if file.extension().is_some_and(|ext| ext == "wast") {
+                    for info in compile_corpus_file(file)? {
+                        let stats = program_stats(&info.body_bytes)?;
+                        print!("{}", program_stats_text(&info.name, &stats));
+                    }
+                } else {
+                    let wasm = read_wasm(file)?;
+                    let body = extract_function_body_ref(&wasm)
+                        .with_context(|| format!("extracting the body of {}", file.display()))?;
+                    let stats = program_stats(body)?;
+                    print!(
+                        "{}",
+                        program_stats_text(&file.display().to_string(), &stats)
+                    );
+                }
+            }
+        }
+        Command::Size { inputs, top } => {
+            let mut files = Vec::new();
+            for input in inputs {
+                if input.is_dir() {
+                    files.extend(corpus_files(input)?);
+                } else {
+                    files.push(input.clone());
+                }
+            }
+            for file in &files {
+                if file.extension().is_some_and(|ext| ext == "wast") {
+                    for info in compile_corpus_file(file)? {
+                        let size = size_breakdown(&info.body_bytes, None);
+                        print!("{}", size_breakdown_text(&info.name, &size, *top));
+                    }
+                    continue;
+                }
+                let wasm = read_wasm(file)?;
+                let body = extract_function_body_ref(&wasm)
+                    .with_context(|| format!("extracting the body of {}", file.display()))?;
+                // Lines only for WAT read from a file; stdin is gone by now.
+                let source = (file.as_os_str() != "-")
+                    .then(|| fs::read_to_string(file).ok())
+                    .flatten()
+                    .filter(|s| !s.as_bytes().starts_with(WASM_MAGIC));
+                let size = size_breakdown(body, source.as_deref());
+                print!(
+                    "{}",
+                    size_breakdown_text(&file.display().to_string(), &size, *top)
+                );
+            }
+        }
+        Command::Usage { inputs, hardware } => {
+            let spec = hardware_spec(hardware.as_deref())?;
+            let mut reports = Vec::new();
+            for input in inputs {
+                let files = if input.is_dir() {
+                    corpus_files(input)?
+                } else {
+                    vec![input.clone()]
+                };
+                for file in files {
+                    let infos = if file.extension().is_some_and(|ext| ext == "wast") {
+                        compile_corpus_file(&file)?
+                    } else {
+                        vec![compile_input(&file)?]
+                    };
+                    for info in infos {
+                        reports.push(spec.resource_usage(&info)?);
+                    }
+                }
+            }
+            print!("{}", resource_usage_text(&reports));
+        }
+        Command::Pack { input, output } => {
+            let info = compile_wat_file(input)?;
             if let Some(parent) = output.parent() {
                 fs::create_dir_all(parent)?;
             }
-            fs::write(output, &svh).with_context(|| format!("writing {}", output.display()))?;
-
+            write_boot_image(output, &info)?;
+            println!(
+                "{}: packed {} bytes, {} branch entries, {} data segment(s) into {}",
+                info.name,
+                info.body_bytes.len(),
+                info.branch_table.len(),
+                info.mem_init.len(),
+                output.display()
+            );
+        }
+        Command::Link {
+            input,
+            output,
+            hardware,
+        } => {
+            let spec = hardware_spec(hardware.as_deref())?;
+            let info = compile_wat_file(input)?;
+            let linked = link_module(&read_wasm(input)?, &info, &spec)?;
+            if let Some(parent) = output.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(output, &linked).with_context(|| format!("writing {}", output.display()))?;
+            println!(
+                "{}: linked {} branch entries into {} ({} bytes)",
+                info.name,
+                info.branch_table.len(),
+                output.display(),
+                linked.len()
+            );
+        }
+        Command::GenRom {
+            input,
+            output,
+            lang,
+            table,
+            module,
+            style,
+            lookup,
+            data_width,
+            endian,
+            depth,
+        } => {
+            let info = compile_wat_file(input)?;
+            let module = module.clone().unwrap_or_else(|| {
+                match (lang, table) {
+                    (HdlArg::Sv, RomTableArg::Prog) => "prog_rom",
+                    (HdlArg::Sv, RomTableArg::Branch) => "branch_rom",
+                    (HdlArg::Veryl, RomTableArg::Prog) => "ProgRom",
+                    (HdlArg::Veryl, RomTableArg::Branch) => "BranchRom",
+                }
+                .to_string()
+            });
+            let opts = RomOptions {
+                module_name: module.clone(),
+                style: (*style).into(),
+                data_width: *data_width,
+                endian: (*endian).into(),
+                depth: *depth,
+            };
+            let source = match (lang, table) {
+                (HdlArg::Sv, RomTableArg::Prog) => generate_sv_rom(&info.body_bytes, &opts)?,
+                (HdlArg::Veryl, RomTableArg::Prog) => {
+                    generate_veryl_prog_rom(&info.body_bytes, &opts)?
+                }
+                (HdlArg::Veryl, RomTableArg::Branch) => {
+                    generate_veryl_branch_rom(&info.branch_table, &module, (*lookup).into())
+                }
+                (HdlArg::Sv, RomTableArg::Branch) => {
+                    generate_sv_branch_rom(&info.branch_table, &module, (*lookup).into())
+                }
+            };
+            if let Some(parent) = output.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(output, &source).with_context(|| format!("writing {}", output.display()))?;
+            println!(
+                "{}: {} bytes, {} branch entries into {} ({})",
+                info.name,
+                info.body_bytes.len(),
+                info.branch_table.len(),
+                module,
+                output.display()
+            );
+        }
+        Command::GenCocotb {
+            wat_dir,
+            filter,
+            output,
+            hex_dir,
+        } => {
+            let tests = compile_corpus(wat_dir, filter, None, 0, None, None, None)?;
+            let out_dir = output.parent().map(PathBuf::from).unwrap_or_default();
+            let hex_dir = hex_dir.clone().unwrap_or_else(|| out_dir.clone());
+            let emit = EmitOptions::default();
+            for info in &tests {
+                emit_artifacts(info, &hex_dir, &emit)?;
+            }
+            // The module finds its hex files relative to itself.
+            let rel = match hex_dir.strip_prefix(&out_dir) {
+                Ok(rel) => rel.to_path_buf(),
+                Err(_) => hex_dir
+                    .canonicalize()
+                    .with_context(|| format!("resolving {}", hex_dir.display()))?,
+            };
+            let rel = rel.to_string_lossy().replace('\\', "/");
+            let source = generate_cocotb(&tests, if rel.is_empty() { "." } else { &rel });
+            if !out_dir.as_os_str().is_empty() {
+                fs::create_dir_all(&out_dir)?;
+            }
+            fs::write(output, source).with_context(|| format!("writing {}", output.display()))?;
             println!(
                 "Generated {} with {} WAT test(s)",
                 output.display(),
                 tests.len()
             );
         }
+        Command::GenVerilator {
+            wat_dir,
+            filter,
+            out_dir,
+            top,
+        } => {
+            let tests = compile_corpus(wat_dir, filter, None, 0, None, None, None)?;
+            fs::create_dir_all(out_dir)
+                .with_context(|| format!("creating {}", out_dir.display()))?;
+            for (name, text) in [
+                ("sim_main.cpp", generate_verilator_main(&tests, top)?),
+                (VERILATOR_SUPPORT_HEADER, VERILATOR_SUPPORT.to_string()),
+            ] {
+                let path = out_dir.join(name);
+                fs::write(&path, text).with_context(|| format!("writing {}", path.display()))?;
+                log::info!("  wrote {}", path.display());
+            }
+            println!(
+                "Generated Verilator harness with {} WAT test(s)",
+                tests.len()
+            );
+        }
+        Command::GenVerylTb {
+            output,
+            module,
+            core,
+            include,
+            svh_config,
+            port_map,
+            hardware,
+            force,
+        } => {
+            // The skeleton is meant to be edited; don't lose the edits.
+            if output.exists() && !*force {
+                bail!("{} exists; pass --force to overwrite it", output.display());
+            }
+            let mut opts = SvhOptions {
+                hardware: hardware_spec(hardware.as_deref())?,
+                ..Default::default()
+            };
+            if let Some(path) = port_map {
+                opts.set_ports(PortMap::load(path)?);
+            }
+            if let Some(path) = svh_config {
+                let text = fs::read_to_string(path)
+                    .with_context(|| format!("reading {}", path.display()))?;
+                let config: serde_json::Value = serde_json::from_str(&text)
+                    .with_context(|| format!("parsing {}", path.display()))?;
+                opts.apply_json(&config)
+                    .with_context(|| format!("applying {}", path.display()))?;
+            }
+            if let Some(parent) = output.parent().filter(|p| !p.as_os_str().is_empty()) {
+                fs::create_dir_all(parent)
+                    .with_context(|| format!("creating {}", parent.display()))?;
+            }
+            fs::write(
+                output,
+                generate_veryl_testbench(module, core, include, &opts),
+            )
+            .with_context(|| format!("writing {}", output.display()))?;
+            log::info!("  wrote {}", output.display());
+        }
+        Command::GenDpi { output } => {
+            if let Some(parent) = output.parent().filter(|p| !p.as_os_str().is_empty()) {
+                fs::create_dir_all(parent)
+                    .with_context(|| format!("creating {}", parent.display()))?;
+            }
+            fs::write(output, DPI_IMPORTS)
+                .with_context(|| format!("writing {}", output.display()))?;
+            log::info!("  wrote {}", output.display());
+        }
+        Command::GenMarlin {
+            wat_dir,
+            filter,
+            output,
+        } => {
+            let filter = filter.filter()?;
+            let out_dir = output.parent().map(PathBuf::from).unwrap_or_default();
+            if !out_dir.as_os_str().is_empty() {
+                fs::create_dir_all(&out_dir)?;
+            }
+            let mut cases = Vec::new();
+            for path in wat_corpus_files(wat_dir)? {
+                let name = path.file_stem().unwrap_or_default().to_string_lossy();
+                if !filter.accepts(&name) {
+                    continue;
+                }
+                let rel = relative_path(&out_dir, &path)?;
+                cases.push((name.to_string(), rel.to_string_lossy().replace('\\', "/")));
+            }
+            fs::write(output, generate_marlin_tests(&cases))
+                .with_context(|| format!("writing {}", output.display()))?;
+            println!(
+                "Generated {} with {} WAT test(s)",
+                output.display(),
+                cases.len()
+            );
+        }
+        Command::GenTests {
+            wat_dir,
+            filter,
+            output,
+            readmemh,
+            hex_dir,
+            split,
+            task_prefix,
+            shuffle,
+            svh_config,
+            port_map,
+            defines,
+            package,
+            depths,
+            sva,
+            bind_target,
+            scoreboard,
+            scoreboard_target,
+            report,
+            flavor,
+            uvm_base,
+            cache_dir,
+            jobs,
+            hardware,
+            summary_json,
+            no_manifest,
+            ..
+        } => {
+            let started = Instant::now();
+            if *flavor == FlavorArg::Uvm && (*split || *readmemh || *shuffle) {
+                bail!("--split, --readmemh, and --shuffle only apply to the tasks flavor");
+            }
+            let hardware = hardware_spec(hardware.as_deref())?;
+            let mut records = Vec::new();
+            let mut skipped = Vec::new();
+            let tests = compile_corpus(
+                wat_dir,
+                filter,
+                cache_dir.as_deref(),
+                *jobs,
+                Some(&hardware),
+                Some(&mut records),
+                Some(&mut skipped),
+            )?;
+
+            let out_dir = output.parent().map(PathBuf::from).unwrap_or_default();
+            let mut opts = SvhOptions {
+                task_prefix: task_prefix.clone(),
+                shuffle: *shuffle,
+                hardware,
+                ..Default::default()
+            };
+            if let Some(path) = port_map {
+                opts.set_ports(PortMap::load(path)?);
+            }
+            if let Some(path) = svh_config {
+                let text = fs::read_to_string(path)
+                    .with_context(|| format!("reading {}", path.display()))?;
+                let config: serde_json::Value = serde_json::from_str(&text)
+                    .with_context(|| format!("parsing {}", path.display()))?;
+                opts.apply_json(&config)
+                    .with_context(|| format!("applying {}", path.display()))?;
+            }
+            for record in &records {
+                if let (Some(category), false) =
+                    (record["category"].as_str(), record["skipped"] == true)
+                {
+                    let name = record["name"].as_str().unwrap_or_default().to_string();
+                    opts.categories
+                        .entry(category.to_string())
+                        .or_default()
+                        .push(name);
+                }
+            }
+            // Each generated test's files, for the manifest.
+            let mut artifacts = vec![Vec::new(); tests.len()];
+            if *readmemh {
+                let hex_dir = hex_dir.clone().unwrap_or_else(|| out_dir.clone());
+                let emit = EmitOptions {
+                    hardware: opts.hardware.clone(),
+                    ..Default::default()
+                };
+                for (info, files) in tests.iter().zip(&mut artifacts) {
+                    files.extend(emit_artifacts(info, &hex_dir, &emit)?);
+                }
+                opts.load = SvhLoad::Readmemh {
+                    hex_dir: hex_dir.to_string_lossy().replace('\\', "/"),
+                };
+            }
+
+            if let Some(parent) = output.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            if *flavor == FlavorArg::Uvm || *split {
+                let svh = if *split {
+                    for (info, files) in tests.iter().zip(&mut artifacts) {
+                        let path = out_dir.join(test_svh_file_name(info));
+                        fs::write(&path, generate_test_svh(info, &opts))
+                            .with_context(|| format!("writing {}", path.display()))?;
+                        files.push(path);
+                    }
+                    generate_svh_includes(&tests, &opts)
+                } else {
+                    generate_uvm_svh(&tests, &opts, uvm_base)
+                };
+                fs::write(output, &svh).with_context(|| format!("writing {}", output.display()))?;
+            } else {
+                let file = fs::File::create(output)
+                    .with_context(|| format!("creating {}", output.display()))?;
+                write_svh_io(std::io::BufWriter::new(file), &tests, &opts)
+                    .with_context(|| format!("writing {}", output.display()))?;
+            }
+            if let Some(path) = defines {
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                fs::write(path, generate_defines_svh(&tests, &opts))
+                    .with_context(|| format!("writing {}", path.display()))?;
+            }
+            if let Some(path) = sva {
+                let source = generate_sva_with(&tests, bind_target, &opts.ports)?;
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                fs::write(path, source).with_context(|| format!("writing {}", path.display()))?;
+            }
+            if let Some(path) = scoreboard {
+                let source = generate_branch_scoreboard(&tests, scoreboard_target)?;
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                fs::write(path, source).with_context(|| format!("writing {}", path.display()))?;
+            }
+            if let Some(path) = report {
+                let source = corpus_markdown(&tests, &skipped, &opts.hardware)?;
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                fs::write(path, source).with_context(|| format!("writing {}", path.display()))?;
+            }
+            if let Some(path) = package {
+                let name = path.file_stem().unwrap_or_default().to_string_lossy();
+                let source = generate_sv_package(&tests, &name)?;
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                fs::write(path, source).with_context(|| format!("writing {}", path.display()))?;
+            }
+            if let Some(path) = depths {
+                let source = if path.extension().is_some_and(|e| e == "veryl") {
+                    let name = path.file_stem().unwrap_or_default().to_string_lossy();
+                    generate_veryl_depths_package(&tests, &name)?
+                } else {
+                    generate_depths_svh(&tests, &opts)?
+                };
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                fs::write(path, source).with_context(|| format!("writing {}", path.display()))?;
+            }
+
+            // Records cover skipped tests too; the generated ones are the
+            // rest, in the same order.
+            let mut generated = tests.iter().zip(artifacts);
+            for record in &mut records {
+                if record["skipped"] == true {
+                    continue;
+                }
+                let (info, files) = generated.next().expect("one record per test");
+                let mut files: Vec<String> =
+                    files.iter().map(|f| f.display().to_string()).collect();
+                files.push(output.display().to_string());
+                record["artifacts"] = files.into();
+                record["mem_init_bytes"] = info
+                    .mem_init
+                    .iter()
+                    .map(|s| s.data.len())
+                    .sum::<usize>()
+                    .into();
+            }
+            if !*no_manifest {
+                let path = out_dir.join("manifest.json");
+                let manifest = serde_json::json!({
+                    "generator": format!("wasm-compile {}", env!("CARGO_PKG_VERSION")),
+                    "output": output.display().to_string(),
+                    "tests": records,
+                });
+                fs::write(&path, serde_json::to_string_pretty(&manifest)? + "\n")
+                    .with_context(|| format!("writing {}", path.display()))?;
+                let path = out_dir.join(CORPUS_MANIFEST);
+                fs::write(&path, corpus_manifest(&records).to_toml())
+                    .with_context(|| format!("writing {}", path.display()))?;
+            }
+
+            if *summary_json {
+                println!(
+                    "{}",
+                    serde_json::json!({
+                        "output": output.display().to_string(),
+                        "tests": records,
+                        "generated": tests.len(),
+                        "skipped": records.len() - tests.len(),
+                        "elapsed_ms": millis(started.elapsed()),
+                    })
+                );
+            } else {
+                println!(
+                    "Generated {} with {} WAT test(s)",
+                    output.display(),
+                    tests.len()
+                );
+            }
+        }
+        Command::Bench {
+            wat_dir,
+            filter,
+            results,
+            output,
+            max_cycles,
+        } => {
+            let tests = compile_corpus(wat_dir, filter, None, 0, None, None, None)?;
+            let measured = match results {
+                Some(path) => {
+                    let text = fs::read_to_string(path)
+                        .with_context(|| format!("reading {}", path.display()))?;
+                    parse_results(&text).with_context(|| format!("parsing {}", path.display()))?
+                }
+                None => Vec::new(),
+            };
+            let csv = bench_csv(&tests, &measured, *max_cycles)?;
+            match output {
+                Some(path) => {
+                    if let Some(parent) = path.parent() {
+                        fs::create_dir_all(parent)?;
+                    }
+                    fs::write(path, csv).with_context(|| format!("writing {}", path.display()))?;
+                }
+                None => print!("{}", csv),
+            }
+        }
+        Command::ParseResults {
+            input,
+            junit,
+            json,
+            suite,
+        } => {
+            if junit.is_none() && json.is_none() {
+                bail!("nothing to write; pass --junit and/or --json");
+            }
+            let text = fs::read_to_string(input)
+                .with_context(|| format!("reading {}", input.display()))?;
+            let results =
+                parse_results(&text).with_context(|| format!("parsing {}", input.display()))?;
+            for (path, text) in [
+                (junit, results_junit(&results, suite)),
+                (json, results_json(&results)),
+            ] {
+                if let Some(path) = path {
+                    if let Some(parent) = path.parent() {
+                        fs::create_dir_all(parent)?;
+                    }
+                    fs::write(path, text).with_context(|| format!("writing {}", path.display()))?;
+                }
+            }
+            let failed = results.iter().filter(|r| !r.passed).count();
+            println!(
+                "{}: {} passed, {} failed",
+                input.display(),
+                results.len() - failed,
+                failed
+            );
+        }
+        Command::AnalyzeVcd {
+            vcd,
+            wat,
+            clock,
+            pc,
+            stack_top,
+            running,
+            output,
+        } => {
+            let info = compile_wat_file(wat)?;
+            let text =
+                fs::read_to_string(vcd).with_context(|| format!("reading {}", vcd.display()))?;
+            let dump = parse_vcd(&text).with_context(|| format!("parsing {}", vcd.display()))?;
+            let names = VcdSignalNames {
+                clock: clock.clone(),
+                pc: pc.clone(),
+                stack_top: stack_top.clone(),
+                running: (!running.is_empty()).then(|| running.clone()),
+            };
+            let steps = reconstruct_execution(&dump, &info.body_bytes, &names)?;
+            let listing = format_execution(&steps);
+            match output {
+                Some(path) => {
+                    fs::write(path, listing)
+                        .with_context(|| format!("writing {}", path.display()))?;
+                    println!(
+                        "{}: {} steps -> {}",
+                        vcd.display(),
+                        steps.len(),
+                        path.display()
+                    );
+                }
+                None => print!("{}", listing),
+            }
+        }
+        #[cfg(feature = "sim")]
+        Command::DiffTrace {
+            actual,
+            golden,
+            context,
+            source_map,
+        } => {
+            use wasm_ic::sim::TraceRecorder;
+            let read = |path: &PathBuf| -> Result<TraceRecorder> {
+                let text = fs::read_to_string(path)
+                    .with_context(|| format!("reading {}", path.display()))?;
+                TraceRecorder::parse_jsonl(&text)
+                    .with_context(|| format!("parsing {}", path.display()))
+            };
+            let (actual_trace, golden_trace) = (read(actual)?, read(golden)?);
+            match actual_trace.divergence(&golden_trace, *context) {
+                Some(divergence) => {
+                    let mut message = divergence.to_string();
+                    if let (Some(path), Some(pc)) = (source_map, divergence.pc) {
+                        let text = fs::read_to_string(path)
+                            .with_context(|| format!("reading {}", path.display()))?;
+                        let map = PcSourceMap::from_json(&text)
+                            .with_context(|| format!("parsing {}", path.display()))?;
+                        if let Some(at) = usize::try_from(pc).ok().and_then(|pc| map.describe(pc)) {
+                            message.push_str(&format!("\nat {}", at));
+                        }
+                    }
+                    return Err(ErrorCategory::Mismatch.error(message));
+                }
+                None => println!(
+                    "{}: matches {} ({} samples)",
+                    actual.display(),
+                    golden.display(),
+                    actual_trace.samples().len()
+                ),
+            }
+        }
+        Command::Serve { port, host, jobs } => {
+            let listener = std::net::TcpListener::bind((host.as_str(), *port))
+                .with_context(|| format!("binding {}:{}", host, port))?;
+            println!("Serving compile requests on {}", listener.local_addr()?);
+            let jobs = match jobs {
+                0 => std::thread::available_parallelism().map_or(1, |n| n.get()),
+                n => *n,
+            };
+            let (send, receive) = std::sync::mpsc::sync_channel::<std::net::TcpStream>(0);
+            let receive = Mutex::new(receive);
+            std::thread::scope(|scope| {
+                for _ in 0..jobs {
+                    scope.spawn(|| loop {
+                        let Ok(stream) = receive.lock().unwrap().recv() else {
+                            return;
+                        };
+                        if let Err(e) = serve_client(stream) {
+                            log::error!("client: {:#}", e);
+                        }
+                    });
+                }
+                for stream in listener.incoming() {
+                    match stream {
+                        Ok(stream) => send.send(stream).expect("the workers outlive the listener"),
+                        Err(e) => log::error!("accepting a connection: {}", e),
+                    }
+                }
+            });
+        }
+        Command::GenCorpus {
+            out_dir,
+            template,
+            sizes,
+            seed,
+            hardware,
+        } => {
+            let spec = hardware_spec(hardware.as_deref())?;
+            let templates: Vec<StressTemplate> = if template.is_empty() {
+                StressTemplate::ALL.to_vec()
+            } else {
+                template.iter().map(|&t| t.into()).collect()
+            };
+            fs::create_dir_all(out_dir)
+                .with_context(|| format!("creating {}", out_dir.display()))?;
+            let mut written = 0;
+            for template in templates {
+                let sizes = if sizes.is_empty() {
+                    template.default_sizes(&spec)
+                } else {
+                    sizes.clone()
+                };
+                for size in sizes {
+                    let path = out_dir.join(format!("{}_{}.wat", template.name(), size));
+                    fs::write(&path, stress_wat(template, size, *seed))
+                        .with_context(|| format!("writing {}", path.display()))?;
+                    log::info!("  wrote {}", path.display());
+                    written += 1;
+                }
+            }
+            println!("Generated {} programs in {}", written, out_dir.display());
+        }
+        Command::Fuzz {
+            out_dir,
+            iterations,
+            seed,
+            max_instructions,
+            max_nesting,
+            hardware,
+        } => {
+            let spec = hardware_spec(hardware.as_deref())?;
+            let config = fuzz_config(*max_instructions, *max_nesting);
+            fs::create_dir_all(out_dir)
+                .with_context(|| format!("creating {}", out_dir.display()))?;
+            let mut state = *seed;
+            let mut coverage = std::collections::BTreeSet::new();
+            let (mut rejected, mut passed, mut saved, mut failed) = (0, 0, 0, 0);
+            for i in 0..*iterations {
+                let name = format!("case_{}", i);
+                let (wasm, outcome) = fuzz_case(&name, &fuzz_input(&mut state), &config, &spec)?;
+                match outcome {
+                    FuzzOutcome::Rejected(reason) => {
+                        log::debug!("{}: rejected: {}", name, reason);
+                        rejected += 1;
+                    }
+                    FuzzOutcome::Passed(info) => {
+                        passed += 1;
+                        let points = fuzz_coverage(&info.body_bytes)?;
+                        if !points.is_subset(&coverage) {
+                            coverage.extend(points);
+                            let path = out_dir.join(format!("{}.wasm", name));
+                            fs::write(&path, &wasm)
+                                .with_context(|| format!("writing {}", path.display()))?;
+                            log::info!("  new coverage: {}", path.display());
+                            saved += 1;
+                        }
+                    }
+                    FuzzOutcome::Failed(e) => {
+                        let path = out_dir.join(format!("fail_{}.wasm", i));
+                        fs::write(&path, &wasm)
+                            .with_context(|| format!("writing {}", path.display()))?;
+                        let text = path.with_extension("txt");
+                        fs::write(&text, format!("{:#}\n", e))
+                            .with_context(|| format!("writing {}", text.display()))?;
+                        log::warn!("{}: {:#}", path.display(), e);
+                        failed += 1;
+                    }
+                }
+            }
+            println!(
+                "{} cases: {} rejected, {} passed ({} saved, {} coverage points), {} failed",
+                iterations,
+                rejected,
+                passed,
+                saved,
+                coverage.len(),
+                failed
+            );
+            if failed > 0 {
+                return Err(ErrorCategory::Mismatch.error(format!(
+                    "{} fuzz cases failed; see {}",
+                    failed,
+                    out_dir.display()
+                )));
+            }
+        }
+        Command::Shrink { input, out } => {
+            let wasm = read_module(input)?;
+            // Failures match on category and outermost message, which
+            // leaves out offsets that move as the module shrinks.
+            let failure = |wasm: &[u8]| {
+                compile_wasm_bytes("shrink", wasm)
+                    .and_then(|info| disassembly_listing(&info.body_bytes, &info.branch_table))
+                    .err()
+                    .map(|e| (e.category(), e.to_string(), format!("{:#}", e)))
+            };
+            let Some((category, message, detail)) = failure(&wasm) else {
+                bail!(
+                    "{} compiles; there is no failure to shrink",
+                    input.display()
+                );
+            };
+            log::info!("shrinking a failure: {}", detail);
+            let shrunk = shrink_module(&wasm, |candidate| {
+                Ok(failure(candidate).is_some_and(|(c, m, _)| c == category && m == message))
+            })?;
+            let out = out
+                .clone()
+                .unwrap_or_else(|| input.with_extension("min.wat"));
+            fs::write(&out, module_wat(&shrunk)?)
+                .with_context(|| format!("writing {}", out.display()))?;
+            println!(
+                "Shrunk {} from {} to {} bytes into {}",
+                input.display(),
+                wasm.len(),
+                shrunk.len(),
+                out.display()
+            );
+        }
+        Command::ImportSpec {
+            testsuite,
+            out_dir,
+            report,
+        } => {
+            let mut scripts = Vec::new();
+            find_wast_files(testsuite, &mut scripts)?;
+            scripts.sort();
+            if scripts.is_empty() {
+                bail!("no .wast files under {}", testsuite.display());
+            }
+            fs::create_dir_all(out_dir)?;
+            let mut imports = Vec::new();
+            for path in &scripts {
+                // Scripts in subdirectories (proposals) get their path in
+                // the name, so names stay unique in one flat corpus.
+                let name = path
+                    .strip_prefix(testsuite)?
+                    .with_extension("")
+                    .to_string_lossy()
+                    .replace(|c: char| !c.is_ascii_alphanumeric(), "_");
+                let import = fs::read_to_string(path)
+                    .with_context(|| format!("reading {}", path.display()))
+                    .and_then(|source| import_spec_script(&name, &source).map_err(Into::into));
+                match &import {
+                    Ok(import) if import.kept > 0 => {
+                        let out = out_dir.join(format!("{}.wast", name));
+                        fs::write(&out, &import.wast)
+                            .with_context(|| format!("writing {}", out.display()))?;
+                        log::info!(
+                            "  {}: {} kept, {} excluded",
+                            name,
+                            import.kept,
+                            import.excluded.len()
+                        );
+                    }
+                    Ok(_) => {}
+                    Err(e) => log::warn!("{}: not imported: {:#}", name, e),
+                }
+                imports.push((name, import));
+            }
+            if let Some(parent) = report.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(report, spec_import_report(&imports))
+                .with_context(|| format!("writing {}", report.display()))?;
+            let kept: usize = imports
+                .iter()
+                .filter_map(|(_, i)| i.as_ref().ok())
+                .map(|i| i.kept)
+                .sum();
+            println!(
+                "Imported {} checks from {} scripts into {}; report in {}",
+                kept,
+                scripts.len(),
+                out_dir.display(),
+                report.display()
+            );
+        }
     }
 
     Ok(())
 }
+
+/// Every `.wast` file below `dir`.
+fn find_wast_files(dir: &Path, out: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in fs::read_dir(dir).with_context(|| format!("reading {}", dir.display()))? {
+        let path = entry?.path();
+        if path.is_dir() {
+            find_wast_files(&path, out)?;
+        } else if path.extension().is_some_and(|ext| ext == "wast") {
+            out.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Answer each request line from one `serve` client until it hangs up,
+/// sends a line over [`SERVE_MAX_LINE`] bytes, or sits idle for
+/// [`SERVE_IDLE_SECS`].
+fn serve_client(stream: std::net::TcpStream) -> Result<()> {
+    use std::io::BufRead;
+    stream.set_read_timeout(Some(std::time::Duration::from_secs(SERVE_IDLE_SECS)))?;
+    let peer = stream.peer_addr()?;
+    let mut out = stream.try_clone()?;
+    let mut reader = std::io::BufReader::new(stream);
+    let mut line = String::new();
+    loop {
+        line.clear();
+        let read = match (&mut reader)
+            .take(SERVE_MAX_LINE as u64 + 1)
+            .read_line(&mut line)
+        {
+            Ok(read) => read,
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+                ) =>
+            {
+                log::info!("{}: idle for {} s, hanging up", peer, SERVE_IDLE_SECS);
+                return Ok(());
+            }
+            Err(e) => return Err(e.into()),
+        };
+        if read == 0 {
+            return Ok(());
+        }
+        if read > SERVE_MAX_LINE && !line.ends_with('\n') {
+            let message = format!("invalid request: over {} bytes", SERVE_MAX_LINE);
+            writeln!(out, "{}", rpc_error(-32600, &message))?;
+            log::warn!("{}: {}, hanging up", peer, message);
+            return Ok(());
+        }
+        if line.trim().is_empty() {
+            continue;
+        }
+        if let Some(response) = handle_rpc(&line) {
+            writeln!(out, "{}", response)?;
+        }
+    }
+}
@@ -0,0 +1,492 @@
+//! Hardware regression runner: compiles every WAT, .wasm, or .wast file
+//! in a directory and runs each test on the Verilated core through the
+//! marlin harness, printing a results table and optionally writing JUnit,
+//! JSON, Markdown, or CSV reports. Needs `veryl` and `verilator` on the
+//! PATH and is built with `--features hw`.
+//!
+//! With `--jobs N`, N worker threads each own a runtime and take tests
+//! off a shared queue. Every test gets a fresh model, its own recorder,
+//! and its own `<name>.vcd`, so runs never share state.
+//!
+//! Built with `--features tui`, `--tui` shows a live dashboard (see
+//! [`wasm_ic::dashboard`]) in place of the line per test while it runs.
+
+use anyhow::{anyhow, bail, Context, Result};
+use clap::Parser;
+use marlin::verilator::VerilatedModelConfig;
+use marlin::veryl::prelude::*;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{mpsc, Mutex};
+use std::thread;
+use wasm_ic::sim::{
+    self, PortLoader, Rom, RomTiming, TestFailure, Timeout, TraceRecorder, WaveOptions,
+};
+use wasm_ic::*;
+
+#[veryl(src = "src/wasm_core_tb.veryl", name = "WasmCoreTb")]
+pub struct WasmCoreTb;
+
+wasm_ic::impl_wasm_core_dut!(WasmCoreTb);
+
+#[derive(Parser)]
+#[command(
+    name = "wasm-ic-run",
+    about = "Run the WAT corpus on the Verilated wasm-ic core",
+    after_help = EXIT_CODES_HELP
+)]
+struct Cli {
+    /// Directory containing WAT files
+    #[arg(long, default_value = "tests/wat")]
+    wat_dir: PathBuf,
+    /// Only run tests whose name contains this
+    #[arg(long)]
+    filter: Option<String>,
+    /// Leave out files whose name (without extension) matches this glob;
+    /// repeatable
+    #[arg(long)]
+    skip: Vec<String>,
+    /// Run only files whose name matches one of these globs; repeatable
+    #[arg(long)]
+    only: Vec<String>,
+    /// Also leave out the files listed in this skip-list file
+    #[arg(long)]
+    skip_list: Option<PathBuf>,
+    /// Cycles any test may run after start before it times out
+    #[arg(long, default_value_t = Timeout::default().base_cycles)]
+    base_cycles: u32,
+    /// Extra cycles a test may run per program byte
+    #[arg(long, default_value_t = Timeout::default().cycles_per_byte)]
+    cycles_per_byte: u32,
+    /// Fixed cycle limit for every test instead of one scaled to its size
+    #[arg(long)]
+    max_cycles: Option<u32>,
+    /// Leave the waveform and stimulus of each failing test in this directory
+    #[arg(long)]
+    waves: Option<PathBuf>,
+    /// Shrink each failing WAT or wasm test to a minimal module that still
+    /// fails on the core, written to this directory as `<name>.min.wat`
+    #[arg(long)]
+    shrink: Option<PathBuf>,
+    /// Tests to run at once, each on its own model; 0 for one per CPU
+    #[arg(long, short, default_value_t = 1)]
+    jobs: usize,
+    /// Skip `veryl build`, e.g. when the SV is already up to date
+    #[arg(long)]
+    no_build: bool,
+    /// Write JUnit XML to this path
+    #[arg(long)]
+    junit: Option<PathBuf>,
+    /// Write JSON to this path
+    #[arg(long)]
+    json: Option<PathBuf>,
+    /// Write a Markdown summary to this path
+    #[arg(long)]
+    markdown: Option<PathBuf>,
+    /// Write a CSV report to this path
+    #[arg(long)]
+    csv: Option<PathBuf>,
+    /// Take default options from this file instead of the nearest
+    /// wasm-ic.toml
+    #[arg(long)]
+    config: Option<PathBuf>,
+    /// Don't look for a wasm-ic.toml
+    #[arg(long, conflicts_with = "config")]
+    no_config: bool,
+    /// Log more: -v adds stage timings, -vv everything
+    #[arg(long, short, action = clap::ArgAction::Count)]
+    verbose: u8,
+    /// Log only errors
+    #[arg(long, short, conflicts_with = "verbose")]
+    quiet: bool,
+    /// Show a live dashboard of the run instead of a line per test;
+    /// only errors are logged meanwhile
+    #[cfg(feature = "tui")]
+    #[arg(long)]
+    tui: bool,
+}
+
+impl Cli {
+    fn tui(&self) -> bool {
+        #[cfg(feature = "tui")]
+        return self.tui;
+        #[cfg(not(feature = "tui"))]
+        false
+    }
+}
+
+/// What the workers tell the dashboard: a file was taken off the queue,
+/// or has run. Only the dashboard reads them.
+#[cfg_attr(not(feature = "tui"), allow(dead_code))]
+enum Progress {
+    Started(usize),
+    Finished(usize, Vec<SimResult>),
+}
+
+fn main() -> std::process::ExitCode {
+    exit_status(parse_with_project_config().and_then(|cli: Cli| run(&cli)))
+}
+
+fn run(cli: &Cli) -> Result<()> {
+    // Log lines would tear through the dashboard.
+    init_logging(log_level(cli.verbose, cli.quiet || cli.tui()));
+    let mut corpus_filter = CorpusFilter {
+        skip: cli.skip.clone(),
+        only: cli.only.clone(),
+    };
+    if let Some(path) = &cli.skip_list {
+        corpus_filter.add_skip_list(path)?;
+    }
+    let mut files = corpus_files_filtered(&cli.wat_dir, &corpus_filter)?;
+    if let Some(filter) = &cli.filter {
+        files.retain(|f| {
+            f.file_stem()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .contains(filter)
+        });
+    }
+    if files.is_empty() {
+        bail!("no WAT files to run in {}", cli.wat_dir.display());
+    }
+    // `veryl build` isn't thread safe, so it runs once up front and the
+    // workers' runtimes only load the result.
+    VerylRuntime::new(VerylRuntimeOptions {
+        call_veryl_build: !cli.no_build,
+        ..Default::default()
+    })
+    .map_err(|e| anyhow!("creating the Veryl runtime: {}", e))?;
+    let jobs = match cli.jobs {
+        0 => thread::available_parallelism().map_or(1, |n| n.get()),
+        n => n,
+    }
+    .min(files.len());
+
+    let next = AtomicUsize::new(0);
+    let stop = AtomicBool::new(false);
+    let done = Mutex::new(Vec::new());
+    let (progress, events) = mpsc::channel();
+    thread::scope(|scope| {
+        let workers: Vec<_> = (0..jobs)
+            .map(|_| {
+                let progress = progress.clone();
+                let (next, stop, done, files) = (&next, &stop, &done, &files);
+                scope.spawn(move || -> Result<()> {
+                    let runtime = VerylRuntime::new(VerylRuntimeOptions::default())
+                        .map_err(|e| anyhow!("creating the Veryl runtime: {}", e))?;
+                    while !stop.load(Ordering::Relaxed) {
+                        let index = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(file) = files.get(index) else {
+                            return Ok(());
+                        };
+                        // Without a dashboard, or once it has closed,
+                        // nothing listens and the sends fail harmlessly.
+                        let _ = progress.send(Progress::Started(index));
+                        let entries = run_file(&runtime, cli, file);
+                        let results = entries.iter().map(|e| e.result.clone()).collect();
+                        let _ = progress.send(Progress::Finished(index, results));
+                        if !cli.tui() {
+                            for entry in &entries {
+                                let result = &entry.result;
+                                println!(
+                                    "{:<24} {:<4} {:>8} {:>10}  {}",
+                                    result.name,
+                                    if result.passed { "pass" } else { "FAIL" },
+                                    result.cycles,
+                                    result.result,
+                                    one_line(&result.detail)
+                                );
+                            }
+                        }
+                        done.lock().unwrap().push((index, entries));
+                    }
+                    Ok(())
+                })
+            })
+            .collect();
+        // Only the workers' senders are left, so the dashboard sees the
+        // channel close when the last of them is done.
+        drop(progress);
+        #[cfg(feature = "tui")]
+        if cli.tui {
+            let names = files
+                .iter()
+                .map(|f| f.file_stem().unwrap_or_default().to_string_lossy().into());
+            show_dashboard(names, &events, &stop)?;
+        }
+        drop(events);
+        workers
+            .into_iter()
+            .try_for_each(|w| w.join().map_err(|_| anyhow!("a worker panicked"))?)
+    })?;
+    // Reports list the tests in corpus order whatever order they ran in.
+    let mut done = done.into_inner().unwrap();
+    done.sort_by_key(|(index, _)| *index);
+    if done.len() < files.len() {
+        log::warn!("stopped after {} of {} files", done.len(), files.len());
+    }
+    let mut entries: Vec<RegressionEntry> = done.into_iter().flat_map(|(_, e)| e).collect();
+    for entry in &mut entries {
+        entry.result.detail = one_line(&entry.result.detail);
+    }
+    if cli.tui() {
+        for entry in &entries {
+            let result = &entry.result;
+            println!(
+                "{:<24} {:<4} {:>8} {:>10}  {}",
+                result.name,
+                if result.passed { "pass" } else { "FAIL" },
+                result.cycles,
+                result.result,
+                result.detail
+            );
+        }
+    }
+
+    let results: Vec<SimResult> = entries.iter().map(|e| e.result.clone()).collect();
+    for (path, text) in [
+        (&cli.junit, results_junit(&results, "wasm-ic-run")),
+        (&cli.json, results_json(&results)),
+        (&cli.markdown, regression_markdown(&entries)),
+        (&cli.csv, regression_csv(&entries)),
+    ] {
+        if let Some(path) = path {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(path, text).with_context(|| format!("writing {}", path.display()))?;
+        }
+    }
+    let failed = results.iter().filter(|r| !r.passed).count();
+    println!("{} passed, {} failed", results.len() - failed, failed);
+    if failed > 0 {
+        return Err(ErrorCategory::Mismatch.error(format!(
+            "{} of {} tests failed",
+            failed,
+            results.len()
+        )));
+    }
+    Ok(())
+}
+
+/// Compile and run one corpus file into its report entries, one per test
+/// (a `.wast` script has several), or a single error entry if it doesn't
+/// compile. Details keep their line breaks, for the dashboard; see
+/// [`one_line`].
+fn run_file(runtime: &VerylRuntime, cli: &Cli, file: &Path) -> Vec<RegressionEntry> {
+    let infos = match compile_corpus_file(&file.to_path_buf()) {
+        Ok(infos) => infos,
+        Err(e) => {
+            let name = file.file_stem().unwrap_or_default().to_string_lossy();
+            return vec![RegressionEntry {
+                result: SimResult {
+                    name: name.to_string(),
+                    passed: false,
+                    cycles: 0,
+                    result: "error".to_string(),
+                    detail: format!("{:#}", e),
+                },
+                rom_bytes: None,
+                branch_entries: None,
+            }];
+        }
+    };
+    infos
+        .iter()
+        .map(|info| {
+            let name = info.name.clone();
+            let result = match run_test(runtime, cli, file, info) {
+                Ok(outcome) => SimResult {
+                    name,
+                    passed: true,
+                    cycles: outcome.cycles as u64,
+                    result: outcome.result(),
+                    detail: String::new(),
+                },
+                Err(e) => {
+                    let outcome = e.downcast_ref::<TestFailure>().map(|f| f.outcome);
+                    if let (Some(dir), Some(_)) = (&cli.shrink, outcome) {
+                        if let Err(e) = shrink_failure(runtime, cli, file, &info.name, dir) {
+                            log::error!("shrinking {}: {:#}", info.name, e);
+                        }
+                    }
+                    SimResult {
+                        name,
+                        passed: false,
+                        cycles: outcome.map_or(0, |o| o.cycles as u64),
+                        result: outcome.map_or("error".to_string(), |o| o.result()),
+                        detail: format!("{:#}", e),
+                    }
+                }
+            };
+            RegressionEntry {
+                result,
+                rom_bytes: Some(info.body_bytes.len()),
+                branch_entries: Some(info.branch_table.len()),
+            }
+        })
+        .collect()
+}
+
+/// Run one compiled test from `file`, checking it against a golden trace
+/// if one sits next to the file as `<test name>.trace.jsonl`.
+fn run_test(
+    runtime: &VerylRuntime,
+    cli: &Cli,
+    file: &Path,
+    info: &CompiledProgram,
+) -> Result<sim::SimOutcome> {
+    let golden_path = file.with_file_name(format!("{}.trace.jsonl", info.name));
+    let golden = if golden_path.exists() {
+        Some(read_trace(&golden_path)?)
+    } else {
+        None
+    };
+    let wave = match &cli.waves {
+        Some(dir) => WaveOptions::in_dir(dir, &info.name),
+        None => WaveOptions::default(),
+    };
+    run_on_core(runtime, cli, info, &wave, golden.as_ref())
+}
+
+/// Run `info` on a fresh model, failing on a wrong result or trap, or on
+/// a divergence from `golden` if given.
+fn run_on_core(
+    runtime: &VerylRuntime,
+    cli: &Cli,
+    info: &CompiledProgram,
+    wave: &WaveOptions,
+    golden: Option<&TraceRecorder>,
+) -> Result<sim::SimOutcome> {
+    let mut dut = runtime
+        .create_model_with_config::<WasmCoreTb>(&VerilatedModelConfig {
+            enable_tracing: wave.path.is_some(),
+            ..Default::default()
+        })
+        .map_err(|e| anyhow!("creating the model: {}", e))?;
+    let timeout = Timeout {
+        base_cycles: cli.base_cycles,
+        cycles_per_byte: cli.cycles_per_byte,
+    };
+    let timing = RomTiming::default();
+    let max_cycles = cli
+        .max_cycles
+        .unwrap_or_else(|| timeout.max_cycles_with(info, &timing));
+    let mut rom = Rom::new(&info.body_bytes, timing);
+    sim::run_test_checked(
+        &mut dut,
+        &mut rom,
+        &mut PortLoader,
+        info,
+        max_cycles,
+        wave,
+        golden,
+    )
+}
+
+/// Shrink the WAT or wasm test in `file`, which failed on the core, to a
+/// minimal module that still does, and write it to `dir`. The golden
+/// trace and waveforms are left out, since the shrunk program runs
+/// differently; only a wrong result, trap, or timeout counts.
+fn shrink_failure(
+    runtime: &VerylRuntime,
+    cli: &Cli,
+    file: &Path,
+    name: &str,
+    dir: &Path,
+) -> Result<()> {
+    if file.extension().is_some_and(|ext| ext == "wast") {
+        log::warn!("{}: can't shrink a test from a .wast script", name);
+        return Ok(());
+    }
+    let wasm = read_module(file)?;
+    let shrunk = shrink_module(&wasm, |candidate| {
+        Ok(compile_wasm_bytes(name, candidate).is_ok_and(|info| {
+            run_on_core(runtime, cli, &info, &WaveOptions::default(), None)
+                .is_err_and(|e| e.is::<TestFailure>())
+        }))
+    })?;
+    fs::create_dir_all(dir)?;
+    let path = dir.join(format!("{}.min.wat", name));
+    fs::write(&path, module_wat(&shrunk)?)
+        .with_context(|| format!("writing {}", path.display()))?;
+    log::info!(
+        "{}: shrunk from {} to {} bytes into {}",
+        name,
+        wasm.len(),
+        shrunk.len(),
+        path.display()
+    );
+    Ok(())
+}
+
+fn read_trace(path: &Path) -> Result<TraceRecorder> {
+    let text = fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+    TraceRecorder::parse_jsonl(&text).with_context(|| format!("parsing {}", path.display()))
+}
+
+/// A failure's detail on one line, for the table and the reports.
+fn one_line(detail: &str) -> String {
+    detail.replace(['\n', '\t'], " ")
+}
+
+/// Draw the dashboard until the run is over and a key is pressed, taking
+/// the workers' progress from `events`. `q` or Esc sets `stop`, so no
+/// more files start; the terminal is restored however this returns.
+#[cfg(feature = "tui")]
+fn show_dashboard(
+    names: impl IntoIterator<Item = String>,
+    events: &mpsc::Receiver<Progress>,
+    stop: &AtomicBool,
+) -> Result<()> {
+    use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+    use std::time::Duration;
+    use wasm_ic::dashboard::Dashboard;
+
+    let mut dashboard = Dashboard::new(names);
+    let mut terminal = ratatui::init();
+    let mut running = true;
+    let result = (|| -> Result<()> {
+        loop {
+            while running {
+                match events.try_recv() {
+                    Ok(Progress::Started(index)) => dashboard.start(index),
+                    Ok(Progress::Finished(index, results)) => dashboard.finish(index, &results),
+                    Err(mpsc::TryRecvError::Empty) => break,
+                    Err(mpsc::TryRecvError::Disconnected) => {
+                        dashboard.done();
+                        running = false;
+                    }
+                }
+            }
+            terminal.draw(|frame| dashboard.render(frame))?;
+            if !event::poll(Duration::from_millis(100))? {
+                continue;
+            }
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            if !running {
+                return Ok(());
+            }
+            match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => {
+                    stop.store(true, Ordering::Relaxed);
+                    dashboard.stopping();
+                }
+                KeyCode::Up => dashboard.scroll_by(-1),
+                KeyCode::Down => dashboard.scroll_by(1),
+                KeyCode::PageUp => dashboard.scroll_by(-20),
+                KeyCode::PageDown => dashboard.scroll_by(20),
+                KeyCode::Char('f') | KeyCode::End => dashboard.follow(),
+                _ => {}
+            }
+        }
+    })();
+    ratatui::restore();
+    result
+}
@@ -0,0 +1,29 @@
+//! The branch table: for each branch in a body, the PC it goes to, which
+//! the core reads instead of scanning for the matching `end`. The
+//! computation is the `no_std` `wasm-ic-branch` crate, so firmware can
+//! share it; this adds the compiler's [`Error`].
+
+use crate::Error;
+use wasm_ic_branch::BranchError;
+
+pub use wasm_ic_branch::{collect_instructions, BodyError, BranchEntry, InstrKind, InstrRecord};
+
+/// Compute branch table entries from raw function body bytes.
+///
+/// `body_bytes` is the raw bytecode of the function body (operators only,
+/// no locals prefix). Offsets are relative to the start of body_bytes,
+/// which corresponds to PC=0 in the hardware.
+pub fn compute_branch_table(body_bytes: &[u8]) -> Result<Vec<BranchEntry>, Error> {
+    wasm_ic_branch::compute_branch_table(body_bytes).map_err(|e| match e {
+        BranchError::Decode(e) => e.into(),
+        BranchError::Body(e) => e.into(),
+    })
+}
+
+/// [`compute_branch_table`] for a body already decoded into its
+/// operators and their offsets, in order.
+pub fn branch_table_of<'a, 'op: 'a>(
+    ops: impl IntoIterator<Item = (usize, &'a wasmparser::Operator<'op>)>,
+) -> Result<Vec<BranchEntry>, Error> {
+    Ok(wasm_ic_branch::branch_table_of(ops)?)
+}
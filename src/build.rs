@@ -0,0 +1,136 @@
+//! Test generation for build scripts.
+//!
+//! Call [`generate_tests`] from a consumer's `build.rs` to regenerate the
+//! WAT test suite into `OUT_DIR` on every corpus change, then pull it in
+//! with `include!(concat!(env!("OUT_DIR"), "/wat_tests.rs"))` or point the
+//! simulator at the generated `.svh`:
+//!
+//! ```no_run
+//! // in build.rs `main`
+//! wasm_ic::build::generate_tests("tests/wat", "wat_tests.rs").unwrap();
+//! ```
+
+use crate::{
+    compile_corpus_file, corpus_files, generate_marlin_tests, generate_svh_with, wat_corpus_files,
+    SvhOptions,
+};
+use anyhow::{anyhow, Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Generate the tests for every `.wat` file in `wat_dir` into
+/// `$OUT_DIR/<out>`, telling cargo to rerun when the corpus changes.
+///
+/// The extension of `out` picks the output: `.rs` writes a marlin test
+/// module (see [`generate_marlin_tests`]), `.svh` a SystemVerilog header
+/// with default [`SvhOptions`]. Returns the path written.
+pub fn generate_tests(wat_dir: impl AsRef<Path>, out: impl AsRef<Path>) -> Result<PathBuf> {
+    generate_tests_with(wat_dir, out, &SvhOptions::default())
+}
+
+/// [`generate_tests`] with explicit options for the `.svh` output.
+pub fn generate_tests_with(
+    wat_dir: impl AsRef<Path>,
+    out: impl AsRef<Path>,
+    opts: &SvhOptions,
+) -> Result<PathBuf> {
+    let out_dir = std::env::var_os("OUT_DIR")
+        .ok_or_else(|| anyhow!("OUT_DIR is not set; call this from a build script"))?;
+    let (path, inputs) = generate_into(
+        wat_dir.as_ref(),
+        &PathBuf::from(out_dir).join(out.as_ref()),
+        opts,
+    )?;
+    println!("cargo:rerun-if-changed={}", wat_dir.as_ref().display());
+    for input in inputs {
+        println!("cargo:rerun-if-changed={}", input.display());
+    }
+    Ok(path)
+}
+
+/// Write the tests to `path` and return it along with the WAT files read.
+fn generate_into(
+    wat_dir: &Path,
+    path: &Path,
+    opts: &SvhOptions,
+) -> Result<(PathBuf, Vec<PathBuf>)> {
+    let rust = path.extension().is_some_and(|e| e == "rs");
+    // The Rust tests embed WAT source, so only they leave out .wasm and
+    // .wast files.
+    let wat_files = if rust {
+        wat_corpus_files(&wat_dir.to_path_buf())?
+    } else {
+        corpus_files(&wat_dir.to_path_buf())?
+    };
+    let text = match path.extension().and_then(|e| e.to_str()) {
+        Some("rs") => {
+            // OUT_DIR is far from the corpus, so include_str! gets
+            // absolute paths.
+            let mut cases = Vec::new();
+            for file in &wat_files {
+                let abs = file
+                    .canonicalize()
+                    .with_context(|| format!("resolving {}", file.display()))?;
+                let name = file.file_stem().unwrap_or_default().to_string_lossy();
+                cases.push((name.to_string(), abs.to_string_lossy().to_string()));
+            }
+            generate_marlin_tests(&cases)
+        }
+        Some("svh") => {
+            let mut tests = Vec::new();
+            for file in &wat_files {
+                tests.extend(
+                    compile_corpus_file(file)
+                        .with_context(|| format!("compiling {}", file.display()))?,
+                );
+            }
+            generate_svh_with(&tests, opts)
+        }
+        _ => {
+            return Err(anyhow!(
+                "don't know what to generate for {}; use a .rs or .svh file name",
+                path.display()
+            ))
+        }
+    };
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).with_context(|| format!("creating {}", parent.display()))?;
+    }
+    fs::write(path, text).with_context(|| format!("writing {}", path.display()))?;
+    Ok((path.to_path_buf(), wat_files))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generate_into() {
+        let dir = std::env::temp_dir().join(format!("wasm-ic-build-{}", std::process::id()));
+        let wat_dir = dir.join("wat");
+        fs::create_dir_all(&wat_dir).unwrap();
+        fs::write(
+            wat_dir.join("two.wat"),
+            r#"(module (func (export "main") (result i32) i32.const 2))"#,
+        )
+        .unwrap();
+        fs::write(wat_dir.join("loop.wat"), "skipped").unwrap();
+        fs::write(wat_dir.join(crate::SKIP_LIST), "loop\n").unwrap();
+        fs::write(wat_dir.join("notes.txt"), "ignored").unwrap();
+        let opts = SvhOptions::default();
+
+        let (path, inputs) = generate_into(&wat_dir, &dir.join("out/tests.rs"), &opts).unwrap();
+        assert_eq!(inputs, [wat_dir.join("two.wat")]);
+        let rust = fs::read_to_string(path).unwrap();
+        let abs = wat_dir.join("two.wat").canonicalize().unwrap();
+        assert!(rust.contains(&format!("include_str!({:?})", abs.to_string_lossy())));
+
+        let (path, _) = generate_into(&wat_dir, &dir.join("out/tests.svh"), &opts).unwrap();
+        assert!(fs::read_to_string(path)
+            .unwrap()
+            .contains("wat_check(\"two\", 32'sd2);"));
+
+        assert!(generate_into(&wat_dir, &dir.join("out/tests.txt"), &opts).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
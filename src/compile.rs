@@ -0,0 +1,1205 @@
+//! Compiling one module: the options, the passes from wasm to a
+//! [`CompiledProgram`], and the golden run behind it.
+
+use crate::emit::{test_json, ChecksumAlgo};
+use crate::logging::timed;
+use crate::parse::{check_single_memory, disabled_proposal, locate_in_wat, memory_count};
+use crate::{
+    compute_branch_table, emit, emit_artifacts, expected_memory, extract_export_body,
+    extract_function_bodies, extract_function_body_raw, extract_memory_init, generate_test_svh,
+    lower, refexec, rewrite_for_hardware, value_type_summary, value_type_uses, ArtifactFormat,
+    BranchEntry, EmitOptions, Error, HardwareSpec, MemCheck, MemSegment, SvhOptions,
+};
+use anyhow::{anyhow, Context, Result};
+use std::fs;
+use std::path::PathBuf;
+use wasmparser::Payload;
+
+// ---------------------------------------------------------------------------
+// Compilation
+// ---------------------------------------------------------------------------
+
+/// A compiled test: the program the core runs, its branch table and
+/// data, and what the golden run expects. Fields may be added, so it is
+/// only built by the compile functions, which
+/// [`CompiledProgram::from_file`] and its siblings wrap, or from
+/// [`Default`] with the fields a test needs set.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct CompiledProgram {
+    pub name: String,
+    pub body_bytes: Vec<u8>,
+    pub branch_table: Vec<BranchEntry>,
+    pub mem_init: Vec<MemSegment>,
+    pub expected: i32,
+    /// Data RAM words the program changes, with their final values.
+    pub mem_expected: Vec<MemCheck>,
+    /// The golden run traps, so the core must trap too; `expected` and
+    /// `mem_expected` are unused.
+    pub expect_trap: bool,
+    /// Most cycles from start to halt the test allows, from a
+    /// `;; max-cycles: N` line in the source (see [`parse_cycle_budget`]).
+    pub cycle_budget: Option<u32>,
+    /// The trap the golden run ended in when `expect_trap`, e.g.
+    /// `integer divide by zero` (see [`emit::ExpectedSpec::trap`]); `None`
+    /// if any trap will do.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub trap_kind: Option<String>,
+    /// CRC-32 of the source the test was compiled from: the WAT text, the
+    /// wasm binary, or the `.wast` script. Stamped into its artifacts (see
+    /// [`ArtifactMetadata`](crate::emit::ArtifactMetadata)).
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub input_hash: Option<u32>,
+}
+
+/// The name [`CompiledProgram`] had before, kept so existing code builds.
+pub type WatTestInfo = CompiledProgram;
+
+impl CompiledProgram {
+    /// See [`compile_wat_file`].
+    pub fn from_file(path: &std::path::Path) -> Result<Self, Error> {
+        compile_wat_file(path)
+    }
+
+    /// See [`compile_wat_source`].
+    pub fn from_wat(name: &str, wat_source: &str) -> Result<Self, Error> {
+        compile_wat_source(name, wat_source)
+    }
+
+    /// See [`compile_wasm_bytes`].
+    pub fn from_wasm(name: &str, wasm_bytes: &[u8]) -> Result<Self, Error> {
+        compile_wasm_bytes(name, wasm_bytes)
+    }
+
+    /// Write the `$readmemh` images to `dir` as `<name>.prog.hex` and so
+    /// on, returning their paths; [`emit_artifacts`] with the default
+    /// options.
+    pub fn emit_hex(&self, dir: &std::path::Path) -> Result<Vec<PathBuf>, Error> {
+        emit_artifacts(self, dir, &EmitOptions::default())
+    }
+
+    /// This test's `.svh` file, as `gen-tests --split` writes it.
+    pub fn emit_svh(&self) -> String {
+        generate_test_svh(self, &SvhOptions::default())
+    }
+
+    /// This test as the JSON object of `--format json` and the server.
+    pub fn to_json(&self) -> serde_json::Value {
+        test_json(self)
+    }
+}
+
+/// Which function of a module [`compile`] turns into the test.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum Entry {
+    /// The first function with code, run through the `main` export.
+    #[default]
+    First,
+    /// The function exported under this name.
+    Export(String),
+    /// The function with this index, imports counted. It has no golden
+    /// run; leave out [`Pass::RefExec`] or use [`Entry::Export`].
+    Index(u32),
+}
+
+/// How strictly [`compile`] checks the test against the core.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Validation {
+    /// Only what the wasm validator and the branch table need; the
+    /// simulation finds out the rest.
+    #[default]
+    Lenient,
+    /// Also reject operators the core lacks ([`HardwareSpec::check_supported`])
+    /// and tests too big for it ([`HardwareSpec::check_fits`]).
+    Strict,
+}
+
+/// What [`compile`] makes of the `wasm-ic` section of a module already
+/// linked, e.g. on another machine (see [`emit::link_module`]).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Embedded {
+    /// Compute the branch table afresh, as for any module.
+    #[default]
+    Ignore,
+    /// Take the branch table from the section instead of computing it.
+    /// The section's program must still be the module's.
+    Trust,
+    /// Compute the branch table and fail, listing the differences, if the
+    /// section holds another program or table.
+    Verify,
+}
+
+/// An optional stage of [`compile`]; the branch table is always built.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pass {
+    /// Run the entry on wasmtime for `expected`, `expect_trap`, and
+    /// `mem_expected`.
+    RefExec,
+    /// Read the data segments into `mem_init` and, with
+    /// [`Pass::RefExec`], the words they end up changed to.
+    Memory,
+    /// Read a `;; max-cycles: N` budget from WAT source.
+    CycleBudget,
+}
+
+/// What [`compile`] reads.
+#[derive(Debug, Clone, Copy)]
+pub enum Source<'a> {
+    Wat(&'a str),
+    Wasm(&'a [u8]),
+    /// A WAT file, or a `.wasm` binary recognized by its `\0asm` magic.
+    File(&'a std::path::Path),
+}
+
+/// Options for [`compile`], built up from [`CompileOptions::new`] with
+/// the setters below, e.g.
+/// `CompileOptions::new().entry(Entry::Export("add".into())).validation(Validation::Strict)`.
+#[derive(Debug, Clone)]
+pub struct CompileOptions {
+    name: Option<String>,
+    entry: Entry,
+    rewrite_end: bool,
+    expand_unsupported: bool,
+    normalize_leb: bool,
+    validation: Validation,
+    features: wasmparser::WasmFeatures,
+    embedded: Embedded,
+    hardware: HardwareSpec,
+    passes: Vec<Pass>,
+    out_dir: Option<PathBuf>,
+    formats: Vec<ArtifactFormat>,
+    fuel: Option<u64>,
+}
+
+impl Default for CompileOptions {
+    fn default() -> Self {
+        CompileOptions {
+            name: None,
+            entry: Entry::First,
+            rewrite_end: true,
+            expand_unsupported: false,
+            normalize_leb: false,
+            validation: Validation::Lenient,
+            features: wasmparser::WasmFeatures::default(),
+            embedded: Embedded::Ignore,
+            hardware: HardwareSpec::default(),
+            passes: vec![
+                #[cfg(feature = "refexec")]
+                Pass::RefExec,
+                Pass::Memory,
+                Pass::CycleBudget,
+            ],
+            out_dir: None,
+            formats: Vec::new(),
+            fuel: None,
+        }
+    }
+}
+
+impl CompileOptions {
+    /// Every pass the build has (no [`Pass::RefExec`] without the
+    /// `refexec` feature), the first function, lenient checks, nothing
+    /// written.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The test's name; a file's stem or `main` if not given.
+    pub fn name(mut self, name: &str) -> Self {
+        self.name = Some(name.to_string());
+        self
+    }
+
+    pub fn entry(mut self, entry: Entry) -> Self {
+        self.entry = entry;
+        self
+    }
+
+    /// Whether to [`rewrite_for_hardware`], making the body's final `end`
+    /// the `return` the core halts on (the default).
+    pub fn rewrite_end(mut self, rewrite: bool) -> Self {
+        self.rewrite_end = rewrite;
+        self
+    }
+
+    /// Whether to replace instructions [`CompileOptions::hardware`] lacks
+    /// with sequences of ones it has where there are such sequences (see
+    /// [`lower::expand_unsupported`]); off by default.
+    pub fn expand_unsupported(mut self, expand: bool) -> Self {
+        self.expand_unsupported = expand;
+        self
+    }
+
+    /// Whether to re-encode overlong LEB128s in their shortest form (see
+    /// [`lower::normalize_leb`]); off by default, when they are only
+    /// warned about.
+    pub fn normalize_leb(mut self, normalize: bool) -> Self {
+        self.normalize_leb = normalize;
+        self
+    }
+
+    pub fn validation(mut self, validation: Validation) -> Self {
+        self.validation = validation;
+        self
+    }
+
+    /// The proposals a module may use; it is validated against these
+    /// before anything is extracted. wasmparser's defaults if not given.
+    pub fn features(mut self, features: wasmparser::WasmFeatures) -> Self {
+        self.features = features;
+        self
+    }
+
+    /// Whether to use or check a linked module's embedded branch table;
+    /// ignored by default. Modules without one compile as usual.
+    pub fn embedded(mut self, embedded: Embedded) -> Self {
+        self.embedded = embedded;
+        self
+    }
+
+    /// The core strict validation checks against and artifacts are
+    /// written for.
+    pub fn hardware(mut self, hardware: HardwareSpec) -> Self {
+        self.hardware = hardware;
+        self
+    }
+
+    /// Run only these of the optional passes.
+    pub fn passes(mut self, passes: &[Pass]) -> Self {
+        self.passes = passes.to_vec();
+        self
+    }
+
+    /// Write the test's artifacts to `dir` in each format added with
+    /// [`CompileOptions::format`].
+    pub fn out_dir(mut self, dir: &std::path::Path) -> Self {
+        self.out_dir = Some(dir.to_path_buf());
+        self
+    }
+
+    /// Also write the artifacts in `format`; hex if none is added.
+    pub fn format(mut self, format: ArtifactFormat) -> Self {
+        if !self.formats.contains(&format) {
+            self.formats.push(format);
+        }
+        self
+    }
+
+    /// Fail the golden run once it burns `fuel` in wasmtime instead of
+    /// letting it run for as long as it takes; unlimited by default.
+    pub fn fuel(mut self, fuel: u64) -> Self {
+        self.fuel = Some(fuel);
+        self
+    }
+
+    fn runs(&self, pass: Pass) -> bool {
+        self.passes.contains(&pass)
+    }
+}
+
+/// Compile `source` into a test as `options` says, writing its artifacts
+/// if they give an output directory. The `compile_*` functions are this
+/// with the default options.
+pub fn compile(source: Source, options: &CompileOptions) -> Result<CompiledProgram, Error> {
+    let info = match source {
+        Source::Wat(wat_source) => {
+            let name = options.name.as_deref().unwrap_or("main");
+            compile_wat_at(name, None, wat_source, options)?
+        }
+        Source::Wasm(wasm_bytes) => {
+            let name = options.name.as_deref().unwrap_or("main");
+            compile_binary(name, wasm_bytes, options)?
+        }
+        Source::File(path) => compile_file(path, options)?,
+    };
+    if options.validation == Validation::Strict {
+        options.hardware.check_supported(&info)?;
+        options.hardware.check_fits(&info)?;
+    }
+    if let Some(dir) = &options.out_dir {
+        let formats = if options.formats.is_empty() {
+            &[ArtifactFormat::Hex][..]
+        } else {
+            &options.formats[..]
+        };
+        for &format in formats {
+            let emit = EmitOptions {
+                format,
+                hardware: options.hardware.clone(),
+                ..EmitOptions::default()
+            };
+            emit_artifacts(&info, dir, &emit)?;
+        }
+    }
+    Ok(info)
+}
+
+/// Compile a WAT file, or a precompiled `.wasm` binary, recognized by its
+/// `\0asm` magic whatever the file is called.
+pub fn compile_wat_file(path: &std::path::Path) -> Result<CompiledProgram, Error> {
+    compile(Source::File(path), &CompileOptions::new())
+}
+
+fn compile_file(path: &std::path::Path, options: &CompileOptions) -> Result<CompiledProgram> {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = options.name.as_deref().unwrap_or(&stem);
+    let bytes = fs::read(path).with_context(|| format!("reading {}", path.display()))?;
+    if bytes.starts_with(WASM_MAGIC) {
+        return compile_binary(name, &bytes, options)
+            .with_context(|| format!("compiling wasm from {}", path.display()));
+    }
+    if path.extension().is_some_and(|ext| ext == "wasm") {
+        return Err(Error::ParseError(anyhow!("{} is not a wasm binary", path.display())).into());
+    }
+    let wat_source = String::from_utf8(bytes)
+        .with_context(|| format!("reading {} as WAT text", path.display()))?;
+    compile_wat_at(name, Some(path), &wat_source, options)
+        .with_context(|| format!("compiling WAT from {}", path.display()))
+}
+
+/// The first bytes of every wasm binary.
+pub const WASM_MAGIC: &[u8] = b"\0asm";
+
+/// [`compile_wat_file`] for WAT source already in memory. Errors at an
+/// instruction quote the source line it is on.
+pub fn compile_wat_source(name: &str, wat_source: &str) -> Result<CompiledProgram, Error> {
+    compile(Source::Wat(wat_source), &CompileOptions::new().name(name))
+}
+
+/// Compile WAT read from `path`, which labels errors in place of `name`.
+fn compile_wat_at(
+    name: &str,
+    path: Option<&std::path::Path>,
+    wat_source: &str,
+    options: &CompileOptions,
+) -> Result<CompiledProgram, Error> {
+    let wasm_bytes = timed("parse", name, || {
+        wat::Parser::new().parse_str(path, wat_source)
+    })?;
+    let label = path.map_or(name.to_string(), |p| p.display().to_string());
+    let cycle_budget = if options.runs(Pass::CycleBudget) {
+        parse_cycle_budget(wat_source)?
+    } else {
+        None
+    };
+    Ok(CompiledProgram {
+        cycle_budget,
+        input_hash: Some(ChecksumAlgo::Crc32.checksum(wat_source.as_bytes())),
+        ..compile_binary(name, &wasm_bytes, options)
+            .map_err(|e| locate_in_wat(e, &label, wat_source))?
+    })
+}
+
+/// Compile a wasm binary, e.g. from rustc or clang. There is no source to
+/// declare a cycle budget in, so the test gets the default one.
+pub fn compile_wasm_bytes(name: &str, wasm_bytes: &[u8]) -> Result<CompiledProgram, Error> {
+    compile(Source::Wasm(wasm_bytes), &CompileOptions::new().name(name))
+}
+
+/// [`compile_wasm_bytes`] for the function exported as `export` instead
+/// of the first function and `main`.
+pub fn compile_wasm_export(
+    name: &str,
+    wasm_bytes: &[u8],
+    export: &str,
+) -> Result<CompiledProgram, Error> {
+    let options = CompileOptions::new()
+        .name(name)
+        .entry(Entry::Export(export.to_string()));
+    compile(Source::Wasm(wasm_bytes), &options)
+}
+
+/// One function of a module, compiled by [`compile_functions`].
+#[derive(Debug)]
+pub struct FunctionTest {
+    /// Function index, imports counted.
+    pub index: u32,
+    /// The test's name, also when it failed.
+    pub name: String,
+    /// The test, or why the function couldn't be made one.
+    pub test: Result<CompiledProgram, Error>,
+    /// Whether the test's `expected`, `expect_trap`, and `mem_expected`
+    /// come from a golden run. Only a function exported with no
+    /// parameters and an `i32` result has one, as the core cannot be
+    /// passed arguments.
+    pub golden: bool,
+}
+
+/// A test of every function with code in a module, e.g. one rustc built
+/// from many, named `<name>_<export>` or, for a function not exported,
+/// `<name>_func<index>`. `options` apply to each, apart from its entry
+/// and name, and functions without a golden run skip [`Pass::RefExec`].
+/// A function that fails to compile doesn't stop the others.
+pub fn compile_functions(
+    name: &str,
+    wasm_bytes: &[u8],
+    options: &CompileOptions,
+) -> Result<Vec<FunctionTest>, Error> {
+    let exports = function_exports(wasm_bytes)?;
+    let mut tests = Vec::new();
+    for body in extract_function_bodies(wasm_bytes)? {
+        let export = exports.iter().find(|e| e.index == body.index);
+        let runnable = exports.iter().find(|e| e.index == body.index && e.runnable);
+        let golden = runnable.is_some() && options.runs(Pass::RefExec);
+        let mut options = match (runnable, export) {
+            (Some(e), _) => options.clone().entry(Entry::Export(e.name.clone())),
+            _ => options.clone().entry(Entry::Index(body.index)),
+        };
+        if !golden {
+            options.passes.retain(|&pass| pass != Pass::RefExec);
+        }
+        let function = match export {
+            Some(e) => emit::sanitize_ident(&e.name),
+            None => format!("func{}", body.index),
+        };
+        let name = format!("{}_{}", name, function);
+        let options = options.name(&name);
+        tests.push(FunctionTest {
+            index: body.index,
+            test: compile(Source::Wasm(wasm_bytes), &options),
+            name,
+            golden,
+        });
+    }
+    Ok(tests)
+}
+
+/// A function export of a module, and whether the golden run can call
+/// it: it takes nothing and returns an `i32`, and the module imports
+/// nothing, as the golden run provides no imports.
+struct FunctionExport {
+    name: String,
+    index: u32,
+    runnable: bool,
+}
+
+fn function_exports(wasm_bytes: &[u8]) -> Result<Vec<FunctionExport>> {
+    let mut types = Vec::new();
+    // The type of every function, imported ones first.
+    let mut function_types = Vec::new();
+    let mut exports = Vec::new();
+    let mut imports = false;
+    for payload in wasmparser::Parser::new(0).parse_all(wasm_bytes) {
+        match payload? {
+            Payload::TypeSection(reader) => {
+                for ty in reader.into_iter_err_on_gc_types() {
+                    types.push(ty?);
+                }
+            }
+            Payload::ImportSection(reader) => {
+                for import in reader.into_imports() {
+                    imports = true;
+                    if let wasmparser::TypeRef::Func(ty) = import?.ty {
+                        function_types.push(ty);
+                    }
+                }
+            }
+            Payload::FunctionSection(reader) => {
+                for ty in reader {
+                    function_types.push(ty?);
+                }
+            }
+            Payload::ExportSection(reader) => {
+                for e in reader {
+                    let e = e?;
+                    if e.kind == wasmparser::ExternalKind::Func {
+                        exports.push((e.name.to_string(), e.index));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(exports
+        .into_iter()
+        .map(|(name, index)| {
+            let ty = function_types
+                .get(index as usize)
+                .and_then(|&ty| types.get(ty as usize));
+            let runnable = !imports
+                && ty.is_some_and(|ty| {
+                    ty.params().is_empty() && ty.results() == [wasmparser::ValType::I32]
+                });
+            FunctionExport {
+                name,
+                index,
+                runnable,
+            }
+        })
+        .collect())
+}
+
+/// The test for the entry of a module as `options` say.
+fn compile_binary(
+    name: &str,
+    wasm_bytes: &[u8],
+    options: &CompileOptions,
+) -> Result<CompiledProgram, Error> {
+    timed("validate", name, || {
+        wasmparser::Validator::new_with_features(options.features).validate_all(wasm_bytes)
+    })
+    .map_err(
+        |e| match disabled_proposal(wasm_bytes, options.features, &e) {
+            Some(proposal) => anyhow::Error::from(e).context(proposal),
+            None => e.into(),
+        },
+    )
+    .context("validating module")?;
+    check_single_memory(wasm_bytes)?;
+    let mut body_bytes = timed("extract", name, || match &options.entry {
+        Entry::First => extract_function_body_raw(wasm_bytes),
+        Entry::Export(export) => extract_export_body(wasm_bytes, export),
+        Entry::Index(index) => extract_function_bodies(wasm_bytes)?
+            .into_iter()
+            .find(|body| body.index == *index)
+            .map(|body| body.ops)
+            .ok_or_else(|| anyhow!("no function {} with code", index).into()),
+    })
+    .context("extracting function body")?;
+    if options.rewrite_end {
+        rewrite_for_hardware(&mut body_bytes);
+    }
+    let (normalized, overlong) = lower::normalize_leb(&body_bytes)?;
+    if options.normalize_leb {
+        body_bytes = normalized;
+    } else if let Some(first) = overlong.first() {
+        log::warn!(
+            "{}: {} instructions pad their LEB128s, from pc {:#06X}; normalizing would save {} bytes",
+            name,
+            overlong.len(),
+            first,
+            body_bytes.len() - normalized.len()
+        );
+    }
+    if options.expand_unsupported {
+        body_bytes = lower::expand_unsupported(&body_bytes, &options.hardware)?;
+    }
+    let linked = match options.embedded {
+        Embedded::Ignore => None,
+        Embedded::Trust | Embedded::Verify => emit::linked_image(wasm_bytes)?,
+    };
+    let trusted = match &linked {
+        Some(image) if options.embedded == Embedded::Trust => {
+            if image.prog != body_bytes {
+                return Err(Error::ParseError(anyhow!(
+                    "the embedded program ({} bytes) is not this module's ({} bytes); link it again",
+                    image.prog.len(),
+                    body_bytes.len()
+                )));
+            }
+            Some(image.branch_table.clone())
+        }
+        _ => None,
+    };
+    let run = if options.runs(Pass::RefExec) {
+        let run = timed("refexec", name, || {
+            refexec::run_entry(wasm_bytes, &options.entry, options.fuel)
+        });
+        if options.runs(Pass::Memory)
+            && matches!(run, Ok((_, None)))
+            && memory_count(wasm_bytes)? > 0
+        {
+            log::warn!(
+                "{}: the module doesn't export its memory as `memory`, \
+                 so the test checks none of it",
+                name
+            );
+        }
+        run
+    } else {
+        Ok((0, None))
+    };
+    let info = compile_module(
+        name,
+        wasm_bytes,
+        body_bytes,
+        trusted,
+        run,
+        options.runs(Pass::Memory),
+    )?;
+    if let Some(image) = linked.filter(|_| options.embedded == Embedded::Verify) {
+        let diffs = embedded_differences(&image, &info);
+        if !diffs.is_empty() {
+            return Err(Error::ParseError(anyhow!(
+                "the embedded branch table doesn't match the module:\n  {}",
+                diffs.join("\n  ")
+            )));
+        }
+    }
+    Ok(info)
+}
+
+/// How a linked module's embedded image differs from what compiling it
+/// gives, one line per difference.
+fn embedded_differences(image: &emit::BootImage, info: &CompiledProgram) -> Vec<String> {
+    let mut diffs = Vec::new();
+    if image.prog != info.body_bytes {
+        let at = image
+            .prog
+            .iter()
+            .zip(&info.body_bytes)
+            .position(|(a, b)| a != b)
+            .unwrap_or(image.prog.len().min(info.body_bytes.len()));
+        diffs.push(format!(
+            "program: embedded {} bytes, computed {}, first differing at PC {:#x}",
+            image.prog.len(),
+            info.body_bytes.len(),
+            at
+        ));
+    }
+    for e in &image.branch_table {
+        if !info.branch_table.contains(e) {
+            diffs.push(format!(
+                "branch {:#x} -> {:#x}: embedded only",
+                e.source_pc, e.target_pc
+            ));
+        }
+    }
+    for e in &info.branch_table {
+        if !image.branch_table.contains(e) {
+            diffs.push(format!(
+                "branch {:#x} -> {:#x}: computed only",
+                e.source_pc, e.target_pc
+            ));
+        }
+    }
+    diffs
+}
+
+/// The test for `body_bytes` of a module, given its golden run and, if
+/// it is to be trusted rather than computed, its branch table.
+fn compile_module(
+    name: &str,
+    wasm_bytes: &[u8],
+    body_bytes: Vec<u8>,
+    branch_table: Option<Vec<BranchEntry>>,
+    run: Result<(i32, Option<Vec<u8>>), Error>,
+    memory: bool,
+) -> Result<CompiledProgram, Error> {
+    let branch_table = match branch_table {
+        Some(table) => table,
+        None => timed("branch", name, || compute_branch_table(&body_bytes))
+            .context("computing branch table")?,
+    };
+    let mem_init = if memory {
+        extract_memory_init(wasm_bytes).context("extracting data segments")?
+    } else {
+        Vec::new()
+    };
+    let (expected, final_mem, trap_kind) = match run {
+        Ok((expected, final_mem)) => (expected, final_mem.filter(|_| memory), None),
+        Err(Error::RefExecFailed(e)) if refexec::is_trap(&e) => {
+            (0, None, Some(refexec::trap_kind(&e)))
+        }
+        Err(e) => {
+            let e = anyhow::Error::from(e).context("running with wasmtime");
+            // Name what the core lacks, not only how wasmtime failed.
+            return Err(match value_type_uses(wasm_bytes) {
+                Ok(uses) if !uses.is_empty() => e.context(value_type_summary(&uses)),
+                _ => e,
+            }
+            .into());
+        }
+    };
+    let mem_expected = match final_mem {
+        Some(mem) => expected_memory(&mem_init, &mem)?,
+        None => Vec::new(),
+    };
+
+    Ok(CompiledProgram {
+        name: name.to_string(),
+        body_bytes,
+        branch_table,
+        mem_init,
+        expected,
+        mem_expected,
+        expect_trap: trap_kind.is_some(),
+        cycle_budget: None,
+        trap_kind: trap_kind.flatten(),
+        input_hash: Some(ChecksumAlgo::Crc32.checksum(wasm_bytes)),
+    })
+}
+
+/// The per-test cycle budget declared by a `;; max-cycles: N` comment in
+/// WAT source, so the test fails when the core gets slower than that.
+pub fn parse_cycle_budget(wat_source: &str) -> Result<Option<u32>, Error> {
+    let mut budget = None;
+    for line in wat_source.lines() {
+        let Some((_, comment)) = line.split_once(";;") else {
+            continue;
+        };
+        if let Some(value) = comment.trim().strip_prefix("max-cycles:") {
+            let value = value.trim();
+            budget = Some(
+                value
+                    .parse()
+                    .map_err(|_| anyhow!("bad max-cycles value `{}`", value))?,
+            );
+        }
+    }
+    Ok(budget)
+}
+
+#[cfg(all(test, feature = "refexec"))]
+mod tests {
+    use super::*;
+    use crate::corpus::{test_info_from_json, test_info_json};
+    use crate::emit::{link_module, ExpectedSpec};
+    use crate::parse::{memory_image, parse_wasm_features};
+    use crate::{
+        compatibility_issues, compile_wat, corpus_files, extract_function_body, run_with_wasmtime,
+        wat_corpus_files, ErrorCategory,
+    };
+
+    fn compile_and_check(wat: &str, expected_result: i32, expected_branches: &[(u32, u32)]) {
+        let wasm = wat::parse_str(wat).expect("WAT parse failed");
+        let body = extract_function_body(&wasm).expect("body extraction failed");
+        let branches = compute_branch_table(&body).expect("branch table failed");
+        let result = run_with_wasmtime(&wasm).expect("wasmtime failed");
+
+        assert_eq!(result, expected_result, "wasmtime result mismatch");
+
+        let branch_pairs: Vec<(u32, u32)> = branches
+            .iter()
+            .map(|e| (e.source_pc, e.target_pc))
+            .collect();
+        assert_eq!(branch_pairs, expected_branches, "branch table mismatch");
+    }
+
+    #[test]
+    fn test_add() {
+        compile_and_check(
+            r#"(module (func (export "main") (result i32)
+                i32.const 10
+                i32.const 20
+                i32.add))"#,
+            30,
+            &[],
+        );
+    }
+
+    #[test]
+    fn test_expr() {
+        compile_and_check(
+            r#"(module (func (export "main") (result i32)
+                i32.const 3
+                i32.const 5
+                i32.add
+                i32.const 2
+                i32.mul))"#,
+            16,
+            &[],
+        );
+    }
+
+    #[test]
+    fn test_sub() {
+        compile_and_check(
+            r#"(module (func (export "main") (result i32)
+                i32.const 20
+                i32.const 7
+                i32.sub))"#,
+            13,
+            &[],
+        );
+    }
+
+    #[test]
+    fn test_block_br() {
+        let wat = r#"(module (func (export "main") (result i32)
+                block
+                  br 0
+                end
+                i32.const 99))"#;
+        let wasm = wat::parse_str(wat).expect("WAT parse failed");
+        let body = extract_function_body(&wasm).expect("body extraction failed");
+        let branches = compute_branch_table(&body).expect("branch table failed");
+        let result = run_with_wasmtime(&wasm).expect("wasmtime failed");
+
+        assert_eq!(result, 99);
+        assert!(!branches.is_empty(), "should have branch entries");
+        let br_entry = &branches[0];
+        assert!(
+            br_entry.target_pc > br_entry.source_pc,
+            "br should jump forward"
+        );
+    }
+
+    #[test]
+    fn test_if_else() {
+        let wat = r#"(module (func (export "main") (result i32)
+                i32.const 1
+                if (result i32)
+                  i32.const 42
+                else
+                  i32.const 0
+                end))"#;
+        let wasm = wat::parse_str(wat).expect("WAT parse failed");
+        let body = extract_function_body(&wasm).expect("body extraction failed");
+        let branches = compute_branch_table(&body).expect("branch table failed");
+        let result = run_with_wasmtime(&wasm).expect("wasmtime failed");
+
+        assert_eq!(result, 42);
+        assert_eq!(branches.len(), 2, "if/else should produce 2 branch entries");
+        assert!(
+            branches[1].target_pc > branches[0].target_pc,
+            "else target should be past if target"
+        );
+    }
+
+    #[test]
+    fn test_multiple_memories() {
+        let source = r#"(module
+            (memory 1) (memory $b 1)
+            (data (memory $b) (i32.const 0) "\01")
+            (func (export "main") (result i32) i32.const 0))"#;
+        let wasm = wat::parse_str(source).unwrap();
+        assert_eq!(memory_count(&wasm).unwrap(), 2);
+        let segments = extract_memory_init(&wasm).unwrap();
+        assert_eq!(segments[0].memory, 1);
+        let e = memory_image(&segments).unwrap_err();
+        assert_eq!(e.category(), Some(ErrorCategory::Unsupported));
+
+        let e = compile_wat_source("mm", source).unwrap_err();
+        assert_eq!(e.category(), Some(ErrorCategory::Unsupported));
+        assert!(
+            e.to_string().starts_with("module declares 2 memories"),
+            "{}",
+            e
+        );
+        let issues = compatibility_issues(&wasm, &HardwareSpec::default()).unwrap();
+        assert!(issues.iter().any(
+            |i| i.issue == "the module declares 2 memories, but the core has a single data RAM"
+        ));
+
+        // An imported memory counts too; one memory is fine.
+        let wasm = wat::parse_str(r#"(module (import "m" "mem" (memory 1)) (memory 1))"#).unwrap();
+        assert_eq!(memory_count(&wasm).unwrap(), 2);
+        let info = compile_wat_source(
+            "one",
+            r#"(module (memory (export "memory") 1) (data (i32.const 4) "\07")
+                (func (export "main") (result i32) i32.const 4 i32.const 9 i32.store i32.const 0))"#,
+        )
+        .unwrap();
+        assert_eq!(info.mem_init[0].memory, 0);
+
+        // Entries written before the index existed read back as memory 0.
+        let mut json = test_info_json(&info);
+        assert_eq!(json["mem_init"][0]["memory"], 0);
+        assert_eq!(json["mem_expected"][0][2], 0);
+        json["mem_init"][0]
+            .as_object_mut()
+            .unwrap()
+            .remove("memory");
+        json["mem_expected"][0].as_array_mut().unwrap().truncate(2);
+        let read = test_info_from_json(&json).unwrap();
+        assert_eq!(read.mem_init, info.mem_init);
+        assert_eq!(read.mem_expected, info.mem_expected);
+        let spec = ExpectedSpec::of(&info).to_json();
+        assert_eq!(spec["memory"][0]["memory"], 0);
+    }
+
+    #[test]
+    fn test_compile_wasm_input() {
+        let dir = std::env::temp_dir().join(format!("wasm-ic-binary-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let source = ";; max-cycles: 9\n(module (func (export \"main\") (result i32) i32.const 3))";
+        let wasm = wat::parse_str(source).unwrap();
+        fs::write(dir.join("three.wat"), source).unwrap();
+        fs::write(dir.join("three.wasm"), &wasm).unwrap();
+        fs::write(dir.join("four.wasm"), &wasm).unwrap();
+        // The magic decides, not the extension.
+        fs::write(dir.join("renamed.wat"), &wasm).unwrap();
+        fs::write(dir.join("text.wasm"), source).unwrap();
+
+        let from_text = compile_wat_file(&dir.join("three.wat")).unwrap();
+        let from_wasm = compile_wat_file(&dir.join("four.wasm")).unwrap();
+        assert_eq!(from_wasm.name, "four");
+        assert_eq!(from_wasm.body_bytes, from_text.body_bytes);
+        assert_eq!(from_wasm.expected, 3);
+        assert_eq!(
+            (from_text.cycle_budget, from_wasm.cycle_budget),
+            (Some(9), None)
+        );
+        assert_eq!(
+            compile_wat_file(&dir.join("renamed.wat")).unwrap().expected,
+            3
+        );
+        let err = compile_wat_file(&dir.join("text.wasm")).unwrap_err();
+        assert!(err.to_string().ends_with("is not a wasm binary"));
+
+        let names = |files: Vec<PathBuf>| -> Vec<String> {
+            files
+                .iter()
+                .map(|p| p.file_name().unwrap().to_string_lossy().to_string())
+                .collect()
+        };
+        assert_eq!(
+            names(corpus_files(&dir).unwrap()),
+            ["four.wasm", "renamed.wat", "text.wasm", "three.wat"]
+        );
+        assert_eq!(
+            names(wat_corpus_files(&dir).unwrap()),
+            ["renamed.wat", "three.wat"]
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_compiled_program() {
+        let program = CompiledProgram::from_wat(
+            "agg",
+            r#"(module (memory 1) (data (i32.const 0) "\07")
+                (func (export "main") (result i32) i32.const 0 i32.load8_u))"#,
+        )
+        .unwrap();
+        assert_eq!(program.expected, 7);
+        let wasm = compile_wat("(module (func (export \"main\") (result i32) i32.const 7))");
+        assert_eq!(
+            CompiledProgram::from_wasm("agg", &wasm.unwrap())
+                .unwrap()
+                .expected,
+            7
+        );
+
+        let dir = std::env::temp_dir().join(format!("wasm-ic-program-{}", std::process::id()));
+        let written = program.emit_hex(&dir).unwrap();
+        let names: Vec<_> = written
+            .iter()
+            .map(|p| p.file_name().unwrap().to_string_lossy().into_owned())
+            .collect();
+        assert!(names.contains(&"agg.prog.hex".to_string()), "{:?}", names);
+        assert!(
+            names.contains(&"agg.mem_init.hex".to_string()),
+            "{:?}",
+            names
+        );
+        let reloaded = CompiledProgram::from_file(&dir.join("missing.wat"));
+        assert!(matches!(reloaded, Err(Error::Io(_))));
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(program.emit_svh().contains("agg"));
+        assert_eq!(program.to_json()["name"], "agg");
+        assert_eq!(program.to_json()["expected"], 7);
+    }
+
+    #[test]
+    fn test_compile_functions() {
+        let wasm = compile_wat(
+            r#"(module
+                (func $inc (param i32) (result i32) local.get 0 i32.const 1 i32.add)
+                (func (export "seven") (result i32) i32.const 3 i32.const 4 i32.add)
+                (func (export "oops") (result i32) unreachable)
+                (func (export "two-args") (param i32 i32) (result i32) i32.const 2))"#,
+        )
+        .unwrap();
+        let strict = CompileOptions::new().validation(Validation::Strict);
+        let tests = compile_functions("mod", &wasm, &strict).unwrap();
+        let summary: Vec<(u32, &str, bool)> = tests
+            .iter()
+            .map(|t| (t.index, t.name.as_str(), t.golden))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (0, "mod_func0", false),
+                (1, "mod_seven", cfg!(feature = "refexec")),
+                (2, "mod_oops", cfg!(feature = "refexec")),
+                (3, "mod_two_args", false),
+            ]
+        );
+        // `local.get` is not for the core; the rest still compile.
+        assert!(matches!(tests[0].test, Err(Error::UnsupportedOps(_))));
+        let seven = tests[1].test.as_ref().unwrap();
+        assert_eq!(seven.body_bytes, [0x41, 0x03, 0x41, 0x04, 0x6A, 0x0F]);
+        let two = tests[3].test.as_ref().unwrap();
+        assert_eq!((two.expected, two.expect_trap), (0, false));
+        #[cfg(feature = "refexec")]
+        {
+            assert_eq!(seven.expected, 7);
+            assert!(tests[2].test.as_ref().unwrap().expect_trap);
+        }
+
+        // The golden run can't satisfy imports.
+        let wasm = compile_wat(
+            r#"(module (import "env" "f" (func))
+                (func (export "main") (result i32) i32.const 1))"#,
+        )
+        .unwrap();
+        let tests = compile_functions("imp", &wasm, &CompileOptions::new()).unwrap();
+        assert_eq!(
+            (tests.len(), tests[0].index, tests[0].golden),
+            (1, 1, false)
+        );
+        assert!(tests[0].test.is_ok());
+    }
+
+    #[test]
+    fn test_compile_options() {
+        let src = r#"(module (memory 1) (data (i32.const 0) "\05")
+            ;; max-cycles: 500
+            (func (export "main") (result i32) i32.const 1)
+            (func (export "add") (result i32) i32.const 0 i32.load8_u i32.const 2 i32.add))"#;
+        let info = compile(Source::Wat(src), &CompileOptions::new()).unwrap();
+        assert_eq!((info.name.as_str(), info.expected), ("main", 1));
+        assert_eq!(info.cycle_budget, Some(500));
+        assert_eq!(info.body_bytes.last(), Some(&0x0F));
+
+        let options = CompileOptions::new()
+            .name("add")
+            .entry(Entry::Export("add".into()))
+            .rewrite_end(false);
+        let info = compile(Source::Wat(src), &options).unwrap();
+        assert_eq!(info.expected, 7);
+        assert_eq!(info.body_bytes.last(), Some(&0x0B));
+
+        let bare = compile(Source::Wat(src), &options.clone().passes(&[])).unwrap();
+        assert_eq!((bare.expected, bare.cycle_budget), (0, None));
+        assert!(bare.mem_init.is_empty());
+
+        let call = r#"(module (func $f) (func (export "main") (result i32) call $f i32.const 1))"#;
+        assert!(compile(
+            Source::Wat(call),
+            &CompileOptions::new().entry(Entry::Export("main".into()))
+        )
+        .is_ok());
+        let strict = CompileOptions::new()
+            .entry(Entry::Export("main".into()))
+            .validation(Validation::Strict);
+        assert!(matches!(
+            compile(Source::Wat(call), &strict),
+            Err(Error::UnsupportedOps(_))
+        ));
+
+        let dir = std::env::temp_dir().join(format!("wasm-ic-options-{}", std::process::id()));
+        let options = options
+            .out_dir(&dir)
+            .format(ArtifactFormat::Hex)
+            .format(ArtifactFormat::Json);
+        compile(Source::Wat(src), &options).unwrap();
+        assert!(dir.join("add.prog.hex").is_file());
+        assert!(dir.join("add.program.json").is_file());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_round_trip() {
+        let info = compile_wat_source(
+            "rt",
+            r#"(module (memory 1) (data (i32.const 4) "\01")
+                (func (export "main") (result i32)
+                  (block (br_if 0 (i32.const 1)))
+                  i32.const 4 i32.const 9 i32.store i32.const 3))"#,
+        )
+        .unwrap();
+        let text = serde_json::to_string(&info).unwrap();
+        let back: CompiledProgram = serde_json::from_str(&text).unwrap();
+        assert_eq!(back.body_bytes, info.body_bytes);
+        assert_eq!(back.branch_table, info.branch_table);
+        assert_eq!(back.mem_init, info.mem_init);
+        assert_eq!(back.mem_expected, info.mem_expected);
+        assert_eq!((back.name, back.expected), (info.name, 3));
+    }
+
+    #[test]
+    fn test_validate_features() {
+        let wat = r#"(module (func (export "main") (result i32)
+            v128.const i32x4 1 2 3 4 i32x4.extract_lane 0))"#;
+        let source = Source::Wat(wat);
+        assert!(compile(source, &CompileOptions::new().passes(&[])).is_ok());
+        let mvp = wasmparser::WasmFeatures::WASM1;
+        let e = compile(source, &CompileOptions::new().features(mvp)).unwrap_err();
+        assert!(matches!(e, Error::ParseError(_)), "{:?}", e);
+        assert!(e.to_string().starts_with(
+            "SIMD support is not enabled; the module uses the `simd` proposal, which is disabled\n  --> main:2:13"
+        ));
+        let wasm = wat::parse_str(wat).unwrap();
+        let e = compile(Source::Wasm(&wasm), &CompileOptions::new().features(mvp)).unwrap_err();
+        assert!(format!("{:#}", e).starts_with(
+            "validating module: the module uses the `simd` proposal, which is disabled: SIMD support"
+        ));
+
+        let features = parse_wasm_features("wasm1, +sign-extension, simd").unwrap();
+        assert_eq!(
+            features,
+            mvp | wasmparser::WasmFeatures::SIGN_EXTENSION | wasmparser::WasmFeatures::SIMD
+        );
+        let features = parse_wasm_features("-simd,-threads").unwrap();
+        assert!(!features.simd() && !features.threads() && features.multi_value());
+        assert_eq!(parse_wasm_features("").unwrap(), Default::default());
+        let e = parse_wasm_features("wasm1,-simdd").unwrap_err();
+        assert!(matches!(e, Error::ParseError(_)), "{:?}", e);
+        assert!(e
+            .to_string()
+            .starts_with("unknown wasm feature `simdd`; expected one of "));
+
+        // A body that decodes but doesn't type-check never reaches the ROM.
+        let e = compile_wat_source("bad", "(module (func (result i32) i64.const 1))").unwrap_err();
+        assert!(matches!(e, Error::ParseError(_)), "{:?}", e);
+    }
+
+    #[test]
+    fn test_embedded_table() {
+        let wasm = compile_wat(
+            r#"(module (func (export "main") (result i32) (block (br 0)) i32.const 1))"#,
+        )
+        .unwrap();
+        let info = compile_wasm_bytes("linked", &wasm).unwrap();
+        let mut tampered = info.clone();
+        tampered.branch_table[0].target_pc = 3;
+        let linked = link_module(&wasm, &tampered, &HardwareSpec::default()).unwrap();
+        let with = |embedded| {
+            let options = CompileOptions::new().embedded(embedded);
+            compile(Source::Wasm(&linked), &options)
+        };
+
+        assert_eq!(
+            with(Embedded::Ignore).unwrap().branch_table,
+            info.branch_table
+        );
+        assert_eq!(
+            with(Embedded::Trust).unwrap().branch_table,
+            tampered.branch_table
+        );
+        let err = format!("{:#}", with(Embedded::Verify).unwrap_err());
+        assert!(err.contains("branch 0x2 -> 0x3: embedded only"), "{}", err);
+        assert!(err.contains("branch 0x2 -> 0x5: computed only"), "{}", err);
+
+        // An honest link verifies, and an unlinked module just compiles.
+        let linked = link_module(&wasm, &info, &HardwareSpec::default()).unwrap();
+        let options = CompileOptions::new().embedded(Embedded::Verify);
+        assert_eq!(
+            compile(Source::Wasm(&linked), &options)
+                .unwrap()
+                .branch_table,
+            info.branch_table
+        );
+        assert!(compile(Source::Wasm(&wasm), &options).is_ok());
+
+        // A table linked for another build of the program isn't trusted.
+        let options = CompileOptions::new()
+            .embedded(Embedded::Trust)
+            .rewrite_end(false);
+        let err = compile(Source::Wasm(&linked), &options).unwrap_err();
+        assert!(
+            format!("{:#}", err).contains("not this module's"),
+            "{:#}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_cycle_budget() {
+        let source =
+            "(module ;; max-cycles: 40\n (func (export \"main\") (result i32) i32.const 1))";
+        assert_eq!(parse_cycle_budget(source).unwrap(), Some(40));
+        assert_eq!(parse_cycle_budget("(module) ;; adds").unwrap(), None);
+        assert!(parse_cycle_budget(";; max-cycles: lots").is_err());
+
+        let info = compile_wat_source("one", source).unwrap();
+        assert_eq!(info.cycle_budget, Some(40));
+        assert_eq!(SvhOptions::default().cycle_budget(&info), 40);
+    }
+}
@@ -0,0 +1,277 @@
+//! The `wasm-ic.toml` project config and the CLI defaults read from it.
+
+use crate::Error;
+use anyhow::{anyhow, Context, Result};
+use std::fs;
+use std::path::PathBuf;
+
+// ---------------------------------------------------------------------------
+// Project config
+// ---------------------------------------------------------------------------
+
+/// File holding a project's default options, looked for in the current
+/// directory and then each parent.
+pub const PROJECT_CONFIG: &str = "wasm-ic.toml";
+
+/// Default options from a [`PROJECT_CONFIG`], so Makefiles don't repeat
+/// long command lines. Top-level keys apply to every command that takes
+/// an option of that name; a table named after a subcommand (or
+/// `[wasm-ic-run]` for the runner) applies to that command alone and wins
+/// over them:
+///
+/// ```toml
+/// wat-dir = "tests/wat"
+/// skip = ["loop*"]
+///
+/// [gen-tests]
+/// output = "build/wat_tests.svh"
+/// readmemh = true
+/// jobs = 4
+/// ```
+///
+/// Keys are long option names, with `_` or `-`. Flags take booleans,
+/// repeatable options arrays, and relative paths are taken from the
+/// config's directory. Options given on the command line override the
+/// config, or for repeatable ones add to it.
+#[derive(Debug, Clone)]
+pub struct ProjectConfig {
+    pub path: PathBuf,
+    table: toml::Table,
+}
+
+impl ProjectConfig {
+    pub fn load(path: &std::path::Path) -> Result<Self, Error> {
+        let text =
+            fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+        Self::parse(path, &text)
+    }
+
+    /// Parse config `text` read from `path`.
+    pub fn parse(path: &std::path::Path, text: &str) -> Result<Self, Error> {
+        let table: toml::Table = text
+            .parse()
+            .with_context(|| format!("parsing {}", path.display()))?;
+        Ok(ProjectConfig {
+            path: path.to_path_buf(),
+            table,
+        })
+    }
+
+    /// The arguments the config gives the command `cmd`: top-level keys
+    /// `cmd` has an option for, then every key of its own table, which
+    /// must all be options of it.
+    pub fn args_for(&self, cmd: &clap::Command) -> Result<Vec<std::ffi::OsString>, Error> {
+        let mut values = std::collections::BTreeMap::new();
+        for (key, value) in &self.table {
+            if !value.is_table() && find_long(cmd, key).is_some() {
+                values.insert(key.replace('_', "-"), value);
+            }
+        }
+        if let Some(section) = self.table.get(cmd.get_name()) {
+            let section = section.as_table().ok_or_else(|| {
+                anyhow!(
+                    "{}: `{}` must be a table of options",
+                    self.path.display(),
+                    cmd.get_name()
+                )
+            })?;
+            for (key, value) in section {
+                if find_long(cmd, key).is_none() {
+                    return Err(Error::ParseError(anyhow!(
+                        "{}: [{}] has no option --{}",
+                        self.path.display(),
+                        cmd.get_name(),
+                        key.replace('_', "-")
+                    )));
+                }
+                values.insert(key.replace('_', "-"), value);
+            }
+        }
+        let base = self.path.parent().unwrap_or(std::path::Path::new("."));
+        let mut args = Vec::new();
+        for (key, value) in values {
+            let arg = find_long(cmd, &key).expect("only options of cmd are kept");
+            let flag = format!("--{}", key);
+            let bad = |expected: &str| {
+                Error::ParseError(anyhow!(
+                    "{}: {} for --{} in [{}] must be {}",
+                    self.path.display(),
+                    value,
+                    key,
+                    cmd.get_name(),
+                    expected
+                ))
+            };
+            let items = match (arg.get_action(), value) {
+                (clap::ArgAction::SetTrue, toml::Value::Boolean(set)) => {
+                    if *set {
+                        args.push(flag.into());
+                    }
+                    continue;
+                }
+                (clap::ArgAction::SetTrue, _) => return Err(bad("true or false")),
+                (clap::ArgAction::Append, toml::Value::Array(items)) => items.iter().collect(),
+                (_, toml::Value::Array(_) | toml::Value::Table(_) | toml::Value::Boolean(_)) => {
+                    return Err(bad("a string or number"))
+                }
+                _ => vec![value],
+            };
+            for item in items {
+                let text = match item {
+                    toml::Value::String(s) => s.clone(),
+                    toml::Value::Integer(_) | toml::Value::Float(_) => item.to_string(),
+                    _ => return Err(bad("strings or numbers")),
+                };
+                let value = if arg.get_value_hint() == clap::ValueHint::AnyPath && text != "-" {
+                    base.join(&text).into_os_string()
+                } else {
+                    text.into()
+                };
+                args.push(flag.clone().into());
+                args.push(value);
+            }
+        }
+        Ok(args)
+    }
+
+    /// `args` (a whole command line, program name first) with the
+    /// config's arguments put right after the subcommand, or the program
+    /// name for a command without subcommands, so the command line's own
+    /// come later and override them.
+    pub fn insert_args(
+        &self,
+        cmd: &clap::Command,
+        mut args: Vec<std::ffi::OsString>,
+    ) -> Result<Vec<std::ffi::OsString>, Error> {
+        let (at, target) = if cmd.has_subcommands() {
+            let found = args.iter().enumerate().skip(1).find_map(|(i, a)| {
+                let sub = cmd.find_subcommand(a)?;
+                (args[i - 1] != "--config").then_some((i, sub))
+            });
+            match found {
+                Some(found) => found,
+                // No subcommand: clap reports that itself.
+                None => return Ok(args),
+            }
+        } else {
+            (0, cmd)
+        };
+        let extra = self.args_for(target)?;
+        args.splice(at + 1..at + 1, extra);
+        Ok(args)
+    }
+}
+
+/// The option of `cmd` whose long name is `key`, spelled with `_` or `-`.
+fn find_long<'a>(cmd: &'a clap::Command, key: &str) -> Option<&'a clap::Arg> {
+    let key = key.replace('_', "-");
+    cmd.get_arguments()
+        .find(|a| a.get_long() == Some(key.as_str()))
+}
+
+/// The nearest [`PROJECT_CONFIG`] in `start` or one of its parents.
+pub fn find_project_config(start: &std::path::Path) -> Option<PathBuf> {
+    start
+        .ancestors()
+        .map(|dir| dir.join(PROJECT_CONFIG))
+        .find(|path| path.is_file())
+}
+
+/// Parse the process's command line as `P`, with defaults from the
+/// project config: the one named by `--config`, or else the nearest
+/// [`PROJECT_CONFIG`] above the current directory unless `--no-config` is
+/// given. `P` should declare both as global options so they show in help.
+pub fn parse_with_project_config<P: clap::Parser>() -> Result<P> {
+    let mut args: Vec<std::ffi::OsString> = std::env::args_os().collect();
+    let cmd = P::command().args_override_self(true);
+    let named =
+        args.iter()
+            .enumerate()
+            .find_map(|(i, a)| match a.to_str()?.strip_prefix("--config") {
+                Some("") => args.get(i + 1).map(PathBuf::from),
+                Some(path) => path.strip_prefix('=').map(PathBuf::from),
+                None => None,
+            });
+    let config = match named {
+        Some(path) => Some(path),
+        None if args.iter().any(|a| a == "--no-config") => None,
+        None => find_project_config(&std::env::current_dir()?),
+    };
+    if let Some(path) = config {
+        args = ProjectConfig::load(&path)?.insert_args(&cmd, args)?;
+    }
+    let matches = cmd.get_matches_from(args);
+    Ok(P::from_arg_matches(&matches).unwrap_or_else(|e| e.exit()))
+}
+
+#[cfg(all(test, feature = "refexec"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_project_config() {
+        use clap::{Arg, ArgAction, Command};
+        let cmd = Command::new("wasm-compile")
+            .subcommand(
+                Command::new("gen-tests")
+                    .arg(
+                        Arg::new("wat_dir")
+                            .long("wat-dir")
+                            .value_parser(clap::value_parser!(PathBuf)),
+                    )
+                    .arg(Arg::new("skip").long("skip").action(ArgAction::Append))
+                    .arg(
+                        Arg::new("readmemh")
+                            .long("readmemh")
+                            .action(ArgAction::SetTrue),
+                    )
+                    .arg(Arg::new("jobs").long("jobs")),
+            )
+            .subcommand(Command::new("stats"));
+        let path = std::path::Path::new("proj").join(PROJECT_CONFIG);
+        let config = ProjectConfig::parse(
+            &path,
+            "wat_dir = \"wat\"\nskip = [\"loop*\"]\n\n[gen-tests]\nreadmemh = true\njobs = 4\n",
+        )
+        .unwrap();
+        let args = |line: &[&str]| -> Vec<String> {
+            let line = line.iter().map(Into::into).collect();
+            config
+                .insert_args(&cmd, line)
+                .unwrap()
+                .iter()
+                .map(|a| a.to_string_lossy().replace('\\', "/"))
+                .collect()
+        };
+        assert_eq!(
+            args(&["wasm-compile", "gen-tests", "--jobs", "1"]),
+            [
+                "wasm-compile",
+                "gen-tests",
+                "--jobs",
+                "4",
+                "--readmemh",
+                "--skip",
+                "loop*",
+                "--wat-dir",
+                "proj/wat",
+                "--jobs",
+                "1"
+            ]
+        );
+        assert_eq!(
+            args(&["wasm-compile", "stats", "x"]),
+            ["wasm-compile", "stats", "x"]
+        );
+
+        let bad = ProjectConfig::parse(&path, "[gen-tests]\nfoo = 1\n").unwrap();
+        assert!(bad
+            .args_for(cmd.find_subcommand("gen-tests").unwrap())
+            .is_err());
+        let bad = ProjectConfig::parse(&path, "[gen-tests]\nreadmemh = \"yes\"\n").unwrap();
+        assert!(bad
+            .args_for(cmd.find_subcommand("gen-tests").unwrap())
+            .is_err());
+        assert!(ProjectConfig::parse(&path, "wat_dir = ").is_err());
+    }
+}
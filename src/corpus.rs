@@ -0,0 +1,685 @@
+//! Finding the test files of a corpus, its skip list and manifest, and
+//! the content-hash cache of their compile results.
+
+use crate::emit::ChecksumAlgo;
+use crate::{
+    compile_wast_source, compile_wat_file, BranchEntry, CompiledProgram, Error, MemCheck,
+    MemSegment,
+};
+use anyhow::{anyhow, Context, Result};
+use std::fs;
+use std::path::PathBuf;
+
+// ---------------------------------------------------------------------------
+// Corpus files and the compile cache
+// ---------------------------------------------------------------------------
+
+/// File in a corpus directory listing the tests to leave out, one glob
+/// per line, with `#` comments. The corpus decides what it skips (e.g.
+/// tests needing opcodes the hardware lacks) without a crate rebuild.
+pub const SKIP_LIST: &str = "skip.txt";
+
+/// Which corpus files to take, by globs (`*` and `?`) on the file stem.
+/// A directory's [`SKIP_LIST`] always applies on top.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CorpusFilter {
+    /// Leave out files matching any of these.
+    pub skip: Vec<String>,
+    /// If not empty, take only files matching one of these.
+    pub only: Vec<String>,
+}
+
+impl CorpusFilter {
+    /// Add the globs of a skip-list file to [`CorpusFilter::skip`].
+    pub fn add_skip_list(&mut self, path: &std::path::Path) -> Result<(), Error> {
+        let text =
+            fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+        self.skip.extend(
+            text.lines()
+                .map(|l| l.split('#').next().unwrap_or_default().trim())
+                .filter(|l| !l.is_empty())
+                .map(String::from),
+        );
+        Ok(())
+    }
+
+    /// Whether a file with this stem is taken.
+    pub fn accepts(&self, stem: &str) -> bool {
+        !self.skip.iter().any(|g| glob_match(g, stem))
+            && (self.only.is_empty() || self.only.iter().any(|g| glob_match(g, stem)))
+    }
+}
+
+/// Match `text` against a glob where `*` is any run of characters and
+/// `?` any one character.
+pub fn glob_match(glob: &str, text: &str) -> bool {
+    let (glob, text): (Vec<char>, Vec<char>) = (glob.chars().collect(), text.chars().collect());
+    let (mut g, mut t) = (0, 0);
+    // Where the last `*` was and the text position it matched up to.
+    let mut star = None;
+    while t < text.len() {
+        match glob.get(g) {
+            Some('*') => {
+                star = Some((g, t));
+                g += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                g += 1;
+                t += 1;
+            }
+            _ => match star {
+                Some((sg, st)) => {
+                    g = sg + 1;
+                    t = st + 1;
+                    star = Some((sg, st + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    glob[g..].iter().all(|&c| c == '*')
+}
+
+/// The `.wat` files in `wat_dir` not left out by its [`SKIP_LIST`], in
+/// file name order.
+pub fn wat_corpus_files(wat_dir: &PathBuf) -> Result<Vec<PathBuf>, Error> {
+    Ok(corpus_files_with(
+        wat_dir,
+        &["wat"],
+        &CorpusFilter::default(),
+    )?)
+}
+
+/// [`wat_corpus_files`] plus precompiled `.wasm` files and `.wast`
+/// scripts, for the tools that compile the corpus (see
+/// [`compile_corpus_file`]) rather than embed its source. A `.wasm` file
+/// with a `.wat` of the same name beside it is taken to be built from it
+/// and left out.
+pub fn corpus_files(wat_dir: &PathBuf) -> Result<Vec<PathBuf>, Error> {
+    corpus_files_filtered(wat_dir, &CorpusFilter::default())
+}
+
+/// [`corpus_files`] narrowed further by `filter`.
+pub fn corpus_files_filtered(
+    wat_dir: &PathBuf,
+    filter: &CorpusFilter,
+) -> Result<Vec<PathBuf>, Error> {
+    let mut files = corpus_files_with(wat_dir, &["wat", "wasm", "wast"], filter)?;
+    let wat_stems: Vec<PathBuf> = files
+        .iter()
+        .filter(|p| p.extension().is_some_and(|ext| ext == "wat"))
+        .map(|p| p.with_extension(""))
+        .collect();
+    files.retain(|p| {
+        p.extension().is_none_or(|ext| ext != "wasm") || !wat_stems.contains(&p.with_extension(""))
+    });
+    Ok(files)
+}
+
+/// [`corpus_files_filtered`] of `wat_dir` and every directory under it,
+/// a directory's own files before its subdirectories', in name order.
+/// `filter` applies everywhere; each directory's [`SKIP_LIST`] applies to
+/// its own files. Hidden directories are left out. See
+/// [`corpus_category`] for how the files are grouped.
+pub fn corpus_files_recursive(
+    wat_dir: &PathBuf,
+    filter: &CorpusFilter,
+) -> Result<Vec<PathBuf>, Error> {
+    let mut files = corpus_files_filtered(wat_dir, filter)?;
+    let mut dirs: Vec<PathBuf> = fs::read_dir(wat_dir)
+        .with_context(|| format!("reading directory {}", wat_dir.display()))?
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| p.is_dir())
+        .filter(|p| {
+            !p.file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .starts_with('.')
+        })
+        .collect();
+    dirs.sort();
+    for dir in dirs {
+        files.extend(corpus_files_recursive(&dir, filter)?);
+    }
+    Ok(files)
+}
+
+/// The category of a corpus file from [`corpus_files_recursive`]: the
+/// directory it is in relative to `wat_dir`, levels joined with `/`, e.g.
+/// `control/loops`. `None` for a file directly in `wat_dir`.
+pub fn corpus_category(wat_dir: &std::path::Path, file: &std::path::Path) -> Option<String> {
+    let dir = file.parent()?.strip_prefix(wat_dir).ok()?;
+    let levels: Vec<String> = dir
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy().into_owned())
+        .collect();
+    (!levels.is_empty()).then(|| levels.join("/"))
+}
+
+/// File `gen-tests` writes the [`CorpusManifest`] of the corpus to,
+/// beside its output.
+pub const CORPUS_MANIFEST: &str = "manifest.toml";
+
+/// One test's entry in a [`CorpusManifest`]. Every option is optional;
+/// one left out keeps what the source and the golden run give.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ManifestTest {
+    pub name: String,
+    /// Corpus file the test is compiled from, for the reader's benefit.
+    pub source: Option<String>,
+    /// Overrides the category of the directory the file is in.
+    pub category: Option<String>,
+    /// Cycle budget, overriding a `;; max-cycles:` line in the source.
+    pub max_cycles: Option<u32>,
+    /// Whether the core must trap, overriding the golden run.
+    pub expect_trap: Option<bool>,
+    /// Leave the test out, for this reason.
+    pub skip: Option<String>,
+}
+
+impl ManifestTest {
+    /// Apply the cycle budget and trap expectation to `info`.
+    pub fn apply(&self, info: &mut CompiledProgram) {
+        if let Some(budget) = self.max_cycles {
+            info.cycle_budget = Some(budget);
+        }
+        if let Some(trap) = self.expect_trap {
+            if !trap {
+                info.trap_kind = None;
+            }
+            info.expect_trap = trap;
+        }
+    }
+}
+
+/// The layout of a corpus and its per-test options, as TOML, so tools
+/// outside the crate can read it and per-test configuration can live in
+/// data rather than in the sources:
+///
+/// ```toml
+/// [[test]]
+/// name = "div_zero"
+/// source = "tests/wat/arith/div_zero.wat"
+/// category = "arith"
+/// max_cycles = 200
+/// expect_trap = true
+/// skip = "needs i64"
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CorpusManifest {
+    pub tests: Vec<ManifestTest>,
+}
+
+impl CorpusManifest {
+    /// Parse a manifest. Unknown keys are an error, as for
+    /// [`HardwareSpec::from_toml`](crate::HardwareSpec::from_toml); so are `args`, as the core cannot be
+    /// passed arguments.
+    pub fn from_toml(text: &str) -> Result<Self, Error> {
+        let table: toml::Table = text.parse()?;
+        let mut manifest = CorpusManifest::default();
+        for (key, value) in &table {
+            if key != "test" {
+                return Err(Error::ParseError(anyhow!("unknown manifest key `{}`", key)));
+            }
+            let entries = value
+                .as_array()
+                .ok_or_else(|| anyhow!("manifest `test` must be an array of tables"))?;
+            for entry in entries {
+                manifest.tests.push(manifest_test(entry)?);
+            }
+        }
+        Ok(manifest)
+    }
+
+    pub fn load(path: &std::path::Path) -> Result<Self, Error> {
+        let text =
+            fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+        Ok(Self::from_toml(&text).with_context(|| format!("parsing {}", path.display()))?)
+    }
+
+    /// The manifest as TOML that [`CorpusManifest::from_toml`] reads back.
+    pub fn to_toml(&self) -> String {
+        let quote = |s: &str| toml::Value::String(s.to_string()).to_string();
+        let mut out = String::new();
+        for test in &self.tests {
+            if !out.is_empty() {
+                out.push('\n');
+            }
+            out.push_str("[[test]]\n");
+            out.push_str(&format!("name = {}\n", quote(&test.name)));
+            if let Some(source) = &test.source {
+                out.push_str(&format!("source = {}\n", quote(source)));
+            }
+            if let Some(category) = &test.category {
+                out.push_str(&format!("category = {}\n", quote(category)));
+            }
+            if let Some(budget) = test.max_cycles {
+                out.push_str(&format!("max_cycles = {}\n", budget));
+            }
+            if let Some(trap) = test.expect_trap {
+                out.push_str(&format!("expect_trap = {}\n", trap));
+            }
+            if let Some(reason) = &test.skip {
+                out.push_str(&format!("skip = {}\n", quote(reason)));
+            }
+        }
+        out
+    }
+
+    /// The entry for the test `name`, if any.
+    pub fn test(&self, name: &str) -> Option<&ManifestTest> {
+        self.tests.iter().find(|t| t.name == name)
+    }
+}
+
+fn manifest_test(entry: &toml::Value) -> Result<ManifestTest> {
+    let table = entry
+        .as_table()
+        .ok_or_else(|| anyhow!("manifest `test` entries must be tables"))?;
+    let name = table
+        .get("name")
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| anyhow!("manifest test without a `name`"))?;
+    let mut test = ManifestTest {
+        name: name.to_string(),
+        ..Default::default()
+    };
+    let string = |key: &str, value: &toml::Value| {
+        value
+            .as_str()
+            .map(str::to_string)
+            .ok_or_else(|| anyhow!("test `{}`: `{}` must be a string", name, key))
+    };
+    for (key, value) in table {
+        match key.as_str() {
+            "name" => {}
+            "source" => test.source = Some(string(key, value)?),
+            "category" => test.category = Some(string(key, value)?),
+            "skip" => test.skip = Some(string(key, value)?),
+            "max_cycles" => {
+                test.max_cycles = Some(
+                    value
+                        .as_integer()
+                        .and_then(|v| u32::try_from(v).ok())
+                        .ok_or_else(|| {
+                            anyhow!("test `{}`: `max_cycles` must be a cycle count", name)
+                        })?,
+                )
+            }
+            "expect_trap" => {
+                test.expect_trap = Some(value.as_bool().ok_or_else(|| {
+                    anyhow!("test `{}`: `expect_trap` must be true or false", name)
+                })?)
+            }
+            "args" => return Err(anyhow!("test `{}`: the core cannot be passed `args`", name)),
+            _ => return Err(anyhow!("test `{}`: unknown manifest key `{}`", name, key)),
+        }
+    }
+    Ok(test)
+}
+
+fn corpus_files_with(
+    wat_dir: &PathBuf,
+    extensions: &[&str],
+    filter: &CorpusFilter,
+) -> Result<Vec<PathBuf>> {
+    let mut filter = filter.clone();
+    let skip_list = wat_dir.join(SKIP_LIST);
+    if skip_list.is_file() {
+        filter.add_skip_list(&skip_list)?;
+    }
+    let mut wat_files: Vec<PathBuf> = fs::read_dir(wat_dir)
+        .with_context(|| format!("reading directory {}", wat_dir.display()))?
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| {
+            p.extension()
+                .is_some_and(|ext| extensions.iter().any(|e| ext == *e))
+        })
+        .filter(|p| filter.accepts(&p.file_stem().unwrap_or_default().to_string_lossy()))
+        .collect();
+    wat_files.sort();
+    Ok(wat_files)
+}
+
+/// Compile a corpus file: every check of a `.wast` script, or the single
+/// test of a WAT or wasm file.
+pub fn compile_corpus_file(path: &PathBuf) -> Result<Vec<CompiledProgram>, Error> {
+    if path.extension().is_none_or(|ext| ext != "wast") {
+        return Ok(vec![compile_wat_file(path)?]);
+    }
+    let name = path.file_stem().unwrap_or_default().to_string_lossy();
+    let source = fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+    Ok(compile_wast_source(&name, &source)
+        .with_context(|| format!("compiling script {}", path.display()))?)
+}
+
+/// [`compile_corpus_file`] through a cache in `cache_dir`, so an
+/// unchanged file isn't compiled and run again. Entries are keyed by the
+/// file's name and contents and the tool version; failures aren't cached.
+/// Returns whether the result came from the cache.
+pub fn compile_corpus_file_cached(
+    path: &PathBuf,
+    cache_dir: &std::path::Path,
+) -> Result<(Vec<CompiledProgram>, bool), Error> {
+    let source = fs::read(path).with_context(|| format!("reading {}", path.display()))?;
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    let mut keyed = format!(
+        "wasm-compile {}\0{}\0",
+        env!("CARGO_PKG_VERSION"),
+        file_name
+    )
+    .into_bytes();
+    keyed.extend_from_slice(&source);
+    let entry = cache_dir.join(format!(
+        "{}-{:08x}{:08x}.json",
+        path.file_stem().unwrap_or_default().to_string_lossy(),
+        ChecksumAlgo::Crc32.checksum(&keyed),
+        ChecksumAlgo::Crc32c.checksum(&keyed)
+    ));
+    // An unreadable entry, e.g. from an interrupted write, is a miss.
+    let cached = fs::read_to_string(&entry)
+        .ok()
+        .and_then(|text| serde_json::from_str::<serde_json::Value>(&text).ok())
+        .filter(|json| json["source_len"] == source.len())
+        .and_then(|json| {
+            json["tests"]
+                .as_array()?
+                .iter()
+                .map(test_info_from_json)
+                .collect::<Result<Vec<_>>>()
+                .ok()
+        });
+    if let Some(tests) = cached {
+        log::debug!("{}: reusing {}", path.display(), entry.display());
+        return Ok((tests, true));
+    }
+
+    let tests = compile_corpus_file(path)?;
+    let json = serde_json::json!({
+        "source_len": source.len(),
+        "tests": tests.iter().map(test_info_json).collect::<Vec<_>>(),
+    });
+    fs::create_dir_all(cache_dir).with_context(|| format!("creating {}", cache_dir.display()))?;
+    fs::write(&entry, json.to_string()).with_context(|| format!("writing {}", entry.display()))?;
+    Ok((tests, false))
+}
+
+/// Every field of a [`CompiledProgram`], for [`test_info_from_json`] to read
+/// back.
+pub(crate) fn test_info_json(info: &CompiledProgram) -> serde_json::Value {
+    serde_json::json!({
+        "name": info.name,
+        "body_bytes": info.body_bytes,
+        "branch_table": info
+            .branch_table
+            .iter()
+            .map(|e| [e.source_pc, e.target_pc])
+            .collect::<Vec<_>>(),
+        "mem_init": info
+            .mem_init
+            .iter()
+            .map(|s| serde_json::json!({ "memory": s.memory, "addr": s.addr, "data": s.data }))
+            .collect::<Vec<_>>(),
+        "expected": info.expected,
+        "mem_expected": info
+            .mem_expected
+            .iter()
+            .map(|c| [c.addr, c.value, c.memory])
+            .collect::<Vec<_>>(),
+        "expect_trap": info.expect_trap,
+        "trap_kind": info.trap_kind,
+        "cycle_budget": info.cycle_budget,
+        "input_hash": info.input_hash,
+    })
+}
+
+pub(crate) fn test_info_from_json(json: &serde_json::Value) -> Result<CompiledProgram> {
+    let bad = || anyhow!("malformed test entry");
+    let u32_at = |v: &serde_json::Value| -> Result<u32> {
+        v.as_u64()
+            .and_then(|n| u32::try_from(n).ok())
+            .ok_or_else(bad)
+    };
+    let array = |key: &str| json[key].as_array().ok_or_else(bad);
+    let bytes = |v: &serde_json::Value| -> Result<Vec<u8>> {
+        v.as_array()
+            .ok_or_else(bad)?
+            .iter()
+            .map(|b| {
+                b.as_u64()
+                    .and_then(|b| u8::try_from(b).ok())
+                    .ok_or_else(bad)
+            })
+            .collect()
+    };
+    let pair =
+        |v: &serde_json::Value| -> Result<(u32, u32)> { Ok((u32_at(&v[0])?, u32_at(&v[1])?)) };
+    // Memory indexes came after the first entries were written; 0 then.
+    let index = |v: &serde_json::Value| -> Result<u32> {
+        match v {
+            serde_json::Value::Null => Ok(0),
+            v => u32_at(v),
+        }
+    };
+    Ok(CompiledProgram {
+        name: json["name"].as_str().ok_or_else(bad)?.to_string(),
+        body_bytes: bytes(&json["body_bytes"])?,
+        branch_table: array("branch_table")?
+            .iter()
+            .map(|e| {
+                let (source_pc, target_pc) = pair(e)?;
+                Ok(BranchEntry {
+                    source_pc,
+                    target_pc,
+                })
+            })
+            .collect::<Result<_>>()?,
+        mem_init: array("mem_init")?
+            .iter()
+            .map(|s| {
+                Ok(MemSegment {
+                    memory: index(&s["memory"])?,
+                    addr: u32_at(&s["addr"])?,
+                    data: bytes(&s["data"])?,
+                })
+            })
+            .collect::<Result<_>>()?,
+        expected: json["expected"]
+            .as_i64()
+            .and_then(|v| i32::try_from(v).ok())
+            .ok_or_else(bad)?,
+        mem_expected: array("mem_expected")?
+            .iter()
+            .map(|c| {
+                let (addr, value) = pair(c)?;
+                Ok(MemCheck {
+                    memory: index(&c[2])?,
+                    addr,
+                    value,
+                })
+            })
+            .collect::<Result<_>>()?,
+        expect_trap: json["expect_trap"].as_bool().ok_or_else(bad)?,
+        cycle_budget: match &json["cycle_budget"] {
+            serde_json::Value::Null => None,
+            v => Some(u32_at(v)?),
+        },
+        trap_kind: json["trap_kind"].as_str().map(str::to_string),
+        input_hash: match &json["input_hash"] {
+            serde_json::Value::Null => None,
+            v => Some(u32_at(v)?),
+        },
+    })
+}
+
+#[cfg(all(test, feature = "refexec"))]
+mod tests {
+    use super::*;
+    use crate::{compile_wat_source, generate_svh_with, SvhOptions};
+
+    #[test]
+    fn test_compile_cache() {
+        let root = std::env::temp_dir().join(format!("wasm-ic-cache-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        let wat = root.join("mem.wat");
+        let source = r#";; max-cycles: 90
+(module (memory (export "memory") 1) (data (i32.const 4) "\07")
+  (func (export "main") (result i32)
+    (block (br 0)) i32.const 0 i32.const 9 i32.store i32.const 4 i32.load))"#;
+        fs::write(&wat, source).unwrap();
+        let cache = root.join("cache");
+
+        let (fresh, hit) = compile_corpus_file_cached(&wat, &cache).unwrap();
+        assert!(!hit);
+        let (cached, hit) = compile_corpus_file_cached(&wat, &cache).unwrap();
+        assert!(hit);
+        assert_eq!(format!("{:?}", cached), format!("{:?}", fresh));
+        assert_eq!(cached[0].cycle_budget, Some(90));
+
+        fs::write(&wat, source.replace("i32.const 9", "i32.const 8")).unwrap();
+        let (changed, hit) = compile_corpus_file_cached(&wat, &cache).unwrap();
+        assert!(!hit);
+        assert_eq!(
+            changed[0].mem_expected,
+            [MemCheck {
+                memory: 0,
+                addr: 0,
+                value: 8
+            }]
+        );
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_corpus_filter() {
+        assert!(glob_match("loop*", "loop_nested"));
+        assert!(glob_match("*_s?", "div_s8"));
+        assert!(glob_match("a*b*c", "axxbyyc"));
+        assert!(!glob_match("a*b", "abc"));
+        assert!(!glob_match("loop", "loops"));
+
+        let dir = std::env::temp_dir().join(format!("wasm-ic-filter-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        for name in ["add.wat", "add_big.wat", "loop.wat", "mem.wast"] {
+            fs::write(dir.join(name), "").unwrap();
+        }
+        fs::write(dir.join(SKIP_LIST), "# needs locals\nloop\n\n").unwrap();
+        let names = |filter: &CorpusFilter| -> Vec<String> {
+            corpus_files_filtered(&dir, filter)
+                .unwrap()
+                .iter()
+                .map(|p| p.file_name().unwrap().to_string_lossy().to_string())
+                .collect()
+        };
+        assert_eq!(
+            names(&CorpusFilter::default()),
+            ["add.wat", "add_big.wat", "mem.wast"]
+        );
+        let filter = CorpusFilter {
+            skip: vec!["*big".to_string()],
+            only: vec!["add*".to_string(), "loop".to_string()],
+        };
+        assert_eq!(names(&filter), ["add.wat"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_corpus_manifest() {
+        let manifest = CorpusManifest {
+            tests: vec![
+                ManifestTest {
+                    name: "div_zero".to_string(),
+                    source: Some("wat/arith/div_zero.wat".to_string()),
+                    category: Some("arith".to_string()),
+                    max_cycles: Some(200),
+                    expect_trap: Some(true),
+                    skip: None,
+                },
+                ManifestTest {
+                    name: "wide \"mul\"".to_string(),
+                    skip: Some("needs i64".to_string()),
+                    ..Default::default()
+                },
+            ],
+        };
+        let text = manifest.to_toml();
+        assert!(text.starts_with("[[test]]\nname = \"div_zero\"\n"));
+        assert_eq!(CorpusManifest::from_toml(&text).unwrap(), manifest);
+
+        let mut add = compile_wat_source(
+            "add",
+            r#"(module (func (export "main") (result i32) i32.const 1 i32.const 2 i32.add))"#,
+        )
+        .unwrap();
+        manifest.tests[0].apply(&mut add);
+        assert_eq!((add.cycle_budget, add.expect_trap), (Some(200), true));
+        assert!(manifest.test("add").is_none());
+
+        for bad in [
+            "[[test]]\nname = \"a\"\nargs = [1]\n",
+            "[[test]]\nname = \"a\"\ntimeout = 5\n",
+            "[[test]]\nmax_cycles = 5\n",
+            "[[test]]\nname = \"a\"\nmax_cycles = -1\n",
+            "tests = []\n",
+        ] {
+            assert!(CorpusManifest::from_toml(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_corpus_categories() {
+        let dir = std::env::temp_dir().join(format!("wasm-ic-categories-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        for sub in ["arith", "control/loops", ".git"] {
+            fs::create_dir_all(dir.join(sub)).unwrap();
+        }
+        for file in [
+            "top.wat",
+            "arith/add.wat",
+            "arith/sub.wat",
+            "control/if.wat",
+            "control/loops/count.wat",
+            ".git/stray.wat",
+        ] {
+            fs::write(dir.join(file), "").unwrap();
+        }
+        fs::write(dir.join("arith").join(SKIP_LIST), "sub\n").unwrap();
+
+        let files = corpus_files_recursive(&dir, &CorpusFilter::default()).unwrap();
+        let found: Vec<(String, Option<String>)> = files
+            .iter()
+            .map(|f| {
+                let stem = f.file_stem().unwrap().to_string_lossy().into_owned();
+                (stem, corpus_category(&dir, f))
+            })
+            .collect();
+        let category = |c: &str| Some(c.to_string());
+        assert_eq!(
+            found,
+            [
+                ("top".to_string(), None),
+                ("add".to_string(), category("arith")),
+                ("if".to_string(), category("control")),
+                ("count".to_string(), category("control/loops")),
+            ]
+        );
+        fs::remove_dir_all(&dir).unwrap();
+
+        let add = compile_wat_source(
+            "add",
+            r#"(module (func (export "main") (result i32) i32.const 1 i32.const 2 i32.add))"#,
+        )
+        .unwrap();
+        let mut opts = SvhOptions::default();
+        opts.categories
+            .insert("control/loops".to_string(), vec!["add".to_string()]);
+        let svh = generate_svh_with(&[add], &opts);
+        assert!(svh.contains(
+            "task run_control_loops_tests;\n    if (wat_selected(\"add\")) run_wat_add();\nendtask\n"
+        ));
+    }
+}
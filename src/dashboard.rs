@@ -0,0 +1,279 @@
+//! A live terminal view of a regression run, for `wasm-ic-run --tui`: a
+//! progress gauge with the pass and fail counts so far, a row per test
+//! with its status, cycles, and result, and the failures with their
+//! reasons, a golden trace's first divergence and the samples around it
+//! included. The runner feeds it as files start and finish and draws it
+//! with ratatui; the state itself knows nothing of the simulator.
+
+use crate::SimResult;
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Gauge, Paragraph, Row, Table};
+use ratatui::Frame;
+use std::time::{Duration, Instant};
+
+/// Where a test is in the run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TestStatus {
+    Queued,
+    Running,
+    Passed,
+    Failed,
+}
+
+impl TestStatus {
+    fn label(self) -> &'static str {
+        match self {
+            TestStatus::Queued => "queued",
+            TestStatus::Running => "running",
+            TestStatus::Passed => "pass",
+            TestStatus::Failed => "FAIL",
+        }
+    }
+
+    fn style(self) -> Style {
+        match self {
+            TestStatus::Queued => Style::default().fg(Color::DarkGray),
+            TestStatus::Running => Style::default().fg(Color::Yellow),
+            TestStatus::Passed => Style::default().fg(Color::Green),
+            TestStatus::Failed => Style::default().fg(Color::Red).add_modifier(Modifier::BOLD),
+        }
+    }
+}
+
+/// One row of the table: a corpus file until it has run, then each test
+/// it made (a `.wast` script makes several).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DashboardRow {
+    pub name: String,
+    pub status: TestStatus,
+    pub cycles: u64,
+    pub result: String,
+    /// Why the test failed, line breaks kept; empty otherwise.
+    pub detail: String,
+}
+
+/// The state of a run, one entry per corpus file in run order.
+#[derive(Debug, Clone)]
+pub struct Dashboard {
+    files: Vec<Vec<DashboardRow>>,
+    /// Failed tests by file and row, in the order they finished.
+    failures: Vec<(usize, usize)>,
+    started: Instant,
+    elapsed: Option<Duration>,
+    /// First table row shown; `None` follows the tests as they run.
+    scroll: Option<usize>,
+    stopping: bool,
+}
+
+impl Dashboard {
+    /// A run of the files named `names`, all queued.
+    pub fn new(names: impl IntoIterator<Item = String>) -> Self {
+        Dashboard {
+            files: names
+                .into_iter()
+                .map(|name| {
+                    vec![DashboardRow {
+                        name,
+                        status: TestStatus::Queued,
+                        cycles: 0,
+                        result: String::new(),
+                        detail: String::new(),
+                    }]
+                })
+                .collect(),
+            failures: Vec::new(),
+            started: Instant::now(),
+            elapsed: None,
+            scroll: None,
+            stopping: false,
+        }
+    }
+
+    /// File `index` has been taken by a worker.
+    pub fn start(&mut self, index: usize) {
+        if let Some(rows) = self.files.get_mut(index) {
+            for row in rows {
+                row.status = TestStatus::Running;
+            }
+        }
+    }
+
+    /// File `index` has run, making `results`.
+    pub fn finish(&mut self, index: usize, results: &[SimResult]) {
+        let Some(rows) = self.files.get_mut(index) else {
+            return;
+        };
+        *rows = results
+            .iter()
+            .map(|r| DashboardRow {
+                name: r.name.clone(),
+                status: if r.passed {
+                    TestStatus::Passed
+                } else {
+                    TestStatus::Failed
+                },
+                cycles: r.cycles,
+                result: r.result.clone(),
+                detail: r.detail.clone(),
+            })
+            .collect();
+        for (i, row) in rows.iter().enumerate() {
+            if row.status == TestStatus::Failed {
+                self.failures.push((index, i));
+            }
+        }
+    }
+
+    /// The run is over; the clock stops.
+    pub fn done(&mut self) {
+        self.elapsed.get_or_insert(self.started.elapsed());
+    }
+
+    /// The user asked to stop: no more files will start.
+    pub fn stopping(&mut self) {
+        self.stopping = true;
+    }
+
+    /// Scroll the table by `rows`, leaving follow mode.
+    pub fn scroll_by(&mut self, rows: isize) {
+        let first = self.scroll.unwrap_or_else(|| self.follow_offset(0));
+        let last = self.rows().count().saturating_sub(1);
+        self.scroll = Some(first.saturating_add_signed(rows).min(last));
+    }
+
+    /// Go back to following the running tests.
+    pub fn follow(&mut self) {
+        self.scroll = None;
+    }
+
+    pub fn rows(&self) -> impl Iterator<Item = &DashboardRow> {
+        self.files.iter().flatten()
+    }
+
+    /// Files finished and in all.
+    pub fn progress(&self) -> (usize, usize) {
+        let finished = self
+            .files
+            .iter()
+            .filter(|rows| {
+                rows.iter()
+                    .all(|r| matches!(r.status, TestStatus::Passed | TestStatus::Failed))
+            })
+            .count();
+        (finished, self.files.len())
+    }
+
+    /// Tests passed and failed so far.
+    pub fn counts(&self) -> (usize, usize) {
+        let failed = self.failures.len();
+        let passed = self
+            .rows()
+            .filter(|r| r.status == TestStatus::Passed)
+            .count();
+        (passed, failed)
+    }
+
+    /// The first row shown when following, so that the last test started
+    /// or finished is on screen in a table `height` rows high.
+    fn follow_offset(&self, height: usize) -> usize {
+        let active = self
+            .rows()
+            .enumerate()
+            .filter(|(_, r)| r.status != TestStatus::Queued)
+            .last()
+            .map_or(0, |(i, _)| i + 1);
+        active.saturating_sub(height.max(1))
+    }
+
+    /// Draw the gauge, the test table, and the failures into `frame`.
+    pub fn render(&self, frame: &mut Frame) {
+        let failure_height = if self.failures.is_empty() { 3 } else { 12 };
+        let [gauge_area, table_area, failure_area, help_area] = Layout::vertical([
+            Constraint::Length(3),
+            Constraint::Min(5),
+            Constraint::Length(failure_height),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+
+        let (finished, total) = self.progress();
+        let (passed, failed) = self.counts();
+        let elapsed = self.elapsed.unwrap_or_else(|| self.started.elapsed());
+        let gauge = Gauge::default()
+            .block(Block::bordered().title(" wasm-ic-run "))
+            .gauge_style(Style::default().fg(if failed > 0 { Color::Red } else { Color::Green }))
+            .ratio(finished as f64 / total.max(1) as f64)
+            .label(format!(
+                "{}/{} files   {} passed   {} failed   {:.0?}",
+                finished,
+                total,
+                passed,
+                failed,
+                Duration::from_secs(elapsed.as_secs())
+            ));
+        frame.render_widget(gauge, gauge_area);
+
+        // Borders and the header take three rows.
+        let height = usize::from(table_area.height.saturating_sub(3));
+        let offset = self.scroll.unwrap_or_else(|| self.follow_offset(height));
+        let rows = self.rows().skip(offset).take(height).map(|r| {
+            Row::new(vec![
+                Span::styled(r.status.label(), r.status.style()),
+                Span::raw(r.name.clone()),
+                Span::raw(if r.cycles > 0 {
+                    r.cycles.to_string()
+                } else {
+                    String::new()
+                }),
+                Span::raw(r.result.clone()),
+            ])
+        });
+        let table = Table::new(
+            rows,
+            [
+                Constraint::Length(8),
+                Constraint::Min(24),
+                Constraint::Length(10),
+                Constraint::Length(12),
+            ],
+        )
+        .header(
+            Row::new(vec!["status", "test", "cycles", "result"])
+                .style(Style::default().add_modifier(Modifier::BOLD)),
+        )
+        .block(Block::bordered().title(" tests "));
+        frame.render_widget(table, table_area);
+
+        // Latest first, each reason in full: a divergence's samples
+        // follow its summary line.
+        let mut lines = Vec::new();
+        for &(file, row) in self.failures.iter().rev() {
+            let r = &self.files[file][row];
+            lines.push(Line::from(vec![
+                Span::styled(r.name.clone(), TestStatus::Failed.style()),
+                Span::raw(format!("  {}", r.result)),
+            ]));
+            lines.extend(r.detail.lines().map(|l| Line::raw(format!("  {}", l))));
+        }
+        if lines.is_empty() {
+            lines.push(Line::raw("none yet"));
+        }
+        let failures = Paragraph::new(lines)
+            .block(Block::bordered().title(format!(" failures ({}) ", failed)));
+        frame.render_widget(failures, failure_area);
+
+        let help = if self.elapsed.is_some() {
+            "done: any key to exit"
+        } else if self.stopping {
+            "stopping once the running tests finish"
+        } else {
+            "q: stop after the running tests   up/down/pgup/pgdn: scroll   f: follow"
+        };
+        frame.render_widget(
+            Paragraph::new(help).style(Style::default().fg(Color::DarkGray)),
+            help_area,
+        );
+    }
+}
@@ -0,0 +1,4396 @@
+//! The files a compiled test is written out as: hex, vendor memory
+//! images, raw binary, JSON, C and Rust includes, boot images, ROMs, and
+//! the checks and diffs over them.
+
+use crate::branch::BranchEntry;
+use crate::logging::timed;
+use crate::lower::{lower_program, predecode_table, PredecodeEntry};
+use crate::parse::{
+    disassemble, hex_bytes, memory_image, memory_image_len, opcode_len, Insn, MemSegment,
+};
+use crate::refexec::MemCheck;
+use crate::{compile_wat_file, CompiledProgram, Error, HardwareSpec};
+use anyhow::{anyhow, Context, Result};
+use std::fs;
+use std::path::PathBuf;
+
+// ---------------------------------------------------------------------------
+// Hex file output
+// ---------------------------------------------------------------------------
+
+pub fn write_prog_hex(path: &PathBuf, bytes: &[u8]) -> Result<(), Error> {
+    let mut out = String::new();
+    for (i, b) in bytes.iter().enumerate() {
+        out.push_str(&format!("{:02X}", b));
+        if i + 1 < bytes.len() {
+            out.push('\n');
+        }
+    }
+    out.push('\n');
+    fs::write(path, &out).context("writing prog.hex")?;
+    Ok(())
+}
+
+/// Byte order used when packing program bytes into wider ROM words.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Endian {
+    /// Lowest program address in the least significant byte.
+    #[default]
+    Little,
+    /// Lowest program address in the most significant byte.
+    Big,
+}
+
+/// Pack program bytes into `width`-bit words. A trailing partial word is
+/// zero-padded at the high addresses.
+pub fn pack_words(bytes: &[u8], width: u32, endian: Endian) -> Result<Vec<u64>, Error> {
+    if width == 0 || !width.is_multiple_of(8) || width > 64 {
+        return Err(anyhow!(
+            "data width must be a multiple of 8 between 8 and 64, got {}",
+            width
+        )
+        .into());
+    }
+    let bytes_per_word = (width / 8) as usize;
+    Ok(bytes
+        .chunks(bytes_per_word)
+        .map(|chunk| {
+            chunk.iter().enumerate().fold(0u64, |acc, (i, &b)| {
+                let lane = match endian {
+                    Endian::Little => i,
+                    Endian::Big => bytes_per_word - 1 - i,
+                };
+                acc | (b as u64) << (8 * lane)
+            })
+        })
+        .collect())
+}
+
+/// Render the program as `width`-bit words, one per line. With `annotate`,
+/// each line ends in a `// pc=NN  mnemonic` comment naming the instructions
+/// that start in that word (`$readmemh` skips comments).
+pub(crate) fn prog_hex_text(
+    bytes: &[u8],
+    width: u32,
+    endian: Endian,
+    annotate: bool,
+) -> Result<String> {
+    let insns = if annotate {
+        disassemble(bytes)
+    } else {
+        Vec::new()
+    };
+    annotated_hex_text(bytes, width, endian, &insns)
+}
+
+/// [`prog_hex_text`] commenting each word with `insns`, if there are any.
+fn annotated_hex_text(bytes: &[u8], width: u32, endian: Endian, insns: &[Insn]) -> Result<String> {
+    let words = pack_words(bytes, width, endian)?;
+    let digits = (width / 4) as usize;
+    let bytes_per_word = (width / 8) as usize;
+    let annotate = !insns.is_empty();
+
+    let mut out = String::new();
+    let mut next = insns.iter().peekable();
+    for (i, w) in words.iter().enumerate() {
+        out.push_str(&format!("{:0width$X}", w, width = digits));
+        if annotate {
+            let pc = i * bytes_per_word;
+            let mut names = Vec::new();
+            while let Some(insn) = next.next_if(|insn| insn.offset < pc + bytes_per_word) {
+                names.push(insn.to_string());
+            }
+            out.push_str(&format!("  // pc={}", pc));
+            if !names.is_empty() {
+                out.push_str(&format!("  {}", names.join("; ")));
+            }
+        }
+        out.push('\n');
+    }
+    if words.is_empty() {
+        out.push('\n');
+    }
+    Ok(out)
+}
+
+/// Write the program as `width`-bit words, one per line, for ROMs wider
+/// than a byte. A width of 8 produces the same file as [`write_prog_hex`].
+pub fn write_prog_hex_words(
+    path: &PathBuf,
+    bytes: &[u8],
+    width: u32,
+    endian: Endian,
+) -> Result<(), Error> {
+    let out = prog_hex_text(bytes, width, endian, false)?;
+    fs::write(path, &out).context("writing prog.hex")?;
+    Ok(())
+}
+
+/// Like [`write_prog_hex_words`], with a disassembly comment on every line
+/// to make waveform debugging easier.
+pub fn write_prog_hex_annotated(
+    path: &PathBuf,
+    bytes: &[u8],
+    width: u32,
+    endian: Endian,
+) -> Result<(), Error> {
+    let out = prog_hex_text(bytes, width, endian, true)?;
+    fs::write(path, &out).context("writing prog.hex")?;
+    Ok(())
+}
+
+/// Number base for the branch.hex columns.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Radix {
+    #[default]
+    Hex,
+    Dec,
+}
+
+/// Column layout of branch.hex, for loaders that expect something other
+/// than the default `%08X %08X`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BranchHexLayout {
+    pub radix: Radix,
+    /// Zero-padded field width in digits (0 = no padding).
+    pub width: usize,
+    /// Text between the source and target columns.
+    pub separator: String,
+}
+
+impl Default for BranchHexLayout {
+    fn default() -> Self {
+        BranchHexLayout {
+            radix: Radix::Hex,
+            width: 8,
+            separator: " ".to_string(),
+        }
+    }
+}
+
+pub(crate) fn branch_hex_text(entries: &[BranchEntry], layout: &BranchHexLayout) -> String {
+    let field = |v: u32| match layout.radix {
+        Radix::Hex => format!("{:0width$X}", v, width = layout.width),
+        Radix::Dec => format!("{:0width$}", v, width = layout.width),
+    };
+    let mut out = String::new();
+    for entry in entries {
+        out.push_str(&field(entry.source_pc));
+        out.push_str(&layout.separator);
+        out.push_str(&field(entry.target_pc));
+        out.push('\n');
+    }
+    out
+}
+
+pub fn write_branch_hex(path: &PathBuf, entries: &[BranchEntry]) -> Result<(), Error> {
+    write_branch_hex_with(path, entries, &BranchHexLayout::default())
+}
+
+/// Write branch.hex using a custom column layout.
+pub fn write_branch_hex_with(
+    path: &PathBuf,
+    entries: &[BranchEntry],
+    layout: &BranchHexLayout,
+) -> Result<(), Error> {
+    fs::write(path, branch_hex_text(entries, layout)).context("writing branch.hex")?;
+    Ok(())
+}
+
+/// The pre-decode sideband as predecode.hex reads it: `pc len value` per
+/// line, eight, two, and eight hex digits.
+pub(crate) fn predecode_hex_text(entries: &[PredecodeEntry]) -> String {
+    entries
+        .iter()
+        .map(|e| format!("{:08X} {:02X} {:08X}\n", e.pc, e.len, e.value))
+        .collect()
+}
+
+/// Write the flattened memory image (see [`memory_image`]), one byte per
+/// line, for preloading linear memory with `$readmemh`.
+pub fn write_mem_hex(path: &PathBuf, image: &[u8]) -> Result<(), Error> {
+    fs::write(path, mem_hex_text(image)).context("writing mem_init.hex")?;
+    Ok(())
+}
+
+fn mem_hex_text(image: &[u8]) -> String {
+    let mut out = String::new();
+    for b in image {
+        out.push_str(&format!("{:02X}\n", b));
+    }
+    out
+}
+
+/// One `ADDR VALUE` line per expected memory word, both as 8 hex digits.
+pub(crate) fn mem_expected_hex_text(checks: &[MemCheck]) -> String {
+    checks
+        .iter()
+        .map(|c| format!("{:08X} {:08X}\n", c.addr, c.value))
+        .collect()
+}
+
+pub fn write_expected(path: &PathBuf, value: i32) -> Result<(), Error> {
+    fs::write(path, format!("{}\n", value)).context("writing expected.txt")?;
+    Ok(())
+}
+
+/// [`ExpectedSpec::trap`] when the golden run trapped without saying how.
+pub const ANY_TRAP: &str = "any";
+
+/// How a test must end, as expected.json records it: version 2 of
+/// expected.txt, which holds only the return value. Written beside it
+/// with [`EmitOptions::expected_spec`]:
+///
+/// ```json
+/// {
+///   "version": 2,
+///   "returns": [{ "type": "i32", "value": 7 }],
+///   "trap": null,
+///   "memory": [{ "addr": 16, "value": 42 }],
+///   "cycle_budget": 500
+/// }
+/// ```
+///
+/// The core has no globals, so there is no global state to expect.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExpectedSpec {
+    /// What `return` leaves on the stack, bottom first; the core only
+    /// returns `i32`s. Empty for a trap.
+    pub returns: Vec<i32>,
+    /// The trap the core must end in instead, as the spec tests name it
+    /// (`unreachable`, `integer divide by zero`, ...), or [`ANY_TRAP`].
+    pub trap: Option<String>,
+    /// Data RAM words to check once the program ends.
+    pub memory: Vec<MemCheck>,
+    pub cycle_budget: Option<u32>,
+}
+
+impl ExpectedSpec {
+    pub const VERSION: u64 = 2;
+
+    pub fn of(info: &CompiledProgram) -> Self {
+        let trap = info
+            .expect_trap
+            .then(|| info.trap_kind.as_deref().unwrap_or(ANY_TRAP).to_string());
+        ExpectedSpec {
+            returns: if trap.is_some() {
+                Vec::new()
+            } else {
+                vec![info.expected]
+            },
+            trap,
+            memory: info.mem_expected.clone(),
+            cycle_budget: info.cycle_budget,
+        }
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "version": Self::VERSION,
+            "returns": self
+                .returns
+                .iter()
+                .map(|v| serde_json::json!({ "type": "i32", "value": v }))
+                .collect::<Vec<_>>(),
+            "trap": self.trap,
+            "memory": self
+                .memory
+                .iter()
+                .map(|c| serde_json::json!({ "memory": c.memory, "addr": c.addr, "value": c.value }))
+                .collect::<Vec<_>>(),
+            "cycle_budget": self.cycle_budget,
+        })
+    }
+
+    /// Read a spec back. Keys other than the ones above and `metadata`
+    /// are an error, as is any other version.
+    pub fn from_json(json: &serde_json::Value) -> Result<Self, Error> {
+        let object = json
+            .as_object()
+            .ok_or_else(|| anyhow!("the expected spec must be an object"))?;
+        if json["version"].as_u64() != Some(Self::VERSION) {
+            return Err(anyhow!(
+                "expected spec version {} is not {}",
+                json["version"],
+                Self::VERSION
+            )
+            .into());
+        }
+        let u32_at = |v: &serde_json::Value, what: &str| {
+            v.as_u64()
+                .and_then(|v| u32::try_from(v).ok())
+                .ok_or_else(|| anyhow!("bad {} `{}`", what, v))
+        };
+        let mut spec = ExpectedSpec::default();
+        for (key, value) in object {
+            match key.as_str() {
+                "version" | "metadata" => {}
+                "returns" => {
+                    for ret in value.as_array().into_iter().flatten() {
+                        if ret["type"] != "i32" {
+                            return Err(anyhow!(
+                                "return type {} is not i32, the only one the core has",
+                                ret["type"]
+                            )
+                            .into());
+                        }
+                        spec.returns.push(
+                            ret["value"]
+                                .as_i64()
+                                .and_then(|v| i32::try_from(v).ok())
+                                .ok_or_else(|| anyhow!("bad return value {}", ret["value"]))?,
+                        );
+                    }
+                }
+                "trap" => spec.trap = value.as_str().map(str::to_string),
+                "memory" => {
+                    for check in value.as_array().into_iter().flatten() {
+                        spec.memory.push(MemCheck {
+                            memory: match &check["memory"] {
+                                serde_json::Value::Null => 0,
+                                index => u32_at(index, "memory index")?,
+                            },
+                            addr: u32_at(&check["addr"], "address")?,
+                            value: u32_at(&check["value"], "memory value")?,
+                        });
+                    }
+                }
+                "cycle_budget" if value.is_null() => {}
+                "cycle_budget" => spec.cycle_budget = Some(u32_at(value, "cycle budget")?),
+                _ => return Err(anyhow!("unknown expected spec key `{}`", key).into()),
+            }
+        }
+        if spec.trap.is_some() && !spec.returns.is_empty() {
+            return Err(anyhow!("an expected spec can't both trap and return").into());
+        }
+        Ok(spec)
+    }
+
+    /// Give `info` the outcome this spec expects.
+    pub fn apply(&self, info: &mut CompiledProgram) {
+        info.expect_trap = self.trap.is_some();
+        info.trap_kind = self.trap.clone().filter(|kind| kind != ANY_TRAP);
+        if let Some(&value) = self.returns.last() {
+            info.expected = value;
+        }
+        info.mem_expected = self.memory.clone();
+        info.cycle_budget = self.cycle_budget;
+    }
+}
+
+/// Read an expected.json back into its [`ExpectedSpec`].
+pub fn parse_expected_spec(text: &str) -> Result<ExpectedSpec, Error> {
+    let json: serde_json::Value = serde_json::from_str(text).map_err(anyhow::Error::from)?;
+    ExpectedSpec::from_json(&json)
+}
+
+// ---------------------------------------------------------------------------
+// Memory initialization images (FPGA vendor formats)
+// ---------------------------------------------------------------------------
+
+/// Width in bits of one branch table image word: valid bit + 32-bit target.
+pub const BRANCH_WORD_BITS: u32 = 33;
+
+/// Lay the branch table out the way the hardware RAM stores it.
+///
+/// The table is direct-mapped by the low bits of the source PC, so the image
+/// has `depth` words ([`HardwareSpec::branch_slots`]). Each word holds the
+/// target PC in bits [31:0] and the valid flag in bit 32. Unused slots are
+/// zero (invalid). Two branches that map to the same slot can't both be
+/// stored, so they fail with [`Error::CapacityExceeded`] rather than one
+/// silently replacing the other.
+pub fn branch_table_image(entries: &[BranchEntry], depth: usize) -> Result<Vec<u64>, Error> {
+    let mut image = vec![0u64; depth];
+    let mut sources: Vec<Option<u32>> = vec![None; depth];
+    for entry in entries {
+        let idx = (entry.source_pc as usize)
+            .checked_rem(depth)
+            .ok_or_else(|| {
+                Error::CapacityExceeded(anyhow!(
+                    "branch at pc {:#06X} but the branch table has no slots",
+                    entry.source_pc
+                ))
+            })?;
+        if let Some(other) = sources[idx].replace(entry.source_pc) {
+            return Err(Error::CapacityExceeded(anyhow!(
+                "branches at pc {:#06X} and {:#06X} share slot {} of the {}-slot branch table",
+                other,
+                entry.source_pc,
+                idx,
+                depth
+            )));
+        }
+        image[idx] = (1u64 << 32) | entry.target_pc as u64;
+    }
+    Ok(image)
+}
+
+/// Render words as a Xilinx COE file (hex radix, one word per line).
+pub(crate) fn coe_text(words: &[u64], bits: u32) -> String {
+    let digits = bits.div_ceil(4) as usize;
+    let mut out = String::new();
+    out.push_str("; Auto-generated by wasm-compile. Do not edit.\n");
+    out.push_str("memory_initialization_radix=16;\n");
+    out.push_str("memory_initialization_vector=\n");
+    for (i, w) in words.iter().enumerate() {
+        let sep = if i + 1 < words.len() { ',' } else { ';' };
+        out.push_str(&format!("{:0width$X}{}\n", w, sep, width = digits));
+    }
+    if words.is_empty() {
+        out.push_str(";\n");
+    }
+    out
+}
+
+/// Write the program bytes as a COE file for a Vivado block memory generator.
+pub fn write_prog_coe(path: &PathBuf, bytes: &[u8]) -> Result<(), Error> {
+    let words: Vec<u64> = bytes.iter().map(|&b| b as u64).collect();
+    fs::write(path, coe_text(&words, 8)).context("writing prog.coe")?;
+    Ok(())
+}
+
+/// Write the direct-mapped branch table image (see [`branch_table_image`])
+/// as a COE file.
+pub fn write_branch_coe(
+    path: &PathBuf,
+    entries: &[BranchEntry],
+    depth: usize,
+) -> Result<(), Error> {
+    let words = branch_table_image(entries, depth)?;
+    fs::write(path, coe_text(&words, BRANCH_WORD_BITS)).context("writing branch.coe")?;
+    Ok(())
+}
+
+/// Render words as an Altera/Intel MIF file. Addresses past the end of
+/// `words` up to `depth` are filled with zero.
+pub(crate) fn mif_text(words: &[u64], width: u32, depth: usize) -> Result<String> {
+    if words.len() > depth {
+        return Err(anyhow!(
+            "{} words do not fit in a MIF of depth {}",
+            words.len(),
+            depth
+        ));
+    }
+    let digits = width.div_ceil(4) as usize;
+    let mut out = String::new();
+    out.push_str("-- Auto-generated by wasm-compile. Do not edit.\n");
+    out.push_str(&format!("WIDTH={};\n", width));
+    out.push_str(&format!("DEPTH={};\n", depth));
+    out.push_str("ADDRESS_RADIX=HEX;\n");
+    out.push_str("DATA_RADIX=HEX;\n\n");
+    out.push_str("CONTENT BEGIN\n");
+    for (addr, w) in words.iter().enumerate() {
+        out.push_str(&format!(
+            "    {:X} : {:0width$X};\n",
+            addr,
+            w,
+            width = digits
+        ));
+    }
+    if words.len() + 1 == depth {
+        out.push_str(&format!(
+            "    {:X} : {:0width$X};\n",
+            words.len(),
+            0,
+            width = digits
+        ));
+    } else if words.len() < depth {
+        out.push_str(&format!(
+            "    [{:X}..{:X}] : {:0width$X};\n",
+            words.len(),
+            depth - 1,
+            0,
+            width = digits
+        ));
+    }
+    out.push_str("END;\n");
+    Ok(out)
+}
+
+/// Write the program bytes as a MIF file for Quartus memory initialization.
+///
+/// Bytes are packed into `width`-bit words (see [`pack_words`]). `depth`
+/// pads the memory to a fixed number of words; `None` uses exactly as many
+/// words as the program needs.
+pub fn write_prog_mif(
+    path: &PathBuf,
+    bytes: &[u8],
+    width: u32,
+    endian: Endian,
+    depth: Option<usize>,
+) -> Result<(), Error> {
+    let words = pack_words(bytes, width, endian)?;
+    let depth = depth.unwrap_or(words.len().max(1));
+    fs::write(path, mif_text(&words, width, depth)?).context("writing prog.mif")?;
+    Ok(())
+}
+
+/// Write the direct-mapped branch table image (see [`branch_table_image`])
+/// as a MIF file. Like [`write_branch_coe`], it fails with
+/// [`Error::CapacityExceeded`] rather than write a table that drops a
+/// branch.
+pub fn write_branch_mif(
+    path: &PathBuf,
+    entries: &[BranchEntry],
+    depth: usize,
+) -> Result<(), Error> {
+    let words = branch_table_image(entries, depth)?;
+    fs::write(path, mif_text(&words, BRANCH_WORD_BITS, depth)?).context("writing branch.mif")?;
+    Ok(())
+}
+
+// ---------------------------------------------------------------------------
+// Raw binary output
+// ---------------------------------------------------------------------------
+
+/// Write the program bytes as-is.
+pub fn write_prog_bin(path: &PathBuf, bytes: &[u8]) -> Result<(), Error> {
+    fs::write(path, bytes).context("writing prog.bin")?;
+    Ok(())
+}
+
+/// Write the branch table as consecutive little-endian `u32` pairs
+/// (source PC, target PC), in the same order as branch.hex.
+pub fn write_branch_bin(path: &PathBuf, entries: &[BranchEntry]) -> Result<(), Error> {
+    fs::write(path, branch_bin_bytes(entries)).context("writing branch.bin")?;
+    Ok(())
+}
+
+fn branch_bin_bytes(entries: &[BranchEntry]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(entries.len() * 8);
+    for entry in entries {
+        out.extend_from_slice(&entry.source_pc.to_le_bytes());
+        out.extend_from_slice(&entry.target_pc.to_le_bytes());
+    }
+    out
+}
+
+/// Write the flattened memory image (see [`memory_image`]) as-is.
+pub fn write_mem_bin(path: &PathBuf, image: &[u8]) -> Result<(), Error> {
+    fs::write(path, image).context("writing mem_init.bin")?;
+    Ok(())
+}
+
+// ---------------------------------------------------------------------------
+// JSON output
+// ---------------------------------------------------------------------------
+
+/// Build a single JSON document holding every artifact of a compiled test
+/// and its full expected outcome (result, trap, memory checks, and cycle
+/// budget), for scripts and dashboards that would rather not parse the hex
+/// files.
+pub fn test_json(info: &CompiledProgram) -> serde_json::Value {
+    let branch_table: Vec<_> = info
+        .branch_table
+        .iter()
+        .map(|e| serde_json::json!({ "source_pc": e.source_pc, "target_pc": e.target_pc }))
+        .collect();
+    let mem_init: Vec<_> = info
+        .mem_init
+        .iter()
+        .map(|s| serde_json::json!({ "memory": s.memory, "addr": s.addr, "data": s.data }))
+        .collect();
+    let mem_expected: Vec<_> = info
+        .mem_expected
+        .iter()
+        .map(|c| serde_json::json!({ "memory": c.memory, "addr": c.addr, "value": c.value }))
+        .collect();
+
+    serde_json::json!({
+        "name": info.name,
+        "generator": generator(),
+        "prog_len": info.body_bytes.len(),
+        "prog": info.body_bytes,
+        "branch_table": branch_table,
+        "mem_init": mem_init,
+        "expected": info.expected,
+        "expect_trap": info.expect_trap,
+        "trap_kind": info.trap_kind,
+        "mem_expected": mem_expected,
+        "cycle_budget": info.cycle_budget,
+    })
+}
+
+pub fn write_json(path: &PathBuf, info: &CompiledProgram) -> Result<(), Error> {
+    fs::write(path, json_text(info)?).context("writing program.json")?;
+    Ok(())
+}
+
+fn json_text(info: &CompiledProgram) -> Result<String> {
+    pretty_json(&test_json(info))
+}
+
+fn pretty_json(json: &serde_json::Value) -> Result<String> {
+    let mut out = serde_json::to_string_pretty(json)?;
+    out.push('\n');
+    Ok(out)
+}
+
+// ---------------------------------------------------------------------------
+// C header output
+// ---------------------------------------------------------------------------
+
+/// Turn a test name into a valid C/SV identifier.
+pub(crate) fn sanitize_ident(name: &str) -> String {
+    let mut ident: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    if ident.is_empty() || ident.starts_with(|c: char| c.is_ascii_digit()) {
+        ident.insert(0, '_');
+    }
+    ident
+}
+
+/// Format bytes as comma-separated `0xNN` literals, 12 per line.
+pub(crate) fn byte_rows(bytes: &[u8], indent: &str) -> String {
+    let mut out = String::new();
+    for row in bytes.chunks(12) {
+        out.push_str(indent);
+        let cells: Vec<String> = row.iter().map(|b| format!("0x{:02X},", b)).collect();
+        out.push_str(&cells.join(" "));
+        out.push('\n');
+    }
+    out
+}
+
+/// Render a C header with the program, branch table, and memory image as
+/// `const` arrays, for firmware that boots the core from an embedded image.
+///
+/// Identifiers are prefixed with the sanitized test name so several images
+/// can be included in one translation unit.
+pub fn generate_c_header(info: &CompiledProgram) -> Result<String, Error> {
+    let ident = sanitize_ident(&info.name);
+    let upper = ident.to_uppercase();
+    let mem_image = memory_image(&info.mem_init)?;
+
+    let mut out = String::new();
+    out.push_str("// Auto-generated by wasm-compile. Do not edit.\n\n");
+    out.push_str(&format!("#ifndef WASM_IC_{}_H\n", upper));
+    out.push_str(&format!("#define WASM_IC_{}_H\n\n", upper));
+    out.push_str("#include <stdint.h>\n\n");
+
+    out.push_str(&format!(
+        "#define {}_PROG_LEN {}u\n",
+        upper,
+        info.body_bytes.len()
+    ));
+    out.push_str(&format!(
+        "#define {}_BRANCH_COUNT {}u\n",
+        upper,
+        info.branch_table.len()
+    ));
+    out.push_str(&format!("#define {}_MEM_LEN {}u\n", upper, mem_image.len()));
+    out.push_str(&format!(
+        "#define {}_EXPECTED ((int32_t){})\n\n",
+        upper, info.expected
+    ));
+
+    // C forbids zero-length arrays, so empty tables get one padding element
+    // and callers rely on the length macros instead.
+    out.push_str(&format!("static const uint8_t {}_prog[] = {{\n", ident));
+    if info.body_bytes.is_empty() {
+        out.push_str("    0x00, /* empty */\n");
+    }
+    out.push_str(&byte_rows(&info.body_bytes, "    "));
+    out.push_str("};\n\n");
+
+    out.push_str(&format!(
+        "/* {{ source_pc, target_pc }} */\nstatic const uint32_t {}_branch_table[][2] = {{\n",
+        ident
+    ));
+    if info.branch_table.is_empty() {
+        out.push_str("    { 0, 0 }, /* empty */\n");
+    }
+    for e in &info.branch_table {
+        out.push_str(&format!(
+            "    {{ 0x{:08X}u, 0x{:08X}u }},\n",
+            e.source_pc, e.target_pc
+        ));
+    }
+    out.push_str("};\n\n");
+
+    out.push_str(&format!("static const uint8_t {}_mem_init[] = {{\n", ident));
+    if mem_image.is_empty() {
+        out.push_str("    0x00, /* empty */\n");
+    }
+    out.push_str(&byte_rows(&mem_image, "    "));
+    out.push_str("};\n\n");
+
+    out.push_str(&format!("#endif /* WASM_IC_{}_H */\n", upper));
+    Ok(out)
+}
+
+pub fn write_c_header(path: &PathBuf, info: &CompiledProgram) -> Result<(), Error> {
+    fs::write(path, generate_c_header(info)?).context("writing C header")?;
+    Ok(())
+}
+
+// ---------------------------------------------------------------------------
+// Rust include-file output
+// ---------------------------------------------------------------------------
+
+/// Render a Rust source file with the program image as `pub const` items,
+/// meant to be pulled into firmware or host loaders with `include!`.
+pub fn generate_rust_consts(info: &CompiledProgram) -> Result<String, Error> {
+    let mem_image = memory_image(&info.mem_init)?;
+
+    let mut out = String::new();
+    out.push_str(&format!(
+        "// Auto-generated by wasm-compile from `{}`. Do not edit.\n\n",
+        info.name
+    ));
+
+    out.push_str("pub const PROG: &[u8] = &[\n");
+    out.push_str(&byte_rows(&info.body_bytes, "    "));
+    out.push_str("];\n\n");
+
+    out.push_str("/// `(source_pc, target_pc)` pairs, in load order.\n");
+    out.push_str("pub const BRANCH_TABLE: &[(u32, u32)] = &[\n");
+    for e in &info.branch_table {
+        out.push_str(&format!(
+            "    (0x{:08X}, 0x{:08X}),\n",
+            e.source_pc, e.target_pc
+        ));
+    }
+    out.push_str("];\n\n");
+
+    out.push_str("pub const MEM_INIT: &[u8] = &[\n");
+    out.push_str(&byte_rows(&mem_image, "    "));
+    out.push_str("];\n\n");
+
+    out.push_str(&format!("pub const EXPECTED: i32 = {};\n", info.expected));
+    Ok(out)
+}
+
+pub fn write_rust_consts(path: &PathBuf, info: &CompiledProgram) -> Result<(), Error> {
+    fs::write(path, generate_rust_consts(info)?).context("writing Rust include file")?;
+    Ok(())
+}
+
+// ---------------------------------------------------------------------------
+// Combined stimulus file
+// ---------------------------------------------------------------------------
+
+/// Render every artifact into one labeled text file, for testbenches that
+/// want a single stimulus file per test.
+///
+/// Each section starts with a `NAME <count>` header line followed by exactly
+/// `count` data lines, so a reader never has to guess where a section ends:
+///
+/// ```text
+/// PROG 3          one program byte per line (hex)
+/// 41
+/// 2A
+/// 0F
+/// BRANCH 0        `source target` per line (hex, 8 digits)
+/// MEM 0           flattened memory image, one byte per line (hex)
+/// EXPECTED 1      expected return value (decimal)
+/// 42
+/// ```
+pub fn generate_stimulus(info: &CompiledProgram) -> Result<String, Error> {
+    let mem_image = memory_image(&info.mem_init)?;
+    let mut out = String::new();
+    out.push_str("# Auto-generated by wasm-compile. Do not edit.\n");
+    out.push_str(&format!("PROG {}\n", info.body_bytes.len()));
+    out.push_str(&mem_hex_text(&info.body_bytes));
+    out.push_str(&format!("BRANCH {}\n", info.branch_table.len()));
+    out.push_str(&branch_hex_text(
+        &info.branch_table,
+        &BranchHexLayout::default(),
+    ));
+    out.push_str(&format!("MEM {}\n", mem_image.len()));
+    out.push_str(&mem_hex_text(&mem_image));
+    out.push_str(&format!("EXPECTED 1\n{}\n", info.expected));
+    Ok(out)
+}
+
+// ---------------------------------------------------------------------------
+// Bus loader transaction stream
+// ---------------------------------------------------------------------------
+
+/// Address map of a memory-mapped loader (AXI-lite, APB, ...), used to turn
+/// the artifacts into a stream of 32-bit bus writes. It defines the one
+/// load protocol: [`bus_transactions`] is what bus.txt lists and what
+/// `sim::BusLoader` drives onto a simulated bus.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BusMap {
+    /// Program ROM base; program bytes are written as little-endian words.
+    pub prog_base: u32,
+    /// Branch table base; entry for source PC `n` is written to
+    /// `branch_base + 4 * n` with the target PC as data.
+    pub branch_base: u32,
+    /// Data memory base; the flattened memory image is written as
+    /// little-endian words.
+    pub mem_base: u32,
+    /// Control register that starts the core when written with 1, if any.
+    pub start_addr: Option<u32>,
+}
+
+impl Default for BusMap {
+    fn default() -> Self {
+        BusMap {
+            prog_base: 0x0000_0000,
+            branch_base: 0x0001_0000,
+            mem_base: 0x0002_0000,
+            start_addr: None,
+        }
+    }
+}
+
+/// A single 32-bit bus write.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BusWrite {
+    pub addr: u32,
+    pub data: u32,
+}
+
+/// The full load sequence for a test: program, branch table, memory image,
+/// then the optional start write. A write whose address would run past the
+/// 32-bit bus address space fails with [`Error::CapacityExceeded`] rather
+/// than wrap onto another region.
+pub fn bus_transactions(info: &CompiledProgram, map: &BusMap) -> Result<Vec<BusWrite>, Error> {
+    let word_addr = |region: &str, base: u32, index: usize| -> Result<u32, Error> {
+        u32::try_from(index)
+            .ok()
+            .and_then(|index| index.checked_mul(4))
+            .and_then(|offset| base.checked_add(offset))
+            .ok_or_else(|| {
+                Error::CapacityExceeded(anyhow!(
+                    "{} word {} from base {:#010X} is past the 32-bit bus address space",
+                    region,
+                    index,
+                    base
+                ))
+            })
+    };
+    let words = |region: &str, base: u32, bytes: &[u8]| -> Result<Vec<BusWrite>, Error> {
+        bytes
+            .chunks(4)
+            .enumerate()
+            .map(|(i, chunk)| {
+                let mut word = [0u8; 4];
+                word[..chunk.len()].copy_from_slice(chunk);
+                Ok(BusWrite {
+                    addr: word_addr(region, base, i)?,
+                    data: u32::from_le_bytes(word),
+                })
+            })
+            .collect()
+    };
+
+    let mut writes = words("prog", map.prog_base, &info.body_bytes)?;
+    for e in &info.branch_table {
+        writes.push(BusWrite {
+            addr: word_addr("branch", map.branch_base, e.source_pc as usize)?,
+            data: e.target_pc,
+        });
+    }
+    writes.extend(words("mem", map.mem_base, &memory_image(&info.mem_init)?)?);
+    if let Some(addr) = map.start_addr {
+        writes.push(BusWrite { addr, data: 1 });
+    }
+    Ok(writes)
+}
+
+/// Render the load sequence as text, one `W <addr> <data>` line per write
+/// (both 8-digit hex), with `#` comment lines between sections.
+pub fn generate_bus_stream(info: &CompiledProgram, map: &BusMap) -> Result<String, Error> {
+    let writes = bus_transactions(info, map)?;
+    let prog_words = info.body_bytes.len().div_ceil(4);
+    let branch_end = prog_words + info.branch_table.len();
+    let mem_end = writes.len() - map.start_addr.is_some() as usize;
+
+    let mut out = String::new();
+    out.push_str("# Auto-generated by wasm-compile. Do not edit.\n");
+    out.push_str(&format!("# {}: {} writes\n", info.name, writes.len()));
+    for (i, w) in writes.iter().enumerate() {
+        if i == 0 && prog_words > 0 {
+            out.push_str(&format!("# prog ({} bytes)\n", info.body_bytes.len()));
+        }
+        if i == prog_words && branch_end > prog_words {
+            out.push_str(&format!("# branch ({} entries)\n", info.branch_table.len()));
+        }
+        if i == branch_end && mem_end > branch_end {
+            out.push_str("# mem\n");
+        }
+        if i == mem_end {
+            out.push_str("# start\n");
+        }
+        out.push_str(&format!("W {:08X} {:08X}\n", w.addr, w.data));
+    }
+    Ok(out)
+}
+
+// ---------------------------------------------------------------------------
+// Load-integrity checksums
+// ---------------------------------------------------------------------------
+
+/// Checksum used for load-integrity verification.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ChecksumAlgo {
+    /// CRC-32 (IEEE 802.3, as used by zlib and Ethernet).
+    #[default]
+    Crc32,
+    /// CRC-32C (Castagnoli).
+    Crc32c,
+}
+
+impl ChecksumAlgo {
+    pub fn name(self) -> &'static str {
+        match self {
+            ChecksumAlgo::Crc32 => "crc32",
+            ChecksumAlgo::Crc32c => "crc32c",
+        }
+    }
+
+    /// Checksum `data`. Bitwise and table-free, mirroring what a small
+    /// hardware checker would do one byte per cycle.
+    pub fn checksum(self, data: &[u8]) -> u32 {
+        let poly = match self {
+            ChecksumAlgo::Crc32 => 0xEDB8_8320,
+            ChecksumAlgo::Crc32c => 0x82F6_3B78,
+        };
+        let mut crc = !0u32;
+        for &b in data {
+            crc ^= b as u32;
+            for _ in 0..8 {
+                crc = if crc & 1 != 0 {
+                    (crc >> 1) ^ poly
+                } else {
+                    crc >> 1
+                };
+            }
+        }
+        !crc
+    }
+}
+
+/// Checksums of the loaded payloads of a test. They cover the binary data
+/// the loader writes (program bytes, branch entries as little-endian
+/// `u32` pairs, flattened memory image), so they are the same whichever
+/// text format carried the data.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArtifactChecksums {
+    pub algo: ChecksumAlgo,
+    pub prog: u32,
+    pub branch: u32,
+    pub mem: u32,
+}
+
+pub fn artifact_checksums(
+    info: &CompiledProgram,
+    algo: ChecksumAlgo,
+) -> Result<ArtifactChecksums, Error> {
+    Ok(ArtifactChecksums {
+        algo,
+        prog: algo.checksum(&info.body_bytes),
+        branch: algo.checksum(&branch_bin_bytes(&info.branch_table)),
+        mem: algo.checksum(&memory_image(&info.mem_init)?),
+    })
+}
+
+/// Sidecar manifest listing each payload's length and checksum.
+pub fn checksums_json(info: &CompiledProgram, sums: &ArtifactChecksums) -> String {
+    pretty_json(&checksums_doc(info, sums)).expect("JSON values always serialize")
+}
+
+fn checksums_doc(info: &CompiledProgram, sums: &ArtifactChecksums) -> serde_json::Value {
+    let entry = |len: usize, sum: u32| serde_json::json!({ "len": len, "checksum": format!("0x{:08X}", sum) });
+    serde_json::json!({
+        "name": info.name,
+        "algorithm": sums.algo.name(),
+        "prog": entry(info.body_bytes.len(), sums.prog),
+        "branch": entry(info.branch_table.len() * 8, sums.branch),
+        "mem": entry(memory_image_len(&info.mem_init), sums.mem),
+    })
+}
+
+/// The same checksums as SystemVerilog localparams, for a testbench or
+/// on-chip checker to compare against after loading.
+pub fn checksums_svh(info: &CompiledProgram, sums: &ArtifactChecksums) -> String {
+    let upper = sanitize_ident(&info.name).to_uppercase();
+    let algo = sums.algo.name().to_uppercase();
+    let mut out = String::new();
+    out.push_str("// Auto-generated by wasm-compile. Do not edit.\n");
+    for (what, len, sum) in [
+        ("PROG", info.body_bytes.len(), sums.prog),
+        ("BRANCH", info.branch_table.len() * 8, sums.branch),
+        ("MEM", memory_image_len(&info.mem_init), sums.mem),
+    ] {
+        out.push_str(&format!(
+            "localparam int          {}_{}_BYTES = {};\n",
+            upper, what, len
+        ));
+        out.push_str(&format!(
+            "localparam logic [31:0] {}_{}_{} = 32'h{:08X};\n",
+            upper, what, algo, sum
+        ));
+    }
+    out
+}
+
+// ---------------------------------------------------------------------------
+// Boot image container
+// ---------------------------------------------------------------------------
+//
+// A single-file image for loaders and simulators. All integers are
+// little-endian.
+//
+//   offset  size  field
+//   ------  ----  -----------------------------------------
+//        0     4  magic "WICB"
+//        4     2  format version (BOOT_IMAGE_VERSION)
+//        6     2  section count N
+//        8  12*N  section table: { kind u32, offset u32, length u32 }
+//        …     …  section payloads (offsets are from start of file)
+//
+// Section payloads:
+//   PROG     raw program bytes
+//   BRANCH   { source_pc u32, target_pc u32 } per entry
+//   MEM      { addr u32, length u32, data[length] } per data segment
+//   EXPECTED { value i32, outcome u32 }: outcome 0 if the test returns
+//            value, 1 if it traps instead
+//   HARDWARE { rom_depth, branch_slots, stack_depth, memory_size,
+//            pc_width } as u32, then the opcode mnemonics one per line
+//            (only in linked modules; see `link_module`)
+//   HARDWARE_FLAGS
+//            u32 of capability bits: bit 0 set for a core without
+//            unaligned loads and stores, bit 1 for one with the
+//            sign-extension ops, bit 2 for one with the saturating
+//            truncations, bit 3 for one with memory.copy and
+//            memory.fill (alongside HARDWARE; an image without it
+//            describes a core with the defaults)
+//
+// Readers skip section kinds they don't know, so sections can be added
+// without bumping the version.
+
+pub const BOOT_IMAGE_MAGIC: [u8; 4] = *b"WICB";
+pub const BOOT_IMAGE_VERSION: u16 = 2;
+
+pub const SECTION_PROG: u32 = 1;
+pub const SECTION_BRANCH: u32 = 2;
+pub const SECTION_MEM: u32 = 3;
+pub const SECTION_EXPECTED: u32 = 4;
+pub const SECTION_HARDWARE: u32 = 5;
+pub const SECTION_HARDWARE_FLAGS: u32 = 6;
+
+/// [`SECTION_EXPECTED`] outcome of a test that returns its value.
+const OUTCOME_RETURNS: u32 = 0;
+/// [`SECTION_EXPECTED`] outcome of a test that traps.
+const OUTCOME_TRAPS: u32 = 1;
+
+/// [`SECTION_HARDWARE_FLAGS`] bit of a core without unaligned access.
+const FLAG_ALIGNED_ONLY: u32 = 1;
+/// [`SECTION_HARDWARE_FLAGS`] bit of [`HardwareSpec::sign_extension`].
+const FLAG_SIGN_EXTENSION: u32 = 2;
+/// [`SECTION_HARDWARE_FLAGS`] bit of [`HardwareSpec::saturating_trunc`].
+const FLAG_SATURATING_TRUNC: u32 = 4;
+/// [`SECTION_HARDWARE_FLAGS`] bit of [`HardwareSpec::bulk_memory`].
+const FLAG_BULK_MEMORY: u32 = 8;
+
+/// Contents of a boot image, as recovered by [`parse_boot_image`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct BootImage {
+    pub prog: Vec<u8>,
+    pub branch_table: Vec<BranchEntry>,
+    pub mem_init: Vec<MemSegment>,
+    pub expected: Option<i32>,
+    /// Whether the test traps rather than returning `expected`.
+    pub expect_trap: bool,
+    /// The core the image was linked for, if it says.
+    pub hardware: Option<HardwareSpec>,
+}
+
+/// Serialize a compiled test into the boot image container format.
+pub fn pack_boot_image(info: &CompiledProgram) -> Vec<u8> {
+    pack_sections(&boot_image_sections(info))
+}
+
+fn boot_image_sections(info: &CompiledProgram) -> Vec<(u32, Vec<u8>)> {
+    let branch = branch_bin_bytes(&info.branch_table);
+    let mut mem = Vec::new();
+    for s in &info.mem_init {
+        mem.extend_from_slice(&s.addr.to_le_bytes());
+        mem.extend_from_slice(&(s.data.len() as u32).to_le_bytes());
+        mem.extend_from_slice(&s.data);
+    }
+    let outcome = if info.expect_trap {
+        OUTCOME_TRAPS
+    } else {
+        OUTCOME_RETURNS
+    };
+    let mut expected = info.expected.to_le_bytes().to_vec();
+    expected.extend_from_slice(&outcome.to_le_bytes());
+    vec![
+        (SECTION_PROG, info.body_bytes.clone()),
+        (SECTION_BRANCH, branch),
+        (SECTION_MEM, mem),
+        (SECTION_EXPECTED, expected),
+    ]
+}
+
+fn pack_sections(sections: &[(u32, Vec<u8>)]) -> Vec<u8> {
+    let mut out = Vec::new();
+    out.extend_from_slice(&BOOT_IMAGE_MAGIC);
+    out.extend_from_slice(&BOOT_IMAGE_VERSION.to_le_bytes());
+    out.extend_from_slice(&(sections.len() as u16).to_le_bytes());
+
+    let mut offset = 8 + 12 * sections.len();
+    for (kind, payload) in sections {
+        out.extend_from_slice(&kind.to_le_bytes());
+        out.extend_from_slice(&(offset as u32).to_le_bytes());
+        out.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        offset += payload.len();
+    }
+    for (_, payload) in sections {
+        out.extend_from_slice(payload);
+    }
+    out
+}
+
+fn read_u32_at(bytes: &[u8], at: usize) -> Result<u32, Error> {
+    image_slice(bytes, at, 4)
+        .map(|b| u32::from_le_bytes(b.try_into().unwrap()))
+        .ok_or_else(|| Error::ParseError(anyhow!("boot image truncated at offset {}", at)))
+}
+
+/// The `len` bytes of `bytes` at `at`, if it holds them; offsets and
+/// lengths come from the image, so their sum may overflow.
+fn image_slice(bytes: &[u8], at: usize, len: usize) -> Option<&[u8]> {
+    bytes.get(at..at.checked_add(len)?)
+}
+
+/// Parse a boot image produced by [`pack_boot_image`].
+pub fn parse_boot_image(bytes: &[u8]) -> Result<BootImage, Error> {
+    if bytes.len() < 8 || bytes[0..4] != BOOT_IMAGE_MAGIC {
+        return Err(Error::ParseError(anyhow!(
+            "not a wasm-ic boot image (bad magic)"
+        )));
+    }
+    let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+    if version != BOOT_IMAGE_VERSION {
+        return Err(Error::ParseError(anyhow!(
+            "unsupported boot image version {} (expected {})",
+            version,
+            BOOT_IMAGE_VERSION
+        )));
+    }
+    let count = u16::from_le_bytes([bytes[6], bytes[7]]) as usize;
+
+    let mut image = BootImage {
+        prog: Vec::new(),
+        branch_table: Vec::new(),
+        mem_init: Vec::new(),
+        expected: None,
+        expect_trap: false,
+        hardware: None,
+    };
+    let mut flags = 0;
+
+    for i in 0..count {
+        let entry = 8 + 12 * i;
+        let kind = read_u32_at(bytes, entry)?;
+        let offset = read_u32_at(bytes, entry + 4)? as usize;
+        let len = read_u32_at(bytes, entry + 8)? as usize;
+        let payload = image_slice(bytes, offset, len).ok_or_else(|| {
+            Error::ParseError(anyhow!(
+                "section {} ({} bytes at offset {}) extends past end of image",
+                kind,
+                len,
+                offset
+            ))
+        })?;
+
+        match kind {
+            SECTION_PROG => image.prog = payload.to_vec(),
+            SECTION_BRANCH => {
+                if !len.is_multiple_of(8) {
+                    return Err(Error::ParseError(anyhow!(
+                        "branch section length {} is not a multiple of 8",
+                        len
+                    )));
+                }
+                image.branch_table = payload
+                    .chunks(8)
+                    .map(|c| BranchEntry {
+                        source_pc: u32::from_le_bytes(c[0..4].try_into().unwrap()),
+                        target_pc: u32::from_le_bytes(c[4..8].try_into().unwrap()),
+                    })
+                    .collect();
+            }
+            SECTION_MEM => {
+                let mut at = 0;
+                while at < payload.len() {
+                    let addr = read_u32_at(payload, at)?;
+                    let seg_len = read_u32_at(payload, at + 4)? as usize;
+                    let data = image_slice(payload, at + 8, seg_len).ok_or_else(|| {
+                        Error::ParseError(anyhow!("memory segment at {:#x} is truncated", addr))
+                    })?;
+                    // The image has room for one memory only.
+                    image.mem_init.push(MemSegment {
+                        memory: 0,
+                        addr,
+                        data: data.to_vec(),
+                    });
+                    at += 8 + data.len();
+                }
+            }
+            SECTION_EXPECTED => {
+                image.expected = Some(read_u32_at(payload, 0)? as i32);
+                image.expect_trap = match read_u32_at(payload, 4)? {
+                    OUTCOME_RETURNS => false,
+                    OUTCOME_TRAPS => true,
+                    other => {
+                        return Err(Error::ParseError(anyhow!(
+                            "unknown expected outcome {} in boot image",
+                            other
+                        )))
+                    }
+                };
+            }
+            SECTION_HARDWARE => image.hardware = Some(parse_hardware(payload)?),
+            SECTION_HARDWARE_FLAGS => flags = read_u32_at(payload, 0)?,
+            _ => {}
+        }
+    }
+    if let Some(hardware) = &mut image.hardware {
+        hardware.unaligned = flags & FLAG_ALIGNED_ONLY == 0;
+        hardware.sign_extension = flags & FLAG_SIGN_EXTENSION != 0;
+        hardware.saturating_trunc = flags & FLAG_SATURATING_TRUNC != 0;
+        hardware.bulk_memory = flags & FLAG_BULK_MEMORY != 0;
+    }
+
+    Ok(image)
+}
+
+fn hardware_bytes(spec: &HardwareSpec) -> Vec<u8> {
+    let mut out = Vec::new();
+    for n in [
+        spec.rom_depth,
+        spec.branch_slots,
+        spec.stack_depth,
+        spec.memory_size,
+        spec.pc_width as usize,
+    ] {
+        out.extend_from_slice(&(n as u32).to_le_bytes());
+    }
+    let opcodes: Vec<&str> = spec.opcodes.iter().map(String::as_str).collect();
+    out.extend_from_slice(opcodes.join("\n").as_bytes());
+    out
+}
+
+fn parse_hardware(payload: &[u8]) -> Result<HardwareSpec> {
+    let field = |i: usize| read_u32_at(payload, 4 * i).map(|n| n as usize);
+    let opcodes = std::str::from_utf8(&payload[20.min(payload.len())..])
+        .context("hardware section opcodes are not UTF-8")?;
+    Ok(HardwareSpec {
+        rom_depth: field(0)?,
+        branch_slots: field(1)?,
+        stack_depth: field(2)?,
+        memory_size: field(3)?,
+        pc_width: field(4)? as u32,
+        // Set from the flags section, if there is one.
+        unaligned: true,
+        sign_extension: false,
+        saturating_trunc: false,
+        bulk_memory: false,
+        opcodes: opcodes
+            .lines()
+            .filter(|op| !op.is_empty())
+            .map(str::to_string)
+            .collect(),
+    })
+}
+
+pub fn write_boot_image(path: &PathBuf, info: &CompiledProgram) -> Result<(), Error> {
+    fs::write(path, pack_boot_image(info))
+        .with_context(|| format!("writing {}", path.display()))?;
+    Ok(())
+}
+
+// ---------------------------------------------------------------------------
+// Linked modules
+// ---------------------------------------------------------------------------
+//
+// `link` writes the boot image into the module itself, as a custom section,
+// so a single .wasm carries the program image, branch table, and the core
+// it was checked against. Engines skip custom sections, so the module
+// still runs unchanged.
+
+/// Name of the custom section [`link_module`] adds.
+pub const LINK_SECTION: &str = "wasm-ic";
+
+/// `wasm` with a [`LINK_SECTION`] custom section appended, holding the
+/// boot image of `info` (compiled from `wasm`) plus a HARDWARE section
+/// for `hardware`. A link section already in `wasm` is replaced, and a
+/// test that doesn't fit `hardware` is an error.
+pub fn link_module(
+    wasm: &[u8],
+    info: &CompiledProgram,
+    hardware: &HardwareSpec,
+) -> Result<Vec<u8>, Error> {
+    hardware.check_fits(info)?;
+    let mut out = strip_link_sections(wasm)?;
+    let mut sections = boot_image_sections(info);
+    sections.push((SECTION_HARDWARE, hardware_bytes(hardware)));
+    let mut flags = 0;
+    for (set, flag) in [
+        (!hardware.unaligned, FLAG_ALIGNED_ONLY),
+        (hardware.sign_extension, FLAG_SIGN_EXTENSION),
+        (hardware.saturating_trunc, FLAG_SATURATING_TRUNC),
+        (hardware.bulk_memory, FLAG_BULK_MEMORY),
+    ] {
+        if set {
+            flags |= flag;
+        }
+    }
+    sections.push((SECTION_HARDWARE_FLAGS, flags.to_le_bytes().to_vec()));
+    let mut content = Vec::new();
+    push_uleb(&mut content, LINK_SECTION.len());
+    content.extend_from_slice(LINK_SECTION.as_bytes());
+    content.extend(pack_sections(&sections));
+    out.push(0);
+    push_uleb(&mut out, content.len());
+    out.extend(content);
+    Ok(out)
+}
+
+/// The boot image in a module's [`LINK_SECTION`], or `None` if it was
+/// never linked.
+pub fn linked_image(wasm: &[u8]) -> Result<Option<BootImage>, Error> {
+    for payload in wasmparser::Parser::new(0).parse_all(wasm) {
+        if let wasmparser::Payload::CustomSection(reader) = payload? {
+            if reader.name() == LINK_SECTION {
+                let image = parse_boot_image(reader.data())
+                    .with_context(|| format!("reading the {} section", LINK_SECTION))?;
+                return Ok(Some(image));
+            }
+        }
+    }
+    Ok(None)
+}
+
+fn strip_link_sections(wasm: &[u8]) -> Result<Vec<u8>> {
+    let mut out = Vec::with_capacity(wasm.len());
+    let mut kept = 0;
+    for payload in wasmparser::Parser::new(0).parse_all(wasm) {
+        if let wasmparser::Payload::CustomSection(reader) = payload? {
+            if reader.name() == LINK_SECTION {
+                let range = reader.range();
+                out.extend_from_slice(&wasm[kept..section_id_offset(wasm, range.start)]);
+                kept = range.end;
+            }
+        }
+    }
+    out.extend_from_slice(&wasm[kept..]);
+    Ok(out)
+}
+
+// ---------------------------------------------------------------------------
+// Synthesizable ROM generation
+// ---------------------------------------------------------------------------
+
+/// How the generated ROM stores its contents.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RomStyle {
+    /// A `case` statement on the address (maps well to LUT ROMs).
+    #[default]
+    Case,
+    /// An initialized constant array (lets tools infer block RAM).
+    Array,
+}
+
+/// Options for [`generate_sv_rom`].
+#[derive(Debug, Clone)]
+pub struct RomOptions {
+    pub module_name: String,
+    pub style: RomStyle,
+    /// Word width in bits; program bytes are packed with `endian`.
+    pub data_width: u32,
+    pub endian: Endian,
+    /// Number of words; `None` uses exactly as many as the program needs.
+    pub depth: Option<usize>,
+}
+
+impl Default for RomOptions {
+    fn default() -> Self {
+        RomOptions {
+            module_name: "prog_rom".to_string(),
+            style: RomStyle::Case,
+            data_width: 8,
+            endian: Endian::Little,
+            depth: None,
+        }
+    }
+}
+
+/// Bits needed to address `depth` words (at least 1).
+pub(crate) fn addr_bits(depth: usize) -> u32 {
+    (usize::BITS - depth.saturating_sub(1).leading_zeros()).max(1)
+}
+
+/// Generate a synthesizable SystemVerilog ROM holding the program, for
+/// FPGA builds that bake the program into the bitstream.
+///
+/// Reads are combinational; addresses past the end read as zero.
+pub fn generate_sv_rom(bytes: &[u8], opts: &RomOptions) -> Result<String, Error> {
+    let words = pack_words(bytes, opts.data_width, opts.endian)?;
+    let depth = opts.depth.unwrap_or(words.len().max(1));
+    if words.len() > depth {
+        return Err(anyhow!(
+            "program needs {} words but ROM depth is {}",
+            words.len(),
+            depth
+        )
+        .into());
+    }
+    let width = opts.data_width;
+    let digits = width.div_ceil(4) as usize;
+
+    let mut out = String::new();
+    out.push_str("// Auto-generated by wasm-compile gen-rom. Do not edit.\n\n");
+    out.push_str(&format!("module {} (\n", opts.module_name));
+    out.push_str(&format!(
+        "    input  logic [{}:0] addr,\n",
+        addr_bits(depth) - 1
+    ));
+    out.push_str(&format!("    output logic [{}:0] data\n", width - 1));
+    out.push_str(");\n");
+    out.push_str(&format!("    localparam int DATA_WIDTH = {};\n", width));
+    out.push_str(&format!("    localparam int DEPTH      = {};\n\n", depth));
+
+    match opts.style {
+        RomStyle::Case => {
+            out.push_str("    always_comb begin\n");
+            out.push_str("        case (addr)\n");
+            for (i, w) in words.iter().enumerate() {
+                out.push_str(&format!(
+                    "            {}: data = {}'h{:0digits$X};\n",
+                    i,
+                    width,
+                    w,
+                    digits = digits
+                ));
+            }
+            out.push_str("            default: data = '0;\n");
+            out.push_str("        endcase\n");
+            out.push_str("    end\n");
+        }
+        RomStyle::Array => {
+            out.push_str("    localparam logic [DATA_WIDTH-1:0] ROM [DEPTH] = '{\n");
+            for i in 0..depth {
+                let w = words.get(i).copied().unwrap_or(0);
+                let sep = if i + 1 < depth { "," } else { "" };
+                out.push_str(&format!(
+                    "        {}'h{:0digits$X}{}\n",
+                    width,
+                    w,
+                    sep,
+                    digits = digits
+                ));
+            }
+            out.push_str("    };\n\n");
+            out.push_str("    assign data = (addr < DEPTH) ? ROM[addr] : '0;\n");
+        }
+    }
+
+    out.push_str("endmodule\n");
+    Ok(out)
+}
+
+/// Generate a Veryl program ROM module following the core's port naming.
+///
+/// The read is combinational, mirroring the core's `o_prog_addr` /
+/// `i_prog_data` handshake, so the module has no clock or reset. Only the
+/// `case` style is supported.
+pub fn generate_veryl_prog_rom(bytes: &[u8], opts: &RomOptions) -> Result<String, Error> {
+    if opts.style != RomStyle::Case {
+        return Err(anyhow!("Veryl ROMs only support the case style").into());
+    }
+    let words = pack_words(bytes, opts.data_width, opts.endian)?;
+    let depth = opts.depth.unwrap_or(words.len().max(1));
+    if words.len() > depth {
+        return Err(anyhow!(
+            "program needs {} words but ROM depth is {}",
+            words.len(),
+            depth
+        )
+        .into());
+    }
+    let width = opts.data_width;
+    let digits = width.div_ceil(4) as usize;
+
+    let labels: Vec<String> = (0..words.len()).map(|i| format!("32'd{}", i)).collect();
+    let label_width = labels.iter().map(|l| l.len()).max().unwrap_or(0).max(7);
+
+    let mut out = String::new();
+    out.push_str("/// Program ROM. Auto-generated by wasm-compile gen-rom. Do not edit.\n");
+    out.push_str("///\n");
+    out.push_str("/// Combinational read; addresses past the end read as zero.\n");
+    out.push_str(&format!("module {} (\n", opts.module_name));
+    out.push_str("    i_addr: input  logic<32>, /// Program address (word index)\n");
+    out.push_str(&format!(
+        "    o_data: output logic<{}>, /// Program word\n",
+        width
+    ));
+    out.push_str(") {\n");
+    out.push_str(&format!(
+        "    // {} words of {} bits (depth {})\n",
+        words.len(),
+        width,
+        depth
+    ));
+    out.push_str("    always_comb {\n");
+    out.push_str("        case i_addr {\n");
+    for (label, w) in labels.iter().zip(&words) {
+        out.push_str(&format!(
+            "            {:lw$}: o_data = {}'h{:0digits$X};\n",
+            label,
+            width,
+            w,
+            lw = label_width,
+            digits = digits
+        ));
+    }
+    out.push_str(&format!(
+        "            {:lw$}: o_data = {}'h{:0digits$X};\n",
+        "default",
+        width,
+        0,
+        lw = label_width,
+        digits = digits
+    ));
+    out.push_str("        }\n");
+    out.push_str("    }\n");
+    out.push_str("}\n");
+    Ok(out)
+}
+
+/// How a fixed branch table is turned into combinational logic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LookupStyle {
+    /// A `case` statement on the source PC.
+    #[default]
+    Case,
+    /// A balanced tree of comparators over the sorted source PCs, which
+    /// keeps logic depth logarithmic in the number of entries.
+    Tree,
+}
+
+/// HDL flavour for the lookup tree expression.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Hdl {
+    Sv,
+    Veryl,
+}
+
+/// Entries sorted by source PC. A later entry for the same PC wins, as it
+/// would in the loaded RAM.
+pub(crate) fn sorted_branch_entries(entries: &[BranchEntry]) -> Vec<BranchEntry> {
+    let mut by_pc = std::collections::BTreeMap::new();
+    for e in entries {
+        by_pc.insert(e.source_pc, e.target_pc);
+    }
+    by_pc
+        .into_iter()
+        .map(|(source_pc, target_pc)| BranchEntry {
+            source_pc,
+            target_pc,
+        })
+        .collect()
+}
+
+/// Build a nested conditional expression yielding `{hit, target_pc}` by
+/// binary search over `sorted` source PCs. SystemVerilog gets `c ? a : b`;
+/// Veryl has no ternary operator, so it gets `if c { a } else { b }`.
+pub(crate) fn lookup_tree(sorted: &[BranchEntry], hdl: Hdl, indent: usize) -> String {
+    let pad = " ".repeat(indent);
+    match (sorted, hdl) {
+        ([], _) => "33'h0".to_string(),
+        ([e], Hdl::Sv) => format!(
+            "(i_rd_addr == 32'd{}) ? {{1'b1, 32'h{:08X}}} : 33'h0",
+            e.source_pc, e.target_pc
+        ),
+        ([e], Hdl::Veryl) => format!(
+            "if i_rd_addr == 32'd{} {{ {{1'b1, 32'h{:08X}}} }} else {{ 33'h0 }}",
+            e.source_pc, e.target_pc
+        ),
+        (_, Hdl::Sv) => {
+            let mid = sorted.len() / 2;
+            format!(
+                "(i_rd_addr < 32'd{}) ?\n{pad}    ({})\n{pad}  : ({})",
+                sorted[mid].source_pc,
+                lookup_tree(&sorted[..mid], hdl, indent + 4),
+                lookup_tree(&sorted[mid..], hdl, indent + 4),
+                pad = pad
+            )
+        }
+        (_, Hdl::Veryl) => {
+            let mid = sorted.len() / 2;
+            format!(
+                "if i_rd_addr <: 32'd{} {{\n{pad}    {}\n{pad}}} else {{\n{pad}    {}\n{pad}}}",
+                sorted[mid].source_pc,
+                lookup_tree(&sorted[..mid], hdl, indent + 4),
+                lookup_tree(&sorted[mid..], hdl, indent + 4),
+                pad = pad
+            )
+        }
+    }
+}
+
+/// Generate a combinational SystemVerilog module with the branch table
+/// baked in, for configurations where the table is fixed at synthesis time.
+/// The ports match the read port of `WasmBranchTable`.
+pub fn generate_sv_branch_rom(
+    entries: &[BranchEntry],
+    module_name: &str,
+    style: LookupStyle,
+) -> String {
+    let sorted = sorted_branch_entries(entries);
+
+    let mut out = String::new();
+    out.push_str("// Auto-generated by wasm-compile gen-rom. Do not edit.\n\n");
+    out.push_str(&format!("module {} (\n", module_name));
+    out.push_str("    input  logic [31:0] i_rd_addr,\n");
+    out.push_str("    output logic [31:0] o_rd_data,\n");
+    out.push_str("    output logic        o_rd_valid\n");
+    out.push_str(");\n");
+    match style {
+        LookupStyle::Case => {
+            out.push_str("    always_comb begin\n");
+            out.push_str("        o_rd_valid = 1'b1;\n");
+            out.push_str("        case (i_rd_addr)\n");
+            for e in &sorted {
+                out.push_str(&format!(
+                    "            32'd{}: o_rd_data = 32'h{:08X};\n",
+                    e.source_pc, e.target_pc
+                ));
+            }
+            out.push_str("            default: begin\n");
+            out.push_str("                o_rd_data  = '0;\n");
+            out.push_str("                o_rd_valid = 1'b0;\n");
+            out.push_str("            end\n");
+            out.push_str("        endcase\n");
+            out.push_str("    end\n");
+        }
+        LookupStyle::Tree => {
+            out.push_str(&format!(
+                "    wire [32:0] lookup = {};\n\n",
+                lookup_tree(&sorted, Hdl::Sv, 4)
+            ));
+            out.push_str("    assign o_rd_valid = lookup[32];\n");
+            out.push_str("    assign o_rd_data  = lookup[31:0];\n");
+        }
+    }
+    out.push_str("endmodule\n");
+    out
+}
+
+/// Generate a Veryl module with the branch table baked in, exposing the
+/// same read port as `WasmBranchTable` (`i_rd_addr` -> `o_rd_data`,
+/// `o_rd_valid`) for configurations that don't load the table at runtime.
+pub fn generate_veryl_branch_rom(
+    entries: &[BranchEntry],
+    module_name: &str,
+    style: LookupStyle,
+) -> String {
+    let sorted = sorted_branch_entries(entries);
+
+    let mut out = String::new();
+    out.push_str("/// Branch table ROM. Auto-generated by wasm-compile gen-rom. Do not edit.\n");
+    out.push_str("///\n");
+    out.push_str("/// Drop-in replacement for the read port of `WasmBranchTable` when the\n");
+    out.push_str("/// table is fixed at synthesis time.\n");
+    out.push_str(&format!("module {} (\n", module_name));
+    out.push_str("    i_rd_addr : input  logic<32>, /// Source PC to look up\n");
+    out.push_str("    o_rd_data : output logic<32>, /// Target PC at that entry\n");
+    out.push_str("    o_rd_valid: output logic    , /// Entry exists\n");
+    out.push_str(") {\n");
+    match style {
+        LookupStyle::Case => {
+            let labels: Vec<String> = sorted
+                .iter()
+                .map(|e| format!("32'd{}", e.source_pc))
+                .collect();
+            let label_width = labels.iter().map(|l| l.len()).max().unwrap_or(0).max(7);
+
+            out.push_str("    always_comb {\n");
+            out.push_str("        o_rd_valid = 1'b1;\n");
+            out.push_str("        case i_rd_addr {\n");
+            for (label, e) in labels.iter().zip(&sorted) {
+                out.push_str(&format!(
+                    "            {:lw$}: o_rd_data = 32'h{:08X};\n",
+                    label,
+                    e.target_pc,
+                    lw = label_width
+                ));
+            }
+            out.push_str(&format!(
+                "            {:lw$}: {{\n",
+                "default",
+                lw = label_width
+            ));
+            out.push_str("                o_rd_data  = 32'h00000000;\n");
+            out.push_str("                o_rd_valid = 1'b0;\n");
+            out.push_str("            }\n");
+            out.push_str("        }\n");
+            out.push_str("    }\n");
+        }
+        LookupStyle::Tree => {
+            out.push_str(&format!(
+                "    let lookup: logic<33> = {};\n\n",
+                lookup_tree(&sorted, Hdl::Veryl, 4)
+            ));
+            out.push_str("    assign o_rd_valid = lookup[32];\n");
+            out.push_str("    assign o_rd_data  = lookup[31:0];\n");
+        }
+    }
+    out.push_str("}\n");
+    out
+}
+
+// ---------------------------------------------------------------------------
+// Opcode remapping
+// ---------------------------------------------------------------------------
+
+/// Hardware op codes for wasm instructions, for cores whose decoder takes
+/// a compacted encoding instead of raw wasm opcodes. Remapping swaps each
+/// instruction's opcode, prefix and all, for its one-byte code and keeps
+/// the immediates, so PCs move only past prefixed opcodes.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct OpcodeMap {
+    codes: std::collections::BTreeMap<String, u8>,
+}
+
+impl OpcodeMap {
+    /// A map from a TOML table of mnemonics to codes:
+    ///
+    /// ```toml
+    /// "i32.const" = 0x01
+    /// "i32.add" = 0x10
+    /// end = 0x00
+    /// ```
+    ///
+    /// Two mnemonics sharing a code is an error, since the decoder
+    /// couldn't tell them apart.
+    pub fn from_toml(text: &str) -> Result<Self, Error> {
+        let table: toml::Table = text.parse()?;
+        let mut map = OpcodeMap::default();
+        let mut owners = std::collections::BTreeMap::new();
+        for (name, value) in &table {
+            let code = value
+                .as_integer()
+                .and_then(|v| u8::try_from(v).ok())
+                .ok_or_else(|| {
+                    Error::ParseError(anyhow!("op code for `{}` must be 0 to 255", name))
+                })?;
+            if let Some(other) = owners.insert(code, name) {
+                return Err(Error::ParseError(anyhow!(
+                    "`{}` and `{}` both map to {:#04x}",
+                    other,
+                    name,
+                    code
+                )));
+            }
+            map.codes.insert(name.clone(), code);
+        }
+        Ok(map)
+    }
+
+    pub fn load(path: &std::path::Path) -> Result<Self, Error> {
+        let text =
+            fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+        Ok(Self::from_toml(&text).with_context(|| format!("parsing {}", path.display()))?)
+    }
+
+    /// The code `mnemonic` is remapped to, if the map has one.
+    pub fn code(&self, mnemonic: &str) -> Option<u8> {
+        self.codes.get(mnemonic).copied()
+    }
+
+    /// `body` in the hardware encoding, and its instructions at their new
+    /// PCs. An instruction the map has no code for is an error.
+    pub fn remap_body(&self, body: &[u8]) -> Result<(Vec<u8>, Vec<Insn>), Error> {
+        let mut out = Vec::with_capacity(body.len());
+        let mut insns = disassemble(body);
+        for insn in &mut insns {
+            let code = self
+                .code(&insn.mnemonic)
+                .ok_or_else(|| Error::UnsupportedOp {
+                    offset: insn.offset,
+                    op: insn.mnemonic.clone(),
+                })?;
+            let bytes = insn.bytes(body);
+            let op_len = opcode_len(bytes);
+            insn.offset = out.len();
+            insn.len -= op_len - 1;
+            out.push(code);
+            out.extend_from_slice(&bytes[op_len..]);
+        }
+        Ok((out, insns))
+    }
+
+    /// `info` with its program in the hardware encoding and its branch
+    /// table moved to match, and the remapped instructions.
+    pub fn remap(&self, info: &CompiledProgram) -> Result<(CompiledProgram, Vec<Insn>), Error> {
+        let (body_bytes, insns) = self.remap_body(&info.body_bytes)?;
+        // Old PC to new for every instruction boundary, the end included.
+        let mut pcs: std::collections::HashMap<u32, u32> = disassemble(&info.body_bytes)
+            .iter()
+            .zip(&insns)
+            .map(|(old, new)| (old.offset as u32, new.offset as u32))
+            .collect();
+        pcs.insert(info.body_bytes.len() as u32, body_bytes.len() as u32);
+        let moved = |pc: u32| {
+            pcs.get(&pc).copied().ok_or_else(|| {
+                Error::ParseError(anyhow!(
+                    "branch PC {:#x} is not an instruction boundary",
+                    pc
+                ))
+            })
+        };
+        let branch_table = info
+            .branch_table
+            .iter()
+            .map(|e| {
+                Ok(BranchEntry {
+                    source_pc: moved(e.source_pc)?,
+                    target_pc: moved(e.target_pc)?,
+                })
+            })
+            .collect::<Result<_, Error>>()?;
+        let remapped = CompiledProgram {
+            body_bytes,
+            branch_table,
+            ..info.clone()
+        };
+        Ok((remapped, insns))
+    }
+
+    /// The map as `name=code` lines, for the metadata checksum.
+    fn canonical(&self) -> String {
+        self.codes
+            .iter()
+            .map(|(name, code)| format!("{}={}\n", name, code))
+            .collect()
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Artifact metadata
+// ---------------------------------------------------------------------------
+
+/// The commit the tool was built from, when built in a git checkout.
+pub const GIT_HASH: Option<&str> = option_env!("WASM_IC_GIT_HASH");
+
+/// `wasm-compile` and the crate version, as artifacts name their writer.
+pub(crate) fn generator() -> String {
+    format!("wasm-compile {}", env!("CARGO_PKG_VERSION"))
+}
+
+/// Where an artifact came from. [`render_artifact`] stamps it into every
+/// file with room for it, as comment lines in the text formats and a
+/// `metadata` field in JSON, so a stale or mixed-version artifact set can
+/// be told apart; raw binaries and `expected.txt` go without.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArtifactMetadata {
+    pub generator: String,
+    pub git_hash: Option<String>,
+    /// The emit options that shape the files, as `key=value` words.
+    pub options: String,
+    /// See [`CompiledProgram::input_hash`].
+    pub input_hash: Option<u32>,
+}
+
+impl ArtifactMetadata {
+    pub fn new(info: &CompiledProgram, opts: &EmitOptions) -> Self {
+        ArtifactMetadata {
+            generator: generator(),
+            git_hash: GIT_HASH.map(str::to_string),
+            options: opts.summary(),
+            input_hash: info.input_hash,
+        }
+    }
+
+    /// `(key, value)` pairs in the order they are written; unknown
+    /// fields are left out.
+    pub fn fields(&self) -> Vec<(&'static str, String)> {
+        let mut fields = vec![("generator", self.generator.clone())];
+        if let Some(hash) = &self.git_hash {
+            fields.push(("git", hash.clone()));
+        }
+        fields.push(("options", self.options.clone()));
+        if let Some(hash) = self.input_hash {
+            fields.push(("input", format!("crc32:{:08x}", hash)));
+        }
+        fields
+    }
+
+    /// One `<comment> key: value` line per field.
+    pub fn comment_lines(&self, comment: &str) -> String {
+        self.fields()
+            .into_iter()
+            .map(|(key, value)| format!("{} {}: {}\n", comment, key, value))
+            .collect()
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        self.fields()
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), value.into()))
+            .collect::<serde_json::Map<_, _>>()
+            .into()
+    }
+
+    /// `text` with [`Self::comment_lines`] at the top, after its
+    /// `Auto-generated ...` line if it starts with one.
+    fn stamp(&self, text: String, comment: &str) -> String {
+        let at = match text.strip_prefix(comment) {
+            Some(_) => text.find('\n').map_or(text.len(), |i| i + 1),
+            None => 0,
+        };
+        let mut out = text;
+        out.insert_str(at, &self.comment_lines(comment));
+        out
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Artifact emission
+// ---------------------------------------------------------------------------
+
+/// Which artifact set [`emit_artifacts`] writes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ArtifactFormat {
+    /// prog.hex / branch.hex (+ mem_init.hex) for `$readmemh`
+    #[default]
+    Hex,
+    /// prog.coe / branch.coe for the Vivado block memory generator
+    Coe,
+    /// prog.mif / branch.mif for Quartus
+    Mif,
+    /// prog.bin / branch.bin / mem_init.bin, raw little-endian
+    Bin,
+    /// program.json with every artifact in one document
+    Json,
+    /// program.h with `const` arrays
+    C,
+    /// program.rs with `pub const` items
+    Rust,
+    /// stimulus.txt with labeled PROG/BRANCH/MEM/EXPECTED sections
+    Stimulus,
+    /// bus.txt with the load sequence as 32-bit bus writes
+    Bus,
+    /// insn.hex / const.hex / branch.hex lowered for the fixed-width
+    /// pipelined core (see [`crate::lower`])
+    Fixed,
+}
+
+/// Options controlling how [`emit_artifacts`] lays out its files.
+#[derive(Debug, Clone)]
+pub struct EmitOptions {
+    pub format: ArtifactFormat,
+    /// Program word width in bits (hex and mif).
+    pub data_width: u32,
+    pub endian: Endian,
+    /// Add disassembly comments to prog.hex.
+    pub annotate: bool,
+    pub branch_layout: BranchHexLayout,
+    /// Pad the MIF program image to this many words.
+    pub depth: Option<usize>,
+    /// File name prefix; `None` uses the test name, `Some("")` writes the
+    /// bare `prog.hex`, `branch.hex`, ... names.
+    pub prefix: Option<String>,
+    /// Loader address map for the bus format.
+    pub bus_map: BusMap,
+    /// Also write `checksums.json` and `checksums.svh` using this algorithm.
+    pub checksums: Option<ChecksumAlgo>,
+    /// The core the artifacts are for: tests must fit it, and the branch
+    /// table images have one word per slot of its table.
+    pub hardware: HardwareSpec,
+    /// Write the program in this hardware encoding rather than as wasm.
+    pub opcode_map: Option<OpcodeMap>,
+    /// Also write predecode.hex (hex format) for a decoder that takes
+    /// immediates from it rather than decoding LEB128.
+    pub predecode: bool,
+    /// Write a program longer than the ROM with a warning rather than
+    /// fail; the core loads only the part that fits.
+    pub force: bool,
+    /// Also write expected.json, the [`ExpectedSpec`] of the test.
+    pub expected_spec: bool,
+}
+
+impl EmitOptions {
+    /// Fail unless `info` fits [`EmitOptions::hardware`], as
+    /// [`HardwareSpec::check_fits`], except that with
+    /// [`EmitOptions::force`] a program over the ROM only warns.
+    pub fn check_fits(&self, info: &CompiledProgram) -> Result<(), Error> {
+        let len = info.body_bytes.len();
+        if !self.force || len <= self.hardware.rom_depth {
+            return self.hardware.check_fits(info);
+        }
+        log::warn!(
+            "{}: the program is {} bytes, {} over the {}-byte ROM; writing it anyway",
+            info.name,
+            len,
+            len - self.hardware.rom_depth,
+            self.hardware.rom_depth
+        );
+        // Everything else must still fit.
+        HardwareSpec {
+            rom_depth: len,
+            ..self.hardware.clone()
+        }
+        .check_fits(info)
+    }
+
+    /// The options that shape the files, for [`ArtifactMetadata`].
+    fn summary(&self) -> String {
+        let mut out = format!(
+            "format={} width={} endian={} annotate={} branch_slots={}",
+            self.format.name(),
+            self.data_width,
+            match self.endian {
+                Endian::Little => "little",
+                Endian::Big => "big",
+            },
+            self.annotate,
+            self.hardware.branch_slots,
+        );
+        if let Some(depth) = self.depth {
+            out.push_str(&format!(" depth={}", depth));
+        }
+        if self.predecode {
+            out.push_str(" predecode=true");
+        }
+        if let Some(map) = &self.opcode_map {
+            let sum = ChecksumAlgo::Crc32.checksum(map.canonical().as_bytes());
+            out.push_str(&format!(" opcode_map=crc32:{:08x}", sum));
+        }
+        out
+    }
+}
+
+impl Default for EmitOptions {
+    fn default() -> Self {
+        EmitOptions {
+            format: ArtifactFormat::Hex,
+            data_width: 8,
+            endian: Endian::Little,
+            annotate: false,
+            branch_layout: BranchHexLayout::default(),
+            depth: None,
+            prefix: None,
+            bus_map: BusMap::default(),
+            checksums: None,
+            hardware: HardwareSpec::default(),
+            opcode_map: None,
+            predecode: false,
+            force: false,
+            expected_spec: false,
+        }
+    }
+}
+
+/// Join a prefix and an artifact base name: `add` + `prog.hex` ->
+/// `add.prog.hex`, or just `prog.hex` for an empty prefix.
+pub(crate) fn artifact_file_name(prefix: &str, base: &str) -> String {
+    if prefix.is_empty() {
+        base.to_string()
+    } else {
+        format!("{}.{}", prefix, base)
+    }
+}
+
+/// One artifact of a compiled test. Which kinds exist depends on the
+/// [`ArtifactFormat`]: the vendor and raw formats split the image into
+/// separate program/branch/memory files, while the others put everything
+/// into a single `Program` document.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArtifactKind {
+    Prog,
+    Branch,
+    Mem,
+    MemExpected,
+    Program,
+    Expected,
+    /// The constant ROM of a [`ArtifactFormat::Fixed`] program.
+    Const,
+    /// The LEB128 pre-decode sideband, with [`EmitOptions::predecode`].
+    Predecode,
+    /// The [`ExpectedSpec`], with [`EmitOptions::expected_spec`].
+    ExpectedSpec,
+}
+
+impl ArtifactKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            ArtifactKind::Prog => "prog",
+            ArtifactKind::Branch => "branch",
+            ArtifactKind::Mem => "mem",
+            ArtifactKind::MemExpected => "mem_expected",
+            ArtifactKind::Program => "program",
+            ArtifactKind::Expected => "expected",
+            ArtifactKind::Const => "const",
+            ArtifactKind::Predecode => "predecode",
+            ArtifactKind::ExpectedSpec => "expected_spec",
+        }
+    }
+}
+
+impl ArtifactFormat {
+    pub const ALL: [ArtifactFormat; 10] = [
+        ArtifactFormat::Hex,
+        ArtifactFormat::Coe,
+        ArtifactFormat::Mif,
+        ArtifactFormat::Bin,
+        ArtifactFormat::Json,
+        ArtifactFormat::C,
+        ArtifactFormat::Rust,
+        ArtifactFormat::Stimulus,
+        ArtifactFormat::Bus,
+        ArtifactFormat::Fixed,
+    ];
+
+    /// The format for a lower-case name as the CLI spells it, e.g. `"hex"`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "hex" => ArtifactFormat::Hex,
+            "coe" => ArtifactFormat::Coe,
+            "mif" => ArtifactFormat::Mif,
+            "bin" => ArtifactFormat::Bin,
+            "json" => ArtifactFormat::Json,
+            "c" => ArtifactFormat::C,
+            "rust" => ArtifactFormat::Rust,
+            "stimulus" => ArtifactFormat::Stimulus,
+            "bus" => ArtifactFormat::Bus,
+            "fixed" => ArtifactFormat::Fixed,
+            _ => return None,
+        })
+    }
+
+    /// The name [`Self::from_name`] takes.
+    pub fn name(self) -> &'static str {
+        match self {
+            ArtifactFormat::Hex => "hex",
+            ArtifactFormat::Coe => "coe",
+            ArtifactFormat::Mif => "mif",
+            ArtifactFormat::Bin => "bin",
+            ArtifactFormat::Json => "json",
+            ArtifactFormat::C => "c",
+            ArtifactFormat::Rust => "rust",
+            ArtifactFormat::Stimulus => "stimulus",
+            ArtifactFormat::Bus => "bus",
+            ArtifactFormat::Fixed => "fixed",
+        }
+    }
+
+    /// The artifacts this format produces, in write order.
+    pub fn kinds(self) -> &'static [ArtifactKind] {
+        use ArtifactKind::*;
+        match self {
+            ArtifactFormat::Hex => &[
+                Prog,
+                Branch,
+                Mem,
+                MemExpected,
+                Predecode,
+                Expected,
+                ExpectedSpec,
+            ],
+            ArtifactFormat::Fixed => &[
+                Prog,
+                Const,
+                Branch,
+                Mem,
+                MemExpected,
+                Expected,
+                ExpectedSpec,
+            ],
+            ArtifactFormat::Bin => &[Prog, Branch, Mem, Expected, ExpectedSpec],
+            ArtifactFormat::Coe | ArtifactFormat::Mif => &[Prog, Branch, Expected, ExpectedSpec],
+            ArtifactFormat::Json
+            | ArtifactFormat::C
+            | ArtifactFormat::Rust
+            | ArtifactFormat::Stimulus
+            | ArtifactFormat::Bus => &[Program, Expected, ExpectedSpec],
+        }
+    }
+
+    /// File name (without prefix) of an artifact in this format.
+    pub fn file_name(self, kind: ArtifactKind) -> Option<&'static str> {
+        use ArtifactKind::*;
+        Some(match (self, kind) {
+            (_, Expected) => "expected.txt",
+            (_, ExpectedSpec) => "expected.json",
+            (ArtifactFormat::Hex, Prog) => "prog.hex",
+            (ArtifactFormat::Hex, Branch) => "branch.hex",
+            (ArtifactFormat::Hex, Predecode) => "predecode.hex",
+            (ArtifactFormat::Hex | ArtifactFormat::Fixed, Mem) => "mem_init.hex",
+            (ArtifactFormat::Hex | ArtifactFormat::Fixed, MemExpected) => "mem_expected.hex",
+            (ArtifactFormat::Fixed, Prog) => "insn.hex",
+            (ArtifactFormat::Fixed, Const) => "const.hex",
+            (ArtifactFormat::Fixed, Branch) => "branch.hex",
+            (ArtifactFormat::Coe, Prog) => "prog.coe",
+            (ArtifactFormat::Coe, Branch) => "branch.coe",
+            (ArtifactFormat::Mif, Prog) => "prog.mif",
+            (ArtifactFormat::Mif, Branch) => "branch.mif",
+            (ArtifactFormat::Bin, Prog) => "prog.bin",
+            (ArtifactFormat::Bin, Branch) => "branch.bin",
+            (ArtifactFormat::Bin, Mem) => "mem_init.bin",
+            (ArtifactFormat::Json, Program) => "program.json",
+            (ArtifactFormat::C, Program) => "program.h",
+            (ArtifactFormat::Rust, Program) => "program.rs",
+            (ArtifactFormat::Stimulus, Program) => "stimulus.txt",
+            (ArtifactFormat::Bus, Program) => "bus.txt",
+            _ => return None,
+        })
+    }
+}
+
+/// Render a single artifact into memory, e.g. for streaming to stdout.
+pub fn render_artifact(
+    info: &CompiledProgram,
+    kind: ArtifactKind,
+    opts: &EmitOptions,
+) -> Result<Vec<u8>, Error> {
+    use ArtifactKind::*;
+    let meta = ArtifactMetadata::new(info, opts);
+    let original = info;
+    let remapped;
+    let (info, insns) = match &opts.opcode_map {
+        // The lowering takes the map itself.
+        Some(map) if opts.format != ArtifactFormat::Fixed => {
+            let (program, insns) = map.remap(info)?;
+            remapped = program;
+            (&remapped, Some(insns))
+        }
+        _ => (info, None),
+    };
+    let bytes = match (opts.format, kind) {
+        (_, Expected) => format!("{}\n", info.expected).into_bytes(),
+        (_, ExpectedSpec) => {
+            let mut json = crate::emit::ExpectedSpec::of(info).to_json();
+            json["metadata"] = meta.to_json();
+            pretty_json(&json)?.into_bytes()
+        }
+        (ArtifactFormat::Hex, Prog) => {
+            let insns = match insns {
+                Some(insns) if opts.annotate => insns,
+                _ if opts.annotate => disassemble(&info.body_bytes),
+                _ => Vec::new(),
+            };
+            let text = annotated_hex_text(&info.body_bytes, opts.data_width, opts.endian, &insns)?;
+            meta.stamp(text, "//").into_bytes()
+        }
+        (ArtifactFormat::Hex, Branch) => {
+            let text = branch_hex_text(&info.branch_table, &opts.branch_layout);
+            meta.stamp(text, "//").into_bytes()
+        }
+        (ArtifactFormat::Hex, Predecode) => {
+            let mut table = predecode_table(&original.body_bytes)?;
+            // Remapping moves the instructions after a prefixed opcode.
+            if let Some(insns) = &insns {
+                let moved: std::collections::HashMap<usize, &Insn> =
+                    disassemble(&original.body_bytes)
+                        .iter()
+                        .map(|insn| insn.offset)
+                        .zip(insns)
+                        .collect();
+                for e in &mut table {
+                    let insn = moved[&(e.pc as usize)];
+                    e.pc = insn.offset as u32;
+                    e.len = insn.len as u32;
+                }
+            }
+            meta.stamp(predecode_hex_text(&table), "//").into_bytes()
+        }
+        (ArtifactFormat::Hex | ArtifactFormat::Fixed, Mem) => {
+            let text = mem_hex_text(&memory_image(&info.mem_init)?);
+            meta.stamp(text, "//").into_bytes()
+        }
+        (ArtifactFormat::Hex | ArtifactFormat::Fixed, MemExpected) => {
+            let text = mem_expected_hex_text(&info.mem_expected);
+            meta.stamp(text, "//").into_bytes()
+        }
+        (ArtifactFormat::Fixed, Prog) => {
+            let lowered = lower_program(info, opts.opcode_map.as_ref())?;
+            let insns: Vec<Insn> = if opts.annotate {
+                disassemble(&info.body_bytes)
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, insn)| Insn {
+                        offset: 4 * i,
+                        len: 4,
+                        ..insn
+                    })
+                    .collect()
+            } else {
+                Vec::new()
+            };
+            let text = annotated_hex_text(&lowered.insn_bytes(), 32, Endian::Little, &insns)?;
+            meta.stamp(text, "//").into_bytes()
+        }
+        (ArtifactFormat::Fixed, Const) => {
+            let lowered = lower_program(info, opts.opcode_map.as_ref())?;
+            let text = annotated_hex_text(&lowered.const_bytes(), 32, Endian::Little, &[])?;
+            meta.stamp(text, "//").into_bytes()
+        }
+        (ArtifactFormat::Fixed, Branch) => {
+            let lowered = lower_program(info, opts.opcode_map.as_ref())?;
+            let text = branch_hex_text(&lowered.branch_table, &opts.branch_layout);
+            meta.stamp(text, "//").into_bytes()
+        }
+        (ArtifactFormat::Coe, Prog) => {
+            let words: Vec<u64> = info.body_bytes.iter().map(|&b| b as u64).collect();
+            meta.stamp(coe_text(&words, 8), ";").into_bytes()
+        }
+        (ArtifactFormat::Coe, Branch) => {
+            let words = branch_table_image(&info.branch_table, opts.hardware.branch_slots)?;
+            meta.stamp(coe_text(&words, BRANCH_WORD_BITS), ";")
+                .into_bytes()
+        }
+        (ArtifactFormat::Mif, Prog) => {
+            let words = pack_words(&info.body_bytes, opts.data_width, opts.endian)?;
+            let depth = opts.depth.unwrap_or(words.len().max(1));
+            let text = mif_text(&words, opts.data_width, depth)?;
+            meta.stamp(text, "--").into_bytes()
+        }
+        (ArtifactFormat::Mif, Branch) => {
+            let slots = opts.hardware.branch_slots;
+            let words = branch_table_image(&info.branch_table, slots)?;
+            let text = mif_text(&words, BRANCH_WORD_BITS, slots)?;
+            meta.stamp(text, "--").into_bytes()
+        }
+        (ArtifactFormat::Bin, Prog) => info.body_bytes.clone(),
+        (ArtifactFormat::Bin, Branch) => branch_bin_bytes(&info.branch_table),
+        (ArtifactFormat::Bin, Mem) => memory_image(&info.mem_init)?,
+        (ArtifactFormat::Json, Program) => {
+            let mut json = test_json(info);
+            json["metadata"] = meta.to_json();
+            pretty_json(&json)?.into_bytes()
+        }
+        (ArtifactFormat::C, Program) => meta.stamp(generate_c_header(info)?, "//").into_bytes(),
+        (ArtifactFormat::Rust, Program) => {
+            meta.stamp(generate_rust_consts(info)?, "//").into_bytes()
+        }
+        (ArtifactFormat::Stimulus, Program) => {
+            meta.stamp(generate_stimulus(info)?, "#").into_bytes()
+        }
+        (ArtifactFormat::Bus, Program) => meta
+            .stamp(generate_bus_stream(info, &opts.bus_map)?, "#")
+            .into_bytes(),
+        (format, kind) => {
+            return Err(anyhow!("{:?} output has no {:?} artifact", format, kind).into())
+        }
+    };
+    Ok(bytes)
+}
+
+/// Write the artifacts of a compiled test into `out_dir` and return the
+/// paths that were produced, in write order. A test that doesn't fit
+/// `opts.hardware` is an error and writes nothing (see
+/// [`EmitOptions::check_fits`]).
+pub fn emit_artifacts(
+    info: &CompiledProgram,
+    out_dir: &std::path::Path,
+    opts: &EmitOptions,
+) -> Result<Vec<PathBuf>, Error> {
+    Ok(timed("emit", &info.name, || {
+        write_artifacts(info, out_dir, opts)
+    })?)
+}
+
+fn write_artifacts(
+    info: &CompiledProgram,
+    out_dir: &std::path::Path,
+    opts: &EmitOptions,
+) -> Result<Vec<PathBuf>> {
+    opts.check_fits(info)?;
+    let prefix = opts.prefix.as_deref().unwrap_or(&info.name);
+    let builtins: Vec<BuiltinEmitter> = opts
+        .format
+        .kinds()
+        .iter()
+        .map(|&kind| BuiltinEmitter::new(opts.format, kind, opts.clone()))
+        .collect();
+    let emitters: Vec<&dyn ArtifactEmitter> =
+        builtins.iter().map(|e| e as &dyn ArtifactEmitter).collect();
+    let mut written = write_emitted(info, out_dir, prefix, &emitters)?;
+
+    if let Some(algo) = opts.checksums {
+        for (base, text) in checksum_files(info, opts, algo)? {
+            let path = out_dir.join(artifact_file_name(prefix, base));
+            fs::write(&path, text).with_context(|| format!("writing {}", path.display()))?;
+            written.push(path);
+        }
+    }
+
+    Ok(written)
+}
+
+/// `checksums.json` and `checksums.svh` as [`emit_artifacts`] writes them,
+/// stamped with their [`ArtifactMetadata`].
+fn checksum_files(
+    info: &CompiledProgram,
+    opts: &EmitOptions,
+    algo: ChecksumAlgo,
+) -> Result<[(&'static str, String); 2]> {
+    let meta = ArtifactMetadata::new(info, opts);
+    // The checksums are of what the core loads, in its encoding.
+    let remapped;
+    let info = match &opts.opcode_map {
+        _ if opts.format == ArtifactFormat::Fixed => {
+            let lowered = lower_program(info, opts.opcode_map.as_ref())?;
+            remapped = CompiledProgram {
+                body_bytes: lowered.insn_bytes(),
+                branch_table: lowered.branch_table,
+                ..info.clone()
+            };
+            &remapped
+        }
+        Some(map) => {
+            remapped = map.remap(info)?.0;
+            &remapped
+        }
+        None => info,
+    };
+    let sums = artifact_checksums(info, algo)?;
+    let mut json = checksums_doc(info, &sums);
+    json["metadata"] = meta.to_json();
+    Ok([
+        ("checksums.json", pretty_json(&json)?),
+        (
+            "checksums.svh",
+            meta.stamp(checksums_svh(info, &sums), "//"),
+        ),
+    ])
+}
+
+/// Compile a WAT file and write its artifacts into `out_dir`, named after
+/// the input stem unless `opts.prefix` says otherwise.
+pub fn compile_to_dir(
+    input: &std::path::Path,
+    out_dir: &std::path::Path,
+    opts: &EmitOptions,
+) -> Result<(CompiledProgram, Vec<PathBuf>), Error> {
+    let info = compile_wat_file(input)?;
+    let paths = emit_artifacts(&info, out_dir, opts)?;
+    Ok((info, paths))
+}
+
+// ---------------------------------------------------------------------------
+// Pluggable emitters
+// ---------------------------------------------------------------------------
+
+/// One file written for a compiled test. The built-in formats go through
+/// [`BuiltinEmitter`]; a crate with its own loader format implements this
+/// and hands it to [`emit_with`] or an [`EmitterRegistry`] instead of
+/// changing this module.
+pub trait ArtifactEmitter {
+    /// Name to pick the emitter by, e.g. `hex-prog`.
+    fn name(&self) -> &str;
+
+    /// File name after the test's prefix, e.g. `prog.hex` for
+    /// `add.prog.hex`.
+    fn extension(&self) -> &str;
+
+    fn emit(&self, info: &CompiledProgram) -> Result<Vec<u8>, Error>;
+
+    /// Whether `info` has this artifact at all; the file isn't written if
+    /// not.
+    fn applies(&self, _info: &CompiledProgram) -> bool {
+        true
+    }
+}
+
+/// A built-in artifact: [`render_artifact`] of one kind in one format.
+#[derive(Debug, Clone)]
+pub struct BuiltinEmitter {
+    kind: ArtifactKind,
+    name: String,
+    extension: &'static str,
+    opts: EmitOptions,
+}
+
+impl BuiltinEmitter {
+    /// # Panics
+    ///
+    /// If `format` has no `kind` artifact (see [`ArtifactFormat::kinds`]).
+    pub fn new(format: ArtifactFormat, kind: ArtifactKind, opts: EmitOptions) -> Self {
+        let extension = format
+            .file_name(kind)
+            .unwrap_or_else(|| panic!("{:?} output has no {:?} artifact", format, kind));
+        let name = match kind {
+            ArtifactKind::Expected => "expected".to_string(),
+            ArtifactKind::Program => format.name().to_string(),
+            _ => format!("{}-{}", format.name(), kind.name()),
+        };
+        BuiltinEmitter {
+            kind,
+            name,
+            extension,
+            opts: EmitOptions { format, ..opts },
+        }
+    }
+}
+
+impl ArtifactEmitter for BuiltinEmitter {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn extension(&self) -> &str {
+        self.extension
+    }
+
+    fn emit(&self, info: &CompiledProgram) -> Result<Vec<u8>, Error> {
+        render_artifact(info, self.kind, &self.opts)
+    }
+
+    /// An empty hex memory image only makes `$readmemh` warn.
+    fn applies(&self, info: &CompiledProgram) -> bool {
+        if self.kind == ArtifactKind::Predecode {
+            return self.opts.predecode;
+        }
+        if self.kind == ArtifactKind::ExpectedSpec {
+            return self.opts.expected_spec;
+        }
+        !matches!(
+            self.opts.format,
+            ArtifactFormat::Hex | ArtifactFormat::Fixed
+        ) || match self.kind {
+            ArtifactKind::Mem => !info.mem_init.is_empty(),
+            ArtifactKind::MemExpected => !info.mem_expected.is_empty(),
+            _ => true,
+        }
+    }
+}
+
+/// Emitters by name: the built-in ones and any a crate adds, so a tool
+/// can offer them all behind one `--format`-style choice.
+#[derive(Default)]
+pub struct EmitterRegistry {
+    emitters: Vec<Box<dyn ArtifactEmitter>>,
+}
+
+impl EmitterRegistry {
+    /// No emitters.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Every artifact of every built-in format, laid out as `opts` says.
+    pub fn with_builtins(opts: &EmitOptions) -> Self {
+        let mut registry = Self::new();
+        for format in ArtifactFormat::ALL {
+            for &kind in format.kinds() {
+                registry.register(BuiltinEmitter::new(format, kind, opts.clone()));
+            }
+        }
+        registry
+    }
+
+    /// Add `emitter`, replacing any of the same name.
+    pub fn register(&mut self, emitter: impl ArtifactEmitter + 'static) -> &mut Self {
+        self.emitters.retain(|e| e.name() != emitter.name());
+        self.emitters.push(Box::new(emitter));
+        self
+    }
+
+    pub fn get(&self, name: &str) -> Option<&dyn ArtifactEmitter> {
+        self.emitters
+            .iter()
+            .find(|e| e.name() == name)
+            .map(|e| e.as_ref())
+    }
+
+    /// Names in registration order.
+    pub fn names(&self) -> Vec<&str> {
+        self.emitters.iter().map(|e| e.name()).collect()
+    }
+
+    /// [`emit_with`] the emitters called `names`.
+    pub fn emit(
+        &self,
+        info: &CompiledProgram,
+        out_dir: &std::path::Path,
+        prefix: &str,
+        names: &[&str],
+    ) -> Result<Vec<PathBuf>, Error> {
+        let emitters = names
+            .iter()
+            .map(|&name| {
+                self.get(name)
+                    .ok_or_else(|| anyhow!("no artifact emitter called {:?}", name))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        emit_with(info, out_dir, prefix, &emitters)
+    }
+}
+
+/// Write a file per emitter that applies to `info` into `out_dir`, named
+/// `<prefix>.<extension>` (bare `<extension>` for an empty prefix), and
+/// return their paths in order.
+pub fn emit_with(
+    info: &CompiledProgram,
+    out_dir: &std::path::Path,
+    prefix: &str,
+    emitters: &[&dyn ArtifactEmitter],
+) -> Result<Vec<PathBuf>, Error> {
+    Ok(write_emitted(info, out_dir, prefix, emitters)?)
+}
+
+fn write_emitted(
+    info: &CompiledProgram,
+    out_dir: &std::path::Path,
+    prefix: &str,
+    emitters: &[&dyn ArtifactEmitter],
+) -> Result<Vec<PathBuf>> {
+    fs::create_dir_all(out_dir).with_context(|| format!("creating {}", out_dir.display()))?;
+    let mut written = Vec::new();
+    for emitter in emitters.iter().filter(|e| e.applies(info)) {
+        let path = out_dir.join(artifact_file_name(prefix, emitter.extension()));
+        let bytes = emitter
+            .emit(info)
+            .with_context(|| format!("emitting {}", emitter.name()))?;
+        fs::write(&path, bytes).with_context(|| format!("writing {}", path.display()))?;
+        log::trace!("{}: wrote {}", info.name, path.display());
+        written.push(path);
+    }
+    Ok(written)
+}
+
+// ---------------------------------------------------------------------------
+// Artifact verification
+// ---------------------------------------------------------------------------
+
+/// Read a prog.hex written with `width`-bit words back into program
+/// bytes, ignoring `//` comments. A padded final word yields its zero
+/// padding too.
+pub fn parse_prog_hex(text: &str, width: u32, endian: Endian) -> Result<Vec<u8>, Error> {
+    pack_words(&[], width, endian)?;
+    let bytes_per_word = (width / 8) as usize;
+    let mut bytes = Vec::new();
+    for (line, word) in data_lines(text) {
+        let value = u64::from_str_radix(word, 16)
+            .map_err(|e| anyhow!("line {}: bad word `{}`: {}", line, word, e))?;
+        for lane in 0..bytes_per_word {
+            let lane = match endian {
+                Endian::Little => lane,
+                Endian::Big => bytes_per_word - 1 - lane,
+            };
+            bytes.push((value >> (8 * lane)) as u8);
+        }
+    }
+    Ok(bytes)
+}
+
+/// Read a branch.hex written with `layout` back into its entries,
+/// ignoring `//` comments.
+pub fn parse_branch_hex(text: &str, layout: &BranchHexLayout) -> Result<Vec<BranchEntry>, Error> {
+    let field = |line: usize, s: &str| {
+        let s = s.trim();
+        match layout.radix {
+            Radix::Hex => u32::from_str_radix(s, 16),
+            Radix::Dec => s.parse(),
+        }
+        .map_err(|e| anyhow!("line {}: bad field `{}`: {}", line, s, e))
+    };
+    let mut entries = Vec::new();
+    for (line, text) in data_lines(text) {
+        let (source, target) = if layout.separator.is_empty() {
+            text.split_at_checked(layout.width)
+        } else {
+            text.split_once(layout.separator.as_str())
+        }
+        .ok_or_else(|| anyhow!("line {}: expected two columns", line))?;
+        entries.push(BranchEntry {
+            source_pc: field(line, source)?,
+            target_pc: field(line, target)?,
+        });
+    }
+    Ok(entries)
+}
+
+/// The lines of an artifact that hold data: `//` comments, such as the
+/// metadata header, and blank lines dropped, with their line numbers.
+fn data_lines(text: &str) -> impl Iterator<Item = (usize, &str)> {
+    text.lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.split("//").next().unwrap_or_default().trim()))
+        .filter(|(_, line)| !line.is_empty())
+}
+
+/// Read an expected.txt back into the value the program returns.
+pub fn parse_expected(text: &str) -> Result<i32, Error> {
+    let mut lines = data_lines(text);
+    let (line, value) = lines.next().ok_or_else(|| anyhow!("no expected value"))?;
+    if let Some((extra, _)) = lines.next() {
+        return Err(anyhow!("line {}: more than one expected value", extra).into());
+    }
+    Ok(value
+        .parse()
+        .map_err(|e| anyhow!("line {}: bad value `{}`: {}", line, value, e))?)
+}
+
+/// Read a mem_init.hex, one byte per line, back into the memory image.
+pub fn parse_mem_hex(text: &str) -> Result<Vec<u8>, Error> {
+    Ok(data_lines(text)
+        .map(|(line, byte)| {
+            u8::from_str_radix(byte, 16)
+                .map_err(|e| anyhow!("line {}: bad byte `{}`: {}", line, byte, e))
+        })
+        .collect::<Result<_>>()?)
+}
+
+/// Read a mem_expected.hex back into the memory words to check.
+pub fn parse_mem_expected_hex(text: &str) -> Result<Vec<MemCheck>, Error> {
+    Ok(data_lines(text)
+        .map(|(line, fields)| {
+            let word = |s: &str| {
+                u32::from_str_radix(s, 16)
+                    .map_err(|e| anyhow!("line {}: bad word `{}`: {}", line, s, e))
+            };
+            let (addr, value) = fields
+                .split_once(char::is_whitespace)
+                .ok_or_else(|| anyhow!("line {}: expected ADDR VALUE", line))?;
+            Ok(MemCheck {
+                memory: 0,
+                addr: word(addr)?,
+                value: word(value.trim())?,
+            })
+        })
+        .collect::<Result<_>>()?)
+}
+
+fn read_artifact(path: &std::path::Path) -> Result<String> {
+    fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))
+}
+
+/// [`parse_prog_hex`] of the file at `path`.
+pub fn read_prog_hex(path: &std::path::Path, width: u32, endian: Endian) -> Result<Vec<u8>, Error> {
+    let text = read_artifact(path)?;
+    Ok(parse_prog_hex(&text, width, endian)
+        .with_context(|| format!("parsing {}", path.display()))?)
+}
+
+/// [`parse_branch_hex`] of the file at `path`.
+pub fn read_branch_hex(
+    path: &std::path::Path,
+    layout: &BranchHexLayout,
+) -> Result<Vec<BranchEntry>, Error> {
+    let text = read_artifact(path)?;
+    Ok(parse_branch_hex(&text, layout).with_context(|| format!("parsing {}", path.display()))?)
+}
+
+/// [`parse_expected`] of the file at `path`.
+pub fn read_expected(path: &std::path::Path) -> Result<i32, Error> {
+    let text = read_artifact(path)?;
+    Ok(parse_expected(&text).with_context(|| format!("parsing {}", path.display()))?)
+}
+
+/// [`parse_expected_spec`] of the file at `path`.
+pub fn read_expected_spec(path: &std::path::Path) -> Result<ExpectedSpec, Error> {
+    let text = read_artifact(path)?;
+    Ok(parse_expected_spec(&text).with_context(|| format!("parsing {}", path.display()))?)
+}
+
+/// [`parse_mem_expected_hex`] of the file at `path`.
+pub fn read_mem_expected_hex(path: &std::path::Path) -> Result<Vec<MemCheck>, Error> {
+    let text = read_artifact(path)?;
+    Ok(parse_mem_expected_hex(&text).with_context(|| format!("parsing {}", path.display()))?)
+}
+
+/// The test whose hex artifacts [`emit_artifacts`] wrote to `out_dir`
+/// with `opts`, named `name`, read back. The memory files are optional,
+/// as tests without data or stores have none; the data comes back as one
+/// segment at address 0. The program keeps any padding of its last word.
+/// An expected.json gives the expected trap and cycle budget; without
+/// one, they are left at their defaults.
+pub fn read_hex_artifacts(
+    out_dir: &std::path::Path,
+    name: &str,
+    opts: &EmitOptions,
+) -> Result<CompiledProgram, Error> {
+    if opts.format != ArtifactFormat::Hex {
+        return Err(anyhow!(
+            "only hex artifacts can be read back, not {}",
+            opts.format.name()
+        )
+        .into());
+    }
+    let prefix = opts.prefix.as_deref().unwrap_or(name);
+    let path = |base: &str| out_dir.join(artifact_file_name(prefix, base));
+    let optional = |base: &str| Some(path(base)).filter(|p| p.exists());
+    let mem_init = match optional("mem_init.hex") {
+        Some(p) => {
+            let data = parse_mem_hex(&read_artifact(&p)?)
+                .with_context(|| format!("parsing {}", p.display()))?;
+            vec![MemSegment {
+                memory: 0,
+                addr: 0,
+                data,
+            }]
+        }
+        None => Vec::new(),
+    };
+    let mut info = CompiledProgram {
+        name: name.to_string(),
+        body_bytes: read_prog_hex(&path("prog.hex"), opts.data_width, opts.endian)?,
+        branch_table: read_branch_hex(&path("branch.hex"), &opts.branch_layout)?,
+        mem_init,
+        expected: read_expected(&path("expected.txt"))?,
+        mem_expected: optional("mem_expected.hex")
+            .map(|p| read_mem_expected_hex(&p))
+            .transpose()?
+            .unwrap_or_default(),
+        expect_trap: false,
+        cycle_budget: None,
+        trap_kind: None,
+        input_hash: None,
+    };
+    if let Some(p) = optional("expected.json") {
+        read_expected_spec(&p)?.apply(&mut info);
+    }
+    Ok(info)
+}
+
+/// A stale, missing, or leftover artifact found by [`verify_artifacts`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArtifactProblem {
+    /// File name within the artifact directory.
+    pub file: String,
+    /// What is wrong, in one line.
+    pub problem: String,
+    /// The differences in context, with the fresh compile as expected and
+    /// the file as actual; empty for a missing or leftover file.
+    pub detail: Vec<DiffLine>,
+}
+
+impl std::fmt::Display for ArtifactProblem {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}: {}", self.file, self.problem)
+    }
+}
+
+/// Check the artifacts [`emit_artifacts`] would write for `info` with
+/// `opts` against what is in `out_dir`, returning a problem per stale,
+/// missing, or leftover file. prog.hex and branch.hex are read back and
+/// compared byte by byte and entry by entry, their detail disassembled;
+/// other files must match a fresh render exactly.
+pub fn verify_artifacts(
+    info: &CompiledProgram,
+    out_dir: &std::path::Path,
+    opts: &EmitOptions,
+) -> Result<Vec<ArtifactProblem>, Error> {
+    let prefix = opts.prefix.as_deref().unwrap_or(&info.name);
+    let mut expected = Vec::new();
+    for &kind in opts.format.kinds() {
+        let emitter = BuiltinEmitter::new(opts.format, kind, opts.clone());
+        let bytes = emitter
+            .applies(info)
+            .then(|| emitter.emit(info))
+            .transpose()?;
+        let file = artifact_file_name(prefix, emitter.extension());
+        expected.push((file, Some(kind), bytes));
+    }
+    if let Some(algo) = opts.checksums {
+        for (base, text) in checksum_files(info, opts, algo)? {
+            expected.push((
+                artifact_file_name(prefix, base),
+                None,
+                Some(text.into_bytes()),
+            ));
+        }
+    }
+
+    let mut problems = Vec::new();
+    for (file, kind, want) in expected {
+        let path = out_dir.join(&file);
+        let have = match fs::read(&path) {
+            Ok(have) => Some(have),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => {
+                return Err(Error::Io(
+                    anyhow::Error::from(e).context(format!("reading {}", path.display())),
+                ))
+            }
+        };
+        let problem = match (have, want) {
+            (None, None) => None,
+            (None, Some(_)) => Some(("missing".to_string(), Vec::new())),
+            (Some(_), None) => Some((
+                "left over: the source no longer produces it".to_string(),
+                Vec::new(),
+            )),
+            (Some(have), Some(want)) => {
+                let text = String::from_utf8_lossy(&have);
+                // A remapped program doesn't disassemble, so its files
+                // are only compared as text.
+                let wasm = opts.opcode_map.is_none();
+                match (opts.format, kind) {
+                    (ArtifactFormat::Hex, Some(ArtifactKind::Prog)) if wasm => {
+                        let mut want = info.body_bytes.clone();
+                        let word = (opts.data_width / 8) as usize;
+                        want.resize(want.len().next_multiple_of(word), 0);
+                        let have = parse_prog_hex(&text, opts.data_width, opts.endian)
+                            .with_context(|| format!("parsing {}", path.display()))?;
+                        first_difference(&have, &want, "bytes", |pc, b| {
+                            format!("pc {:#06X}: {:02X}", pc, b)
+                        })
+                        .map(|p| (p, program_diff(&want, &have, DIFF_CONTEXT, DIFF_LIMIT)))
+                    }
+                    (ArtifactFormat::Hex, Some(ArtifactKind::Branch)) if wasm => {
+                        let have = parse_branch_hex(&text, &opts.branch_layout)
+                            .with_context(|| format!("parsing {}", path.display()))?;
+                        first_difference(&have, &info.branch_table, "entries", |i, e| {
+                            format!("entry {}: {} -> {}", i, e.source_pc, e.target_pc)
+                        })
+                        .map(|p| {
+                            let detail = branch_table_diff(
+                                &info.branch_table,
+                                &have,
+                                &info.body_bytes,
+                                DIFF_CONTEXT,
+                                DIFF_LIMIT,
+                            );
+                            (p, detail)
+                        })
+                    }
+                    _ if have == want => None,
+                    _ => {
+                        let want = String::from_utf8_lossy(&want);
+                        let line = text
+                            .lines()
+                            .zip(want.lines())
+                            .position(|(a, b)| a != b)
+                            .unwrap_or_else(|| text.lines().count().min(want.lines().count()));
+                        let want: Vec<&str> = want.lines().collect();
+                        let have: Vec<&str> = text.lines().collect();
+                        Some((
+                            format!("differs from a fresh compile from line {}", line + 1),
+                            line_diff(&want, &have, DIFF_CONTEXT, DIFF_LIMIT),
+                        ))
+                    }
+                }
+            }
+        };
+        if let Some((problem, detail)) = problem {
+            problems.push(ArtifactProblem {
+                file,
+                problem,
+                detail,
+            });
+        }
+    }
+    Ok(problems)
+}
+
+/// Describe where `have` first departs from `want`, items shown by `show`.
+fn first_difference<T: PartialEq>(
+    have: &[T],
+    want: &[T],
+    items: &str,
+    show: impl Fn(usize, &T) -> String,
+) -> Option<String> {
+    match have.iter().zip(want).position(|(a, b)| a != b) {
+        Some(i) => Some(format!(
+            "file has {}, the source gives {}",
+            show(i, &have[i]),
+            show(i, &want[i])
+        )),
+        None if have.len() != want.len() => Some(format!(
+            "file has {} {}, the source gives {}",
+            have.len(),
+            items,
+            want.len()
+        )),
+        None => None,
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Artifact directory diff
+// ---------------------------------------------------------------------------
+
+/// Differences shown per file before the rest are only counted.
+pub const DIFF_LIMIT: usize = 10;
+
+/// Unchanged lines shown either side of a difference in a detailed diff.
+pub const DIFF_CONTEXT: usize = 2;
+
+/// One line of a detailed diff. Verification takes the fresh compile as
+/// expected and the file on disk as actual; a directory diff takes the
+/// old directory as expected and the new one as actual.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DiffLine {
+    /// The same on both sides, shown for context.
+    Same(String),
+    /// Only on the expected side.
+    Expected(String),
+    /// Only on the actual side.
+    Actual(String),
+    /// This many unchanged lines left out.
+    Skipped(usize),
+}
+
+/// Line up two programs by PC and disassemble both, showing each
+/// instruction that differs with `context` instructions around it: its
+/// PC, raw bytes, and mnemonic, or `??` for bytes that don't decode. Stops
+/// after `limit` differing instructions.
+pub fn program_diff(expected: &[u8], actual: &[u8], context: usize, limit: usize) -> Vec<DiffLine> {
+    let (expected, actual) = (program_rows(expected), program_rows(actual));
+    let raw_width = expected
+        .iter()
+        .chain(&actual)
+        .map(|(_, raw, _)| raw.len())
+        .max()
+        .unwrap_or(0);
+    let mut by_pc: std::collections::BTreeMap<usize, (Option<String>, Option<String>)> =
+        std::collections::BTreeMap::new();
+    for (rows, actual) in [(expected, false), (actual, true)] {
+        for (pc, raw, name) in rows {
+            let row = format!("{:04X}  {:<raw_width$}  {}", pc, raw, name);
+            let slot = by_pc.entry(pc).or_default();
+            if actual {
+                slot.1 = Some(row);
+            } else {
+                slot.0 = Some(row);
+            }
+        }
+    }
+    contextual_diff(by_pc.into_values().collect(), context, limit)
+}
+
+/// Each instruction's PC, raw bytes, and mnemonic.
+fn program_rows(bytes: &[u8]) -> Vec<(usize, String, String)> {
+    disassemble(bytes)
+        .iter()
+        .map(|insn| (insn.offset, hex_bytes(insn.bytes(bytes)), insn.to_string()))
+        .collect()
+}
+
+/// Compare branch tables entry by entry, each entry annotated with the
+/// instruction at its source PC in `body_bytes`.
+fn branch_table_diff(
+    expected: &[BranchEntry],
+    actual: &[BranchEntry],
+    body_bytes: &[u8],
+    context: usize,
+    limit: usize,
+) -> Vec<DiffLine> {
+    let names: std::collections::BTreeMap<usize, String> = program_rows(body_bytes)
+        .into_iter()
+        .map(|(pc, _, name)| (pc, name))
+        .collect();
+    let row = |i: usize, e: &BranchEntry| {
+        let name = names.get(&(e.source_pc as usize)).map_or("?", |n| n);
+        format!(
+            "#{:<3} {:04X} -> {:04X}  {}",
+            i, e.source_pc, e.target_pc, name
+        )
+    };
+    let rows = (0..expected.len().max(actual.len()))
+        .map(|i| {
+            (
+                expected.get(i).map(|e| row(i, e)),
+                actual.get(i).map(|e| row(i, e)),
+            )
+        })
+        .collect();
+    contextual_diff(rows, context, limit)
+}
+
+/// Compare text line by line, each line shown with its number.
+fn line_diff(expected: &[&str], actual: &[&str], context: usize, limit: usize) -> Vec<DiffLine> {
+    let row = |i: usize, line: &str| format!("{:>4}  {}", i + 1, line);
+    let rows = (0..expected.len().max(actual.len()))
+        .map(|i| {
+            (
+                expected.get(i).map(|l| row(i, l)),
+                actual.get(i).map(|l| row(i, l)),
+            )
+        })
+        .collect();
+    contextual_diff(rows, context, limit)
+}
+
+/// Compare binary data in rows of 16 bytes, each shown with its offset.
+fn byte_diff(expected: &[u8], actual: &[u8], context: usize, limit: usize) -> Vec<DiffLine> {
+    let row = |i: usize, chunk: &[u8]| {
+        let bytes = chunk
+            .iter()
+            .map(|b| format!("{:02X}", b))
+            .collect::<Vec<_>>()
+            .join(" ");
+        format!("{:04X}  {}", i * 16, bytes)
+    };
+    let (expected, actual): (Vec<_>, Vec<_>) =
+        (expected.chunks(16).collect(), actual.chunks(16).collect());
+    let rows = (0..expected.len().max(actual.len()))
+        .map(|i| {
+            (
+                expected.get(i).map(|c| row(i, c)),
+                actual.get(i).map(|c| row(i, c)),
+            )
+        })
+        .collect();
+    contextual_diff(rows, context, limit)
+}
+
+/// Turn aligned (expected, actual) rows into a diff that keeps `context`
+/// unchanged rows around each change and counts the rest, stopping after
+/// `limit` changed rows. Empty if nothing changed.
+fn contextual_diff(
+    rows: Vec<(Option<String>, Option<String>)>,
+    context: usize,
+    limit: usize,
+) -> Vec<DiffLine> {
+    let changed: Vec<usize> = (0..rows.len())
+        .filter(|&i| rows[i].0 != rows[i].1)
+        .take(limit)
+        .collect();
+    let shown = |i: usize| changed.iter().any(|&c| c.abs_diff(i) <= context);
+    let mut out = Vec::new();
+    let mut skipped = 0;
+    for (i, (expected, actual)) in rows.iter().enumerate() {
+        if !shown(i) {
+            skipped += 1;
+            continue;
+        }
+        if skipped > 0 {
+            out.push(DiffLine::Skipped(skipped));
+            skipped = 0;
+        }
+        if expected == actual {
+            out.extend(expected.clone().map(DiffLine::Same));
+        } else {
+            out.extend(expected.clone().map(DiffLine::Expected));
+            out.extend(actual.clone().map(DiffLine::Actual));
+        }
+    }
+    if skipped > 0 && !out.is_empty() {
+        out.push(DiffLine::Skipped(skipped));
+    }
+    out
+}
+
+/// How one file differs between two artifact directories.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArtifactDiff {
+    /// Path relative to both directories.
+    pub file: PathBuf,
+    /// One line per difference: a changed line (one byte, word, or
+    /// branch entry in the hex formats) or byte, a length change, or the
+    /// file being in only one directory.
+    pub changes: Vec<String>,
+    /// The same differences in context, old as expected and new as
+    /// actual. Programs (`*.prog.bin`, and `*.prog.hex` read back as
+    /// little-endian words) are disassembled.
+    pub detail: Vec<DiffLine>,
+}
+
+/// Compare every file under `old` with its counterpart under `new`, e.g.
+/// the outputs of two tool versions, returning the files that differ in
+/// path order. Text files are compared line by line and binary files byte
+/// by byte, both by position, since a shifted ROM image is a real change;
+/// at most `limit` differences are listed per file.
+pub fn diff_artifact_dirs(
+    old: &std::path::Path,
+    new: &std::path::Path,
+    limit: usize,
+) -> Result<Vec<ArtifactDiff>, Error> {
+    let mut files = std::collections::BTreeSet::new();
+    for dir in [old, new] {
+        collect_relative_files(dir, dir, &mut files)?;
+    }
+    let mut diffs = Vec::new();
+    for file in files {
+        let read = |dir: &std::path::Path| -> Result<Option<Vec<u8>>> {
+            let path = dir.join(&file);
+            if path.is_file() {
+                Ok(Some(
+                    fs::read(&path).with_context(|| format!("reading {}", path.display()))?,
+                ))
+            } else {
+                Ok(None)
+            }
+        };
+        let name = file.to_string_lossy();
+        let (changes, detail) = match (read(old)?, read(new)?) {
+            (Some(a), Some(b)) if a == b => continue,
+            (Some(a), Some(b)) => match (std::str::from_utf8(&a), std::str::from_utf8(&b)) {
+                (Ok(a), Ok(b)) if is_text(a) && is_text(b) => {
+                    let detail = match (
+                        name.ends_with(".prog.hex"),
+                        prog_hex_bytes(a),
+                        prog_hex_bytes(b),
+                    ) {
+                        (true, Some(a), Some(b)) => program_diff(&a, &b, DIFF_CONTEXT, limit),
+                        _ => Vec::new(),
+                    };
+                    let a: Vec<&str> = a.lines().collect();
+                    let b: Vec<&str> = b.lines().collect();
+                    let changes = positional_changes(&a, &b, limit, "lines", |i, x, y| {
+                        format!("line {}: {} -> {}", i + 1, x, y)
+                    });
+                    let detail = if detail.is_empty() {
+                        line_diff(&a, &b, DIFF_CONTEXT, limit)
+                    } else {
+                        detail
+                    };
+                    (changes, detail)
+                }
+                _ => {
+                    let changes = positional_changes(&a, &b, limit, "bytes", |i, x, y| {
+                        format!("offset {:#06X}: {:02X} -> {:02X}", i, x, y)
+                    });
+                    let detail = if name.ends_with(".prog.bin") {
+                        program_diff(&a, &b, DIFF_CONTEXT, limit)
+                    } else {
+                        byte_diff(&a, &b, DIFF_CONTEXT, limit)
+                    };
+                    (changes, detail)
+                }
+            },
+            (Some(_), None) => (vec![format!("only in {}", old.display())], Vec::new()),
+            (None, _) => (vec![format!("only in {}", new.display())], Vec::new()),
+        };
+        diffs.push(ArtifactDiff {
+            file,
+            changes,
+            detail,
+        });
+    }
+    Ok(diffs)
+}
+
+/// Read a prog.hex back with its word width taken from the digits on
+/// its first line, assuming the default little-endian layout.
+fn prog_hex_bytes(text: &str) -> Option<Vec<u8>> {
+    let digits = text
+        .lines()
+        .map(|l| l.split("//").next().unwrap_or_default().trim())
+        .find(|w| !w.is_empty())?
+        .len();
+    parse_prog_hex(text, digits as u32 * 4, Endian::Little).ok()
+}
+
+/// Whether decoded file contents look like text rather than an image
+/// that happens to be valid UTF-8.
+fn is_text(s: &str) -> bool {
+    s.chars()
+        .all(|c| !c.is_control() || matches!(c, '\n' | '\r' | '\t'))
+}
+
+/// Every file below `dir`, relative to `root`.
+fn collect_relative_files(
+    root: &std::path::Path,
+    dir: &std::path::Path,
+    out: &mut std::collections::BTreeSet<PathBuf>,
+) -> Result<()> {
+    for entry in fs::read_dir(dir).with_context(|| format!("reading {}", dir.display()))? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_relative_files(root, &path, out)?;
+        } else {
+            out.insert(path.strip_prefix(root)?.to_path_buf());
+        }
+    }
+    Ok(())
+}
+
+/// The positions where `old` and `new` differ, then any length change.
+fn positional_changes<T: PartialEq>(
+    old: &[T],
+    new: &[T],
+    limit: usize,
+    unit: &str,
+    show: impl Fn(usize, &T, &T) -> String,
+) -> Vec<String> {
+    let differing: Vec<usize> = (0..old.len().min(new.len()))
+        .filter(|&i| old[i] != new[i])
+        .collect();
+    let mut changes: Vec<String> = differing
+        .iter()
+        .take(limit)
+        .map(|&i| show(i, &old[i], &new[i]))
+        .collect();
+    if differing.len() > limit {
+        changes.push(format!("... {} more", differing.len() - limit));
+    }
+    if old.len() != new.len() {
+        changes.push(format!("{} {} -> {}", old.len(), unit, new.len()));
+    }
+    changes
+}
+
+// ---------------------------------------------------------------------------
+// Module encoding
+// ---------------------------------------------------------------------------
+
+/// Where the section whose contents start at `contents` begins: its id
+/// and size come just before, the size a LEB128 whose bytes all have the
+/// top bit set but the last.
+pub(crate) fn section_id_offset(wasm: &[u8], contents: usize) -> usize {
+    let mut id = contents - 1;
+    while wasm[id - 1] & 0x80 != 0 {
+        id -= 1;
+    }
+    id - 1
+}
+
+/// Append `value` as an unsigned LEB128.
+pub(crate) fn push_uleb(out: &mut Vec<u8>, mut value: usize) {
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+/// Append `value` as a signed LEB128.
+pub(crate) fn push_sleb(out: &mut Vec<u8>, mut value: i64) {
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        if (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0) {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+#[cfg(all(test, feature = "refexec"))]
+mod tests {
+    use super::*;
+    use crate::parse::operator_name;
+    use crate::{
+        compile_wasm_bytes, compile_wat, compile_wat_source, emit, generate_svh, run_with_wasmtime,
+    };
+    use wasmparser::Operator;
+
+    #[test]
+    fn test_json_output() {
+        let info = CompiledProgram {
+            name: "br".to_string(),
+            body_bytes: vec![0x02, 0x40, 0x0C, 0x00, 0x0B, 0x0F],
+            branch_table: vec![BranchEntry {
+                source_pc: 2,
+                target_pc: 5,
+            }],
+            expected: -1,
+            ..Default::default()
+        };
+        let json = test_json(&info);
+        assert_eq!(json["name"], "br");
+        assert_eq!(json["prog_len"], 6);
+        assert_eq!(json["prog"][2], 0x0C);
+        assert_eq!(json["branch_table"][0]["target_pc"], 5);
+        assert_eq!(json["expected"], -1);
+        assert_eq!(json["expect_trap"], false);
+        assert!(json["trap_kind"].is_null());
+        assert_eq!(json["mem_expected"], serde_json::json!([]));
+        assert!(json["cycle_budget"].is_null());
+    }
+
+    #[test]
+    fn test_c_header_output() {
+        let info = CompiledProgram {
+            name: "if-else".to_string(),
+            body_bytes: vec![0x41, 0x01, 0x0F],
+            expected: 7,
+            ..Default::default()
+        };
+        let h = generate_c_header(&info).unwrap();
+        assert!(h.contains("#ifndef WASM_IC_IF_ELSE_H\n"));
+        assert!(h.contains("#define IF_ELSE_PROG_LEN 3u\n"));
+        assert!(h.contains("#define IF_ELSE_BRANCH_COUNT 0u\n"));
+        assert!(h.contains("static const uint8_t if_else_prog[] = {\n    0x41, 0x01, 0x0F,\n};"));
+        assert!(h.contains("    { 0, 0 }, /* empty */\n"));
+        assert_eq!(sanitize_ident("1st"), "_1st");
+    }
+
+    #[test]
+    fn test_rust_consts_output() {
+        let info = CompiledProgram {
+            name: "branch".to_string(),
+            body_bytes: vec![0x02, 0x40, 0x0C, 0x00, 0x0B, 0x0F],
+            branch_table: vec![BranchEntry {
+                source_pc: 2,
+                target_pc: 5,
+            }],
+            expected: 99,
+            ..Default::default()
+        };
+        let rs = generate_rust_consts(&info).unwrap();
+        assert!(
+            rs.contains("pub const PROG: &[u8] = &[\n    0x02, 0x40, 0x0C, 0x00, 0x0B, 0x0F,\n];")
+        );
+        assert!(rs.contains(
+            "pub const BRANCH_TABLE: &[(u32, u32)] = &[\n    (0x00000002, 0x00000005),\n];"
+        ));
+        assert!(rs.contains("pub const MEM_INIT: &[u8] = &[\n];"));
+        assert!(rs.contains("pub const EXPECTED: i32 = 99;"));
+    }
+
+    #[test]
+    fn test_boot_image_round_trip() {
+        let info = CompiledProgram {
+            name: "img".to_string(),
+            body_bytes: vec![0x41, 0x2A, 0x0F],
+            branch_table: vec![BranchEntry {
+                source_pc: 0x10,
+                target_pc: 0x20,
+            }],
+            mem_init: vec![MemSegment {
+                memory: 0,
+                addr: 8,
+                data: vec![1, 2, 3],
+            }],
+            expected: -7,
+            ..Default::default()
+        };
+        let bytes = pack_boot_image(&info);
+        assert_eq!(&bytes[0..4], b"WICB");
+
+        let image = parse_boot_image(&bytes).unwrap();
+        assert_eq!(image.prog, info.body_bytes);
+        assert_eq!(image.branch_table, info.branch_table);
+        assert_eq!(image.mem_init, info.mem_init);
+        assert_eq!(image.expected, Some(-7));
+        assert!(!image.expect_trap);
+
+        let trapping = CompiledProgram {
+            expect_trap: true,
+            ..info.clone()
+        };
+        let image = parse_boot_image(&pack_boot_image(&trapping)).unwrap();
+        assert!(image.expect_trap);
+
+        assert!(parse_boot_image(&bytes[..bytes.len() - 1]).is_err());
+        assert!(parse_boot_image(b"XXXX\x02\x00\x00\x00").is_err());
+        // Version 1 images had no outcome word.
+        assert!(parse_boot_image(b"WICB\x01\x00\x00\x00").is_err());
+
+        // Offsets and lengths at the top of the range are rejected, not
+        // summed past it.
+        let mut huge = b"WICB\x02\x00\x01\x00".to_vec();
+        for word in [SECTION_MEM, 20, 12] {
+            huge.extend_from_slice(&word.to_le_bytes());
+        }
+        huge.extend_from_slice(&[0; 4]);
+        huge.extend_from_slice(&u32::MAX.to_le_bytes());
+        huge.extend_from_slice(&[0; 4]);
+        assert!(matches!(parse_boot_image(&huge), Err(Error::ParseError(_))));
+        huge[12..16].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(parse_boot_image(&huge), Err(Error::ParseError(_))));
+    }
+
+    #[test]
+    fn test_coe_output() {
+        let entries = [BranchEntry {
+            source_pc: 2,
+            target_pc: 9,
+        }];
+        let image = branch_table_image(&entries, 4).unwrap();
+        assert_eq!(image, vec![0, 0, 0x1_0000_0009, 0]);
+
+        // pc 6 lands in pc 2's slot of a 4-slot table.
+        let clash = [
+            entries[0].clone(),
+            BranchEntry {
+                source_pc: 6,
+                target_pc: 1,
+            },
+        ];
+        assert!(matches!(
+            branch_table_image(&clash, 4),
+            Err(Error::CapacityExceeded(_))
+        ));
+        assert!(branch_table_image(&clash, 8).is_ok());
+
+        let coe = coe_text(&[0x41, 0x0A, 0x0F], 8);
+        assert!(coe.contains("memory_initialization_radix=16;\n"));
+        assert!(coe.ends_with("memory_initialization_vector=\n41,\n0A,\n0F;\n"));
+
+        let coe = coe_text(&image, BRANCH_WORD_BITS);
+        assert!(coe.contains("\n100000009,\n"));
+    }
+
+    #[test]
+    fn test_pack_words_endian() {
+        let prog = [0x41, 0x0A, 0x41, 0x14, 0x6A, 0x0F];
+        assert_eq!(
+            pack_words(&prog, 32, Endian::Little).unwrap(),
+            vec![0x14410A41, 0x0F6A]
+        );
+        assert_eq!(
+            pack_words(&prog, 32, Endian::Big).unwrap(),
+            vec![0x410A4114, 0x6A0F0000]
+        );
+        assert_eq!(
+            pack_words(&prog, 8, Endian::Big).unwrap(),
+            prog.iter().map(|&b| b as u64).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_annotated_hex() {
+        let body = [0x41, 0x0A, 0x41, 0x14, 0x6A, 0x28, 0x02, 0x04, 0x0F];
+        let text = prog_hex_text(&body, 8, Endian::Little, true).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[0], "41  // pc=0  i32.const 10");
+        assert_eq!(lines[1], "0A  // pc=1");
+        assert_eq!(lines[4], "6A  // pc=4  i32.add");
+        assert_eq!(lines[5], "28  // pc=5  i32.load offset=4");
+        assert_eq!(lines[8], "0F  // pc=8  return");
+
+        let text = prog_hex_text(&body, 32, Endian::Little, true).unwrap();
+        assert_eq!(
+            text.lines().next().unwrap(),
+            "14410A41  // pc=0  i32.const 10; i32.const 20"
+        );
+        assert_eq!(
+            operator_name(&Operator::I32Load8S {
+                memarg: wasmparser::MemArg {
+                    align: 0,
+                    max_align: 0,
+                    offset: 0,
+                    memory: 0
+                }
+            }),
+            "i32.load8_s"
+        );
+        assert_eq!(
+            operator_name(&Operator::MemorySize { mem: 0 }),
+            "memory.size"
+        );
+        assert_eq!(operator_name(&Operator::I32Extend8S), "i32.extend8_s");
+        let memarg = wasmparser::MemArg {
+            align: 0,
+            max_align: 0,
+            offset: 0,
+            memory: 0,
+        };
+        assert_eq!(
+            operator_name(&Operator::I32AtomicRmw8AddU { memarg }),
+            "i32.atomic.rmw8.add_u"
+        );
+        assert_eq!(
+            operator_name(&Operator::MemoryAtomicWait32 { memarg }),
+            "memory.atomic.wait32"
+        );
+        assert_eq!(operator_name(&Operator::AtomicFence), "atomic.fence");
+        assert_eq!(
+            operator_name(&Operator::I8x16ExtractLaneS { lane: 0 }),
+            "i8x16.extract_lane_s"
+        );
+        assert_eq!(operator_name(&Operator::V128AnyTrue), "v128.any_true");
+        assert_eq!(
+            operator_name(&Operator::TypedSelect {
+                ty: wasmparser::ValType::I32
+            }),
+            "select"
+        );
+        assert_eq!(
+            operator_name(&Operator::BrIf { relative_depth: 0 }),
+            "br_if"
+        );
+    }
+
+    #[test]
+    fn test_branch_hex_layout() {
+        let entries = [BranchEntry {
+            source_pc: 2,
+            target_pc: 26,
+        }];
+        assert_eq!(
+            branch_hex_text(&entries, &BranchHexLayout::default()),
+            "00000002 0000001A\n"
+        );
+        let layout = BranchHexLayout {
+            radix: Radix::Dec,
+            width: 4,
+            separator: ", ".to_string(),
+        };
+        assert_eq!(branch_hex_text(&entries, &layout), "0002, 0026\n");
+        let layout = BranchHexLayout {
+            radix: Radix::Hex,
+            width: 0,
+            separator: "\t".to_string(),
+        };
+        assert_eq!(branch_hex_text(&entries, &layout), "2\t1A\n");
+    }
+
+    #[test]
+    fn test_compile_to_dir_naming() {
+        let dir = std::env::temp_dir().join(format!("wasm-ic-naming-{}", std::process::id()));
+        let input = dir.join("two.wat");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            &input,
+            r#"(module (func (export "main") (result i32) i32.const 2))"#,
+        )
+        .unwrap();
+
+        let (info, paths) = compile_to_dir(&input, &dir, &EmitOptions::default()).unwrap();
+        assert_eq!(info.expected, 2);
+        let names: Vec<_> = paths
+            .iter()
+            .map(|p| p.file_name().unwrap().to_string_lossy().to_string())
+            .collect();
+        assert_eq!(
+            names,
+            ["two.prog.hex", "two.branch.hex", "two.expected.txt"]
+        );
+        assert!(paths.iter().all(|p| p.exists()));
+
+        let opts = EmitOptions {
+            prefix: Some(String::new()),
+            ..Default::default()
+        };
+        let (_, paths) = compile_to_dir(&input, &dir, &opts).unwrap();
+        assert_eq!(paths[0], dir.join("prog.hex"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_expected_spec() {
+        let info = compile_wat_source(
+            "dz",
+            r#";; max-cycles: 90
+            (module (func (export "main") (result i32) i32.const 1 i32.const 0 i32.div_s))"#,
+        )
+        .unwrap();
+        let spec = emit::ExpectedSpec::of(&info);
+        assert_eq!(spec.cycle_budget, Some(90));
+        #[cfg(feature = "refexec")]
+        {
+            assert_eq!(spec.trap.as_deref(), Some("integer divide by zero"));
+            assert!(spec.returns.is_empty());
+        }
+        assert_eq!(
+            emit::ExpectedSpec::from_json(&spec.to_json()).unwrap(),
+            spec
+        );
+
+        let dir = std::env::temp_dir().join(format!("wasm-ic-spec-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let opts = EmitOptions {
+            expected_spec: true,
+            ..EmitOptions::default()
+        };
+        let paths = emit_artifacts(&info, &dir, &opts).unwrap();
+        assert!(paths.iter().any(|p| p.ends_with("dz.expected.txt")));
+        assert!(paths.iter().any(|p| p.ends_with("dz.expected.json")));
+        let read = read_hex_artifacts(&dir, "dz", &opts).unwrap();
+        assert_eq!(
+            (read.expect_trap, read.trap_kind, read.cycle_budget),
+            (info.expect_trap, info.trap_kind.clone(), Some(90))
+        );
+        assert!(verify_artifacts(&info, &dir, &opts).unwrap().is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+
+        let returns = emit::parse_expected_spec(
+            r#"{"version": 2, "returns": [{"type": "i32", "value": -7}],
+                "memory": [{"addr": 8, "value": 4294967293}], "metadata": {}}"#,
+        )
+        .unwrap();
+        let mut read = info.clone();
+        returns.apply(&mut read);
+        assert_eq!((read.expected, read.expect_trap), (-7, false));
+        assert_eq!(
+            read.mem_expected,
+            [MemCheck {
+                memory: 0,
+                addr: 8,
+                value: 0xFFFF_FFFD
+            }]
+        );
+        for bad in [
+            r#"{"version": 1}"#,
+            r#"{"version": 2, "returns": [{"type": "i64", "value": 1}]}"#,
+            r#"{"version": 2, "returns": [{"type": "i32", "value": 1}], "trap": "any"}"#,
+            r#"{"version": 2, "globals": []}"#,
+        ] {
+            assert!(emit::parse_expected_spec(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_read_hex_artifacts() {
+        let info = compile_wat_source(
+            "r",
+            r#"(module (memory 1) (data (i32.const 2) "\05\06")
+                (func (export "main") (result i32)
+                (i32.store (i32.const 8) (i32.const -3))
+                (block (br 0)) i32.const -7))"#,
+        )
+        .unwrap();
+        let dir = std::env::temp_dir().join(format!("wasm-ic-read-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let opts = EmitOptions {
+            annotate: true,
+            ..EmitOptions::default()
+        };
+        emit_artifacts(&info, &dir, &opts).unwrap();
+        let read = read_hex_artifacts(&dir, "r", &opts).unwrap();
+        assert_eq!(read.body_bytes, info.body_bytes);
+        assert_eq!(read.branch_table, info.branch_table);
+        assert_eq!(read.expected, -7);
+        assert_eq!(read.mem_expected, info.mem_expected);
+        assert_eq!(read.mem_init[0].data, [0, 0, 5, 6]);
+        assert_eq!(
+            read_expected(&dir.join("r.expected.txt")).unwrap(),
+            info.expected
+        );
+
+        assert_eq!(
+            parse_mem_expected_hex("// c\n00000008 FFFFFFFD\n").unwrap(),
+            [MemCheck {
+                memory: 0,
+                addr: 8,
+                value: 0xFFFF_FFFD
+            }]
+        );
+        assert!(parse_expected("1\n2\n").is_err());
+        assert!(parse_mem_hex("0G\n").is_err());
+        fs::remove_file(dir.join("r.branch.hex")).unwrap();
+        assert!(read_hex_artifacts(&dir, "r", &opts).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_verify_artifacts() {
+        let info = compile_wat_source(
+            "v",
+            r#"(module (func (export "main") (result i32)
+                (block (br 0)) i32.const 7))"#,
+        )
+        .unwrap();
+        let dir = std::env::temp_dir().join(format!("wasm-ic-verify-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let opts = EmitOptions {
+            data_width: 16,
+            annotate: true,
+            ..EmitOptions::default()
+        };
+        emit_artifacts(&info, &dir, &opts).unwrap();
+        assert_eq!(verify_artifacts(&info, &dir, &opts).unwrap(), []);
+
+        let prog = dir.join("v.prog.hex");
+        let text = fs::read_to_string(&prog).unwrap();
+        fs::write(&prog, text.replacen("0C", "0D", 1)).unwrap();
+        fs::write(dir.join("v.branch.hex"), "").unwrap();
+        fs::remove_file(dir.join("v.expected.txt")).unwrap();
+        fs::write(dir.join("v.mem_init.hex"), "00\n").unwrap();
+        let problems = verify_artifacts(&info, &dir, &opts).unwrap();
+        assert_eq!(
+            problems.iter().map(|p| p.to_string()).collect::<Vec<_>>(),
+            [
+                "v.prog.hex: file has pc 0x0002: 0D, the source gives pc 0x0002: 0C",
+                "v.branch.hex: file has 0 entries, the source gives 1",
+                "v.mem_init.hex: left over: the source no longer produces it",
+                "v.expected.txt: missing",
+            ]
+        );
+        assert_eq!(
+            problems[0].detail[..3],
+            [
+                DiffLine::Same("0000  02 40  block".to_string()),
+                DiffLine::Expected("0002  0C 00  br 0".to_string()),
+                DiffLine::Actual("0002  0D 00  br_if 0".to_string()),
+            ]
+        );
+        assert_eq!(
+            problems[1].detail,
+            [DiffLine::Expected("#0   0002 -> 0005  br 0".to_string())]
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_diff_artifact_dirs() {
+        let root = std::env::temp_dir().join(format!("wasm-ic-diff-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let (old, new) = (root.join("old"), root.join("new"));
+        for dir in [&old, &new] {
+            fs::create_dir_all(dir.join("add")).unwrap();
+            fs::write(dir.join("add/add.expected.txt"), "30\n").unwrap();
+        }
+        fs::write(old.join("add/add.prog.hex"), "41\n0A\n0F\n").unwrap();
+        fs::write(new.join("add/add.prog.hex"), "41\n0B\n6A\n0F\n").unwrap();
+        fs::write(old.join("add/add.prog.bin"), [0x41, 0x0A, 0x0F]).unwrap();
+        fs::write(new.join("add/add.prog.bin"), [0x41, 0x0B, 0x0F]).unwrap();
+        fs::write(new.join("add/add.branch.hex"), "").unwrap();
+
+        let diffs = diff_artifact_dirs(&old, &new, 1).unwrap();
+        let files: Vec<_> = diffs.iter().map(|d| d.file.clone()).collect();
+        assert_eq!(
+            files,
+            ["add/add.branch.hex", "add/add.prog.bin", "add/add.prog.hex"].map(PathBuf::from)
+        );
+        assert_eq!(diffs[0].changes, [format!("only in {}", new.display())]);
+        assert_eq!(diffs[1].changes, ["offset 0x0001: 0A -> 0B"]);
+        assert_eq!(
+            diffs[2].changes,
+            ["line 2: 0A -> 0B", "... 1 more", "3 lines -> 4"]
+        );
+        assert_eq!(
+            diffs[1].detail,
+            [
+                DiffLine::Expected("0000  41 0A  i32.const 10".to_string()),
+                DiffLine::Actual("0000  41 0B  i32.const 11".to_string()),
+                DiffLine::Same("0002  0F     return".to_string()),
+            ]
+        );
+        assert_eq!(
+            diffs[2].detail[4],
+            DiffLine::Actual("0003  0F     return".to_string())
+        );
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_emit_rom_overflow() {
+        let info = compile_wat_source(
+            "long",
+            "(module (func (export \"main\") (result i32) i32.const 1 i32.const 2 i32.add))",
+        )
+        .unwrap();
+        let len = info.body_bytes.len();
+        let mut opts = EmitOptions {
+            hardware: HardwareSpec::from_toml("rom_depth = 4").unwrap(),
+            ..EmitOptions::default()
+        };
+        let dir = std::env::temp_dir().join(format!("wasm-ic-overflow-{}", std::process::id()));
+        let error = emit_artifacts(&info, &dir, &opts).unwrap_err();
+        assert!(matches!(error, Error::CapacityExceeded(_)));
+        assert!(
+            error
+                .to_string()
+                .contains(&format!("{} bytes, {} over the 4-byte ROM", len, len - 4)),
+            "{}",
+            error
+        );
+        assert!(!dir.exists());
+
+        opts.force = true;
+        let paths = emit_artifacts(&info, &dir, &opts).unwrap();
+        let prog = paths.iter().find(|p| p.ends_with("long.prog.hex")).unwrap();
+        let text = fs::read_to_string(prog).unwrap();
+        assert_eq!(text.lines().filter(|l| !l.starts_with("//")).count(), len);
+        // Forcing only lets the program past the ROM.
+        opts.hardware.stack_depth = 1;
+        assert!(opts.check_fits(&info).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_artifact_metadata() {
+        let source = r#"(module (memory 1) (data (i32.const 0) "\07")
+            (func (export "main") (result i32)
+              (block (br 0)) i32.const 0 i32.load8_u))"#;
+        let info = compile_wat_source("meta", source).unwrap();
+        let input = format!(
+            "crc32:{:08x}",
+            ChecksumAlgo::Crc32.checksum(source.as_bytes())
+        );
+        assert_eq!(
+            info.input_hash,
+            Some(ChecksumAlgo::Crc32.checksum(source.as_bytes()))
+        );
+
+        let dir = std::env::temp_dir().join(format!("wasm-ic-meta-{}", std::process::id()));
+        let opts = EmitOptions {
+            checksums: Some(ChecksumAlgo::Crc32),
+            ..EmitOptions::default()
+        };
+        emit_artifacts(&info, &dir, &opts).unwrap();
+        for file in ["meta.prog.hex", "meta.branch.hex", "meta.mem_init.hex"] {
+            let text = fs::read_to_string(dir.join(file)).unwrap();
+            assert!(text.contains(&format!("// input: {}\n", input)), "{}", file);
+        }
+        let branch = fs::read_to_string(dir.join("meta.branch.hex")).unwrap();
+        let layout = BranchHexLayout::default();
+        assert_eq!(
+            parse_branch_hex(&branch, &layout).unwrap(),
+            info.branch_table
+        );
+        assert_eq!(verify_artifacts(&info, &dir, &opts).unwrap(), []);
+        let sums = fs::read_to_string(dir.join("meta.checksums.json")).unwrap();
+        let sums: serde_json::Value = serde_json::from_str(&sums).unwrap();
+        assert_eq!(sums["metadata"]["input"], input);
+        assert_eq!(sums["metadata"]["generator"], emit::generator());
+        fs::remove_dir_all(&dir).unwrap();
+
+        let json = EmitOptions {
+            format: ArtifactFormat::Json,
+            ..EmitOptions::default()
+        };
+        let doc = render_artifact(&info, ArtifactKind::Program, &json).unwrap();
+        let doc: serde_json::Value = serde_json::from_slice(&doc).unwrap();
+        assert_eq!(
+            doc["metadata"]["options"],
+            "format=json width=8 endian=little annotate=false branch_slots=256"
+        );
+        let coe = EmitOptions {
+            format: ArtifactFormat::Coe,
+            ..EmitOptions::default()
+        };
+        let coe = render_artifact(&info, ArtifactKind::Prog, &coe).unwrap();
+        let coe = String::from_utf8(coe).unwrap();
+        assert!(coe.starts_with("; Auto-generated by wasm-compile. Do not edit.\n; generator: "));
+
+        let svh = generate_svh(std::slice::from_ref(&info));
+        assert!(svh.contains(&format!("// meta input: {}\nlocalparam", input)));
+        let bin = EmitOptions {
+            format: ArtifactFormat::Bin,
+            ..EmitOptions::default()
+        };
+        let prog = render_artifact(&info, ArtifactKind::Prog, &bin).unwrap();
+        assert_eq!(prog, info.body_bytes);
+    }
+
+    #[test]
+    fn test_artifact_emitter() {
+        struct Loader;
+        impl ArtifactEmitter for Loader {
+            fn name(&self) -> &str {
+                "loader"
+            }
+            fn extension(&self) -> &str {
+                "ldr"
+            }
+            fn emit(&self, info: &CompiledProgram) -> Result<Vec<u8>, Error> {
+                Ok([&[info.body_bytes.len() as u8][..], &info.body_bytes].concat())
+            }
+        }
+
+        let info = compile_wat_source(
+            "plug",
+            r#"(module (func (export "main") (result i32) i32.const 3))"#,
+        )
+        .unwrap();
+        let mut registry = EmitterRegistry::with_builtins(&EmitOptions::default());
+        let names = registry.names();
+        assert_eq!(names[..3], ["hex-prog", "hex-branch", "hex-mem"]);
+        assert!(names.contains(&"json") && names.contains(&"bin-prog"));
+        assert_eq!(names.iter().filter(|&&n| n == "expected").count(), 1);
+        registry.register(Loader);
+
+        let dir = std::env::temp_dir().join(format!("wasm-ic-emitter-{}", std::process::id()));
+        let paths = registry
+            .emit(&info, &dir, "plug", &["loader", "hex-mem", "bin-prog"])
+            .unwrap();
+        assert_eq!(paths, [dir.join("plug.ldr"), dir.join("plug.prog.bin")]);
+        assert_eq!(
+            fs::read(dir.join("plug.ldr")).unwrap(),
+            [3, 0x41, 0x03, 0x0F]
+        );
+        let e = registry.emit(&info, &dir, "plug", &["nope"]).unwrap_err();
+        assert!(e.to_string().contains("\"nope\""));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_link_module() {
+        let wasm = compile_wat(
+            r#"(module (memory 1) (data (i32.const 4) "\01\02")
+                (func (export "main") (result i32) (block (br 0)) i32.const 9))"#,
+        )
+        .unwrap();
+        let info = compile_wasm_bytes("linked", &wasm).unwrap();
+        let spec = HardwareSpec::from_toml(
+            "branch_slots = 8\nrom_depth = 256\nunaligned = false\nsign_extension = true",
+        )
+        .unwrap();
+        assert_eq!(linked_image(&wasm).unwrap(), None);
+
+        let linked = link_module(&wasm, &info, &spec).unwrap();
+        let image = linked_image(&linked).unwrap().unwrap();
+        assert_eq!(image.prog, info.body_bytes);
+        assert_eq!(image.branch_table, info.branch_table);
+        assert_eq!(image.mem_init, info.mem_init);
+        assert_eq!(image.expected, Some(9));
+        assert_eq!(image.hardware, Some(spec.clone()));
+
+        // Still the same program to an engine and to the compiler, and
+        // linking again replaces the section rather than adding one.
+        assert_eq!(run_with_wasmtime(&linked).unwrap(), 9);
+        let relinked = link_module(&linked, &info, &HardwareSpec::default()).unwrap();
+        let sections = wasmparser::Parser::new(0)
+            .parse_all(&relinked)
+            .filter(|p| matches!(p, Ok(wasmparser::Payload::CustomSection(r)) if r.name() == LINK_SECTION))
+            .count();
+        assert_eq!(sections, 1);
+        let image = linked_image(&relinked).unwrap().unwrap();
+        assert_eq!(image.hardware, Some(HardwareSpec::default()));
+        assert_eq!(
+            compile_wasm_bytes("linked", &relinked).unwrap().body_bytes,
+            info.body_bytes
+        );
+
+        let tight = HardwareSpec::from_toml("rom_depth = 2").unwrap();
+        assert!(matches!(
+            link_module(&wasm, &info, &tight),
+            Err(Error::CapacityExceeded(_))
+        ));
+    }
+
+    #[test]
+    fn test_opcode_map() {
+        let info = compile_wat_source(
+            "remap",
+            r#"(module (memory 1)
+                (func (export "main") (result i32)
+                  (block (br 0))
+                  i32.const 0 i32.const 0 i32.const 0 memory.fill
+                  (block (br 0))
+                  i32.const 3))"#,
+        )
+        .unwrap();
+        let map = OpcodeMap::from_toml(
+            "block = 1\nbr = 2\nend = 3\n\"i32.const\" = 4\n\"memory.fill\" = 5\nreturn = 6",
+        )
+        .unwrap();
+        let (remapped, insns) = map.remap(&info).unwrap();
+        assert_eq!(
+            remapped.body_bytes,
+            [1, 0x40, 2, 0, 3, 4, 0, 4, 0, 4, 0, 5, 0, 1, 0x40, 2, 0, 3, 4, 3, 6]
+        );
+        // The prefixed memory.fill shrinks by a byte, moving what follows.
+        let pairs: Vec<_> = remapped
+            .branch_table
+            .iter()
+            .map(|e| (e.source_pc, e.target_pc))
+            .collect();
+        assert_eq!(pairs, [(2, 5), (15, 18)]);
+        assert_eq!((insns[6].offset, insns[6].len), (11, 2));
+
+        let opts = EmitOptions {
+            annotate: true,
+            opcode_map: Some(map),
+            ..EmitOptions::default()
+        };
+        let hex =
+            String::from_utf8(render_artifact(&info, ArtifactKind::Prog, &opts).unwrap()).unwrap();
+        assert!(hex.contains(" opcode_map=crc32:"), "{}", hex);
+        assert!(hex.contains("05  // pc=11  memory.fill"), "{}", hex);
+        assert!(hex.contains("01  // pc=13  block"), "{}", hex);
+
+        let partial = OpcodeMap::from_toml("block = 1").unwrap();
+        assert!(matches!(
+            partial.remap_body(&info.body_bytes),
+            Err(Error::UnsupportedOp { offset: 2, .. })
+        ));
+        let err = OpcodeMap::from_toml("nop = 1\nend = 1").unwrap_err();
+        assert!(err.to_string().contains("both map to 0x01"), "{}", err);
+        assert!(OpcodeMap::from_toml("nop = 256").is_err());
+    }
+
+    #[test]
+    fn test_render_artifact() {
+        let info = CompiledProgram {
+            name: "r".to_string(),
+            body_bytes: vec![0x41, 0x05, 0x0F],
+            expected: 5,
+            ..Default::default()
+        };
+        let opts = EmitOptions::default();
+        let prog = String::from_utf8(render_artifact(&info, ArtifactKind::Prog, &opts).unwrap());
+        let prog = prog.unwrap();
+        assert!(prog.starts_with("// generator: wasm-compile "), "{}", prog);
+        assert!(prog.contains("\n// options: format=hex width=8 endian=little"));
+        assert!(!prog.contains("// input:"));
+        assert!(prog.ends_with("\n41\n05\n0F\n"));
+        assert_eq!(
+            parse_prog_hex(&prog, 8, Endian::Little).unwrap(),
+            info.body_bytes
+        );
+        assert_eq!(
+            render_artifact(&info, ArtifactKind::Expected, &opts).unwrap(),
+            b"5\n"
+        );
+        assert!(render_artifact(&info, ArtifactKind::Program, &opts).is_err());
+
+        let opts = EmitOptions {
+            format: ArtifactFormat::Bin,
+            ..Default::default()
+        };
+        assert_eq!(
+            render_artifact(&info, ArtifactKind::Prog, &opts).unwrap(),
+            info.body_bytes
+        );
+    }
+
+    #[test]
+    fn test_stimulus_output() {
+        let info = CompiledProgram {
+            name: "s".to_string(),
+            body_bytes: vec![0x41, 0x2A, 0x0F],
+            branch_table: vec![BranchEntry {
+                source_pc: 1,
+                target_pc: 2,
+            }],
+            mem_init: vec![MemSegment {
+                memory: 0,
+                addr: 1,
+                data: vec![0xFF],
+            }],
+            expected: 42,
+            ..Default::default()
+        };
+        let text = generate_stimulus(&info).unwrap();
+        let body: Vec<&str> = text.lines().skip(1).collect();
+        assert_eq!(
+            body,
+            [
+                "PROG 3",
+                "41",
+                "2A",
+                "0F",
+                "BRANCH 1",
+                "00000001 00000002",
+                "MEM 2",
+                "00",
+                "FF",
+                "EXPECTED 1",
+                "42"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_sv_rom() {
+        let prog = [0x41, 0x07, 0x0F];
+        let sv = generate_sv_rom(&prog, &RomOptions::default()).unwrap();
+        assert!(sv.contains(
+            "module prog_rom (\n    input  logic [1:0] addr,\n    output logic [7:0] data\n);"
+        ));
+        assert!(sv.contains("            1: data = 8'h07;\n"));
+        assert!(sv.contains("default: data = '0;"));
+
+        let opts = RomOptions {
+            style: RomStyle::Array,
+            data_width: 16,
+            depth: Some(4),
+            ..Default::default()
+        };
+        let sv = generate_sv_rom(&prog, &opts).unwrap();
+        assert!(sv.contains(
+            "        16'h0741,\n        16'h000F,\n        16'h0000,\n        16'h0000\n    };"
+        ));
+
+        let opts = RomOptions {
+            depth: Some(2),
+            ..Default::default()
+        };
+        assert!(generate_sv_rom(&prog, &opts).is_err());
+        assert_eq!(addr_bits(1), 1);
+        assert_eq!(addr_bits(256), 8);
+        assert_eq!(addr_bits(257), 9);
+    }
+
+    #[test]
+    fn test_veryl_roms() {
+        let opts = RomOptions {
+            module_name: "ProgRom".to_string(),
+            ..Default::default()
+        };
+        let veryl = generate_veryl_prog_rom(&[0x41, 0x07, 0x0F], &opts).unwrap();
+        assert!(veryl.contains("module ProgRom (\n"));
+        assert!(veryl.contains("    o_data: output logic<8>, /// Program word\n"));
+        assert!(veryl.contains("            32'd1  : o_data = 8'h07;\n"));
+        assert!(veryl.contains("            default: o_data = 8'h00;\n"));
+
+        let entries = [BranchEntry {
+            source_pc: 2,
+            target_pc: 7,
+        }];
+        let veryl = generate_veryl_branch_rom(&entries, "BranchRom", LookupStyle::Case);
+        assert!(veryl.contains("module BranchRom (\n"));
+        assert!(veryl.contains("            32'd2  : o_rd_data = 32'h00000007;\n"));
+        assert!(veryl.contains("o_rd_valid = 1'b0;"));
+
+        let opts = RomOptions {
+            style: RomStyle::Array,
+            ..Default::default()
+        };
+        assert!(generate_veryl_prog_rom(&[0x0F], &opts).is_err());
+    }
+
+    #[test]
+    fn test_branch_lookup_tree() {
+        let entries = [
+            BranchEntry {
+                source_pc: 9,
+                target_pc: 1,
+            },
+            BranchEntry {
+                source_pc: 2,
+                target_pc: 7,
+            },
+            BranchEntry {
+                source_pc: 5,
+                target_pc: 3,
+            },
+        ];
+        let sorted = sorted_branch_entries(&entries);
+        assert_eq!(
+            sorted.iter().map(|e| e.source_pc).collect::<Vec<_>>(),
+            [2, 5, 9]
+        );
+
+        let tree = lookup_tree(&sorted, Hdl::Sv, 0);
+        assert!(tree.starts_with("(i_rd_addr < 32'd5) ?"));
+        assert!(tree.contains("(i_rd_addr == 32'd2) ? {1'b1, 32'h00000007} : 33'h0"));
+        assert!(tree.contains("(i_rd_addr == 32'd9) ? {1'b1, 32'h00000001} : 33'h0"));
+
+        let sv = generate_sv_branch_rom(&entries, "branch_rom", LookupStyle::Case);
+        assert!(sv.contains("            32'd5: o_rd_data = 32'h00000003;\n"));
+
+        let veryl = generate_veryl_branch_rom(&entries, "BranchRom", LookupStyle::Tree);
+        assert!(veryl.contains("let lookup: logic<33> = if i_rd_addr <: 32'd5 {\n"));
+        assert!(veryl.contains(
+            "        if i_rd_addr == 32'd2 { {1'b1, 32'h00000007} } else { 33'h0 }\n    } else {\n"
+        ));
+        assert!(!veryl.contains('?'));
+        assert_eq!(lookup_tree(&[], Hdl::Veryl, 0), "33'h0");
+    }
+
+    #[test]
+    fn test_bus_stream() {
+        let info = CompiledProgram {
+            name: "bus".to_string(),
+            body_bytes: vec![0x41, 0x01, 0x04, 0x7F, 0x0F],
+            branch_table: vec![BranchEntry {
+                source_pc: 2,
+                target_pc: 5,
+            }],
+            mem_init: vec![MemSegment {
+                memory: 0,
+                addr: 0,
+                data: vec![0xAA],
+            }],
+            ..Default::default()
+        };
+        let map = BusMap {
+            start_addr: Some(0x3000_0000),
+            ..Default::default()
+        };
+        let writes = bus_transactions(&info, &map).unwrap();
+        assert_eq!(
+            writes,
+            [
+                BusWrite {
+                    addr: 0x0000_0000,
+                    data: 0x7F04_0141
+                },
+                BusWrite {
+                    addr: 0x0000_0004,
+                    data: 0x0000_000F
+                },
+                BusWrite {
+                    addr: 0x0001_0008,
+                    data: 5
+                },
+                BusWrite {
+                    addr: 0x0002_0000,
+                    data: 0xAA
+                },
+                BusWrite {
+                    addr: 0x3000_0000,
+                    data: 1
+                },
+            ]
+        );
+        let text = generate_bus_stream(&info, &map).unwrap();
+        assert!(text.contains("# branch (1 entries)\nW 00010008 00000005\n# mem\n"));
+        assert!(text.ends_with("# start\nW 30000000 00000001\n"));
+
+        // A branch slot or word past 4 GiB fails instead of wrapping.
+        let high = BusMap {
+            branch_base: 0xFFFF_FFFC,
+            ..Default::default()
+        };
+        assert!(matches!(
+            bus_transactions(&info, &high),
+            Err(Error::CapacityExceeded(_))
+        ));
+        let high = BusMap {
+            prog_base: 0xFFFF_FFFC,
+            ..Default::default()
+        };
+        assert!(matches!(
+            bus_transactions(&info, &high),
+            Err(Error::CapacityExceeded(_))
+        ));
+    }
+
+    #[test]
+    fn test_checksums() {
+        assert_eq!(ChecksumAlgo::Crc32.checksum(b"123456789"), 0xCBF4_3926);
+        assert_eq!(ChecksumAlgo::Crc32c.checksum(b"123456789"), 0xE306_9283);
+        assert_eq!(ChecksumAlgo::Crc32.checksum(&[]), 0);
+
+        let info = CompiledProgram {
+            name: "sum".to_string(),
+            body_bytes: b"123456789".to_vec(),
+            ..Default::default()
+        };
+        let sums = artifact_checksums(&info, ChecksumAlgo::Crc32).unwrap();
+        assert_eq!(sums.prog, 0xCBF4_3926);
+        let svh = checksums_svh(&info, &sums);
+        assert!(svh.contains("localparam logic [31:0] SUM_PROG_CRC32 = 32'hCBF43926;\n"));
+        assert!(svh.contains("localparam int          SUM_PROG_BYTES = 9;\n"));
+        assert!(checksums_json(&info, &sums).contains("\"checksum\": \"0xCBF43926\""));
+    }
+
+    #[test]
+    fn test_mif_output() {
+        let words = pack_words(&[0x41, 0x0A, 0x0F], 16, Endian::Little).unwrap();
+        assert_eq!(words, vec![0x0A41, 0x000F]);
+        assert!(pack_words(&[0], 12, Endian::Little).is_err());
+
+        let mif = mif_text(&words, 16, 8).unwrap();
+        assert!(mif.contains("WIDTH=16;\nDEPTH=8;\n"));
+        assert!(mif.contains("    0 : 0A41;\n    1 : 000F;\n    [2..7] : 0000;\nEND;\n"));
+
+        assert!(mif_text(&words, 16, 1).is_err());
+
+        // The branch image goes through the same slot check as branch.coe.
+        let dir = std::env::temp_dir().join(format!("wasm-ic-mif-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("branch.mif");
+        let clash = [
+            BranchEntry {
+                source_pc: 1,
+                target_pc: 4,
+            },
+            BranchEntry {
+                source_pc: 5,
+                target_pc: 8,
+            },
+        ];
+        assert!(matches!(
+            write_branch_mif(&path, &clash, 4),
+            Err(Error::CapacityExceeded(_))
+        ));
+        assert!(!path.exists());
+        write_branch_mif(&path, &clash, 8).unwrap();
+        let mif = fs::read_to_string(&path).unwrap();
+        assert!(mif.contains("WIDTH=33;\nDEPTH=8;\n"));
+        assert!(mif.contains("    1 : 100000004;\n"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,375 @@
+//! The library's [`Error`], the failure categories, and the exit codes
+//! the binaries map them to.
+
+use crate::{BodyError, SupportReport};
+use anyhow::Result;
+
+// ---------------------------------------------------------------------------
+// Errors, categories, and exit codes
+// ---------------------------------------------------------------------------
+
+/// Why a library call failed, so tools built on the crate can match on
+/// the kind of failure. Variants holding an [`anyhow::Error`] keep its
+/// whole chain of context: `{:#}` prints it as before, and
+/// [`std::error::Error::source`] walks it. The command-line plumbing
+/// ([`parse_with_project_config`](crate::parse_with_project_config), [`exit_status`]) and the `sim`
+/// harness stay on `anyhow`, like the tools that use them; an `Error`
+/// converts with `?`.
+#[derive(Debug)]
+pub enum Error {
+    /// An input doesn't parse: WAT, wasm, a `.wast` script, a config
+    /// file, or a function body whose branches don't nest.
+    ParseError(anyhow::Error),
+    /// The program uses `op`, at `offset` in its function body, which the
+    /// core doesn't have.
+    UnsupportedOp { offset: usize, op: String },
+    /// The program uses every instruction in the report, which the core
+    /// doesn't have (see [`HardwareSpec::check_supported`](crate::HardwareSpec::check_supported)).
+    UnsupportedOps(SupportReport),
+    /// The module has no function code to compile.
+    NoCodeSection,
+    /// The module exports no function called `name`.
+    NoSuchExport { name: String },
+    /// The function exported as `name` is imported, so has no code here.
+    ImportedFunction { name: String },
+    /// Running the module with wasmtime for its expected result failed:
+    /// it doesn't instantiate, has no `main: () -> i32`, or (where a
+    /// result is asked for directly) traps.
+    RefExecFailed(anyhow::Error),
+    /// The program doesn't fit the core's ROM, branch table, stack, or
+    /// memory.
+    CapacityExceeded(anyhow::Error),
+    /// Reading or writing a file failed.
+    Io(anyhow::Error),
+    /// Any other failure.
+    Other(anyhow::Error),
+}
+
+impl Error {
+    /// The exit code category of this failure, if it has one; see
+    /// [`ErrorCategory::of`].
+    pub fn category(&self) -> Option<ErrorCategory> {
+        ErrorCategory::of_cause(self)
+    }
+
+    fn map_inner(self, f: impl FnOnce(anyhow::Error) -> anyhow::Error) -> Error {
+        match self {
+            Error::ParseError(e) => Error::ParseError(f(e)),
+            Error::RefExecFailed(e) => Error::RefExecFailed(f(e)),
+            Error::CapacityExceeded(e) => Error::CapacityExceeded(f(e)),
+            Error::Io(e) => Error::Io(f(e)),
+            Error::Other(e) => Error::Other(f(e)),
+            Error::UnsupportedOp { .. }
+            | Error::UnsupportedOps(_)
+            | Error::NoCodeSection
+            | Error::NoSuchExport { .. }
+            | Error::ImportedFunction { .. } => self,
+        }
+    }
+
+    pub(crate) fn inner(&self) -> Option<&anyhow::Error> {
+        match self {
+            Error::ParseError(e)
+            | Error::RefExecFailed(e)
+            | Error::CapacityExceeded(e)
+            | Error::Io(e)
+            | Error::Other(e) => Some(e),
+            Error::UnsupportedOp { .. }
+            | Error::UnsupportedOps(_)
+            | Error::NoCodeSection
+            | Error::NoSuchExport { .. }
+            | Error::ImportedFunction { .. } => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Error::UnsupportedOp { offset, op } => {
+                write!(f, "{} is not supported at offset {}", op, offset)
+            }
+            Error::UnsupportedOps(report) => write!(f, "the core lacks {}", report),
+            Error::NoCodeSection => f.write_str("No code section found in WASM binary"),
+            Error::NoSuchExport { name } => write!(f, "no exported function {:?}", name),
+            Error::ImportedFunction { name } => write!(f, "{:?} is an imported function", name),
+            // `{:#}` shows the whole chain, as on an `anyhow::Error`.
+            _ if f.alternate() => write!(f, "{:#}", self.inner().expect("wraps an error")),
+            _ => write!(f, "{}", self.inner().expect("wraps an error")),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.inner().and_then(|e| e.chain().nth(1))
+    }
+}
+
+/// Sort an error from the crate's internals into its kind. An [`Error`]
+/// under some context keeps its kind and takes the context on; any other
+/// error goes by its [`ErrorCategory`].
+impl From<anyhow::Error> for Error {
+    fn from(e: anyhow::Error) -> Self {
+        if e.chain().any(|cause| cause.is::<Error>()) {
+            let context: Vec<String> = e
+                .chain()
+                .take_while(|cause| !cause.is::<Error>())
+                .map(|cause| cause.to_string())
+                .collect();
+            match e.downcast::<Error>() {
+                Ok(typed) => {
+                    return typed.map_inner(|inner| {
+                        context.into_iter().rev().fold(inner, |e, c| e.context(c))
+                    })
+                }
+                // Reached through some error's own `source`, not context.
+                Err(e) => return Error::Other(e),
+            }
+        }
+        match ErrorCategory::of(&e) {
+            Some(ErrorCategory::Parse) => Error::ParseError(e),
+            Some(ErrorCategory::Capacity) => Error::CapacityExceeded(e),
+            Some(ErrorCategory::Io) => Error::Io(e),
+            _ => Error::Other(e),
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e.into())
+    }
+}
+
+impl From<wasmparser::BinaryReaderError> for Error {
+    fn from(e: wasmparser::BinaryReaderError) -> Self {
+        Error::ParseError(e.into())
+    }
+}
+
+impl From<BodyError> for Error {
+    fn from(e: BodyError) -> Self {
+        Error::ParseError(e.into())
+    }
+}
+
+impl From<wat::Error> for Error {
+    fn from(e: wat::Error) -> Self {
+        Error::ParseError(e.into())
+    }
+}
+
+impl From<toml::de::Error> for Error {
+    fn from(e: toml::de::Error) -> Self {
+        Error::ParseError(e.into())
+    }
+}
+
+/// Why a command failed, broadly, so scripts can branch on the exit code
+/// instead of the message. Failures outside these exit with 1, and bad
+/// command lines with clap's 2.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCategory {
+    /// An input doesn't parse: WAT, wasm, a `.wast` script, or an
+    /// instruction stream with a bad branch.
+    Parse,
+    /// A program uses an instruction or feature the core doesn't have.
+    Unsupported,
+    /// A program doesn't fit the core's ROM, branch table, stack, or
+    /// memory.
+    Capacity,
+    /// A result disagrees with its reference: a test's run with wasmtime,
+    /// a golden trace, or a fresh compile of the artifacts' source.
+    Mismatch,
+    /// Reading or writing a file failed.
+    Io,
+}
+
+/// The exit codes, for the tools' `--help`.
+pub const EXIT_CODES_HELP: &str = "\
+Exit codes:
+  0  success
+  1  any other failure
+  2  bad command line
+  3  an input doesn't parse
+  4  a program uses what the core doesn't support
+  5  a program exceeds the core's capacity
+  6  a result mismatches its reference
+  7  reading or writing a file failed";
+
+impl ErrorCategory {
+    pub fn exit_code(self) -> u8 {
+        match self {
+            ErrorCategory::Parse => 3,
+            ErrorCategory::Unsupported => 4,
+            ErrorCategory::Capacity => 5,
+            ErrorCategory::Mismatch => 6,
+            ErrorCategory::Io => 7,
+        }
+    }
+
+    /// An error with `message` in this category.
+    pub fn error(self, message: impl std::fmt::Display) -> anyhow::Error {
+        CategorizedError {
+            category: self,
+            message: message.to_string(),
+        }
+        .into()
+    }
+
+    /// The category of the outermost error in `e`'s chain that has one:
+    /// an [`Error`], errors made by [`ErrorCategory::error`], and those of
+    /// parsers and the file system.
+    pub fn of(e: &anyhow::Error) -> Option<ErrorCategory> {
+        e.chain().find_map(ErrorCategory::of_cause)
+    }
+
+    /// The category of `cause` itself, not looking past it except into
+    /// an [`Error`] that wraps an uncategorized one.
+    fn of_cause(cause: &(dyn std::error::Error + 'static)) -> Option<ErrorCategory> {
+        if let Some(e) = cause.downcast_ref::<Error>() {
+            match e {
+                Error::ParseError(_) => Some(ErrorCategory::Parse),
+                Error::UnsupportedOp { .. }
+                | Error::UnsupportedOps(_)
+                | Error::NoCodeSection
+                | Error::NoSuchExport { .. }
+                | Error::ImportedFunction { .. } => Some(ErrorCategory::Unsupported),
+                Error::CapacityExceeded(_) => Some(ErrorCategory::Capacity),
+                Error::Io(_) => Some(ErrorCategory::Io),
+                Error::RefExecFailed(e) | Error::Other(e) => ErrorCategory::of(e),
+            }
+        } else if let Some(e) = cause.downcast_ref::<CategorizedError>() {
+            Some(e.category)
+        } else if cause.is::<BodyError>()
+            || cause.is::<wat::Error>()
+            || cause.is::<wast::Error>()
+            || cause.is::<wasmparser::BinaryReaderError>()
+            || cause.is::<toml::de::Error>()
+        {
+            Some(ErrorCategory::Parse)
+        } else if cause.is::<std::io::Error>() {
+            Some(ErrorCategory::Io)
+        } else {
+            None
+        }
+    }
+}
+
+/// An error tagged with its category; see [`ErrorCategory::error`].
+#[derive(Debug)]
+struct CategorizedError {
+    category: ErrorCategory,
+    message: String,
+}
+
+impl std::fmt::Display for CategorizedError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for CategorizedError {}
+
+/// End a tool's `main`: print an error the way returning it would, and
+/// exit with its category's code (see [`EXIT_CODES_HELP`]).
+pub fn exit_status(result: Result<()>) -> std::process::ExitCode {
+    match result {
+        Ok(()) => std::process::ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {:?}", e);
+            std::process::ExitCode::from(ErrorCategory::of(&e).map_or(1, |c| c.exit_code()))
+        }
+    }
+}
+
+#[cfg(all(test, feature = "refexec"))]
+mod tests {
+    use super::*;
+    use crate::{
+        compile_wasm_bytes, compile_wast_source, compile_wat, compile_wat_file, compile_wat_source,
+        run_with_wasmtime, HardwareSpec,
+    };
+    use anyhow::anyhow;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_error_categories() {
+        let category = |e: Error| ErrorCategory::of(&e.into());
+        assert_eq!(
+            category(compile_wat_source("p", "(module").unwrap_err()),
+            Some(ErrorCategory::Parse)
+        );
+        let branch = "(module (func (export \"main\") (result i32) br 1 i32.const 0))";
+        assert_eq!(
+            category(compile_wat_source("b", branch).unwrap_err()),
+            Some(ErrorCategory::Parse)
+        );
+        let missing = PathBuf::from("/nonexistent/wasm-ic/x.wat");
+        assert_eq!(
+            category(compile_wat_file(&missing).unwrap_err()),
+            Some(ErrorCategory::Io)
+        );
+        let info = compile_wat_source(
+            "c",
+            "(module (func (export \"main\") (result i32) i32.const 1))",
+        )
+        .unwrap();
+        let tiny = HardwareSpec {
+            rom_depth: 1,
+            ..HardwareSpec::default()
+        };
+        let error = tiny.check_fits(&info).unwrap_err();
+        assert!(error.to_string().starts_with("c does not fit the core"));
+        assert_eq!(category(error), Some(ErrorCategory::Capacity));
+        let wast = r#"(module (func (export "f") (result i32) i32.const 1))
+            (assert_return (invoke "f") (i32.const 2))"#;
+        let tests = compile_wast_source("w", wast).unwrap_err();
+        assert_eq!(category(tests), Some(ErrorCategory::Mismatch));
+        assert_eq!(category(anyhow!("other").into()), None);
+        assert_eq!(ErrorCategory::Capacity.exit_code(), 5);
+        assert!(EXIT_CODES_HELP.contains("  5  a program exceeds the core's capacity"));
+    }
+
+    #[test]
+    fn test_error_kinds() {
+        let no_code = compile_wasm_bytes("n", &compile_wat("(module)").unwrap()).unwrap_err();
+        assert!(matches!(no_code, Error::NoCodeSection), "{:?}", no_code);
+        assert!(matches!(
+            compile_wat_source("p", "(module").unwrap_err(),
+            Error::ParseError(_)
+        ));
+
+        // A parse error keeps its context, and `source` walks it.
+        let branch = "(module (func (export \"main\") (result i32) br 1 i32.const 0))";
+        let wasm = compile_wat(branch).unwrap();
+        let error = compile_wasm_bytes("b", &wasm).unwrap_err();
+        assert!(matches!(error, Error::ParseError(_)));
+        assert_eq!(error.to_string(), "validating module");
+        let source = std::error::Error::source(&error).unwrap();
+        assert!(source.is::<wasmparser::BinaryReaderError>());
+
+        let imports = r#"(module (import "m" "f" (func (result i32)))
+            (func (export "main") (result i32) call 0))"#;
+        let error = run_with_wasmtime(&compile_wat(imports).unwrap()).unwrap_err();
+        assert!(matches!(error, Error::RefExecFailed(_)));
+        let trap = compile_wat("(module (func (export \"main\") (result i32) unreachable))");
+        assert!(compile_wasm_bytes("t", &trap.unwrap()).unwrap().expect_trap);
+
+        let info = compile_wat_source(
+            "u",
+            "(module (func (export \"main\") (result i32) i64.const 1 i32.wrap_i64))",
+        )
+        .unwrap();
+        let error = HardwareSpec::default().check_supported(&info).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "the core lacks i64.const (1x at pc 0x0000), i32.wrap_i64 (1x at pc 0x0002)"
+        );
+        assert_eq!(error.category(), Some(ErrorCategory::Unsupported));
+
+        let missing = std::path::Path::new("/nonexistent/wasm-ic/spec.toml");
+        assert!(matches!(HardwareSpec::load(missing), Err(Error::Io(_))));
+    }
+}
@@ -0,0 +1,694 @@
+//! Generated inputs: the stress corpus, wasm-smith fuzzing, and shrinking
+//! a failing module to a small reproducer.
+
+use crate::emit::{push_sleb, push_uleb, section_id_offset};
+use crate::parse::operator_name;
+use crate::{
+    compatibility_issues, compile_wasm_bytes, parse, CompiledProgram, Error, HardwareSpec,
+    WASM_MAGIC,
+};
+use anyhow::{anyhow, Context, Result};
+use std::fs;
+use wasmparser::{Operator, Payload};
+
+// ---------------------------------------------------------------------------
+// Stress corpus generation
+// ---------------------------------------------------------------------------
+
+/// A family of generated stress programs, each scaled by a size, that
+/// probes one limit of the core. All use only [`CORE_OPCODES`](crate::CORE_OPCODES).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StressTemplate {
+    /// `size` nested blocks and ifs, the innermost branching out of all
+    /// of them at once.
+    Nesting,
+    /// `size` arithmetic instructions in one dependent chain, with
+    /// operands from a seeded generator.
+    Arithmetic,
+    /// `size` conditional blocks and if/else pairs in a row, about one
+    /// and a half branch table entries each.
+    Branches,
+    /// Straight-line code exactly `size` bytes long.
+    Size,
+}
+
+impl StressTemplate {
+    pub const ALL: [StressTemplate; 4] = [
+        StressTemplate::Nesting,
+        StressTemplate::Arithmetic,
+        StressTemplate::Branches,
+        StressTemplate::Size,
+    ];
+
+    /// Name used in generated file names.
+    pub fn name(self) -> &'static str {
+        match self {
+            StressTemplate::Nesting => "nesting",
+            StressTemplate::Arithmetic => "arithmetic",
+            StressTemplate::Branches => "branches",
+            StressTemplate::Size => "size",
+        }
+    }
+
+    /// Sizes for a sweep that ends at the limits of `spec`: a full ROM for
+    /// [`Size`](StressTemplate::Size), and for
+    /// [`Branches`](StressTemplate::Branches) about as many entries as the
+    /// branch table has slots.
+    pub fn default_sizes(self, spec: &HardwareSpec) -> Vec<usize> {
+        match self {
+            StressTemplate::Nesting => vec![8, 64, 512],
+            StressTemplate::Arithmetic => vec![16, 256, 4096],
+            StressTemplate::Branches => vec![8, 64, spec.branch_slots * 2 / 3],
+            StressTemplate::Size => vec![64, spec.rom_depth / 4, spec.rom_depth],
+        }
+    }
+}
+
+/// WAT source of `template` at `size`, with `seed` choosing the
+/// arithmetic operands. Sizes too small for a template are raised to its
+/// minimum (3 bytes for [`Size`](StressTemplate::Size), 1 otherwise).
+pub fn stress_wat(template: StressTemplate, size: usize, seed: u64) -> String {
+    let mut body = String::new();
+    let mut line = |depth: usize, text: &str| {
+        body.push_str(&"  ".repeat(depth + 2));
+        body.push_str(text);
+        body.push('\n');
+    };
+    let size = size.max(1);
+    match template {
+        StressTemplate::Nesting => {
+            for depth in 0..size {
+                if depth % 2 == 1 {
+                    line(depth, "i32.const 1");
+                    line(depth, "if");
+                } else {
+                    line(depth, "block");
+                }
+            }
+            line(size, &format!("br {}", size - 1));
+            for depth in (0..size).rev() {
+                line(depth, "end");
+            }
+            line(0, &format!("i32.const {}", size));
+        }
+        StressTemplate::Arithmetic => {
+            const OPS: &[&str] = &[
+                "i32.add", "i32.mul", "i32.xor", "i32.sub", "i32.rotl", "i32.or", "i32.rotr",
+                "i32.and",
+            ];
+            let mut state = seed;
+            let mut next = || lcg_next(&mut state);
+            line(0, &format!("i32.const {}", next() as i32));
+            for _ in 0..size {
+                let op = OPS[next() as usize % OPS.len()];
+                let operand = match op {
+                    // Keep rotates from being no-ops mod 32.
+                    "i32.rotl" | "i32.rotr" => 1 + next() % 31,
+                    // Even factors and ands with many clear bits would
+                    // soon zero the chain, so ands clear one bit.
+                    "i32.mul" => next() | 1,
+                    "i32.and" => !(1 << (next() % 32)),
+                    _ => next(),
+                };
+                line(0, &format!("i32.const {}", operand as i32));
+                line(0, op);
+            }
+        }
+        StressTemplate::Branches => {
+            line(0, "i32.const 0");
+            for i in 0..size {
+                let taken = i % 3 == 0;
+                if i % 2 == 0 {
+                    line(0, "block");
+                    line(1, &format!("i32.const {}", taken as i32));
+                    line(1, "br_if 0");
+                    line(1, "nop");
+                    line(0, "end");
+                    line(0, &format!("i32.const {}", i));
+                } else {
+                    line(0, &format!("i32.const {}", taken as i32));
+                    line(0, "if (result i32)");
+                    line(1, &format!("i32.const {}", i));
+                    line(0, "else");
+                    line(1, "i32.const 1");
+                    line(0, "end");
+                }
+                line(0, "i32.add");
+            }
+        }
+        StressTemplate::Size => {
+            // i32.const 0 and the final return take 3 bytes, each
+            // increment 3 more, and nops pad out the rest.
+            let size = size.max(3);
+            line(0, "i32.const 0");
+            for _ in 0..(size - 3) / 3 {
+                line(0, "i32.const 1");
+                line(0, "i32.add");
+            }
+            for _ in 0..(size - 3) % 3 {
+                line(0, "nop");
+            }
+        }
+    }
+    format!(
+        ";; Generated by `wasm-compile gen-corpus`: {} template, size {}{}.\n\
+         (module\n  (func (export \"main\") (result i32)\n{}  ))\n",
+        template.name(),
+        size,
+        if template == StressTemplate::Arithmetic {
+            format!(", seed {}", seed)
+        } else {
+            String::new()
+        },
+        body
+    )
+}
+
+/// Step a 64-bit LCG (Knuth's MMIX constants), returning 32 good bits.
+/// Plenty for generated programs, and reproducible from the seed.
+fn lcg_next(state: &mut u64) -> u32 {
+    *state = state
+        .wrapping_mul(6364136223846793005)
+        .wrapping_add(1442695040888963407);
+    (*state >> 33) as u32
+}
+
+// ---------------------------------------------------------------------------
+// Fuzzing
+// ---------------------------------------------------------------------------
+
+/// Random bytes wasm-smith draws each fuzz case from; more than a case of
+/// [`fuzz_config`]'s size ever uses.
+pub const FUZZ_INPUT_BYTES: usize = 4096;
+
+/// Fuel a fuzz case may burn in wasmtime before it counts as never
+/// halting; generated loops often branch back forever.
+pub const FUZZ_FUEL: u64 = 100_000;
+
+/// The next [`FUZZ_INPUT_BYTES`] random bytes for [`fuzz_case`] from a
+/// generator seeded once per run, so a run can be repeated from its seed.
+pub fn fuzz_input(state: &mut u64) -> Vec<u8> {
+    (0..FUZZ_INPUT_BYTES)
+        .map(|_| lcg_next(state) as u8)
+        .collect()
+}
+
+/// wasm-smith settings for modules close to what the core runs: a single
+/// `main: () -> i32` function of integer, parametric, and structured
+/// control instructions, with no imports, memory, tables, or globals, and
+/// at most `max_instructions` instructions nested `max_nesting` deep.
+/// wasm-smith can't be kept to i32 alone, so some cases still use i64 or
+/// calls; [`fuzz_case`] rejects those. It also declares locals it never
+/// uses, which [`fuzz_case`] drops.
+pub fn fuzz_config(max_instructions: usize, max_nesting: usize) -> wasm_smith::Config {
+    use wasm_smith::{InstructionKind, InstructionKinds};
+    wasm_smith::Config {
+        exports: Some(
+            wat::parse_str(r#"(module (func (export "main") (result i32) unreachable))"#)
+                .expect("fixed module"),
+        ),
+        // `main` comes from the exports and is generated on top of these.
+        max_funcs: 0,
+        max_imports: 0,
+        max_memories: 0,
+        max_tables: 0,
+        max_globals: 0,
+        max_tags: 0,
+        max_data_segments: 0,
+        max_element_segments: 0,
+        max_instructions,
+        max_nesting_depth: max_nesting,
+        allowed_instructions: InstructionKinds::new(&[
+            InstructionKind::NumericInt,
+            InstructionKind::Parametric,
+            InstructionKind::Control,
+        ]),
+        allow_floats: false,
+        bulk_memory_enabled: false,
+        exceptions_enabled: false,
+        gc_enabled: false,
+        memory64_enabled: false,
+        multi_value_enabled: false,
+        reference_types_enabled: false,
+        relaxed_simd_enabled: false,
+        saturating_float_to_int_enabled: false,
+        sign_extension_ops_enabled: false,
+        simd_enabled: false,
+        tail_call_enabled: false,
+        threads_enabled: false,
+        extended_const_enabled: false,
+        ..wasm_smith::Config::default()
+    }
+}
+
+/// What became of one fuzz case.
+#[derive(Debug)]
+pub enum FuzzOutcome {
+    /// The module uses something the core can't run, the first reason
+    /// given; not a finding.
+    Rejected(String),
+    /// The module compiled, with this test.
+    Passed(Box<CompiledProgram>),
+    /// The core should run the module, but the pipeline failed on it.
+    Failed(Error),
+}
+
+/// Generate a module from `input` with `config` and put it through the
+/// pipeline: the compatibility check against `spec`, then compiling,
+/// reference execution, and disassembly. Returns the module with the
+/// outcome; errors only if wasm-smith can't build a module at all.
+#[cfg(feature = "refexec")]
+pub fn fuzz_case(
+    name: &str,
+    input: &[u8],
+    config: &wasm_smith::Config,
+    spec: &HardwareSpec,
+) -> Result<(Vec<u8>, FuzzOutcome), Error> {
+    let mut u = arbitrary::Unstructured::new(input);
+    let wasm = wasm_smith::Module::new(config.clone(), &mut u)
+        .map_err(|e| anyhow!("generating a module: {}", e))?
+        .to_bytes();
+    let wasm = drop_locals(&wasm)?;
+    let issues = compatibility_issues(&wasm, spec)?;
+    if let Some(issue) = issues.first() {
+        return Ok((wasm, FuzzOutcome::Rejected(issue.issue.clone())));
+    }
+    if !halts_within(&wasm, FUZZ_FUEL)? {
+        return Ok((
+            wasm,
+            FuzzOutcome::Rejected(format!("doesn't halt within {} fuel", FUZZ_FUEL)),
+        ));
+    }
+    let outcome = match compile_wasm_bytes(name, &wasm).and_then(|info| {
+        parse::disassembly_listing(&info.body_bytes, &info.branch_table)?;
+        Ok(info)
+    }) {
+        Ok(info) => FuzzOutcome::Passed(Box::new(info)),
+        Err(e) => FuzzOutcome::Failed(e),
+    };
+    Ok((wasm, outcome))
+}
+
+/// Whether `main` returns or traps before burning `fuel` in wasmtime.
+#[cfg(feature = "refexec")]
+fn halts_within(wasm: &[u8], fuel: u64) -> Result<bool> {
+    let mut config = wasmtime::Config::new();
+    config.consume_fuel(true);
+    let engine = wasmtime::Engine::new(&config)?;
+    let module = wasmtime::Module::new(&engine, wasm)?;
+    let mut store = wasmtime::Store::new(&engine, ());
+    store.set_fuel(fuel)?;
+    let instance = wasmtime::Instance::new(&mut store, &module, &[])?;
+    let main_fn = instance.get_typed_func::<(), i32>(&mut store, "main")?;
+    Ok(match main_fn.call(&mut store, ()) {
+        Err(e) => e.downcast_ref::<wasmtime::Trap>() != Some(&wasmtime::Trap::OutOfFuel),
+        Ok(_) => true,
+    })
+}
+
+/// `wasm` with the local declarations of every function dropped, which
+/// only makes sense if no instruction uses them.
+#[cfg(feature = "refexec")]
+fn drop_locals(wasm: &[u8]) -> Result<Vec<u8>> {
+    let Some((section, bodies)) = code_bodies(wasm)? else {
+        return Ok(wasm.to_vec());
+    };
+    let bodies: Vec<Vec<u8>> = bodies
+        .into_iter()
+        .map(|(body, ops)| [&[0][..], &wasm[ops..body.end]].concat())
+        .collect();
+    Ok(replace_code_section(wasm, section, &bodies))
+}
+
+/// The range of `wasm`'s code section contents, if it has one, and each
+/// function body in it: the range of its locals and operators, and where
+/// the operators start.
+#[allow(clippy::type_complexity)]
+fn code_bodies(
+    wasm: &[u8],
+) -> Result<Option<(std::ops::Range<usize>, Vec<(std::ops::Range<usize>, usize)>)>> {
+    let mut section = None;
+    let mut bodies = Vec::new();
+    for payload in wasmparser::Parser::new(0).parse_all(wasm) {
+        match payload? {
+            Payload::CodeSectionStart { range, .. } => section = Some(range),
+            Payload::CodeSectionEntry(body) => {
+                let ops = body.get_operators_reader()?.original_position();
+                bodies.push((body.range(), ops));
+            }
+            _ => {}
+        }
+    }
+    Ok(section.map(|section| (section, bodies)))
+}
+
+/// `wasm` with the code section whose contents are at `section` holding
+/// `bodies` instead, each a function's locals and operators.
+fn replace_code_section(
+    wasm: &[u8],
+    section: std::ops::Range<usize>,
+    bodies: &[Vec<u8>],
+) -> Vec<u8> {
+    let mut content = Vec::new();
+    push_uleb(&mut content, bodies.len());
+    for body in bodies {
+        push_uleb(&mut content, body.len());
+        content.extend_from_slice(body);
+    }
+    let id = section_id_offset(wasm, section.start);
+    let mut out = wasm[..id].to_vec();
+    out.push(wasm[id]);
+    push_uleb(&mut out, content.len());
+    out.extend(content);
+    out.extend_from_slice(&wasm[section.end..]);
+    out
+}
+
+/// The coverage points a program hits: each instruction name, and each
+/// pair of names in a row (`"i32.add"`, `"i32.add br_if"`). A fuzz case
+/// hitting one no earlier case did is worth keeping.
+pub fn fuzz_coverage(body_bytes: &[u8]) -> Result<std::collections::BTreeSet<String>, Error> {
+    let binary_reader = wasmparser::BinaryReader::new(body_bytes, 0);
+    let mut reader = wasmparser::OperatorsReader::new(binary_reader);
+    let mut points = std::collections::BTreeSet::new();
+    let mut previous: Option<String> = None;
+    while !reader.eof() {
+        let name = operator_name(&reader.read()?);
+        if let Some(previous) = &previous {
+            points.insert(format!("{} {}", previous, name));
+        }
+        points.insert(name.clone());
+        previous = Some(name);
+    }
+    Ok(points)
+}
+
+// ---------------------------------------------------------------------------
+// Failing-case minimization
+// ---------------------------------------------------------------------------
+
+/// The wasm binary of a WAT or `.wasm` file, read as
+/// [`compile_wat_file`](crate::compile_wat_file) reads it.
+pub fn read_module(path: &std::path::Path) -> Result<Vec<u8>, Error> {
+    let bytes = fs::read(path).with_context(|| format!("reading {}", path.display()))?;
+    if bytes.starts_with(WASM_MAGIC) {
+        return Ok(bytes);
+    }
+    wat::parse_file(path).map_err(Into::into)
+}
+
+/// `wasm` as WAT text, e.g. to file a shrunk module in a bug report.
+pub fn module_wat(wasm: &[u8]) -> Result<String, Error> {
+    Ok(wasmprinter::print_bytes(wasm)?)
+}
+
+/// Shrink a failing module to a small one that still fails. Edits to the
+/// first function (the one the core runs) are tried biggest first:
+/// removing a whole block, unwrapping one, removing runs of three to one
+/// instructions or a pair a little apart, then zeroing or halving
+/// constants. An edit is kept when
+/// the module stays valid and `still_fails` holds for it, and the search
+/// starts over from the smaller module until no edit is kept.
+pub fn shrink_module(
+    wasm: &[u8],
+    mut still_fails: impl FnMut(&[u8]) -> Result<bool>,
+) -> Result<Vec<u8>, Error> {
+    let mut current = wasm.to_vec();
+    'shrink: loop {
+        let Some((section, bodies)) = code_bodies(&current)? else {
+            return Ok(current);
+        };
+        let Some((first, ops)) = bodies.first().cloned() else {
+            return Ok(current);
+        };
+        let mut new_bodies: Vec<Vec<u8>> = bodies
+            .iter()
+            .map(|(b, _)| current[b.clone()].to_vec())
+            .collect();
+        let locals = current[first.start..ops].to_vec();
+        for edit in shrink_edits(&current[ops..first.end])? {
+            new_bodies[0] = [&locals[..], &edit[..]].concat();
+            let candidate = replace_code_section(&current, section.clone(), &new_bodies);
+            if wasmparser::validate(&candidate).is_ok() && still_fails(&candidate)? {
+                log::debug!("shrunk to {} bytes", candidate.len());
+                current = candidate;
+                continue 'shrink;
+            }
+        }
+        return Ok(current);
+    }
+}
+
+/// How [`shrink_edits`] may change an instruction.
+#[derive(Clone, Copy, PartialEq)]
+enum ShrinkShape {
+    /// `block`, `loop`, or `if`, with its opcode.
+    Open(u8),
+    Else,
+    End,
+    /// `i32.const` or `i64.const`, with its opcode and value.
+    Const(u8, i64),
+    Other,
+}
+
+/// How many instructions apart [`shrink_edits`] looks for a pair to
+/// remove together.
+const PAIR_REACH: usize = 16;
+
+/// Smaller variants of a function's operators `ops`, biggest cuts first.
+/// Most won't validate; [`shrink_module`] skips those.
+fn shrink_edits(ops: &[u8]) -> Result<Vec<Vec<u8>>> {
+    let mut spans = Vec::new();
+    let mut reader = wasmparser::OperatorsReader::new(wasmparser::BinaryReader::new(ops, 0));
+    while !reader.eof() {
+        let (op, start) = reader.read_with_offset()?;
+        let shape = match op {
+            Operator::Block { .. } => ShrinkShape::Open(0x02),
+            Operator::Loop { .. } => ShrinkShape::Open(0x03),
+            Operator::If { .. } => ShrinkShape::Open(0x04),
+            Operator::Else => ShrinkShape::Else,
+            Operator::End => ShrinkShape::End,
+            Operator::I32Const { value } => ShrinkShape::Const(0x41, value as i64),
+            Operator::I64Const { value } => ShrinkShape::Const(0x42, value),
+            _ => ShrinkShape::Other,
+        };
+        spans.push((start, reader.original_position(), shape));
+    }
+    let splice = |cuts: &[(usize, usize)], insert: &[u8]| {
+        let mut out = ops[..cuts[0].0].to_vec();
+        out.extend_from_slice(insert);
+        for (i, &(_, end)) in cuts.iter().enumerate() {
+            let next = cuts.get(i + 1).map_or(ops.len(), |c| c.0);
+            out.extend_from_slice(&ops[end..next]);
+        }
+        out
+    };
+
+    // Each block paired with its end, and whether it has an else.
+    let mut blocks = Vec::new();
+    let mut open = Vec::new();
+    for (i, &(_, _, shape)) in spans.iter().enumerate() {
+        match shape {
+            ShrinkShape::Open(_) => open.push((i, false)),
+            ShrinkShape::Else => {
+                if let Some(top) = open.last_mut() {
+                    top.1 = true;
+                }
+            }
+            ShrinkShape::End => {
+                if let Some((start, has_else)) = open.pop() {
+                    blocks.push((start, i, has_else));
+                }
+            }
+            _ => {}
+        }
+    }
+    blocks.sort_by_key(|&(start, end, _)| std::cmp::Reverse(spans[end].1 - spans[start].0));
+
+    let mut edits = Vec::new();
+    for &(start, end, _) in &blocks {
+        edits.push(splice(&[(spans[start].0, spans[end].1)], &[]));
+    }
+    for &(start, end, has_else) in &blocks {
+        let (header, end) = (
+            (spans[start].0, spans[start].1),
+            (spans[end].0, spans[end].1),
+        );
+        match spans[start].2 {
+            // An `if` runs its body regardless once its condition is dropped.
+            ShrinkShape::Open(0x04) if !has_else => edits.push(splice(&[header, end], &[0x1A])),
+            ShrinkShape::Open(0x02 | 0x03) => edits.push(splice(&[header, end], &[])),
+            _ => {}
+        }
+    }
+    let plain = |s: &(usize, usize, ShrinkShape)| {
+        matches!(s.2, ShrinkShape::Const(..) | ShrinkShape::Other)
+    };
+    for run in (1..=3).rev() {
+        for window in spans.windows(run) {
+            if window.iter().all(plain) {
+                edits.push(splice(&[(window[0].0, window[run - 1].1)], &[]));
+            }
+        }
+    }
+    // A value made early and consumed late, like the `x` of `x ... i32.xor`,
+    // only goes with both instructions at once.
+    for (i, first) in spans.iter().enumerate().filter(|(_, s)| plain(s)) {
+        for second in spans
+            .iter()
+            .skip(i + 2)
+            .take(PAIR_REACH)
+            .filter(|s| plain(s))
+        {
+            edits.push(splice(&[(first.0, first.1), (second.0, second.1)], &[]));
+        }
+    }
+    for &(start, end, shape) in &spans {
+        if let ShrinkShape::Const(opcode, value) = shape {
+            let halved = (value / 2 != 0).then_some(value / 2);
+            for smaller in std::iter::once(0).chain(halved) {
+                if smaller != value {
+                    let mut insert = vec![opcode];
+                    push_sleb(&mut insert, smaller);
+                    edits.push(splice(&[(start, end)], &insert));
+                }
+            }
+        }
+    }
+    Ok(edits)
+}
+
+#[cfg(all(test, feature = "refexec"))]
+mod tests {
+    use super::*;
+    use crate::parse::program_stats;
+    use crate::{
+        compile_wat, compile_wat_source, disassemble, extract_function_body,
+        extract_function_body_raw, run_with_wasmtime,
+    };
+
+    #[test]
+    fn test_stress_wat() {
+        let compile =
+            |template, size| compile_wat_source("s", &stress_wat(template, size, 7)).unwrap();
+        let spec = HardwareSpec::default();
+        let nesting = compile(StressTemplate::Nesting, 5);
+        assert_eq!(nesting.expected, 5);
+        assert_eq!(nesting.branch_table.len(), 1 + 2);
+        let arithmetic = compile(StressTemplate::Arithmetic, 20);
+        assert_eq!(
+            program_stats(&arithmetic.body_bytes).unwrap().unsupported,
+            None
+        );
+        assert_eq!(
+            stress_wat(StressTemplate::Arithmetic, 20, 7),
+            stress_wat(StressTemplate::Arithmetic, 20, 7)
+        );
+        assert_ne!(
+            stress_wat(StressTemplate::Arithmetic, 20, 7),
+            stress_wat(StressTemplate::Arithmetic, 20, 8)
+        );
+        let branches = compile(StressTemplate::Branches, 4);
+        assert_eq!(branches.branch_table.len(), 6);
+        // Entry 1 takes the else; the others give their index.
+        assert_eq!(branches.expected, 6);
+        for size in [3, 4, 5, 100] {
+            let info = compile(StressTemplate::Size, size);
+            assert_eq!(info.body_bytes.len(), size);
+            assert!(spec.skip_reasons(&info).unwrap().is_empty());
+        }
+        let full = compile(StressTemplate::Size, spec.rom_depth);
+        assert_eq!(spec.capacity_issues(&full).unwrap(), Vec::<String>::new());
+        assert_eq!(
+            StressTemplate::Branches.default_sizes(&spec).last(),
+            Some(&(spec.branch_slots * 2 / 3))
+        );
+    }
+
+    #[test]
+    fn test_fuzz_case() {
+        let config = fuzz_config(10, 4);
+        let spec = HardwareSpec::default();
+        let mut state = 7;
+        let (mut passed, mut rejected, mut failed) = (0, 0, 0);
+        for i in 0..100 {
+            let input = fuzz_input(&mut state);
+            assert_eq!(input.len(), FUZZ_INPUT_BYTES);
+            let name = format!("fuzz_{}", i);
+            let (wasm, outcome) = fuzz_case(&name, &input, &config, &spec).unwrap();
+            assert!(wasm.starts_with(WASM_MAGIC));
+            match outcome {
+                FuzzOutcome::Passed(info) => {
+                    passed += 1;
+                    assert!(spec.skip_reasons(&info).unwrap().is_empty());
+                    assert!(fuzz_coverage(&info.body_bytes).unwrap().contains("return"));
+                }
+                FuzzOutcome::Rejected(_) => rejected += 1,
+                // Findings, such as a `br` out of the function itself,
+                // which the branch table doesn't resolve yet.
+                FuzzOutcome::Failed(_) => failed += 1,
+            }
+        }
+        assert!(
+            passed > 0 && rejected > 0,
+            "{} passed, {} failed",
+            passed,
+            failed
+        );
+
+        let body = compile_wat_source(
+            "c",
+            "(module (func (export \"main\") (result i32) i32.const 1 i32.const 2 i32.add))",
+        )
+        .unwrap()
+        .body_bytes;
+        assert_eq!(
+            fuzz_coverage(&body)
+                .unwrap()
+                .into_iter()
+                .collect::<Vec<_>>(),
+            [
+                "i32.add",
+                "i32.add return",
+                "i32.const",
+                "i32.const i32.add",
+                "i32.const i32.const",
+                "return"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_shrink_module() {
+        let wasm = compile_wat(
+            r#"(module (func (export "main") (result i32)
+                i32.const 5
+                block (result i32)
+                  i32.const 3
+                  i32.const 4
+                  i32.add
+                end
+                i32.const 7
+                i32.mul
+                i32.add
+                i32.const 9
+                i32.xor))"#,
+        )
+        .unwrap();
+        let has_mul = |wasm: &[u8]| -> Result<bool> {
+            let body = extract_function_body(wasm)?;
+            Ok(fuzz_coverage(&body)?.contains("i32.mul"))
+        };
+        let shrunk = shrink_module(&wasm, has_mul).unwrap();
+        let body = extract_function_body_raw(&shrunk).unwrap();
+        let text: Vec<String> = disassemble(&body).iter().map(|i| i.to_string()).collect();
+        assert_eq!(text, ["i32.const 0", "i32.const 0", "i32.mul", "end"]);
+        assert!(module_wat(&shrunk).unwrap().contains("i32.mul"));
+
+        // Constants halve toward the smallest value that still fails.
+        let wasm =
+            compile_wat(r#"(module (func (export "main") (result i32) i32.const 1000))"#).unwrap();
+        let shrunk = shrink_module(&wasm, |w| Ok(run_with_wasmtime(w)? >= 100)).unwrap();
+        assert_eq!(run_with_wasmtime(&shrunk).unwrap(), 125);
+    }
+}
@@ -0,0 +1,2094 @@
+//! What the core implements: the hardware capability model, the port map,
+//! and the checks that a module fits them.
+
+use crate::parse::{
+    format_operator, memory_count, memory_image_len, operator_memarg, operator_name, program_stats,
+};
+use crate::{
+    extract_function_body_ref, extract_memory_init, hardware_body, BranchEntry, CompiledProgram,
+    Error, MemSegment, ParsedBody, MEMORY_SIZE,
+};
+use anyhow::{anyhow, Context, Result};
+use std::fs;
+use wasmparser::{Operator, Payload};
+
+// ---------------------------------------------------------------------------
+// Hardware capability model
+// ---------------------------------------------------------------------------
+
+/// Mnemonics the decoder implements: the i32 subset in `docs/decoder.md`.
+/// Locals, calls, and globals are all outside it.
+pub const CORE_OPCODES: &[&str] = &[
+    "unreachable",
+    "nop",
+    "block",
+    "loop",
+    "if",
+    "else",
+    "end",
+    "br",
+    "br_if",
+    "return",
+    "drop",
+    "i32.const",
+    "i32.load",
+    "i32.load8_s",
+    "i32.load8_u",
+    "i32.load16_s",
+    "i32.load16_u",
+    "i32.store",
+    "i32.store8",
+    "i32.store16",
+    "i32.eqz",
+    "i32.eq",
+    "i32.ne",
+    "i32.lt_s",
+    "i32.lt_u",
+    "i32.gt_s",
+    "i32.gt_u",
+    "i32.le_s",
+    "i32.le_u",
+    "i32.ge_s",
+    "i32.ge_u",
+    "i32.clz",
+    "i32.ctz",
+    "i32.popcnt",
+    "i32.add",
+    "i32.sub",
+    "i32.mul",
+    "i32.div_s",
+    "i32.div_u",
+    "i32.rem_s",
+    "i32.rem_u",
+    "i32.and",
+    "i32.or",
+    "i32.xor",
+    "i32.shl",
+    "i32.shr_s",
+    "i32.shr_u",
+    "i32.rotl",
+    "i32.rotr",
+];
+
+/// What a build of the core can hold and execute. The default is the
+/// core in this repository; a TOML file (see [`HardwareSpec::from_toml`])
+/// describes other builds, so validation, emission, and the generated
+/// headers all work from the same numbers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HardwareSpec {
+    /// Program ROM size in bytes.
+    pub rom_depth: usize,
+    /// Slots in the direct-mapped branch table (`WasmBranchTable::DEPTH`).
+    pub branch_slots: usize,
+    /// Most values the operand stack holds (`WasmStack::DEPTH`).
+    pub stack_depth: usize,
+    /// Data RAM size in bytes ([`MEMORY_SIZE`]).
+    pub memory_size: usize,
+    /// Program counter width in bits.
+    pub pc_width: u32,
+    /// Whether loads and stores may be unaligned; the core in this
+    /// repository assembles them a byte at a time.
+    pub unaligned: bool,
+    /// Whether the decoder has the sign-extension ops
+    /// ([`OpFeature::SignExtension`]), whatever `opcodes` says.
+    pub sign_extension: bool,
+    /// Whether it has the saturating float-to-int truncations
+    /// ([`OpFeature::SaturatingTrunc`]).
+    pub saturating_trunc: bool,
+    /// Whether it has `memory.copy` and `memory.fill`
+    /// ([`OpFeature::BulkMemory`]).
+    pub bulk_memory: bool,
+    /// Mnemonics the decoder implements ([`CORE_OPCODES`]).
+    pub opcodes: std::collections::BTreeSet<String>,
+}
+
+impl Default for HardwareSpec {
+    fn default() -> Self {
+        HardwareSpec {
+            rom_depth: 65536,
+            branch_slots: 256,
+            stack_depth: 1024,
+            memory_size: MEMORY_SIZE,
+            pc_width: 32,
+            unaligned: true,
+            sign_extension: false,
+            saturating_trunc: false,
+            bulk_memory: false,
+            opcodes: CORE_OPCODES.iter().map(|op| op.to_string()).collect(),
+        }
+    }
+}
+
+impl HardwareSpec {
+    /// The default spec with the keys of a TOML document overriding it:
+    ///
+    /// ```toml
+    /// rom_depth = 1024
+    /// branch_slots = 64
+    /// unaligned = false
+    /// sign_extension = true
+    /// extra_opcodes = ["i32.wrap_i64"]
+    /// ```
+    ///
+    /// `opcodes` replaces the instruction set and `extra_opcodes` adds to
+    /// it. Unknown keys are an error so typos don't silently fall back to
+    /// the defaults.
+    pub fn from_toml(text: &str) -> Result<Self, Error> {
+        let table: toml::Table = text.parse()?;
+        let mut spec = HardwareSpec::default();
+        // `opcodes` first, so `extra_opcodes` adds to the replacement.
+        if let Some(value) = table.get("opcodes") {
+            spec.opcodes = toml_strings("opcodes", value)?.into_iter().collect();
+        }
+        for (key, value) in &table {
+            match key.as_str() {
+                "rom_depth" => spec.rom_depth = toml_count(key, value)?,
+                "branch_slots" => spec.branch_slots = toml_count(key, value)?,
+                "stack_depth" => spec.stack_depth = toml_count(key, value)?,
+                "memory_size" => spec.memory_size = toml_count(key, value)?,
+                "pc_width" => {
+                    spec.pc_width = match toml_count(key, value)? {
+                        width @ 1..=32 => width as u32,
+                        width => {
+                            return Err(Error::ParseError(anyhow!(
+                                "pc_width {} is not 1 to 32",
+                                width
+                            )))
+                        }
+                    }
+                }
+                "unaligned" => spec.unaligned = toml_flag(key, value)?,
+                "sign_extension" => spec.sign_extension = toml_flag(key, value)?,
+                "saturating_trunc" => spec.saturating_trunc = toml_flag(key, value)?,
+                "bulk_memory" => spec.bulk_memory = toml_flag(key, value)?,
+                "opcodes" => {}
+                "extra_opcodes" => spec.opcodes.extend(toml_strings(key, value)?),
+                _ => {
+                    return Err(Error::ParseError(anyhow!(
+                        "unknown hardware spec key `{}`",
+                        key
+                    )))
+                }
+            }
+        }
+        Ok(spec)
+    }
+
+    pub fn load(path: &std::path::Path) -> Result<Self, Error> {
+        let text =
+            fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+        Ok(Self::from_toml(&text).with_context(|| format!("parsing {}", path.display()))?)
+    }
+
+    /// Whether the decoder implements `op`, with blocks taking no result
+    /// or an `i32` one.
+    pub fn supports(&self, op: &Operator) -> bool {
+        use wasmparser::{BlockType, ValType};
+        match op {
+            Operator::Block { blockty } | Operator::Loop { blockty } | Operator::If { blockty }
+                if !matches!(blockty, BlockType::Empty | BlockType::Type(ValType::I32)) =>
+            {
+                false
+            }
+            _ => {
+                let gated = match OpFeature::of(op) {
+                    Some(OpFeature::SignExtension) => self.sign_extension,
+                    Some(OpFeature::SaturatingTrunc) => self.saturating_trunc,
+                    Some(OpFeature::BulkMemory) => self.bulk_memory,
+                    None => false,
+                };
+                gated || self.opcodes.contains(&operator_name(op))
+            }
+        }
+    }
+
+    /// Every way a compiled test overflows the core: a program too long
+    /// for the ROM or PC, branch entries sharing a table slot, a stack
+    /// deeper than the hardware's, or data past the end of RAM.
+    pub fn capacity_issues(&self, info: &CompiledProgram) -> Result<Vec<String>, Error> {
+        let body = ParsedBody::new(&info.body_bytes)?;
+        Ok(self.program_capacity_issues(&body, &info.branch_table, &info.mem_init)?)
+    }
+
+    fn program_capacity_issues(
+        &self,
+        body: &ParsedBody,
+        branch_table: &[BranchEntry],
+        mem_init: &[MemSegment],
+    ) -> Result<Vec<String>> {
+        let mut issues = Vec::new();
+        let len = body.bytes().len();
+        if len > self.rom_depth {
+            issues.push(format!(
+                "the program is {} bytes, {} over the {}-byte ROM",
+                len,
+                len - self.rom_depth,
+                self.rom_depth
+            ));
+        }
+        if len as u64 > 1u64 << self.pc_width {
+            issues.push(format!(
+                "the program is {} bytes, more than a {}-bit PC addresses",
+                len, self.pc_width
+            ));
+        }
+        let mut slots = std::collections::BTreeMap::new();
+        for entry in branch_table {
+            let slot = entry.source_pc as usize % self.branch_slots.max(1);
+            if let Some(other) = slots.insert(slot, entry.source_pc) {
+                issues.push(format!(
+                    "branches at pc {:#06X} and {:#06X} share slot {} of the {}-slot branch table",
+                    other, entry.source_pc, slot, self.branch_slots
+                ));
+            }
+        }
+        let stack = body.stats()?.max_stack_depth;
+        if stack > self.stack_depth {
+            issues.push(format!(
+                "the program needs {} stack slots, more than the {} the core has",
+                stack, self.stack_depth
+            ));
+        }
+        if let Some(s) = mem_init
+            .iter()
+            .find(|s| s.addr as u64 + s.data.len() as u64 > self.memory_size as u64)
+        {
+            issues.push(format!(
+                "data segment at {} ends at {}, past the {}-byte RAM",
+                s.addr,
+                s.addr as u64 + s.data.len() as u64,
+                self.memory_size
+            ));
+        }
+        Ok(issues)
+    }
+
+    /// Every instruction of `body_bytes` the core lacks, with where and
+    /// how often the program uses it.
+    pub fn support_report(&self, body_bytes: &[u8]) -> Result<SupportReport, Error> {
+        Ok(self.body_support_report(&ParsedBody::new(body_bytes)?))
+    }
+
+    /// [`HardwareSpec::support_report`] of an already parsed body.
+    pub fn body_support_report(&self, body: &ParsedBody) -> SupportReport {
+        let mut report = SupportReport::default();
+        for (offset, _, op) in body.operators() {
+            if self.supports(op) {
+                continue;
+            }
+            let mut mnemonic = operator_name(op);
+            if self.opcodes.contains(&mnemonic) {
+                // Only the block type is at fault.
+                mnemonic.push_str(" with a non-i32 result");
+            }
+            match report
+                .unsupported
+                .iter_mut()
+                .find(|u| u.mnemonic == mnemonic)
+            {
+                Some(used) => used.offsets.push(offset),
+                None => report.unsupported.push(UnsupportedUse {
+                    mnemonic,
+                    offsets: vec![offset],
+                    feature: OpFeature::of(op),
+                }),
+            }
+        }
+        report
+    }
+
+    /// Why the core can't run `info`, empty if it can: the instructions
+    /// it uses outside the instruction set (see
+    /// [`HardwareSpec::support_report`]), then its
+    /// [`HardwareSpec::capacity_issues`].
+    pub fn skip_reasons(&self, info: &CompiledProgram) -> Result<Vec<String>, Error> {
+        let body = ParsedBody::new(&info.body_bytes)?;
+        let report = self.body_support_report(&body);
+        let mut reasons = Vec::new();
+        if !report.is_empty() {
+            reasons.push(format!("uses {}, which the core lacks", report));
+        }
+        reasons.extend(self.program_capacity_issues(&body, &info.branch_table, &info.mem_init)?);
+        Ok(reasons)
+    }
+
+    /// Fail with every instruction of `info` the core lacks, as
+    /// [`Error::UnsupportedOps`].
+    pub fn check_supported(&self, info: &CompiledProgram) -> Result<(), Error> {
+        let report = self.support_report(&info.body_bytes)?;
+        if report.is_empty() {
+            return Ok(());
+        }
+        Err(Error::UnsupportedOps(report))
+    }
+
+    /// Fail with every [`HardwareSpec::capacity_issues`] of `info`.
+    pub fn check_fits(&self, info: &CompiledProgram) -> Result<(), Error> {
+        let issues = self.capacity_issues(info)?;
+        if issues.is_empty() {
+            return Ok(());
+        }
+        Err(Error::CapacityExceeded(anyhow!(
+            "{} does not fit the core: {}",
+            info.name,
+            issues.join("; ")
+        )))
+    }
+
+    /// Every load and store of `body_bytes` the core can't carry out as
+    /// written, by PC: one whose static offset takes it past the end of
+    /// RAM whatever the address, so it always traps, and, on a core
+    /// without [`HardwareSpec::unaligned`] access, one whose alignment
+    /// hint or offset allows an unaligned address.
+    pub fn memory_access_issues(&self, body_bytes: &[u8]) -> Result<Vec<(usize, String)>, Error> {
+        Ok(self.body_memory_access_issues(&ParsedBody::new(body_bytes)?))
+    }
+
+    /// [`HardwareSpec::memory_access_issues`] of an already parsed body.
+    pub fn body_memory_access_issues(&self, body: &ParsedBody) -> Vec<(usize, String)> {
+        let mut issues = Vec::new();
+        for (pc, _, op) in body.operators() {
+            let Some(memarg) = operator_memarg(op) else {
+                continue;
+            };
+            let name = operator_name(op);
+            let width = 1u64 << memarg.max_align;
+            if memarg.offset.saturating_add(width) > self.memory_size as u64 {
+                issues.push((
+                    pc,
+                    format!(
+                        "{} at offset {} always traps: its {} bytes end past the {}-byte RAM",
+                        name, memarg.offset, width, self.memory_size
+                    ),
+                ));
+            }
+            if self.unaligned {
+                continue;
+            }
+            if memarg.align < memarg.max_align {
+                issues.push((
+                    pc,
+                    format!(
+                        "{} declares {}-byte alignment, but the core only does aligned {}-byte accesses",
+                        name,
+                        1u64 << memarg.align,
+                        width
+                    ),
+                ));
+            } else if !memarg.offset.is_multiple_of(width) {
+                issues.push((
+                    pc,
+                    format!(
+                        "{} at offset {} is unaligned from any aligned address, \
+                         which the core can't access",
+                        name, memarg.offset
+                    ),
+                ));
+            }
+        }
+        issues
+    }
+
+    /// How much of each of the core's resources `info` takes: ROM bytes,
+    /// branch table entries, operand stack depth, block nesting (which
+    /// the core has no limit on), and the data RAM its segments reach.
+    pub fn resource_usage(&self, info: &CompiledProgram) -> Result<ResourceReport, Error> {
+        let stats = program_stats(&info.body_bytes)?;
+        let resource = |name, used, limit| ResourceUse { name, used, limit };
+        Ok(ResourceReport {
+            name: info.name.clone(),
+            resources: vec![
+                resource("rom bytes", info.body_bytes.len(), Some(self.rom_depth)),
+                resource(
+                    "branch entries",
+                    info.branch_table.len(),
+                    Some(self.branch_slots),
+                ),
+                resource("stack depth", stats.max_stack_depth, Some(self.stack_depth)),
+                resource("block depth", stats.max_block_depth, None),
+                resource(
+                    "memory bytes",
+                    memory_image_len(&info.mem_init),
+                    Some(self.memory_size),
+                ),
+            ],
+        })
+    }
+}
+
+/// An instruction a [`HardwareSpec`] lacks and every PC the program
+/// uses it at.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnsupportedUse {
+    /// The mnemonic, suffixed `with a non-i32 result` for a block the
+    /// decoder has but not with that block type.
+    pub mnemonic: String,
+    /// PCs in the function body, ascending.
+    pub offsets: Vec<usize>,
+    /// The capability flag that would make the core run it, if any.
+    pub feature: Option<OpFeature>,
+}
+
+impl UnsupportedUse {
+    pub fn count(&self) -> usize {
+        self.offsets.len()
+    }
+}
+
+/// A group of post-MVP instructions a [`HardwareSpec`] has or lacks as a
+/// whole, by a flag rather than mnemonic by mnemonic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpFeature {
+    /// `i32.extend8_s`, `i32.extend16_s`, and their `i64` counterparts.
+    SignExtension,
+    /// The `*.trunc_sat_*` float-to-int conversions.
+    SaturatingTrunc,
+    /// `memory.copy` and `memory.fill`.
+    BulkMemory,
+}
+
+impl OpFeature {
+    /// The group `op` belongs to, if any.
+    pub fn of(op: &Operator) -> Option<OpFeature> {
+        match op {
+            Operator::I32Extend8S
+            | Operator::I32Extend16S
+            | Operator::I64Extend8S
+            | Operator::I64Extend16S
+            | Operator::I64Extend32S => Some(OpFeature::SignExtension),
+            Operator::I32TruncSatF32S
+            | Operator::I32TruncSatF32U
+            | Operator::I32TruncSatF64S
+            | Operator::I32TruncSatF64U
+            | Operator::I64TruncSatF32S
+            | Operator::I64TruncSatF32U
+            | Operator::I64TruncSatF64S
+            | Operator::I64TruncSatF64U => Some(OpFeature::SaturatingTrunc),
+            Operator::MemoryCopy { .. } | Operator::MemoryFill { .. } => {
+                Some(OpFeature::BulkMemory)
+            }
+            _ => None,
+        }
+    }
+
+    /// The [`HardwareSpec`] TOML key of the flag.
+    pub fn key(self) -> &'static str {
+        match self {
+            OpFeature::SignExtension => "sign_extension",
+            OpFeature::SaturatingTrunc => "saturating_trunc",
+            OpFeature::BulkMemory => "bulk_memory",
+        }
+    }
+}
+
+/// Every instruction of a program outside a core's instruction set (see
+/// [`HardwareSpec::support_report`]), in order of first use.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct SupportReport {
+    pub unsupported: Vec<UnsupportedUse>,
+}
+
+impl SupportReport {
+    /// Whether the core runs every instruction.
+    pub fn is_empty(&self) -> bool {
+        self.unsupported.is_empty()
+    }
+}
+
+/// `i64.const (2x at pc 0x0000, 0x0005), i32.wrap_i64 (1x at pc 0x0002)`,
+/// listing the first four PCs of each.
+impl std::fmt::Display for SupportReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        const SHOWN: usize = 4;
+        for (i, used) in self.unsupported.iter().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            let pcs: Vec<String> = used
+                .offsets
+                .iter()
+                .take(SHOWN)
+                .map(|pc| format!("{:#06X}", pc))
+                .collect();
+            let more = if used.count() > SHOWN { ", ..." } else { "" };
+            write!(
+                f,
+                "{} ({}x at pc {}{}",
+                used.mnemonic,
+                used.count(),
+                pcs.join(", "),
+                more
+            )?;
+            match used.feature {
+                Some(feature) => write!(f, "; needs {} = true)", feature.key())?,
+                None => f.write_str(")")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// How much of one of a core's resources a program takes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResourceUse {
+    pub name: &'static str,
+    pub used: usize,
+    /// The [`HardwareSpec`] limit, `None` for what the core doesn't bound.
+    pub limit: Option<usize>,
+}
+
+impl ResourceUse {
+    /// `used` as a percentage of `limit`.
+    pub fn percent(&self) -> Option<f64> {
+        self.limit
+            .map(|limit| 100.0 * self.used as f64 / limit.max(1) as f64)
+    }
+}
+
+/// A test's [`ResourceUse`] of each resource, from
+/// [`HardwareSpec::resource_usage`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResourceReport {
+    pub name: String,
+    pub resources: Vec<ResourceUse>,
+}
+
+/// The peak use of each resource across `reports`, with the test that
+/// reaches it (the first, on a tie).
+pub fn corpus_usage(reports: &[ResourceReport]) -> Vec<(ResourceUse, &str)> {
+    let mut peaks: Vec<(ResourceUse, &str)> = Vec::new();
+    for report in reports {
+        for resource in &report.resources {
+            match peaks
+                .iter_mut()
+                .find(|(peak, _)| peak.name == resource.name)
+            {
+                Some(peak) if peak.0.used >= resource.used => {}
+                Some(peak) => *peak = (resource.clone(), &report.name),
+                None => peaks.push((resource.clone(), &report.name)),
+            }
+        }
+    }
+    peaks
+}
+
+/// `reports` as a table per test, then one of the [`corpus_usage`] peaks
+/// if there is more than one test:
+///
+/// ```text
+/// add
+///   rom bytes             20 / 65536      0.0%
+///   block depth            1
+/// ```
+pub fn resource_usage_text(reports: &[ResourceReport]) -> String {
+    let row = |resource: &ResourceUse| match (resource.limit, resource.percent()) {
+        (Some(limit), Some(percent)) => format!(
+            "  {:<16}{:>6} / {:<8}{:>6.1}%",
+            resource.name, resource.used, limit, percent
+        ),
+        _ => format!("  {:<16}{:>6}", resource.name, resource.used),
+    };
+    let mut out = String::new();
+    for report in reports {
+        out.push_str(&format!("{}\n", report.name));
+        for resource in &report.resources {
+            out.push_str(&format!("{}\n", row(resource)));
+        }
+    }
+    if reports.len() > 1 {
+        out.push_str(&format!("corpus peak of {} tests\n", reports.len()));
+        for (resource, test) in corpus_usage(reports) {
+            out.push_str(&format!("{:<42}  ({})\n", row(&resource), test));
+        }
+    }
+    out
+}
+
+/// How a corpus exercises the core's instruction set (see
+/// [`HardwareSpec::opcode_coverage`]).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OpcodeCoverage {
+    /// Tests in the corpus.
+    pub tests: usize,
+    /// The tests using each mnemonic, whether the core has it or not.
+    pub uses: std::collections::BTreeMap<String, std::collections::BTreeSet<String>>,
+    /// Mnemonics the core implements that some test uses.
+    pub tested: Vec<String>,
+    /// Mnemonics the core implements that no test uses.
+    pub untested: Vec<String>,
+    /// Mnemonics the core lacks, each with the tests it keeps off the
+    /// core, the most blocking first.
+    pub blocking: Vec<(String, Vec<String>)>,
+    /// The flag that would bring each blocking mnemonic that has one.
+    pub features: std::collections::BTreeMap<String, OpFeature>,
+}
+
+impl OpcodeCoverage {
+    /// The tests the core could run after gaining all of `gains`, each a
+    /// mnemonic or a feature key such as `sign_extension`. Only
+    /// instructions count; a test may still overflow the core or need
+    /// parameters it can't be passed.
+    pub fn unlocked_by(&self, gains: &[String]) -> Vec<String> {
+        let gained = |op: &str| {
+            gains
+                .iter()
+                .any(|g| g == op || self.features.get(op).is_some_and(|f| f.key() == g))
+        };
+        let mut blockers = std::collections::BTreeMap::<&str, Vec<&str>>::new();
+        for (op, tests) in &self.blocking {
+            for test in tests {
+                blockers.entry(test).or_default().push(op);
+            }
+        }
+        blockers
+            .into_iter()
+            .filter(|(_, ops)| ops.iter().all(|op| gained(op)))
+            .map(|(test, _)| test.to_string())
+            .collect()
+    }
+
+    /// For each blocking mnemonic and feature on its own, the tests
+    /// gaining it would unlock, the most first: where decoder work pays
+    /// off soonest.
+    pub fn what_if(&self) -> Vec<(String, Vec<String>)> {
+        let mut gains: Vec<String> = self.blocking.iter().map(|(op, _)| op.clone()).collect();
+        for feature in self.features.values() {
+            if !gains.iter().any(|g| g == feature.key()) {
+                gains.push(feature.key().to_string());
+            }
+        }
+        let mut unlocked: Vec<(String, Vec<String>)> = gains
+            .into_iter()
+            .map(|gain| {
+                let tests = self.unlocked_by(std::slice::from_ref(&gain));
+                (gain, tests)
+            })
+            .collect();
+        unlocked.sort_by(|a, b| b.1.len().cmp(&a.1.len()).then_with(|| a.0.cmp(&b.0)));
+        unlocked
+    }
+}
+
+impl HardwareSpec {
+    /// Crosses the instructions of every `(name, wasm)` test against the
+    /// ones the core implements.
+    pub fn opcode_coverage(&self, tests: &[(String, Vec<u8>)]) -> Result<OpcodeCoverage, Error> {
+        let mut bodies = Vec::with_capacity(tests.len());
+        for (name, wasm) in tests {
+            bodies.push((
+                name.as_str(),
+                hardware_body(extract_function_body_ref(wasm)?),
+            ));
+        }
+        let bodies: Vec<(&str, &[u8])> =
+            bodies.iter().map(|(name, body)| (*name, &**body)).collect();
+        self.body_coverage(&bodies)
+    }
+
+    /// [`HardwareSpec::opcode_coverage`] of `(name, body)` tests whose
+    /// bodies are already the core's, like a [`CompiledProgram`]'s.
+    fn body_coverage(&self, tests: &[(&str, &[u8])]) -> Result<OpcodeCoverage, Error> {
+        let mut coverage = OpcodeCoverage {
+            tests: tests.len(),
+            ..OpcodeCoverage::default()
+        };
+        let mut blocking = std::collections::BTreeMap::<String, Vec<String>>::new();
+        for &(name, body) in tests {
+            let name = name.to_string();
+            let body = ParsedBody::new(body)?;
+            for (_, _, op) in body.operators() {
+                coverage
+                    .uses
+                    .entry(operator_name(op))
+                    .or_default()
+                    .insert(name.clone());
+            }
+            for used in self.body_support_report(&body).unsupported {
+                if let Some(feature) = used.feature {
+                    coverage.features.insert(used.mnemonic.clone(), feature);
+                }
+                blocking
+                    .entry(used.mnemonic)
+                    .or_default()
+                    .push(name.clone());
+            }
+        }
+        (coverage.tested, coverage.untested) = self
+            .opcodes
+            .iter()
+            .cloned()
+            .partition(|op| coverage.uses.contains_key(op));
+        coverage.blocking = blocking.into_iter().collect();
+        coverage
+            .blocking
+            .sort_by(|a, b| b.1.len().cmp(&a.1.len()).then_with(|| a.0.cmp(&b.0)));
+        Ok(coverage)
+    }
+}
+
+impl std::fmt::Display for OpcodeCoverage {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{} tests use {} of the {} instructions the core implements",
+            self.tests,
+            self.tested.len(),
+            self.tested.len() + self.untested.len()
+        )?;
+        if !self.untested.is_empty() {
+            writeln!(
+                f,
+                "untested ({}): {}",
+                self.untested.len(),
+                self.untested.join(", ")
+            )?;
+        }
+        if !self.blocking.is_empty() {
+            writeln!(f, "blocking:")?;
+        }
+        for (mnemonic, tests) in &self.blocking {
+            writeln!(f, "{}", test_count_row(mnemonic, tests))?;
+        }
+        Ok(())
+    }
+}
+
+/// `  name   N tests (a, b, c, d, ...)`, for the rows of
+/// [`OpcodeCoverage`] reports.
+fn test_count_row(name: &str, tests: &[String]) -> String {
+    const SHOWN: usize = 4;
+    let row = format!("  {:<20}{:>4} tests", name, tests.len());
+    if tests.is_empty() {
+        return row;
+    }
+    let more = if tests.len() > SHOWN { ", ..." } else { "" };
+    let shown: Vec<&str> = tests.iter().take(SHOWN).map(String::as_str).collect();
+    format!("{} ({}{})", row, shown.join(", "), more)
+}
+
+/// [`OpcodeCoverage::what_if`] as text, one gain per line.
+pub fn what_if_text(coverage: &OpcodeCoverage) -> String {
+    let blocked: std::collections::BTreeSet<&String> = coverage
+        .blocking
+        .iter()
+        .flat_map(|(_, tests)| tests)
+        .collect();
+    let mut out = String::new();
+    out.push_str(&format!(
+        "tests each gain would unlock, of the {} blocked\n",
+        blocked.len()
+    ));
+    for (gain, tests) in coverage.what_if() {
+        out.push_str(&format!("{}\n", test_count_row(&gain, &tests)));
+    }
+    out
+}
+
+/// A Markdown report of a generated corpus, for committing beside it or
+/// pasting into design reviews: each test's ROM bytes, branch table
+/// entries, expected value, and the distinct instructions it uses, then
+/// the tests `spec` can't run with the reasons, then which of the core's
+/// instructions the corpus covers.
+pub fn corpus_markdown(
+    tests: &[CompiledProgram],
+    skipped: &[(CompiledProgram, Vec<String>)],
+    spec: &HardwareSpec,
+) -> Result<String, Error> {
+    let cell = |s: &str| s.replace('|', "\\|").replace('\n', " ");
+    let expected = |info: &CompiledProgram| {
+        if info.expect_trap {
+            "trap".to_string()
+        } else {
+            info.expected.to_string()
+        }
+    };
+    let instructions = |info: &CompiledProgram| -> Result<usize, Error> {
+        let body = ParsedBody::new(&info.body_bytes)?;
+        let used: std::collections::BTreeSet<String> = body
+            .operators()
+            .map(|(_, _, op)| operator_name(op))
+            .collect();
+        Ok(used.len())
+    };
+    let mut out = String::from("# WAT test corpus\n\n");
+    out.push_str(&format!(
+        "{} tests generated, {} skipped.\n\n",
+        tests.len(),
+        skipped.len()
+    ));
+    if !tests.is_empty() {
+        out.push_str("| Test | ROM bytes | Branch entries | Expected | Instructions |\n");
+        out.push_str("|------|----------:|---------------:|---------:|-------------:|\n");
+        for info in tests {
+            out.push_str(&format!(
+                "| {} | {} | {} | {} | {} |\n",
+                cell(&info.name),
+                info.body_bytes.len(),
+                info.branch_table.len(),
+                expected(info),
+                instructions(info)?
+            ));
+        }
+        let bytes: usize = tests.iter().map(|t| t.body_bytes.len()).sum();
+        let entries: usize = tests.iter().map(|t| t.branch_table.len()).sum();
+        out.push_str(&format!(
+            "\n{} ROM bytes and {} branch entries in all.\n\n",
+            bytes, entries
+        ));
+    }
+    if !skipped.is_empty() {
+        out.push_str(
+            "## Skipped\n\n| Test | ROM bytes | Reason |\n|------|----------:|--------|\n",
+        );
+        for (info, reasons) in skipped {
+            out.push_str(&format!(
+                "| {} | {} | {} |\n",
+                cell(&info.name),
+                info.body_bytes.len(),
+                cell(&reasons.join("; "))
+            ));
+        }
+        out.push('\n');
+    }
+    let bodies: Vec<(&str, &[u8])> = tests
+        .iter()
+        .chain(skipped.iter().map(|(info, _)| info))
+        .map(|info| (info.name.as_str(), info.body_bytes.as_slice()))
+        .collect();
+    let coverage = spec.body_coverage(&bodies)?;
+    out.push_str(&format!(
+        "## Opcode coverage\n\nThe tests use {} of the {} instructions the core implements.\n\n",
+        coverage.tested.len(),
+        coverage.tested.len() + coverage.untested.len()
+    ));
+    out.push_str("| Instruction | Tests |\n|-------------|------:|\n");
+    for op in &coverage.tested {
+        out.push_str(&format!("| `{}` | {} |\n", op, coverage.uses[op].len()));
+    }
+    if !coverage.untested.is_empty() {
+        out.push_str(&format!(
+            "\nUntested: {}.\n",
+            coverage
+                .untested
+                .iter()
+                .map(|op| format!("`{}`", op))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ));
+    }
+    if !coverage.blocking.is_empty() {
+        out.push_str(
+            "\n| Missing instruction | Tests blocked |\n|---------------------|--------------:|\n",
+        );
+        for (op, blocked) in &coverage.blocking {
+            out.push_str(&format!("| `{}` | {} |\n", op, blocked.len()));
+        }
+    }
+    Ok(out)
+}
+
+fn toml_count(key: &str, value: &toml::Value) -> Result<usize> {
+    value
+        .as_integer()
+        .and_then(|v| usize::try_from(v).ok())
+        .filter(|&v| v > 0)
+        .ok_or_else(|| anyhow!("hardware spec `{}` must be a positive integer", key))
+}
+
+fn toml_flag(key: &str, value: &toml::Value) -> Result<bool> {
+    value
+        .as_bool()
+        .ok_or_else(|| anyhow!("hardware spec `{}` must be true or false", key))
+}
+
+fn toml_strings(key: &str, value: &toml::Value) -> Result<Vec<String>> {
+    value
+        .as_array()
+        .and_then(|items| {
+            items
+                .iter()
+                .map(|item| item.as_str().map(str::to_string))
+                .collect()
+        })
+        .ok_or_else(|| anyhow!("hardware spec `{}` must be an array of strings", key))
+}
+
+// ---------------------------------------------------------------------------
+// Port map
+// ---------------------------------------------------------------------------
+
+/// One port of the core: its name, its width in bits, and, for the
+/// single-bit control ports, whether it is asserted low.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Port {
+    pub name: String,
+    pub width: u32,
+    pub active_low: bool,
+}
+
+impl Port {
+    fn new(name: &str, width: u32) -> Self {
+        Port {
+            name: name.to_string(),
+            width,
+            active_low: false,
+        }
+    }
+
+    /// The bit driving the port asserted or not, given its active level.
+    pub fn bit(&self, asserted: bool) -> u64 {
+        (asserted != self.active_low) as u64
+    }
+
+    /// The values the port can carry.
+    pub fn mask(&self) -> u64 {
+        u64::MAX >> (64 - self.width)
+    }
+}
+
+/// The core's ports by role, so the generated testbench code and the
+/// simulation harness (see `sim::PortMapped`) fit a core that doesn't use
+/// the `WasmCoreTb` names. The default is `WasmCoreTb`, whose `i_rst` is
+/// asserted low; a TOML file (see [`PortMap::from_toml`]) describes other
+/// cores.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PortMap {
+    pub clk: Port,
+    pub rst: Port,
+    pub start: Port,
+    pub prog_addr: Port,
+    pub prog_data: Port,
+    pub bt_wr_en: Port,
+    pub bt_wr_addr: Port,
+    pub bt_wr_data: Port,
+    pub mem_load_en: Port,
+    pub mem_load_addr: Port,
+    pub mem_load_data: Port,
+    pub pc: Port,
+    pub running: Port,
+    pub halted: Port,
+    pub trap: Port,
+    pub stack_top: Port,
+}
+
+impl Default for PortMap {
+    fn default() -> Self {
+        PortMap {
+            clk: Port::new("i_clk", 1),
+            rst: Port {
+                active_low: true,
+                ..Port::new("i_rst", 1)
+            },
+            start: Port::new("i_start", 1),
+            prog_addr: Port::new("o_prog_addr", 32),
+            prog_data: Port::new("i_prog_data", 8),
+            bt_wr_en: Port::new("i_bt_wr_en", 1),
+            bt_wr_addr: Port::new("i_bt_wr_addr", 32),
+            bt_wr_data: Port::new("i_bt_wr_data", 32),
+            mem_load_en: Port::new("i_mem_load_en", 1),
+            mem_load_addr: Port::new("i_mem_load_addr", 32),
+            mem_load_data: Port::new("i_mem_load_data", 8),
+            pc: Port::new("o_pc", 32),
+            running: Port::new("o_running", 1),
+            halted: Port::new("o_halted", 1),
+            trap: Port::new("o_trap", 1),
+            stack_top: Port::new("o_stack_top", 32),
+        }
+    }
+}
+
+impl PortMap {
+    /// Roles in port list order, as the TOML keys name them.
+    pub const ROLES: [&'static str; 16] = [
+        "clk",
+        "rst",
+        "start",
+        "prog_addr",
+        "prog_data",
+        "bt_wr_en",
+        "bt_wr_addr",
+        "bt_wr_data",
+        "mem_load_en",
+        "mem_load_addr",
+        "mem_load_data",
+        "pc",
+        "running",
+        "halted",
+        "trap",
+        "stack_top",
+    ];
+
+    /// Roles whose port is a single bit: the clock, and the control
+    /// signals, which have an active level.
+    const CONTROL: [&'static str; 8] = [
+        "clk",
+        "rst",
+        "start",
+        "bt_wr_en",
+        "mem_load_en",
+        "running",
+        "halted",
+        "trap",
+    ];
+
+    /// Each role with its port, in [`PortMap::ROLES`] order.
+    pub fn ports(&self) -> [(&'static str, &Port); 16] {
+        let ports = [
+            &self.clk,
+            &self.rst,
+            &self.start,
+            &self.prog_addr,
+            &self.prog_data,
+            &self.bt_wr_en,
+            &self.bt_wr_addr,
+            &self.bt_wr_data,
+            &self.mem_load_en,
+            &self.mem_load_addr,
+            &self.mem_load_data,
+            &self.pc,
+            &self.running,
+            &self.halted,
+            &self.trap,
+            &self.stack_top,
+        ];
+        std::array::from_fn(|i| (Self::ROLES[i], ports[i]))
+    }
+
+    fn port_mut(&mut self, role: &str) -> Option<&mut Port> {
+        Some(match role {
+            "clk" => &mut self.clk,
+            "rst" => &mut self.rst,
+            "start" => &mut self.start,
+            "prog_addr" => &mut self.prog_addr,
+            "prog_data" => &mut self.prog_data,
+            "bt_wr_en" => &mut self.bt_wr_en,
+            "bt_wr_addr" => &mut self.bt_wr_addr,
+            "bt_wr_data" => &mut self.bt_wr_data,
+            "mem_load_en" => &mut self.mem_load_en,
+            "mem_load_addr" => &mut self.mem_load_addr,
+            "mem_load_data" => &mut self.mem_load_data,
+            "pc" => &mut self.pc,
+            "running" => &mut self.running,
+            "halted" => &mut self.halted,
+            "trap" => &mut self.trap,
+            "stack_top" => &mut self.stack_top,
+            _ => return None,
+        })
+    }
+
+    /// The default map with the roles of a TOML document overriding it,
+    /// each either a new name or a table changing any of `name`, `width`,
+    /// and, for control ports, `active_low`:
+    ///
+    /// ```toml
+    /// clk = "clock"
+    /// rst = { name = "reset", active_low = false }
+    /// stack_top = { name = "result", width = 64 }
+    /// ```
+    ///
+    /// Control ports stay one bit wide, and the program and data RAM
+    /// ports eight bits or more. Unknown roles and keys are an error so
+    /// typos don't silently fall back to the defaults.
+    pub fn from_toml(text: &str) -> Result<Self, Error> {
+        let table: toml::Table = text.parse()?;
+        let mut map = PortMap::default();
+        for (role, value) in &table {
+            let control = Self::CONTROL.contains(&role.as_str());
+            let port = map.port_mut(role).ok_or_else(|| {
+                anyhow!(
+                    "unknown port role `{}`; expected one of {}",
+                    role,
+                    Self::ROLES.join(", ")
+                )
+            })?;
+            let fields = match value {
+                toml::Value::String(name) => {
+                    port.name = name.clone();
+                    continue;
+                }
+                toml::Value::Table(fields) => fields,
+                _ => {
+                    return Err(Error::ParseError(anyhow!(
+                        "port `{}` must be a name or a table",
+                        role
+                    )))
+                }
+            };
+            for (key, value) in fields {
+                match key.as_str() {
+                    "name" => {
+                        port.name = value
+                            .as_str()
+                            .ok_or_else(|| anyhow!("port `{}`: `name` must be a string", role))?
+                            .to_string()
+                    }
+                    "width" => {
+                        port.width = value
+                            .as_integer()
+                            .and_then(|w| u32::try_from(w).ok())
+                            .filter(|w| (1..=64).contains(w))
+                            .ok_or_else(|| anyhow!("port `{}`: `width` must be 1 to 64", role))?
+                    }
+                    "active_low" if control && role != "clk" => {
+                        port.active_low = value.as_bool().ok_or_else(|| {
+                            anyhow!("port `{}`: `active_low` must be true or false", role)
+                        })?
+                    }
+                    "active_low" => {
+                        return Err(Error::ParseError(anyhow!(
+                            "port `{}` has no active level",
+                            role
+                        )))
+                    }
+                    _ => {
+                        return Err(Error::ParseError(anyhow!(
+                            "unknown key `{}` for port `{}`",
+                            key,
+                            role
+                        )))
+                    }
+                }
+            }
+            if control && port.width != 1 {
+                return Err(Error::ParseError(anyhow!(
+                    "port `{}` is a control port and must be 1 bit wide",
+                    role
+                )));
+            }
+            if matches!(role.as_str(), "prog_data" | "mem_load_data") && port.width < 8 {
+                return Err(Error::ParseError(anyhow!(
+                    "port `{}` carries bytes and must be at least 8 bits wide",
+                    role
+                )));
+            }
+        }
+        Ok(map)
+    }
+
+    pub fn load(path: &std::path::Path) -> Result<Self, Error> {
+        let text =
+            fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+        Ok(Self::from_toml(&text).with_context(|| format!("parsing {}", path.display()))?)
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Hardware compatibility check
+// ---------------------------------------------------------------------------
+
+/// The first instruction in a function body the default core can't
+/// execute, as its mnemonic, or `None` if it only uses the decoder's
+/// subset (see [`compatibility_issues`] for all of them).
+pub fn unsupported_instruction(body_bytes: &[u8]) -> Result<Option<String>, Error> {
+    let spec = HardwareSpec::default();
+    let binary_reader = wasmparser::BinaryReader::new(body_bytes, 0);
+    let mut reader = wasmparser::OperatorsReader::new(binary_reader);
+    while !reader.eof() {
+        let op = reader.read()?;
+        if !spec.supports(&op) {
+            return Ok(Some(format_operator(&op)));
+        }
+    }
+    Ok(None)
+}
+
+/// Something in a module the core can't run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompatIssue {
+    /// PC of the offending instruction in the program, as the ROM and
+    /// `disasm` number it; `None` for module-level constructs.
+    pub pc: Option<usize>,
+    pub issue: String,
+    /// The instruction with its neighbours, the offending one in `>> <<`.
+    pub context: String,
+}
+
+/// Every construct in a module that keeps its first function, the one
+/// that gets compiled, from running on the core `spec` describes:
+/// imports, parameters, results other than one `i32`, declared locals,
+/// more than one memory, a program or data too big for it (see
+/// [`HardwareSpec::capacity_issues`]), each instruction outside its
+/// instruction set, and each load or store it can't carry out (see
+/// [`HardwareSpec::memory_access_issues`]).
+pub fn compatibility_issues(
+    wasm_bytes: &[u8],
+    spec: &HardwareSpec,
+) -> Result<Vec<CompatIssue>, Error> {
+    let module_issue = |issue: String| CompatIssue {
+        pc: None,
+        issue,
+        context: String::new(),
+    };
+    let mut issues = Vec::new();
+    let mut types = Vec::new();
+    let mut first_type = None;
+    let mut locals = None;
+    for payload in wasmparser::Parser::new(0).parse_all(wasm_bytes) {
+        match payload? {
+            Payload::TypeSection(reader) => {
+                for ty in reader.into_iter_err_on_gc_types() {
+                    types.push(ty?);
+                }
+            }
+            Payload::ImportSection(reader) => {
+                for import in reader.into_imports() {
+                    let import = import?;
+                    issues.push(module_issue(format!(
+                        "imports {}.{}, which the core cannot provide",
+                        import.module, import.name
+                    )));
+                }
+            }
+            Payload::FunctionSection(reader) => {
+                first_type = reader.into_iter().next().transpose()?;
+            }
+            // Only the first body is compiled.
+            Payload::CodeSectionEntry(body) if locals.is_none() => {
+                let mut count = 0;
+                for decl in body.get_locals_reader()? {
+                    count += decl?.0;
+                }
+                locals = Some(count);
+            }
+            _ => {}
+        }
+    }
+    let func_type = first_type
+        .and_then(|t| types.get(t as usize))
+        .ok_or_else(|| anyhow!("the module defines no function"))?;
+    let list = |types: &[wasmparser::ValType]| {
+        types
+            .iter()
+            .map(|t| t.to_string())
+            .collect::<Vec<_>>()
+            .join(" ")
+    };
+    if !func_type.params().is_empty() {
+        issues.push(module_issue(format!(
+            "the function takes parameters ({}), which the core cannot be passed",
+            list(func_type.params())
+        )));
+    }
+    if func_type.results() != [wasmparser::ValType::I32] {
+        issues.push(module_issue(format!(
+            "the function returns ({}), not a single i32",
+            list(func_type.results())
+        )));
+    }
+    if let Some(count @ 1..) = locals {
+        issues.push(module_issue(format!(
+            "the function declares {} locals, which the core has no storage for",
+            count
+        )));
+    }
+    let memories = memory_count(wasm_bytes)?;
+    if memories > 1 {
+        issues.push(module_issue(format!(
+            "the module declares {} memories, but the core has a single data RAM",
+            memories
+        )));
+    }
+
+    let bytes = hardware_body(extract_function_body_ref(wasm_bytes)?);
+    let body = ParsedBody::new(&bytes)?;
+    // A body the branch pass rejects already has an instruction to report.
+    // Data for another memory has no place in the core's to be measured.
+    if let (Ok(branch_table), 0 | 1) = (body.branch_table(), memories) {
+        let mem_init = extract_memory_init(wasm_bytes)?;
+        for issue in spec.program_capacity_issues(&body, &branch_table, &mem_init)? {
+            issues.push(module_issue(issue));
+        }
+    }
+    let report = spec.body_support_report(&body);
+    let mut unsupported: Vec<(usize, &str)> = report
+        .unsupported
+        .iter()
+        .flat_map(|u| u.offsets.iter().map(|&pc| (pc, u.mnemonic.as_str())))
+        .collect();
+    unsupported.sort_unstable();
+    let value_types = value_type_uses(wasm_bytes)?;
+    let mut located: Vec<(usize, String)> = unsupported
+        .into_iter()
+        .map(|(pc, mnemonic)| {
+            let types: Vec<&str> = value_types
+                .iter()
+                .filter(|used| used.pc == Some(pc))
+                .map(|used| used.ty.as_str())
+                .collect();
+            let issue = if types.is_empty() {
+                format!("{} is not supported", mnemonic)
+            } else {
+                format!(
+                    "{} is not supported; the core has no {}",
+                    mnemonic,
+                    types.join(" or ")
+                )
+            };
+            (pc, issue)
+        })
+        .collect();
+    located.extend(spec.body_memory_access_issues(&body));
+    located.sort_by_key(|(pc, _)| *pc);
+    let insns = body.insns();
+    for (pc, issue) in located {
+        let index = insns.partition_point(|insn| insn.offset < pc);
+        let window = &insns[index.saturating_sub(1)..(index + 2).min(insns.len())];
+        let context = window
+            .iter()
+            .map(|insn| {
+                if insn.offset == pc {
+                    format!(">> {} <<", insn)
+                } else {
+                    insn.to_string()
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("  ");
+        issues.push(CompatIssue {
+            pc: Some(pc),
+            issue,
+            context,
+        });
+    }
+    Ok(issues)
+}
+
+/// Where a module brings in a value type the core lacks: the core only
+/// has `i32`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValueTypeUse {
+    /// `i64`, `f32`, `f64`, `v128`, or a reference type.
+    pub ty: String,
+    /// PC of the instruction that introduces it; `None` for a
+    /// declaration.
+    pub pc: Option<usize>,
+    /// The instruction, e.g. `i64.const 2`, or the declaration: `param`,
+    /// `result`, `local`, or `global`.
+    pub what: String,
+}
+
+/// The value types besides `i32` an instruction takes or produces, from
+/// its mnemonic (`i32.wrap_i64` takes an `i64`) or its block type.
+fn operator_value_types(op: &Operator) -> Vec<String> {
+    use wasmparser::{BlockType, ValType};
+    match op {
+        Operator::Block { blockty } | Operator::Loop { blockty } | Operator::If { blockty } => {
+            return match blockty {
+                BlockType::Type(ty) if *ty != ValType::I32 => vec![ty.to_string()],
+                _ => Vec::new(),
+            };
+        }
+        Operator::TypedSelect { ty } if *ty != ValType::I32 => return vec![ty.to_string()],
+        _ => {}
+    }
+    let name = operator_name(op);
+    let (prefix, rest) = name.split_once('.').unwrap_or((&name, ""));
+    let mut types = Vec::new();
+    match prefix {
+        "i64" | "f32" | "f64" | "v128" => types.push(prefix.to_string()),
+        "ref" | "table" => types.push("ref".to_string()),
+        _ => {}
+    }
+    for ty in ["i64", "f32", "f64"] {
+        if rest.contains(&format!("_{}", ty)) && !types.iter().any(|t| t == ty) {
+            types.push(ty.to_string());
+        }
+    }
+    types
+}
+
+/// Every place the module's first function, the one that gets compiled,
+/// brings in a value type other than `i32`: its parameters, results, and
+/// locals, the module's globals, then each instruction, in PC order as
+/// [`compatibility_issues`] numbers them.
+pub fn value_type_uses(wasm_bytes: &[u8]) -> Result<Vec<ValueTypeUse>, Error> {
+    let declared = |ty: &wasmparser::ValType, what: &str| ValueTypeUse {
+        ty: ty.to_string(),
+        pc: None,
+        what: what.to_string(),
+    };
+    let i32 = wasmparser::ValType::I32;
+    let mut uses = Vec::new();
+    let mut types = Vec::new();
+    let mut first_type = None;
+    let mut locals = Vec::new();
+    let mut seen_body = false;
+    for payload in wasmparser::Parser::new(0).parse_all(wasm_bytes) {
+        match payload? {
+            Payload::TypeSection(reader) => {
+                for ty in reader.into_iter_err_on_gc_types() {
+                    types.push(ty?);
+                }
+            }
+            Payload::FunctionSection(reader) => {
+                first_type = reader.into_iter().next().transpose()?;
+            }
+            Payload::GlobalSection(reader) => {
+                for global in reader {
+                    let ty = global?.ty.content_type;
+                    if ty != i32 {
+                        uses.push(declared(&ty, "global"));
+                    }
+                }
+            }
+            Payload::CodeSectionEntry(body) if !seen_body => {
+                seen_body = true;
+                for decl in body.get_locals_reader()? {
+                    locals.push(decl?.1);
+                }
+            }
+            _ => {}
+        }
+    }
+    let mut signature = Vec::new();
+    if let Some(func_type) = first_type.and_then(|t| types.get(t as usize)) {
+        signature.extend(func_type.params().iter().map(|ty| (ty, "param")));
+        signature.extend(func_type.results().iter().map(|ty| (ty, "result")));
+    }
+    signature.extend(locals.iter().map(|ty| (ty, "local")));
+    let mut declarations: Vec<ValueTypeUse> = signature
+        .into_iter()
+        .filter(|(ty, _)| **ty != i32)
+        .map(|(ty, what)| declared(ty, what))
+        .collect();
+    declarations.append(&mut uses);
+
+    let body = hardware_body(extract_function_body_ref(wasm_bytes)?);
+    let binary_reader = wasmparser::BinaryReader::new(&body, 0);
+    let mut reader = wasmparser::OperatorsReader::new(binary_reader);
+    while !reader.eof() {
+        let (op, pc) = reader.read_with_offset()?;
+        for ty in operator_value_types(&op) {
+            declarations.push(ValueTypeUse {
+                ty,
+                pc: Some(pc),
+                what: format_operator(&op),
+            });
+        }
+    }
+    Ok(declarations)
+}
+
+/// What the core would need to run a module with these
+/// [`value_type_uses`], one clause per type in order of first use:
+/// `the core would need i64 (the result; i64.const at pc 0x0000, 0x0005),
+/// f32 (f32.add at pc 0x0007)`. Empty if there are none.
+pub fn value_type_summary(uses: &[ValueTypeUse]) -> String {
+    // Each instruction or declaration with the PCs it is at.
+    type Sites = Vec<(String, Vec<usize>)>;
+    let mut by_type: Vec<(&str, Sites)> = Vec::new();
+    for used in uses {
+        let index = match by_type.iter().position(|(ty, _)| *ty == used.ty) {
+            Some(index) => index,
+            None => {
+                by_type.push((&used.ty, Vec::new()));
+                by_type.len() - 1
+            }
+        };
+        let sites = &mut by_type[index].1;
+        let what = match used.pc {
+            Some(_) => operator_mnemonic(&used.what),
+            None => format!("the {}", used.what),
+        };
+        let site = match sites.iter_mut().find(|(w, _)| *w == what) {
+            Some(site) => site,
+            None => {
+                sites.push((what, Vec::new()));
+                sites.last_mut().expect("just pushed")
+            }
+        };
+        site.1.extend(used.pc);
+    }
+    if by_type.is_empty() {
+        return String::new();
+    }
+    let clauses: Vec<String> = by_type
+        .into_iter()
+        .map(|(ty, sites)| {
+            let sites: Vec<String> = sites
+                .into_iter()
+                .map(|(what, pcs)| {
+                    if pcs.is_empty() {
+                        return what;
+                    }
+                    let pcs: Vec<String> = pcs.iter().map(|pc| format!("{:#06X}", pc)).collect();
+                    format!("{} at pc {}", what, pcs.join(", "))
+                })
+                .collect();
+            format!("{} ({})", ty, sites.join("; "))
+        })
+        .collect();
+    format!("the core would need {}", clauses.join(", "))
+}
+
+/// The mnemonic of an instruction as [`format_operator`] writes it.
+fn operator_mnemonic(text: &str) -> String {
+    text.split_whitespace().next().unwrap_or(text).to_string()
+}
+
+#[cfg(all(test, feature = "refexec"))]
+mod tests {
+    use super::*;
+    use crate::svgen::{generate_sva, generate_sva_with, generate_veryl_testbench};
+    use crate::{
+        compile, compile_wat, compile_wat_source, extract_function_body, generate_svh_with,
+        CompileOptions, Source, SvhOptions,
+    };
+
+    #[test]
+    fn test_compatibility_issues() {
+        let wasm = compile_wat(
+            r#"(module (import "env" "f" (func))
+                (func (param i32) (result i64) (local i32 i32)
+                    i32.const 1 local.get 0 i32.add drop i64.const 2))"#,
+        )
+        .unwrap();
+        let issues = compatibility_issues(&wasm, &HardwareSpec::default()).unwrap();
+        let summary: Vec<_> = issues
+            .iter()
+            .map(|i| (i.pc, i.issue.as_str(), i.context.as_str()))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (None, "imports env.f, which the core cannot provide", ""),
+                (
+                    None,
+                    "the function takes parameters (i32), which the core cannot be passed",
+                    ""
+                ),
+                (None, "the function returns (i64), not a single i32", ""),
+                (
+                    None,
+                    "the function declares 2 locals, which the core has no storage for",
+                    ""
+                ),
+                (
+                    Some(2),
+                    "local.get is not supported",
+                    "i32.const 1  >> local.get 0 <<  i32.add"
+                ),
+                (
+                    Some(6),
+                    "i64.const is not supported; the core has no i64",
+                    "drop  >> i64.const 2 <<  return"
+                ),
+            ]
+        );
+        let mut spec = HardwareSpec::default();
+        spec.opcodes.insert("i64.const".to_string());
+        let allowed = compatibility_issues(&wasm, &spec).unwrap();
+        assert_eq!(allowed.len(), 5);
+
+        let wasm = compile_wat(r#"(module (func (result i32) i32.const 1))"#).unwrap();
+        assert_eq!(
+            compatibility_issues(&wasm, &HardwareSpec::default()).unwrap(),
+            []
+        );
+    }
+
+    #[test]
+    fn test_resource_usage() {
+        let spec = HardwareSpec::from_toml("rom_depth = 40\nmemory_size = 16").unwrap();
+        let small = compile_wat_source(
+            "small",
+            "(module (func (export \"main\") (result i32) i32.const 1))",
+        )
+        .unwrap();
+        let big = compile_wat_source(
+            "big",
+            r#"(module (memory 1) (data (i32.const 4) "abcd")
+                (func (export "main") (result i32)
+                    (block (block (br_if 1 (i32.const 0))))
+                    i32.const 1 i32.const 2 i32.add))"#,
+        )
+        .unwrap();
+        let reports = [
+            spec.resource_usage(&small).unwrap(),
+            spec.resource_usage(&big).unwrap(),
+        ];
+        let rows: Vec<_> = reports[1]
+            .resources
+            .iter()
+            .map(|r| (r.name, r.used, r.limit))
+            .collect();
+        assert_eq!(
+            rows,
+            [
+                ("rom bytes", big.body_bytes.len(), Some(40)),
+                ("branch entries", 1, Some(256)),
+                ("stack depth", 2, Some(1024)),
+                ("block depth", 2, None),
+                ("memory bytes", 8, Some(16)),
+            ]
+        );
+        assert_eq!(reports[1].resources[4].percent(), Some(50.0));
+        assert_eq!(reports[1].resources[3].percent(), None);
+
+        let peaks = corpus_usage(&reports);
+        assert_eq!(peaks.len(), 5);
+        assert!(peaks.iter().all(|(_, test)| *test == "big"));
+        let text = resource_usage_text(&reports);
+        assert!(text.starts_with("small\n  rom bytes "), "{}", text);
+        assert!(text.contains("\n  memory bytes         8 / 16        50.0%\n"));
+        assert!(text.contains("\n  block depth          2\n"));
+        assert!(text.contains("corpus peak of 2 tests\n"));
+        assert!(text.ends_with("50.0%  (big)\n"), "{}", text);
+        assert!(!resource_usage_text(&reports[..1]).contains("corpus"));
+    }
+
+    #[test]
+    fn test_support_report() {
+        let body = compile_wat(
+            "(module (func (result i32) i64.const 1 drop \
+                (block (result i64) (i64.const 2)) drop i64.const 3 i32.wrap_i64))",
+        )
+        .unwrap();
+        let body = extract_function_body(&body).unwrap();
+        let report = HardwareSpec::default().support_report(&body).unwrap();
+        let uses: Vec<_> = report
+            .unsupported
+            .iter()
+            .map(|u| (u.mnemonic.as_str(), u.count(), u.offsets.clone()))
+            .collect();
+        assert_eq!(
+            uses,
+            [
+                ("i64.const", 3, vec![0, 5, 9]),
+                ("block with a non-i32 result", 1, vec![3]),
+                ("i32.wrap_i64", 1, vec![11]),
+            ]
+        );
+        let mut spec = HardwareSpec::default();
+        spec.opcodes
+            .extend(["i64.const", "i32.wrap_i64"].map(String::from));
+        assert_eq!(spec.support_report(&body).unwrap().unsupported.len(), 1);
+
+        let many = compile_wat(
+            "(module (func (result i64) i64.const 1 drop i64.const 1 drop \
+                i64.const 1 drop i64.const 1 drop i64.const 1))",
+        )
+        .unwrap();
+        let many = extract_function_body(&many).unwrap();
+        assert_eq!(
+            HardwareSpec::default()
+                .support_report(&many)
+                .unwrap()
+                .to_string(),
+            "i64.const (5x at pc 0x0000, 0x0003, 0x0006, 0x0009, ...)"
+        );
+    }
+
+    #[test]
+    fn test_value_type_uses() {
+        let wasm = compile_wat(
+            r#"(module (global (mut f64) (f64.const 0))
+                (func (param i32) (result i64) (local i64)
+                    i64.const 1 f32.const 2 i32.trunc_f32_s i64.extend_i32_s i64.add
+                    (block (result f32) (f32.const 0)) drop))"#,
+        )
+        .unwrap();
+        let uses = value_type_uses(&wasm).unwrap();
+        let summary: Vec<_> = uses
+            .iter()
+            .map(|u| (u.ty.as_str(), u.pc, u.what.as_str()))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("i64", None, "result"),
+                ("i64", None, "local"),
+                ("f64", None, "global"),
+                ("i64", Some(0), "i64.const 1"),
+                ("f32", Some(2), "f32.const"),
+                ("f32", Some(7), "i32.trunc_f32_s"),
+                ("i64", Some(8), "i64.extend_i32_s"),
+                ("i64", Some(9), "i64.add"),
+                ("f32", Some(10), "block (result f32)"),
+                ("f32", Some(12), "f32.const"),
+            ]
+        );
+        assert_eq!(
+            value_type_summary(&uses[..6]),
+            "the core would need i64 (the result; the local; i64.const at pc 0x0000), \
+             f64 (the global), f32 (f32.const at pc 0x0002; i32.trunc_f32_s at pc 0x0007)"
+        );
+        assert_eq!(value_type_summary(&[]), "");
+
+        let wasm = compile_wat("(module (func (result i32) i32.const 1))").unwrap();
+        assert!(value_type_uses(&wasm).unwrap().is_empty());
+        let error = compile_wat_source(
+            "wide",
+            "(module (func (export \"main\") (result i64) i64.const 1))",
+        )
+        .unwrap_err();
+        assert!(
+            format!("{:#}", error)
+                .contains("the core would need i64 (the result; i64.const at pc 0x0000)"),
+            "{:#}",
+            error
+        );
+    }
+
+    #[test]
+    fn test_memory_access_issues() {
+        let body = compile_wat(
+            "(module (memory 1) (func (result i32)
+                i32.const 0 i32.load offset=4094
+                i32.const 0 i32.load16_u offset=2 align=1 i32.add
+                i32.const 0 i32.load offset=6
+                i32.const 0 i32.load8_u offset=4095 i32.add i32.add))",
+        )
+        .unwrap();
+        let body = extract_function_body(&body).unwrap();
+        let spec = HardwareSpec::default();
+        let issues = spec.memory_access_issues(&body).unwrap();
+        assert_eq!(
+            issues,
+            [(
+                2,
+                "i32.load at offset 4094 always traps: its 4 bytes end past the 4096-byte RAM"
+                    .to_string()
+            )]
+        );
+
+        let aligned = HardwareSpec::from_toml("unaligned = false").unwrap();
+        let issues: Vec<_> = aligned
+            .memory_access_issues(&body)
+            .unwrap()
+            .into_iter()
+            .map(|(pc, issue)| (pc, issue.split(' ').nth(1).unwrap().to_string()))
+            .collect();
+        // The first load is also a word off its alignment.
+        assert_eq!(
+            issues,
+            [
+                (2, "at".to_string()),
+                (2, "at".to_string()),
+                (8, "declares".to_string()),
+                (14, "at".to_string()),
+            ]
+        );
+        assert!(aligned.memory_access_issues(&body).unwrap()[3]
+            .1
+            .contains("offset 6 is unaligned"));
+
+        let wasm =
+            compile_wat("(module (memory 1) (func (result i32) i32.const 0 i32.load offset=8000))")
+                .unwrap();
+        let issues = compatibility_issues(&wasm, &spec).unwrap();
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].pc, Some(2));
+        assert!(issues[0].context.contains(">> i32.load"), "{:?}", issues[0]);
+        assert!(HardwareSpec::from_toml("unaligned = 1").is_err());
+    }
+
+    #[test]
+    fn test_feature_gating() {
+        let src = r#"(module (func (export "main") (result i32)
+            (i32.extend8_s (i32.const 0x80))
+            (i32.extend16_s (i32.const 0x8000)) i32.add))"#;
+        let info = compile_wat_source("ext", src).unwrap();
+        assert_eq!(info.expected, -128 - 32768);
+        let spec = HardwareSpec::default();
+        let report = spec.support_report(&info.body_bytes).unwrap();
+        assert_eq!(
+            report.to_string(),
+            "i32.extend8_s (1x at pc 0x0003; needs sign_extension = true), \
+             i32.extend16_s (1x at pc 0x0008; needs sign_extension = true)"
+        );
+        let signed = HardwareSpec::from_toml("sign_extension = true").unwrap();
+        assert!(signed.support_report(&info.body_bytes).unwrap().is_empty());
+        let wasm =
+            compile_wat("(module (func (param f32) (result i32) local.get 0 i32.trunc_sat_f32_s))")
+                .unwrap();
+        let body = extract_function_body(&wasm).unwrap();
+        let report = spec.support_report(&body).unwrap();
+        assert_eq!(
+            report.unsupported[1].feature,
+            Some(OpFeature::SaturatingTrunc)
+        );
+        let sat = HardwareSpec::from_toml("saturating_trunc = true").unwrap();
+        assert_eq!(sat.support_report(&body).unwrap().unsupported.len(), 1);
+
+        // Expanded, the extensions become shifts the default core has.
+        let options = CompileOptions::new().expand_unsupported(true);
+        let expanded = compile(Source::Wat(src), &options).unwrap();
+        assert_eq!(expanded.expected, info.expected);
+        assert_eq!(
+            expanded.body_bytes,
+            [
+                0x41, 0x80, 0x01, 0x41, 24, 0x74, 0x41, 24, 0x75, // extend8_s
+                0x41, 0x80, 0x80, 0x02, 0x41, 16, 0x74, 0x41, 16, 0x75, // extend16_s
+                0x6A, 0x0F,
+            ]
+        );
+        assert!(spec.skip_reasons(&expanded).unwrap().is_empty());
+        // Nothing to expand on a core with the ops.
+        let options = options.hardware(signed);
+        let kept = compile(Source::Wat(src), &options).unwrap();
+        assert_eq!(kept.body_bytes, info.body_bytes);
+    }
+
+    #[test]
+    fn test_hardware_spec() {
+        let spec = HardwareSpec::from_toml(
+            "rom_depth = 8\nbranch_slots = 1\nstack_depth = 1\nmemory_size = 4\n\
+             extra_opcodes = [\"i32.wrap_i64\"]\n",
+        )
+        .unwrap();
+        assert_eq!((spec.rom_depth, spec.pc_width), (8, 32));
+        assert!(spec.opcodes.contains("i32.wrap_i64") && spec.opcodes.contains("i32.add"));
+        let info = compile_wat_source(
+            "big",
+            r#"(module (memory 1) (data (i32.const 2) "abcd")
+                (func (export "main") (result i32)
+                    (block (br_if 0 (i32.const 0)))
+                    (block (br_if 0 (i32.const 0)))
+                    i32.const 1 i32.const 2 i32.add))"#,
+        )
+        .unwrap();
+        let issues = spec.capacity_issues(&info).unwrap();
+        assert_eq!(issues.len(), 4, "{:?}", issues);
+        assert!(
+            issues[0].contains("20 bytes, 12 over the 8-byte ROM"),
+            "{}",
+            issues[0]
+        );
+        assert!(issues[1].contains("of the 1-slot branch table"));
+        assert!(issues[2].contains("needs 2 stack slots"));
+        assert!(issues[3].contains("past the 4-byte RAM"));
+        assert!(spec.check_fits(&info).is_err());
+        HardwareSpec::default().check_fits(&info).unwrap();
+
+        assert_eq!(
+            spec.skip_reasons(&info).unwrap().len(),
+            issues.len(),
+            "only capacity issues"
+        );
+
+        let spec = HardwareSpec::from_toml("opcodes = [\"i32.const\", \"end\"]\n").unwrap();
+        assert_eq!(spec.opcodes.len(), 2);
+        assert_eq!(
+            spec.skip_reasons(&info).unwrap()[0],
+            "uses block (2x at pc 0x0000, 0x0007), br_if (2x at pc 0x0004, 0x000B), \
+             i32.add (1x at pc 0x0012), return (1x at pc 0x0013), which the core lacks"
+        );
+        let wide = compile_wat_source(
+            "wide",
+            "(module (func (export \"main\") (result i32) (block (result i32) (i32.const 1))))",
+        )
+        .unwrap();
+        let mut spec = HardwareSpec::default();
+        assert_eq!(spec.skip_reasons(&wide).unwrap(), Vec::<String>::new());
+        spec.opcodes.remove("block");
+        assert_eq!(
+            spec.skip_reasons(&wide).unwrap(),
+            ["uses block (1x at pc 0x0000), which the core lacks"]
+        );
+        for text in [
+            "rom_depth = 0",
+            "pc_width = 40",
+            "opcode = []",
+            "opcodes = [1]",
+        ] {
+            assert!(HardwareSpec::from_toml(text).is_err(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn test_opcode_coverage() {
+        let module = |body: &str| {
+            wat::parse_str(format!(
+                r#"(module (func (export "main") (result i32) {}))"#,
+                body
+            ))
+            .unwrap()
+        };
+        let tests = vec![
+            ("add".to_string(), module("i32.const 1 i32.const 2 i32.add")),
+            (
+                "wide".to_string(),
+                module("i64.const 1 i64.const 2 i64.add i32.wrap_i64"),
+            ),
+            (
+                "wider".to_string(),
+                module("i64.const 1 i64.const 2 i64.mul i32.wrap_i64"),
+            ),
+        ];
+        let spec = HardwareSpec::default();
+        let coverage = spec.opcode_coverage(&tests).unwrap();
+        assert_eq!(coverage.tested, ["i32.add", "i32.const", "return"]);
+        assert!(coverage.untested.contains(&"i32.sub".to_string()));
+        assert!(!coverage.untested.contains(&"i32.add".to_string()));
+        assert_eq!(
+            coverage.tested.len() + coverage.untested.len(),
+            spec.opcodes.len()
+        );
+        assert_eq!(
+            coverage.blocking[0],
+            (
+                "i32.wrap_i64".to_string(),
+                vec!["wide".to_string(), "wider".to_string()]
+            )
+        );
+        assert!(coverage
+            .blocking
+            .iter()
+            .any(|(op, tests)| op == "i64.mul" && tests == &["wider"]));
+        let text = coverage.to_string();
+        assert!(text.starts_with(&format!(
+            "3 tests use 3 of the {} instructions the core implements\n",
+            spec.opcodes.len()
+        )));
+        assert!(text.contains("  i32.wrap_i64           2 tests (wide, wider)\n"));
+    }
+
+    #[test]
+    fn test_corpus_markdown() {
+        let add = compile_wat_source(
+            "add",
+            r#"(module (func (export "main") (result i32) i32.const 1 i32.const 2 i32.add))"#,
+        )
+        .unwrap();
+        let trap = compile_wat_source(
+            "trap",
+            r#"(module (func (export "main") (result i32) (block br 0) unreachable))"#,
+        )
+        .unwrap();
+        let big = compile_wat_source(
+            "big|one",
+            r#"(module (func (export "main") (result i32) i32.const 7 i32.const 3 i32.sub))"#,
+        )
+        .unwrap();
+        let skipped = vec![(big, vec!["needs a bigger ROM".to_string()])];
+        let spec = HardwareSpec::default();
+        let md = corpus_markdown(&[add.clone(), trap.clone()], &skipped, &spec).unwrap();
+        assert!(md.starts_with("# WAT test corpus\n\n2 tests generated, 1 skipped.\n"));
+        assert!(md.contains(&format!("| add | {} | 0 | 3 | 3 |\n", add.body_bytes.len())));
+        assert!(md.contains(&format!("| trap | {} | 1 | trap |", trap.body_bytes.len())));
+        assert!(md.contains("| big\\|one | "));
+        assert!(md.contains(" | needs a bigger ROM |\n"));
+        // Skipped tests count towards coverage too.
+        assert!(md.contains("| `i32.sub` | 1 |\n"));
+        assert!(md.contains("| `i32.const` | 2 |\n"));
+        assert!(md.contains("\nUntested: "));
+        assert!(!md.contains("Missing instruction"));
+    }
+
+    #[test]
+    fn test_port_map() {
+        let ports = PortMap::from_toml(
+            r#"
+            clk = "clock"
+            rst = { name = "reset", active_low = false }
+            start = { name = "go_n", active_low = true }
+            halted = { name = "done_n", active_low = true }
+            stack_top = { name = "result", width = 64 }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(ports.clk.name, "clock");
+        assert!(!ports.rst.active_low);
+        assert_eq!(ports.stack_top.width, 64);
+        assert_eq!(ports.stack_top.mask(), u64::MAX);
+        assert_eq!(ports.pc, PortMap::default().pc);
+        assert_eq!((ports.start.bit(true), ports.start.bit(false)), (0, 1));
+        for (toml, message) in [
+            ("stak_top = \"x\"", "unknown port role `stak_top`"),
+            (
+                "start = { name = \"go\", widht = 1 }",
+                "unknown key `widht`",
+            ),
+            ("start = { width = 2 }", "must be 1 bit wide"),
+            ("pc = { active_low = true }", "has no active level"),
+            ("clk = { active_low = true }", "has no active level"),
+            ("prog_data = { width = 4 }", "at least 8 bits"),
+            ("pc = { width = 65 }", "1 to 64"),
+            ("pc = 3", "a name or a table"),
+        ] {
+            let err = PortMap::from_toml(toml).unwrap_err().to_string();
+            assert!(err.contains(message), "{}: {}", toml, err);
+        }
+
+        let info = compile_wat_source(
+            "add",
+            r#"(module (func (export "main") (result i32) i32.const 1 i32.const 2 i32.add))"#,
+        )
+        .unwrap();
+        let mut opts = SvhOptions::default();
+        opts.set_ports(ports.clone());
+        let svh = generate_svh_with(std::slice::from_ref(&info), &opts);
+        assert!(svh.contains("    go_n = 0;\n    @(posedge clock);\n    go_n = 1;\n"));
+        assert!(svh.contains("        if (!done_n || o_trap) begin\n"));
+        assert!(svh.contains("    end else if (o_trap && done_n) begin\n"));
+        assert!(svh.contains("$signed(result) == expected"));
+
+        let sva = generate_sva_with(std::slice::from_ref(&info), "core", &ports).unwrap();
+        assert!(sva.contains(
+            "u_wat_add_sva (.i_clk(clock), .i_rst(!reset), .o_pc(o_pc), \
+             .o_running(o_running), .o_halted(!done_n), .o_trap(o_trap));\n"
+        ));
+        let default = generate_sva(std::slice::from_ref(&info), "core").unwrap();
+        assert!(default.contains("u_wat_add_sva (.*);\n"));
+
+        let tb = generate_veryl_testbench("tb", "Core", "t.svh", &opts);
+        assert!(tb.contains("    logic [63:0] result;\n"));
+        assert!(tb.contains("        .reset           (reset),\n"));
+        assert!(!tb.contains("o_stack_top"));
+        assert!(tb.contains("        .result          (result)\n"));
+        assert!(tb.contains("    // Reset the core (reset is active-high) and clear the ROM.\n"));
+        assert!(tb.contains("        reset = 1;\n        go_n = 1;\n"));
+        assert!(tb.contains("        repeat (4) @(posedge clock);\n        reset = 0;\n"));
+    }
+
+    #[test]
+    fn test_what_if() {
+        let module = |body: &str| {
+            wat::parse_str(format!(
+                r#"(module (func (export "main") (result i32) {}))"#,
+                body
+            ))
+            .unwrap()
+        };
+        let tests = vec![
+            ("sext8".to_string(), module("i32.const 1 i32.extend8_s")),
+            ("sext16".to_string(), module("i32.const 1 i32.extend16_s")),
+            (
+                "wrap".to_string(),
+                module("i64.const 1 i32.wrap_i64 i32.extend8_s"),
+            ),
+        ];
+        let coverage = HardwareSpec::default().opcode_coverage(&tests).unwrap();
+        assert_eq!(
+            coverage.features.get("i32.extend8_s"),
+            Some(&OpFeature::SignExtension)
+        );
+        // Only the feature flag brings both extensions at once.
+        assert_eq!(
+            coverage.unlocked_by(&["sign_extension".to_string()]),
+            ["sext16", "sext8"]
+        );
+        assert_eq!(
+            coverage.unlocked_by(&[
+                "i32.extend8_s".to_string(),
+                "i32.wrap_i64".to_string(),
+                "i64.const".to_string()
+            ]),
+            ["sext8", "wrap"]
+        );
+        let ranked = coverage.what_if();
+        assert_eq!(
+            ranked[0],
+            (
+                "sign_extension".to_string(),
+                vec!["sext16".to_string(), "sext8".to_string()]
+            )
+        );
+        assert_eq!(ranked.last().unwrap().1.len(), 0);
+        let text = what_if_text(&coverage);
+        assert!(text.starts_with("tests each gain would unlock, of the 3 blocked\n"));
+        assert!(text.contains("  sign_extension         2 tests (sext16, sext8)\n"));
+        assert!(text.contains("  i64.const              0 tests\n"));
+    }
+}
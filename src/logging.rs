@@ -0,0 +1,84 @@
+//! Logging through the `log` facade to stderr, with per-stage timings.
+
+// ---------------------------------------------------------------------------
+// Logging
+// ---------------------------------------------------------------------------
+
+/// Run one stage of compiling test `name`, logging how long it took at
+/// debug level.
+pub(crate) fn timed<T>(stage: &str, name: &str, f: impl FnOnce() -> T) -> T {
+    let started = std::time::Instant::now();
+    let result = f();
+    log::debug!(
+        "{}: {} took {:.3} ms",
+        name,
+        stage,
+        started.elapsed().as_secs_f64() * 1000.0
+    );
+    result
+}
+
+/// The log level for a tool's `-v` count and `--quiet` flag: info (the
+/// progress lines) by default, debug (stage timings) with `-v`, trace
+/// with `-vv`, and only errors when quiet.
+pub fn log_level(verbose: u8, quiet: bool) -> log::LevelFilter {
+    match (quiet, verbose) {
+        (true, _) => log::LevelFilter::Error,
+        (false, 0) => log::LevelFilter::Info,
+        (false, 1) => log::LevelFilter::Debug,
+        (false, _) => log::LevelFilter::Trace,
+    }
+}
+
+/// Logs this crate's records and its tools' to stderr. Dependencies'
+/// records (wasmtime logs a lot at debug level) are dropped.
+struct StderrLogger;
+
+impl log::Log for StderrLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        let target = metadata.target();
+        metadata.level() <= log::max_level()
+            && (target.starts_with("wasm_ic") || target.starts_with("wasm_compile"))
+    }
+
+    fn log(&self, record: &log::Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        match record.level() {
+            log::Level::Info => eprintln!("{}", record.args()),
+            log::Level::Error => eprintln!("error: {}", record.args()),
+            log::Level::Warn => eprintln!("warning: {}", record.args()),
+            level => eprintln!(
+                "[{} {}] {}",
+                level.as_str().to_lowercase(),
+                record.target(),
+                record.args()
+            ),
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Send log records at `level` and above to stderr; see [`log_level`].
+/// Only the first call in a process installs the logger.
+pub fn init_logging(level: log::LevelFilter) {
+    if log::set_logger(&StderrLogger).is_ok() {
+        log::set_max_level(level);
+    }
+}
+
+#[cfg(all(test, feature = "refexec"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_log_level() {
+        assert_eq!(log_level(0, false), log::LevelFilter::Info);
+        assert_eq!(log_level(1, false), log::LevelFilter::Debug);
+        assert_eq!(log_level(3, false), log::LevelFilter::Trace);
+        assert_eq!(log_level(2, true), log::LevelFilter::Error);
+        assert_eq!(timed("stage", "t", || 7), 7);
+    }
+}
@@ -0,0 +1,690 @@
+//! Fixed-width lowering for the pipelined core, which can't decode
+//! variable-length instructions. Every instruction becomes one 32-bit
+//! word with its opcode in the top byte; its immediates, LEB128-decoded,
+//! move to a separate constant ROM that the low 24 bits index. PCs count
+//! instructions, so the branch table is moved to match.
+//!
+//! The byte-oriented core keeps the wasm encoding but can take a
+//! pre-decode sideband ([`predecode_table`]) sparing it the LEB128 decode.
+//! Either can have instructions it lacks expanded into ones it has first
+//! ([`expand_unsupported`]), and padded LEB128s shortened
+//! ([`normalize_leb`]).
+
+use crate::emit::{push_sleb, push_uleb, OpcodeMap};
+use crate::parse::{opcode_len, operator_memarg, operator_name};
+use crate::{BranchEntry, CompiledProgram, Error, HardwareSpec};
+use anyhow::anyhow;
+use wasmparser::{BlockType, Operator, ValType};
+
+/// Bits of an instruction word indexing the constant ROM.
+pub const OPERAND_BITS: u32 = 24;
+
+/// A program as [`lower_program`] leaves it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lowered {
+    /// One word per instruction: the opcode, then the index of its first
+    /// constant, or 0 for an instruction without immediates.
+    pub insns: Vec<u32>,
+    /// Every instruction's immediates in program order, one per word.
+    pub consts: Vec<u32>,
+    /// The branch table with instruction indices for PCs.
+    pub branch_table: Vec<BranchEntry>,
+}
+
+impl Lowered {
+    /// The instruction ROM as little-endian bytes.
+    pub fn insn_bytes(&self) -> Vec<u8> {
+        self.insns.iter().flat_map(|w| w.to_le_bytes()).collect()
+    }
+
+    /// The constant ROM as little-endian bytes.
+    pub fn const_bytes(&self) -> Vec<u8> {
+        self.consts.iter().flat_map(|w| w.to_le_bytes()).collect()
+    }
+}
+
+/// The immediates of `op` as constant ROM words, or `None` for
+/// immediates the lowering doesn't know. `bare` is whether the
+/// instruction is only its opcode.
+fn immediates(op: &Operator, bare: bool) -> Option<Vec<u32>> {
+    Some(match op {
+        Operator::Block { blockty } | Operator::Loop { blockty } | Operator::If { blockty } => {
+            match blockty {
+                BlockType::Empty => vec![0x40],
+                BlockType::Type(ValType::I32) => vec![0x7F],
+                _ => return None,
+            }
+        }
+        Operator::Br { relative_depth } | Operator::BrIf { relative_depth } => {
+            vec![*relative_depth]
+        }
+        Operator::BrTable { targets } => {
+            let mut words = vec![targets.len()];
+            for target in targets.targets() {
+                words.push(target.ok()?);
+            }
+            words.push(targets.default());
+            words
+        }
+        Operator::LocalGet { local_index }
+        | Operator::LocalSet { local_index }
+        | Operator::LocalTee { local_index } => vec![*local_index],
+        Operator::GlobalGet { global_index } | Operator::GlobalSet { global_index } => {
+            vec![*global_index]
+        }
+        Operator::Call { function_index } => vec![*function_index],
+        Operator::CallIndirect {
+            type_index,
+            table_index,
+        } => vec![*type_index, *table_index],
+        Operator::I32Const { value } => vec![*value as u32],
+        Operator::I32Load { memarg }
+        | Operator::I32Load8S { memarg }
+        | Operator::I32Load8U { memarg }
+        | Operator::I32Load16S { memarg }
+        | Operator::I32Load16U { memarg }
+        | Operator::I32Store { memarg }
+        | Operator::I32Store8 { memarg }
+        | Operator::I32Store16 { memarg } => vec![u32::try_from(memarg.offset).ok()?],
+        Operator::MemorySize { mem }
+        | Operator::MemoryGrow { mem }
+        | Operator::MemoryFill { mem } => vec![*mem],
+        Operator::MemoryCopy { dst_mem, src_mem } => vec![*dst_mem, *src_mem],
+        _ if bare => Vec::new(),
+        _ => return None,
+    })
+}
+
+/// Lower a function body and its branch table. Opcodes are the wasm
+/// ones, or `map`'s codes if given; a prefixed opcode needs a map. An
+/// instruction the map lacks, or whose immediates the lowering doesn't
+/// know, is [`Error::UnsupportedOp`].
+pub fn lower_body(
+    body: &[u8],
+    branch_table: &[BranchEntry],
+    map: Option<&OpcodeMap>,
+) -> Result<Lowered, Error> {
+    let mut lowered = Lowered {
+        insns: Vec::new(),
+        consts: Vec::new(),
+        branch_table: Vec::new(),
+    };
+    // Byte PC to instruction index, the end of the body included.
+    let mut pcs = std::collections::HashMap::new();
+    let mut reader = wasmparser::OperatorsReader::new(wasmparser::BinaryReader::new(body, 0));
+    while !reader.eof() {
+        let offset = reader.original_position();
+        let op = reader.read()?;
+        let name = operator_name(&op);
+        let unsupported = || Error::UnsupportedOp {
+            offset,
+            op: name.clone(),
+        };
+        let opcode = match map {
+            Some(map) => map.code(&name).ok_or_else(unsupported)?,
+            None if body[offset] < 0xFB => body[offset],
+            None => return Err(unsupported()),
+        };
+        let bytes = &body[offset..reader.original_position()];
+        let consts = immediates(&op, bytes.len() == opcode_len(bytes)).ok_or_else(unsupported)?;
+        let operand = if consts.is_empty() {
+            0
+        } else {
+            lowered.consts.len() as u32
+        };
+        if operand >> OPERAND_BITS != 0 {
+            return Err(Error::CapacityExceeded(anyhow!(
+                "more than {} constants for a {}-bit operand",
+                1u32 << OPERAND_BITS,
+                OPERAND_BITS
+            )));
+        }
+        pcs.insert(offset as u32, lowered.insns.len() as u32);
+        lowered
+            .insns
+            .push((opcode as u32) << OPERAND_BITS | operand);
+        lowered.consts.extend(consts);
+    }
+    pcs.insert(body.len() as u32, lowered.insns.len() as u32);
+    let moved = |pc: u32| {
+        pcs.get(&pc).copied().ok_or_else(|| {
+            Error::ParseError(anyhow!(
+                "branch PC {:#x} is not an instruction boundary",
+                pc
+            ))
+        })
+    };
+    for e in branch_table {
+        lowered.branch_table.push(BranchEntry {
+            source_pc: moved(e.source_pc)?,
+            target_pc: moved(e.target_pc)?,
+        });
+    }
+    Ok(lowered)
+}
+
+/// [`lower_body`] for a compiled test.
+pub fn lower_program(info: &CompiledProgram, map: Option<&OpcodeMap>) -> Result<Lowered, Error> {
+    lower_body(&info.body_bytes, &info.branch_table, map)
+}
+
+/// One row of the pre-decode sideband: an instruction carrying an
+/// immediate, at `pc`, `len` bytes long, whose immediate is `value`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PredecodeEntry {
+    pub pc: u32,
+    pub len: u32,
+    pub value: u32,
+}
+
+/// The pre-decode sideband for the byte-oriented core: every instruction
+/// of `body` with an immediate, in PC order, so the decoder can take the
+/// value and skip `len` bytes instead of decoding LEB128 over several
+/// cycles. Block types count, as their byte; an instruction with more
+/// than one immediate the core would need, like `br_table`, is
+/// [`Error::UnsupportedOp`].
+pub fn predecode_table(body: &[u8]) -> Result<Vec<PredecodeEntry>, Error> {
+    let mut table = Vec::new();
+    let mut reader = wasmparser::OperatorsReader::new(wasmparser::BinaryReader::new(body, 0));
+    while !reader.eof() {
+        let offset = reader.original_position();
+        let op = reader.read()?;
+        let bytes = &body[offset..reader.original_position()];
+        let value = match immediates(&op, bytes.len() == opcode_len(bytes)).as_deref() {
+            Some([]) => continue,
+            Some(&[value]) => value,
+            _ => {
+                return Err(Error::UnsupportedOp {
+                    offset,
+                    op: operator_name(&op),
+                })
+            }
+        };
+        table.push(PredecodeEntry {
+            pc: offset as u32,
+            len: bytes.len() as u32,
+            value,
+        });
+    }
+    Ok(table)
+}
+
+/// `body` with every LEB128 in its shortest form, and the PCs of the
+/// instructions that weren't. Some producers pad immediates (and the
+/// sub-opcodes of prefixed instructions) to a fixed width, wasting ROM and
+/// breaking a decoder that assumes canonical lengths. PCs move, so the
+/// branch table must be computed from the result.
+pub fn normalize_leb(body: &[u8]) -> Result<(Vec<u8>, Vec<usize>), Error> {
+    let mut out = Vec::with_capacity(body.len());
+    let mut overlong = Vec::new();
+    let mut reader = wasmparser::OperatorsReader::new(wasmparser::BinaryReader::new(body, 0));
+    while !reader.eof() {
+        let offset = reader.original_position();
+        let op = reader.read()?;
+        let bytes = &body[offset..reader.original_position()];
+        match canonical_encoding(&op, bytes) {
+            Some(code) if code.len() < bytes.len() => {
+                overlong.push(offset);
+                out.extend(code);
+            }
+            _ => out.extend_from_slice(bytes),
+        }
+    }
+    Ok((out, overlong))
+}
+
+/// `op`, encoded in `bytes`, with every LEB128 as short as it goes, or
+/// `None` for immediates this doesn't re-encode.
+fn canonical_encoding(op: &Operator, bytes: &[u8]) -> Option<Vec<u8>> {
+    let opcode = opcode_len(bytes);
+    let mut out = vec![bytes[0]];
+    if opcode > 1 {
+        // Prefixed: the sub-opcode is itself a LEB128.
+        let mut reader = wasmparser::BinaryReader::new(&bytes[1..opcode], 0);
+        push_uleb(&mut out, reader.read_var_u32().ok()? as usize);
+    }
+    if bytes.len() == opcode {
+        return Some(out);
+    }
+    match op {
+        Operator::Block { blockty } | Operator::Loop { blockty } | Operator::If { blockty } => {
+            match blockty {
+                BlockType::FuncType(index) => push_sleb(&mut out, *index as i64),
+                _ => out.push(bytes[opcode]),
+            }
+        }
+        Operator::Br { relative_depth } | Operator::BrIf { relative_depth } => {
+            push_uleb(&mut out, *relative_depth as usize)
+        }
+        Operator::BrTable { targets } => {
+            push_uleb(&mut out, targets.len() as usize);
+            for target in targets.targets() {
+                push_uleb(&mut out, target.ok()? as usize);
+            }
+            push_uleb(&mut out, targets.default() as usize);
+        }
+        Operator::LocalGet { local_index }
+        | Operator::LocalSet { local_index }
+        | Operator::LocalTee { local_index } => push_uleb(&mut out, *local_index as usize),
+        Operator::GlobalGet { global_index } | Operator::GlobalSet { global_index } => {
+            push_uleb(&mut out, *global_index as usize)
+        }
+        Operator::Call { function_index } => push_uleb(&mut out, *function_index as usize),
+        Operator::CallIndirect {
+            type_index,
+            table_index,
+        } => {
+            push_uleb(&mut out, *type_index as usize);
+            push_uleb(&mut out, *table_index as usize);
+        }
+        Operator::I32Const { value } => push_sleb(&mut out, *value as i64),
+        Operator::I64Const { value } => push_sleb(&mut out, *value),
+        Operator::F32Const { .. } | Operator::F64Const { .. } => {
+            out.extend_from_slice(&bytes[opcode..])
+        }
+        Operator::MemorySize { mem } | Operator::MemoryGrow { mem } => {
+            push_uleb(&mut out, *mem as usize)
+        }
+        Operator::MemoryFill { mem } => push_uleb(&mut out, *mem as usize),
+        Operator::MemoryCopy { dst_mem, src_mem } => {
+            push_uleb(&mut out, *dst_mem as usize);
+            push_uleb(&mut out, *src_mem as usize);
+        }
+        _ => {
+            let memarg = operator_memarg(op)?;
+            // Bit 6 of the alignment flags a memory index after it.
+            let align = memarg.align as usize;
+            if memarg.memory == 0 {
+                push_uleb(&mut out, align);
+            } else {
+                push_uleb(&mut out, align | 0x40);
+                push_uleb(&mut out, memarg.memory as usize);
+            }
+            push_uleb(&mut out, usize::try_from(memarg.offset).ok()?);
+        }
+    }
+    Some(out)
+}
+
+/// Longest `memory.copy` or `memory.fill` [`expand_unsupported`]
+/// unrolls, in bytes.
+pub const MAX_UNROLLED_BYTES: u32 = 256;
+
+/// `body` with each instruction `spec` lacks but has the parts for
+/// replaced by those: `i32.extend8_s` and `i32.extend16_s` become a shift
+/// left and an arithmetic shift back, and a `memory.copy` or
+/// `memory.fill` whose operands are the three `i32.const` before it
+/// becomes a load and store per word or byte, up to
+/// [`MAX_UNROLLED_BYTES`]. Unrolled rather than a loop, as the core has
+/// no locals to count one in. Anything else is left for validation to
+/// report. PCs move, so the branch table must be computed from the
+/// result.
+pub fn expand_unsupported(body: &[u8], spec: &HardwareSpec) -> Result<Vec<u8>, Error> {
+    let has = |ops: &[&str]| ops.iter().all(|op| spec.opcodes.contains(*op));
+    let shifts = has(&["i32.const", "i32.shl", "i32.shr_s"]);
+    let moves = has(&[
+        "i32.const",
+        "i32.load",
+        "i32.load8_u",
+        "i32.store",
+        "i32.store8",
+    ]);
+    let mut out = Vec::with_capacity(body.len());
+    // The run of `i32.const` just written: where each starts in `out`,
+    // and its value.
+    let mut consts: Vec<(usize, i32)> = Vec::new();
+    let mut reader = wasmparser::OperatorsReader::new(wasmparser::BinaryReader::new(body, 0));
+    while !reader.eof() {
+        let offset = reader.original_position();
+        let op = reader.read()?;
+        let bytes = &body[offset..reader.original_position()];
+        if spec.supports(&op) {
+            match op {
+                Operator::I32Const { value } => consts.push((out.len(), value)),
+                _ => consts.clear(),
+            }
+            out.extend_from_slice(bytes);
+            continue;
+        }
+        let operands = match consts.as_slice() {
+            [.., (at, a), (_, b), (_, n)] => Some((*at, *a as u32, *b as u32, *n as u32)),
+            _ => None,
+        };
+        let expanded = match (op, operands) {
+            (Operator::I32Extend8S, _) if shifts => Some((out.len(), shift_pair(24))),
+            (Operator::I32Extend16S, _) if shifts => Some((out.len(), shift_pair(16))),
+            (Operator::MemoryFill { mem: 0 }, Some((at, dst, value, len))) if moves => {
+                unrolled_fill(dst, value as u8, len, spec).map(|code| (at, code))
+            }
+            (
+                Operator::MemoryCopy {
+                    dst_mem: 0,
+                    src_mem: 0,
+                },
+                Some((at, dst, src, len)),
+            ) if moves => unrolled_copy(dst, src, len, spec).map(|code| (at, code)),
+            _ => None,
+        };
+        match expanded {
+            Some((at, code)) => {
+                // The operands go with the instruction they fed.
+                out.truncate(at);
+                out.extend(code);
+            }
+            None => out.extend_from_slice(bytes),
+        }
+        consts.clear();
+    }
+    Ok(out)
+}
+
+/// `i32.const bits, i32.shl, i32.const bits, i32.shr_s`: sign-extend the
+/// low `32 - bits` bits.
+fn shift_pair(bits: u8) -> Vec<u8> {
+    vec![0x41, bits, 0x74, 0x41, bits, 0x75]
+}
+
+/// The pieces of a `len`-byte access at `addrs`, as (offset, width):
+/// words where `spec` can do them, bytes otherwise.
+fn chunks(addrs: &[u32], len: u32, spec: &HardwareSpec) -> Vec<(u32, u32)> {
+    let mut chunks = Vec::new();
+    let mut k = 0;
+    while k < len {
+        let aligned = addrs.iter().all(|addr| (addr + k).is_multiple_of(4));
+        let width = if len - k >= 4 && (spec.unaligned || aligned) {
+            4
+        } else {
+            1
+        };
+        chunks.push((k, width));
+        k += width;
+    }
+    chunks
+}
+
+/// Whether a `len`-byte access at `addr` stays in the core's RAM, so the
+/// unrolled code can't trap part way where the original would up front.
+fn in_ram(addr: u32, len: u32, spec: &HardwareSpec) -> bool {
+    len <= MAX_UNROLLED_BYTES && addr as u64 + len as u64 <= spec.memory_size as u64
+}
+
+fn push_const(out: &mut Vec<u8>, value: u32) {
+    out.push(0x41);
+    push_sleb(out, value as i32 as i64);
+}
+
+/// The memarg-carrying opcode, with its natural alignment and no offset.
+fn push_access(out: &mut Vec<u8>, word: u8, byte: u8, width: u32) {
+    match width {
+        4 => out.extend_from_slice(&[word, 0x02, 0x00]),
+        _ => out.extend_from_slice(&[byte, 0x00, 0x00]),
+    }
+}
+
+fn unrolled_fill(dst: u32, value: u8, len: u32, spec: &HardwareSpec) -> Option<Vec<u8>> {
+    if !in_ram(dst, len, spec) {
+        return None;
+    }
+    let mut out = Vec::new();
+    for (k, width) in chunks(&[dst], len, spec) {
+        push_const(&mut out, dst + k);
+        push_const(
+            &mut out,
+            match width {
+                4 => value as u32 * 0x0101_0101,
+                _ => value as u32,
+            },
+        );
+        // i32.store or i32.store8
+        push_access(&mut out, 0x36, 0x3A, width);
+    }
+    Some(out)
+}
+
+fn unrolled_copy(dst: u32, src: u32, len: u32, spec: &HardwareSpec) -> Option<Vec<u8>> {
+    if !in_ram(dst, len, spec) || !in_ram(src, len, spec) {
+        return None;
+    }
+    let mut pieces = chunks(&[dst, src], len, spec);
+    // Copy from the far end when moving up, so overlapping source bytes
+    // are read before they are overwritten.
+    if dst > src {
+        pieces.reverse();
+    }
+    let mut out = Vec::new();
+    for (k, width) in pieces {
+        push_const(&mut out, dst + k);
+        push_const(&mut out, src + k);
+        // i32.load or i32.load8_u, then i32.store or i32.store8
+        push_access(&mut out, 0x28, 0x2D, width);
+        push_access(&mut out, 0x36, 0x3A, width);
+    }
+    Some(out)
+}
+
+#[cfg(all(test, feature = "refexec"))]
+mod tests {
+    use super::*;
+    use crate::emit::{render_artifact, ArtifactKind};
+    use crate::{
+        compile, compile_wat, compile_wat_source, emit_artifacts, extract_function_body, lower,
+        run_with_wasmtime_memory, ArtifactFormat, CompileOptions, EmitOptions, OpFeature, Source,
+    };
+    use std::fs;
+
+    /// A module with one page of memory and `main: () -> i32` exported,
+    /// whose body is `body` and a final `end`, assembled by hand so the
+    /// bytes are exactly these.
+    fn module_with_body(body: &[u8]) -> Vec<u8> {
+        let mut code = vec![0x00];
+        code.extend_from_slice(body);
+        code.push(0x0B);
+        let mut entry = vec![0x01];
+        push_uleb(&mut entry, code.len());
+        entry.extend(code);
+        let mut wasm = b"\0asm\x01\0\0\0".to_vec();
+        wasm.extend_from_slice(&[1, 5, 1, 0x60, 0, 1, 0x7F, 3, 2, 1, 0, 5, 3, 1, 0, 1]);
+        wasm.extend_from_slice(&[7, 8, 1, 4, b'm', b'a', b'i', b'n', 0, 0, 10]);
+        push_uleb(&mut wasm, entry.len());
+        wasm.extend(entry);
+        wasm
+    }
+
+    #[test]
+    fn test_normalize_leb() {
+        #[rustfmt::skip]
+        let body = [
+            0x02, 0x40,
+            0x41, 0x81, 0x80, 0x80, 0x80, 0x00, // i32.const 1
+            0x0D, 0x80, 0x00,                   // br_if 0
+            0x41, 0x82, 0x80, 0x00,             // i32.const 2
+            0x1A, 0x0B,
+            0x41, 0x00,
+            0x28, 0x82, 0x00, 0x80, 0x00,       // i32.load
+            0x1A,
+            0x41, 0xAA, 0x80, 0x80, 0x80, 0x00, // i32.const 42
+        ];
+        let (_, overlong) = lower::normalize_leb(&body).unwrap();
+        assert_eq!(overlong, [2, 8, 11, 19, 25]);
+
+        let wasm = module_with_body(&body);
+        let padded = compile(Source::Wasm(&wasm), &CompileOptions::new()).unwrap();
+        assert_eq!(padded.body_bytes.len(), body.len() + 1);
+        let normalized = compile(
+            Source::Wasm(&wasm),
+            &CompileOptions::new().normalize_leb(true),
+        )
+        .unwrap();
+        let canonical = compile_wat_source(
+            "main",
+            r#"(module (memory 1) (func (export "main") (result i32)
+                (block i32.const 1 br_if 0 i32.const 2 drop)
+                i32.const 0 i32.load drop i32.const 42))"#,
+        )
+        .unwrap();
+        assert_eq!(normalized.body_bytes, canonical.body_bytes);
+        assert_eq!(normalized.branch_table, canonical.branch_table);
+        assert_eq!(normalized.expected, 42);
+        // Already canonical bodies come back unchanged.
+        let (same, none) = lower::normalize_leb(&canonical.body_bytes).unwrap();
+        assert_eq!((same, none.len()), (canonical.body_bytes, 0));
+    }
+
+    #[test]
+    fn test_expand_bulk_memory() {
+        let src = r#"(module (memory 1) (func (export "main") (result i32)
+            (i32.store (i32.const 0) (i32.const 0x04030201))
+            (i32.store (i32.const 4) (i32.const 0x08070605))
+            (memory.fill (i32.const 16) (i32.const 0x1AB) (i32.const 7))
+            (memory.copy (i32.const 2) (i32.const 0) (i32.const 7))
+            (memory.copy (i32.const 21) (i32.const 1) (i32.const 6))
+            (memory.copy (i32.const 0) (i32.const 3) (i32.const 5))
+            (i32.load (i32.const 0))))"#;
+        let wasm = compile_wat(src).unwrap();
+        let spec = HardwareSpec::default();
+        let info = compile_wat_source("bulk", src).unwrap();
+        let report = spec.support_report(&info.body_bytes).unwrap();
+        assert_eq!(report.unsupported.len(), 2);
+        assert_eq!(report.unsupported[1].feature, Some(OpFeature::BulkMemory));
+        assert!(HardwareSpec::from_toml("bulk_memory = true")
+            .unwrap()
+            .support_report(&info.body_bytes)
+            .unwrap()
+            .is_empty());
+
+        // The expanded body, run as a module of its own, leaves the same
+        // result and memory as the original.
+        let golden = run_with_wasmtime_memory(&wasm).unwrap();
+        for toml in ["", "unaligned = false"] {
+            let spec = HardwareSpec::from_toml(toml).unwrap();
+            let options = CompileOptions::new()
+                .hardware(spec.clone())
+                .expand_unsupported(true);
+            let expanded = compile(Source::Wat(src), &options).unwrap();
+            assert!(spec.skip_reasons(&expanded).unwrap().is_empty(), "{}", toml);
+            assert!(spec
+                .memory_access_issues(&expanded.body_bytes)
+                .unwrap()
+                .is_empty());
+            assert_eq!(
+                run_with_wasmtime_memory(&module_with_body(&expanded.body_bytes)).unwrap(),
+                golden,
+                "{}",
+                toml
+            );
+        }
+
+        // Operands that aren't constants, or run past the RAM, stay put.
+        for src in [
+            "(module (memory 1) (func (result i32)
+                (memory.fill (i32.load (i32.const 0)) (i32.const 1) (i32.const 4)) i32.const 0))",
+            "(module (memory 1) (func (result i32)
+                (memory.fill (i32.const 4094) (i32.const 1) (i32.const 4)) i32.const 0))",
+            "(module (memory 1) (func (result i32)
+                (memory.copy (i32.const 0) (i32.const 8) (i32.const 300)) i32.const 0))",
+        ] {
+            let body = extract_function_body(&compile_wat(src).unwrap()).unwrap();
+            let kept = lower::expand_unsupported(&body, &spec).unwrap();
+            assert_eq!(kept, body, "{}", src);
+        }
+    }
+
+    #[test]
+    fn test_fixed_lowering() {
+        let info = compile_wat_source(
+            "fixed",
+            r#"(module (memory (export "memory") 1) (data (i32.const 4) "\07")
+                (func (export "main") (result i32)
+                  (block (br 0)) i32.const 0 i32.load offset=4))"#,
+        )
+        .unwrap();
+        let lowered = lower::lower_program(&info, None).unwrap();
+        assert_eq!(
+            lowered.insns,
+            [
+                0x0200_0000,
+                0x0C00_0001,
+                0x0B00_0000,
+                0x4100_0002,
+                0x2800_0003,
+                0x0F00_0000
+            ]
+        );
+        assert_eq!(lowered.consts, [0x40, 0, 0, 4]);
+        assert_eq!(
+            lowered.branch_table,
+            [BranchEntry {
+                source_pc: 1,
+                target_pc: 3
+            }]
+        );
+
+        let opts = EmitOptions {
+            format: ArtifactFormat::Fixed,
+            annotate: true,
+            ..EmitOptions::default()
+        };
+        let render =
+            |kind| String::from_utf8(render_artifact(&info, kind, &opts).unwrap()).unwrap();
+        assert!(render(ArtifactKind::Prog).contains("\n0C000001  // pc=4  br 0\n"));
+        assert!(render(ArtifactKind::Const).ends_with("\n00000040\n00000000\n00000000\n00000004\n"));
+        assert!(render(ArtifactKind::Branch).ends_with("\n00000001 00000003\n"));
+        assert!(render(ArtifactKind::Mem).ends_with("\n07\n"));
+
+        // Immediates it doesn't know, and prefixed opcodes with no map.
+        let body = compile_wat("(module (func i64.const 1 drop))").unwrap();
+        let body = extract_function_body(&body).unwrap();
+        assert!(matches!(
+            lower::lower_body(&body, &[], None),
+            Err(Error::UnsupportedOp { offset: 0, .. })
+        ));
+        let fill = [0x41, 0, 0x41, 0, 0x41, 0, 0xFC, 0x0B, 0, 0x0B];
+        assert!(matches!(
+            lower::lower_body(&fill, &[], None),
+            Err(Error::UnsupportedOp { offset: 6, .. })
+        ));
+        let map = OpcodeMap::from_toml("\"i32.const\" = 1\n\"memory.fill\" = 2\nend = 3").unwrap();
+        let lowered = lower::lower_body(&fill, &[], Some(&map)).unwrap();
+        assert_eq!(lowered.insns[3], 0x0200_0003);
+        assert_eq!(lowered.consts, [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_predecode_table() {
+        let info = compile_wat_source(
+            "predecode",
+            r#"(module (memory 1)
+                (func (export "main") (result i32)
+                  (block (br 0)) i32.const 300 i32.load offset=200))"#,
+        )
+        .unwrap();
+        let rows: Vec<_> = lower::predecode_table(&info.body_bytes)
+            .unwrap()
+            .iter()
+            .map(|e| (e.pc, e.len, e.value))
+            .collect();
+        assert_eq!(rows, [(0, 2, 0x40), (2, 2, 0), (5, 3, 300), (8, 4, 200)]);
+
+        let opts = EmitOptions {
+            predecode: true,
+            ..EmitOptions::default()
+        };
+        let text = render_artifact(&info, ArtifactKind::Predecode, &opts).unwrap();
+        assert!(String::from_utf8(text)
+            .unwrap()
+            .ends_with("\n00000005 03 0000012C\n00000008 04 000000C8\n"));
+        let dir = std::env::temp_dir().join(format!("wasm-ic-predecode-{}", std::process::id()));
+        let paths = emit_artifacts(&info, &dir, &opts).unwrap();
+        assert!(paths.iter().any(|p| p.ends_with("predecode.predecode.hex")));
+        let paths = emit_artifacts(&info, &dir.join("off"), &EmitOptions::default()).unwrap();
+        assert!(!paths.iter().any(|p| p.ends_with("predecode.predecode.hex")));
+        fs::remove_dir_all(&dir).unwrap();
+
+        let body = compile_wat("(module (func (block (br_table 0 0 (i32.const 0)))))").unwrap();
+        let body = extract_function_body(&body).unwrap();
+        assert!(matches!(
+            lower::predecode_table(&body),
+            Err(Error::UnsupportedOp { op, .. }) if op == "br_table"
+        ));
+    }
+}
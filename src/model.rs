@@ -0,0 +1,334 @@
+//! A cycle-approximate model of the core: runs a compiled program one
+//! instruction at a time, taking branches from its branch table like the
+//! fetch unit does, and counts the cycles each fetch unit state would
+//! spend (see `docs/fetch.md`) on a combinational ROM. Close enough to
+//! predict a test's cycle count without a simulator, so drift between
+//! the model and the core shows up as a difference, not a failure.
+
+use crate::parse::{format_operator, memory_image, MEMORY_SIZE};
+use crate::results::csv_field;
+use crate::{CompiledProgram, Error, SimResult};
+use std::collections::HashMap;
+use wasmparser::{BinaryReader, Operator, OperatorsReader};
+
+/// How a [`run_model`] run ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModelOutcome {
+    /// Reached `return` with this on top of the stack.
+    Halted(i32),
+    Trapped,
+    /// Still running after the cycle limit.
+    TimedOut,
+}
+
+/// A [`run_model`] run: how it ended and how long it took.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ModelRun {
+    pub outcome: ModelOutcome,
+    /// Cycles from the first opcode fetch to halt or trap.
+    pub cycles: u64,
+    /// Instructions executed, the final one included.
+    pub instructions: u64,
+}
+
+/// Run `info` on the model for at most `max_cycles`. An instruction the
+/// core doesn't implement is an [`Error::UnsupportedOp`]; traps and
+/// timeouts are outcomes.
+pub fn run_model(info: &CompiledProgram, max_cycles: u64) -> Result<ModelRun, Error> {
+    let body = &info.body_bytes;
+    let targets: HashMap<u32, u32> = info
+        .branch_table
+        .iter()
+        .map(|e| (e.source_pc, e.target_pc))
+        .collect();
+    // Decoded up front: a reader started mid-body rejects an `else` it
+    // hasn't seen the `if` of.
+    let mut decoded = HashMap::new();
+    let mut reader = OperatorsReader::new(BinaryReader::new(body, 0));
+    while !reader.eof() {
+        let (op, offset) = reader.read_with_offset()?;
+        decoded.insert(offset, (op, reader.original_position()));
+    }
+    let mut mem = memory_image(&info.mem_init)?;
+    mem.resize(MEMORY_SIZE, 0);
+    let mut stack: Vec<i32> = Vec::new();
+    let mut pc = 0usize;
+    let mut cycles = 0u64;
+    let mut instructions = 0u64;
+
+    let finish = |outcome, cycles, instructions| ModelRun {
+        outcome,
+        cycles,
+        instructions,
+    };
+    loop {
+        if cycles >= max_cycles {
+            return Ok(finish(ModelOutcome::TimedOut, cycles, instructions));
+        }
+        // Running off the end of the ROM fetches garbage; the core traps.
+        let Some((op, next)) = decoded.get(&pc).cloned() else {
+            return Ok(finish(ModelOutcome::Trapped, cycles, instructions));
+        };
+        let immediate_bytes = (next - pc - 1) as u64;
+        instructions += 1;
+        // FETCH_OPCODE and EXEC, then a state per immediate byte.
+        cycles += 2;
+
+        let branch = |at: usize| targets.get(&(at as u32)).map(|&t| t as usize);
+        let mut jump = None;
+        let mut trapped = false;
+        macro_rules! pop {
+            () => {
+                match stack.pop() {
+                    Some(v) => v,
+                    None => {
+                        trapped = true;
+                        0
+                    }
+                }
+            };
+        }
+        macro_rules! binary {
+            ($f:expr) => {{
+                let b = pop!();
+                let a = pop!();
+                let f: fn(i32, i32) -> Option<i32> = $f;
+                match f(a, b) {
+                    Some(v) => stack.push(v),
+                    None => trapped = true,
+                }
+            }};
+        }
+        match op {
+            Operator::Unreachable => trapped = true,
+            Operator::Nop | Operator::End => {}
+            Operator::Block { .. } | Operator::Loop { .. } => cycles += 1,
+            Operator::If { .. } => {
+                // SKIP_BLOCK; a false condition jumps from there.
+                cycles += 1;
+                if pop!() == 0 {
+                    jump = Some(branch(pc));
+                }
+            }
+            Operator::Else => jump = Some(branch(pc)),
+            Operator::Br { .. } => {
+                cycles += immediate_bytes;
+                jump = Some(branch(pc));
+            }
+            Operator::BrIf { .. } => {
+                cycles += immediate_bytes;
+                if pop!() != 0 {
+                    jump = Some(branch(pc));
+                }
+            }
+            Operator::Return => {
+                let top = stack.last().copied().unwrap_or(0);
+                return Ok(finish(ModelOutcome::Halted(top), cycles, instructions));
+            }
+            Operator::Drop => {
+                pop!();
+            }
+            Operator::I32Const { value } => {
+                // READ_IMM per byte, then EXEC_IMM.
+                cycles += immediate_bytes + 1;
+                stack.push(value);
+            }
+            Operator::I32Load { memarg }
+            | Operator::I32Load8S { memarg }
+            | Operator::I32Load8U { memarg }
+            | Operator::I32Load16S { memarg }
+            | Operator::I32Load16U { memarg } => {
+                // READ_ALIGN and READ_IMM per byte, then EXEC_MEM.
+                cycles += immediate_bytes + 1;
+                let width = match op {
+                    Operator::I32Load { .. } => 4,
+                    Operator::I32Load16S { .. } | Operator::I32Load16U { .. } => 2,
+                    _ => 1,
+                };
+                let addr = (pop!() as u32).wrapping_add(memarg.offset as u32) as usize;
+                match mem.get(addr..addr + width) {
+                    Some(bytes) if !trapped => {
+                        let mut word = [0u8; 4];
+                        word[..width].copy_from_slice(bytes);
+                        let value = u32::from_le_bytes(word);
+                        stack.push(match op {
+                            Operator::I32Load8S { .. } => value as i8 as i32,
+                            Operator::I32Load16S { .. } => value as i16 as i32,
+                            _ => value as i32,
+                        });
+                    }
+                    _ => trapped = true,
+                }
+            }
+            Operator::I32Store { memarg }
+            | Operator::I32Store8 { memarg }
+            | Operator::I32Store16 { memarg } => {
+                cycles += immediate_bytes + 1;
+                let width = match op {
+                    Operator::I32Store { .. } => 4,
+                    Operator::I32Store16 { .. } => 2,
+                    _ => 1,
+                };
+                let value = pop!();
+                let addr = (pop!() as u32).wrapping_add(memarg.offset as u32) as usize;
+                match mem.get_mut(addr..addr + width) {
+                    Some(bytes) if !trapped => {
+                        bytes.copy_from_slice(&value.to_le_bytes()[..width]);
+                    }
+                    _ => trapped = true,
+                }
+            }
+            Operator::I32Eqz => {
+                let a = pop!();
+                stack.push((a == 0) as i32);
+            }
+            Operator::I32Clz => {
+                let a = pop!();
+                stack.push(a.leading_zeros() as i32);
+            }
+            Operator::I32Ctz => {
+                let a = pop!();
+                stack.push(a.trailing_zeros() as i32);
+            }
+            Operator::I32Popcnt => {
+                let a = pop!();
+                stack.push(a.count_ones() as i32);
+            }
+            Operator::I32Eq => binary!(|a, b| Some((a == b) as i32)),
+            Operator::I32Ne => binary!(|a, b| Some((a != b) as i32)),
+            Operator::I32LtS => binary!(|a, b| Some((a < b) as i32)),
+            Operator::I32LtU => binary!(|a, b| Some(((a as u32) < b as u32) as i32)),
+            Operator::I32GtS => binary!(|a, b| Some((a > b) as i32)),
+            Operator::I32GtU => binary!(|a, b| Some((a as u32 > b as u32) as i32)),
+            Operator::I32LeS => binary!(|a, b| Some((a <= b) as i32)),
+            Operator::I32LeU => binary!(|a, b| Some((a as u32 <= b as u32) as i32)),
+            Operator::I32GeS => binary!(|a, b| Some((a >= b) as i32)),
+            Operator::I32GeU => binary!(|a, b| Some((a as u32 >= b as u32) as i32)),
+            Operator::I32Add => binary!(|a, b| Some(a.wrapping_add(b))),
+            Operator::I32Sub => binary!(|a, b| Some(a.wrapping_sub(b))),
+            Operator::I32Mul => binary!(|a, b| Some(a.wrapping_mul(b))),
+            Operator::I32DivS => binary!(|a, b| a.checked_div(b)),
+            Operator::I32DivU => binary!(|a, b| (a as u32).checked_div(b as u32).map(|v| v as i32)),
+            // i32.rem_s of MIN by -1 is 0, not a trap.
+            Operator::I32RemS => binary!(|a, b| (b != 0).then(|| a.wrapping_rem(b))),
+            Operator::I32RemU => binary!(|a, b| (a as u32).checked_rem(b as u32).map(|v| v as i32)),
+            Operator::I32And => binary!(|a, b| Some(a & b)),
+            Operator::I32Or => binary!(|a, b| Some(a | b)),
+            Operator::I32Xor => binary!(|a, b| Some(a ^ b)),
+            Operator::I32Shl => binary!(|a, b| Some(a.wrapping_shl(b as u32))),
+            Operator::I32ShrS => binary!(|a, b| Some(a.wrapping_shr(b as u32))),
+            Operator::I32ShrU => binary!(|a, b| Some((a as u32).wrapping_shr(b as u32) as i32)),
+            Operator::I32Rotl => binary!(|a, b| Some(a.rotate_left(b as u32))),
+            Operator::I32Rotr => binary!(|a, b| Some(a.rotate_right(b as u32))),
+            op => {
+                return Err(Error::UnsupportedOp {
+                    offset: pc,
+                    op: format_operator(&op),
+                })
+            }
+        }
+        if trapped {
+            return Ok(finish(ModelOutcome::Trapped, cycles, instructions));
+        }
+        pc = match jump {
+            // A branch without a table entry traps in the fetch unit.
+            Some(None) => return Ok(finish(ModelOutcome::Trapped, cycles, instructions)),
+            Some(Some(target)) => target,
+            None => next,
+        };
+    }
+}
+
+/// One CSV row per test, with a header: the model's predicted cycles
+/// next to the cycles `measured` on the core (a results file, see
+/// [`parse_results`](crate::parse_results)) and the difference, so both
+/// timing model drift and core regressions show up. Tests without a
+/// measurement leave those columns empty; `budget` is the cycle budget
+/// the test declares, if any.
+pub fn bench_csv(
+    tests: &[CompiledProgram],
+    measured: &[SimResult],
+    max_cycles: u64,
+) -> Result<String, Error> {
+    let mut out = String::from("test,outcome,predicted,measured,delta,budget\n");
+    for info in tests {
+        let run = run_model(info, max_cycles)?;
+        let outcome = match run.outcome {
+            ModelOutcome::Halted(value) => value.to_string(),
+            ModelOutcome::Trapped => "trap".to_string(),
+            ModelOutcome::TimedOut => "timeout".to_string(),
+        };
+        let (measured, delta) = match measured.iter().find(|r| r.name == info.name) {
+            Some(r) => (
+                r.cycles.to_string(),
+                (r.cycles as i64 - run.cycles as i64).to_string(),
+            ),
+            None => (String::new(), String::new()),
+        };
+        out.push_str(&format!(
+            "{},{},{},{},{},{}\n",
+            csv_field(&info.name),
+            outcome,
+            run.cycles,
+            measured,
+            delta,
+            info.cycle_budget.map_or(String::new(), |b| b.to_string())
+        ));
+    }
+    Ok(out)
+}
+
+#[cfg(all(test, feature = "refexec"))]
+mod tests {
+    use super::*;
+    use crate::{compile_wat_source, parse_results};
+
+    #[test]
+    fn test_cycle_model() {
+        // Two consts at 4 cycles each, then add and return at 2.
+        let add = compile_wat_source(
+            "add",
+            r#"(module (func (export "main") (result i32) i32.const 10 i32.const 20 i32.add))"#,
+        )
+        .unwrap();
+        let run = run_model(&add, 1000).unwrap();
+        assert_eq!(run.outcome, ModelOutcome::Halted(30));
+        assert_eq!((run.cycles, run.instructions), (12, 4));
+
+        // Three times round the loop, which a short limit cuts off.
+        let looped = compile_wat_source(
+            "loop",
+            r#"(module (memory 1) (func (export "main") (result i32)
+                 (loop $l
+                   (i32.store (i32.const 0) (i32.add (i32.load (i32.const 0)) (i32.const 1)))
+                   (br_if $l (i32.lt_u (i32.load (i32.const 0)) (i32.const 3))))
+                 (i32.load (i32.const 0))))"#,
+        )
+        .unwrap();
+        let run = run_model(&looped, 10_000).unwrap();
+        assert_eq!(run.outcome, ModelOutcome::Halted(3));
+        assert_eq!(
+            run_model(&looped, 20).unwrap().outcome,
+            ModelOutcome::TimedOut
+        );
+
+        let trap = compile_wat_source(
+            "trap",
+            r#"(module (func (export "main") (result i32) i32.const 1 i32.const 0 i32.div_u))"#,
+        )
+        .unwrap();
+        assert_eq!(
+            run_model(&trap, 1000).unwrap().outcome,
+            ModelOutcome::Trapped
+        );
+
+        let measured = parse_results("add\tpass\t15\t30\t\n").unwrap();
+        let csv = bench_csv(&[add, trap], &measured, 1000).unwrap();
+        assert_eq!(
+            csv,
+            "test,outcome,predicted,measured,delta,budget\n\
+             add,30,12,15,3,\n\
+             trap,trap,10,,,\n"
+        );
+    }
+}
@@ -0,0 +1,1689 @@
+//! Reading wasm: WAT to binary, the body and data segments the core runs,
+//! disassembly and statistics of a body, and errors located in the source.
+
+use crate::branch::{branch_table_of, BodyError, BranchEntry};
+use crate::{Error, ErrorCategory};
+use anyhow::{anyhow, Result};
+use wasmparser::{Operator, Payload};
+
+// ---------------------------------------------------------------------------
+// Disassembly
+// ---------------------------------------------------------------------------
+
+/// One decoded instruction of a function body.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Insn {
+    /// PC of the opcode byte.
+    pub offset: usize,
+    /// Bytes the instruction takes, immediates included.
+    pub len: usize,
+    /// Bare WAT name, e.g. `i32.load8_s`, or `??` for a byte that doesn't
+    /// decode.
+    pub mnemonic: String,
+    /// Immediates as WAT writes them, e.g. `["offset=4"]` or
+    /// `["(result i32)"]`.
+    pub immediates: Vec<String>,
+}
+
+impl Insn {
+    /// The bytes of `body` this instruction takes.
+    pub fn bytes<'a>(&self, body: &'a [u8]) -> &'a [u8] {
+        &body[self.offset..self.offset + self.len]
+    }
+}
+
+impl std::fmt::Display for Insn {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.mnemonic)?;
+        for imm in &self.immediates {
+            write!(f, " {}", imm)?;
+        }
+        Ok(())
+    }
+}
+
+/// Decode every instruction of `body`. It takes any bytes, as a damaged
+/// image may hold: a byte that doesn't decode is a one-byte `??`
+/// instruction and decoding carries on after it.
+pub fn disassemble(body: &[u8]) -> Vec<Insn> {
+    let mut insns = Vec::new();
+    let mut pc = 0;
+    'resync: while pc < body.len() {
+        let binary_reader = wasmparser::BinaryReader::new(&body[pc..], pc);
+        let mut reader = wasmparser::OperatorsReader::new(binary_reader);
+        while !reader.eof() {
+            let Ok(op) = reader.read() else {
+                insns.push(Insn {
+                    offset: pc,
+                    len: 1,
+                    mnemonic: "??".to_string(),
+                    immediates: Vec::new(),
+                });
+                pc += 1;
+                continue 'resync;
+            };
+            let end = reader.original_position();
+            insns.push(Insn {
+                offset: pc,
+                len: end - pc,
+                mnemonic: operator_name(&op),
+                immediates: operator_immediates(&op),
+            });
+            pc = end;
+        }
+        break;
+    }
+    insns
+}
+
+/// `bytes` as space-separated hex pairs, as listings show an
+/// instruction's raw bytes.
+pub(crate) fn hex_bytes(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|b| format!("{:02X}", b))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// WAT-style mnemonic for an operator, including its immediates.
+pub(crate) fn format_operator(op: &Operator) -> String {
+    let mut text = operator_name(op);
+    for imm in operator_immediates(op) {
+        text.push(' ');
+        text.push_str(&imm);
+    }
+    text
+}
+
+/// The immediates [`format_operator`] shows for `op`.
+fn operator_immediates(op: &Operator) -> Vec<String> {
+    let block_type = |bt: &wasmparser::BlockType| match bt {
+        wasmparser::BlockType::Empty => vec![],
+        wasmparser::BlockType::Type(ty) => vec![format!("(result {})", ty)],
+        wasmparser::BlockType::FuncType(idx) => vec![format!("(type {})", idx)],
+    };
+    let mem_immediates = |m: &wasmparser::MemArg| {
+        let mut imms = Vec::new();
+        if m.offset != 0 {
+            imms.push(format!("offset={}", m.offset));
+        }
+        if m.align != m.max_align {
+            imms.push(format!("align={}", 1u64 << m.align));
+        }
+        imms
+    };
+
+    match op {
+        Operator::Block { blockty } | Operator::Loop { blockty } | Operator::If { blockty } => {
+            block_type(blockty)
+        }
+        Operator::Br { relative_depth } | Operator::BrIf { relative_depth } => {
+            vec![relative_depth.to_string()]
+        }
+        Operator::I32Const { value } => vec![value.to_string()],
+        Operator::I64Const { value } => vec![value.to_string()],
+        Operator::LocalGet { local_index }
+        | Operator::LocalSet { local_index }
+        | Operator::LocalTee { local_index } => vec![local_index.to_string()],
+        Operator::GlobalGet { global_index } | Operator::GlobalSet { global_index } => {
+            vec![global_index.to_string()]
+        }
+        Operator::Call { function_index } => vec![function_index.to_string()],
+        Operator::I32Load { memarg }
+        | Operator::I32Load8S { memarg }
+        | Operator::I32Load8U { memarg }
+        | Operator::I32Load16S { memarg }
+        | Operator::I32Load16U { memarg }
+        | Operator::I32Store { memarg }
+        | Operator::I32Store8 { memarg }
+        | Operator::I32Store16 { memarg } => mem_immediates(memarg),
+        _ => vec![],
+    }
+}
+
+/// Bytes of the opcode an instruction's bytes start with: one, or for
+/// the prefixed opcodes a prefix byte and a LEB128 sub-opcode.
+pub(crate) fn opcode_len(insn: &[u8]) -> usize {
+    match insn[0] {
+        0xFB..=0xFE => 2 + insn[1..].iter().take_while(|&&b| b & 0x80 != 0).count(),
+        _ => 1,
+    }
+}
+
+/// Bare WAT instruction name (`i32.load8_s`, `br_if`,
+/// `i32.atomic.rmw8.add_u`), from the name of the wasmparser visitor
+/// method for the operator.
+pub(crate) fn operator_name(op: &Operator) -> String {
+    macro_rules! visit_name {
+        ($( @$proposal:ident $op:ident $({ $($arg:ident: $argty:ty),* })? => $visit:ident ($($ann:tt)*) )*) => {
+            match op {
+                $( Operator::$op { .. } => stringify!($visit), )*
+                _ => "visit_unknown",
+            }
+        };
+    }
+    let visit = wasmparser::for_each_operator!(visit_name);
+    wat_name(visit.trim_start_matches("visit_"))
+}
+
+/// The WAT name of the instruction whose visitor method is `visit_<snake>`.
+fn wat_name(snake: &str) -> String {
+    // The few whose visitor names say more than the text format does.
+    match snake {
+        "typed_select" | "typed_select_multi" => return "select".to_string(),
+        "ref_test_non_null" | "ref_test_nullable" => return "ref.test".to_string(),
+        "ref_cast_non_null" | "ref_cast_nullable" => return "ref.cast".to_string(),
+        "ref_cast_desc_non_null" | "ref_cast_desc_nullable" => return "ref.cast_desc".to_string(),
+        _ => {}
+    }
+    const NAMESPACES: &[&str] = &[
+        "i32", "i64", "f32", "f64", "v128", "i8x16", "i16x8", "i32x4", "i64x2", "f32x4", "f64x2",
+        "local", "global", "memory", "table", "ref", "data", "elem", "atomic", "struct", "array",
+        "any", "extern", "i31", "cont",
+    ];
+    let Some((ns, rest)) = snake
+        .split_once('_')
+        .filter(|(ns, _)| NAMESPACES.contains(ns))
+    else {
+        return snake.to_string();
+    };
+    // `i32.atomic.rmw8.add_u`, `memory.atomic.notify`, `struct.atomic.get`
+    let rest = match rest.strip_prefix("atomic_") {
+        Some(op) => match op.split_once('_').filter(|(rmw, _)| rmw.starts_with("rmw")) {
+            Some((rmw, op)) => format!("atomic.{}.{}", rmw, op),
+            None => format!("atomic.{}", op),
+        },
+        None => rest.to_string(),
+    };
+    format!("{}.{}", ns, rest)
+}
+
+/// A listing of a program as the ROM holds it, one instruction per line:
+/// its PC, its raw bytes, and its mnemonic. Instructions with a branch
+/// table entry end in `-> <target pc>`. Pass the compiled body, so the
+/// final `end` shows as the `return` the core sees.
+pub fn disassembly_listing(
+    body_bytes: &[u8],
+    branch_table: &[BranchEntry],
+) -> Result<String, Error> {
+    Ok(listing_of(
+        body_bytes,
+        &disassemble(body_bytes),
+        branch_table,
+    ))
+}
+
+/// [`disassembly_listing`] of `insns`, decoded from `body_bytes`.
+fn listing_of(body_bytes: &[u8], insns: &[Insn], branch_table: &[BranchEntry]) -> String {
+    let rows: Vec<(usize, String, String)> = insns
+        .iter()
+        .map(|insn| {
+            (
+                insn.offset,
+                hex_bytes(insn.bytes(body_bytes)),
+                insn.to_string(),
+            )
+        })
+        .collect();
+    let raw_width = rows.iter().map(|(_, raw, _)| raw.len()).max().unwrap_or(0);
+
+    let mut out = String::new();
+    for (pc, raw, name) in rows {
+        out.push_str(&format!("{:04X}  {:<raw_width$}  {}", pc, raw, name));
+        if let Some(entry) = branch_table.iter().find(|e| e.source_pc as usize == pc) {
+            out.push_str(&format!("  -> {:04X}", entry.target_pc));
+        }
+        out.push('\n');
+    }
+    out
+}
+
+/// An assembler-style listing of a program for reading alongside its
+/// waveforms: a header naming it, then [`disassembly_listing`] with the
+/// WAT line, column, and expression of each instruction `map` places.
+pub fn listing_file(
+    name: &str,
+    body_bytes: &[u8],
+    branch_table: &[BranchEntry],
+    map: Option<&PcSourceMap>,
+) -> Result<String, Error> {
+    let listing = disassembly_listing(body_bytes, branch_table)?;
+    let mut out = format!(
+        "; {}: {} bytes, {} branch entries\n",
+        name,
+        body_bytes.len(),
+        branch_table.len()
+    );
+    let Some(map) = map else {
+        out.push_str(&listing);
+        return Ok(out);
+    };
+    out.push_str(&format!("; source: {}\n", map.file));
+    let width = listing.lines().map(str::len).max().unwrap_or(0);
+    for line in listing.lines() {
+        let location = usize::from_str_radix(line.split_whitespace().next().unwrap_or(""), 16)
+            .ok()
+            .and_then(|pc| map.at(pc));
+        match location {
+            Some(l) => out.push_str(&format!(
+                "{:<width$}  ; {}:{} {}\n",
+                line, l.line, l.col, l.text
+            )),
+            None => out.push_str(&format!("{}\n", line)),
+        }
+    }
+    Ok(out)
+}
+
+// ---------------------------------------------------------------------------
+// Program statistics
+// ---------------------------------------------------------------------------
+
+/// Size and shape of a function body, for judging whether the core can
+/// run it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProgramStats {
+    pub body_bytes: usize,
+    pub instructions: usize,
+    /// Instruction count per mnemonic, without immediates.
+    pub opcodes: std::collections::BTreeMap<String, usize>,
+    /// Deepest nesting of `block`, `loop`, and `if`.
+    pub max_block_depth: usize,
+    /// `br`, `br_if`, and `br_table` instructions.
+    pub branches: usize,
+    pub branch_entries: usize,
+    /// Most values on the operand stack at once. Estimated from each
+    /// instruction's pops and pushes, ignoring calls' signatures.
+    pub max_stack_depth: usize,
+    /// The first instruction outside the core's subset (see
+    /// [`unsupported_instruction`](crate::unsupported_instruction)).
+    pub unsupported: Option<String>,
+}
+
+/// Gather [`ProgramStats`] for a function body. Works on any body, not
+/// just ones the core can run.
+pub fn program_stats(body_bytes: &[u8]) -> Result<ProgramStats, Error> {
+    ParsedBody::new(body_bytes)?.stats()
+}
+
+/// Net values an instruction pushes (negative for pops), guessed from its
+/// mnemonic.
+fn stack_effect(name: &str) -> isize {
+    const BINARY: &[&str] = &[
+        "add", "sub", "mul", "div", "div_s", "div_u", "rem_s", "rem_u", "and", "or", "xor", "shl",
+        "shr_s", "shr_u", "rotl", "rotr", "eq", "ne", "lt", "lt_s", "lt_u", "gt", "gt_s", "gt_u",
+        "le", "le_s", "le_u", "ge", "ge_s", "ge_u", "min", "max", "copysign",
+    ];
+    let op = name.rsplit('.').next().unwrap_or_default();
+    match name {
+        "local.get" | "global.get" | "memory.size" => 1,
+        "drop" | "local.set" | "global.set" => -1,
+        "select" => -2,
+        _ if op == "const" => 1,
+        _ if op.starts_with("store") => -2,
+        _ if name.contains('.') && BINARY.contains(&op) => -1,
+        _ => 0,
+    }
+}
+
+/// [`ProgramStats`] as an indented block under `name`, opcodes most
+/// used first.
+pub fn program_stats_text(name: &str, stats: &ProgramStats) -> String {
+    let mut opcodes: Vec<_> = stats.opcodes.iter().collect();
+    opcodes.sort_by(|a, b| b.1.cmp(a.1).then_with(|| a.0.cmp(b.0)));
+    let mut out = format!("{}\n", name);
+    for (label, value) in [
+        ("body bytes", stats.body_bytes.to_string()),
+        ("instructions", stats.instructions.to_string()),
+        ("block depth", stats.max_block_depth.to_string()),
+        ("stack depth", format!("~{}", stats.max_stack_depth)),
+        (
+            "branches",
+            format!(
+                "{} ({} table entries)",
+                stats.branches, stats.branch_entries
+            ),
+        ),
+        (
+            "unsupported",
+            stats.unsupported.clone().unwrap_or_else(|| "none".into()),
+        ),
+    ] {
+        out.push_str(&format!("  {:<14}{}\n", label, value));
+    }
+    out.push_str("  opcodes\n");
+    for (op, count) in opcodes {
+        out.push_str(&format!("    {:<20}{:>6}\n", op, count));
+    }
+    out
+}
+
+/// What kind of work an instruction does, for [`SizeBreakdown`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Construct {
+    /// `i32.const` and the other constants.
+    Constants,
+    /// Numeric operators: arithmetic, comparisons, bit ops, conversions.
+    Arithmetic,
+    /// Blocks, branches, calls, `return`, `unreachable`, and `nop`.
+    Control,
+    /// `local.*` and `global.*`.
+    Locals,
+    /// Loads, stores, and the `memory.*` instructions.
+    Memory,
+    /// `drop`, `select`, and bytes that don't decode.
+    Other,
+}
+
+impl Construct {
+    pub const ALL: [Construct; 6] = [
+        Construct::Constants,
+        Construct::Arithmetic,
+        Construct::Control,
+        Construct::Locals,
+        Construct::Memory,
+        Construct::Other,
+    ];
+
+    /// The construct of an instruction by its bare mnemonic, e.g.
+    /// `i32.load8_s`.
+    pub fn of(mnemonic: &str) -> Construct {
+        let (prefix, op) = mnemonic.split_once('.').unwrap_or(("", mnemonic));
+        match (prefix, op) {
+            ("local" | "global", _) => Construct::Locals,
+            ("memory", _) => Construct::Memory,
+            (_, "const") => Construct::Constants,
+            ("", "drop" | "select") | ("", "??") => Construct::Other,
+            ("", _) => Construct::Control,
+            (_, op) if op.starts_with("load") || op.starts_with("store") => Construct::Memory,
+            _ => Construct::Arithmetic,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Construct::Constants => "constants",
+            Construct::Arithmetic => "arithmetic",
+            Construct::Control => "control flow",
+            Construct::Locals => "locals",
+            Construct::Memory => "memory ops",
+            Construct::Other => "other",
+        }
+    }
+}
+
+/// Bytes and instructions attributed to one group of a [`SizeBreakdown`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SizeEntry {
+    /// The construct's label, or the WAT expression starting the line.
+    pub label: String,
+    /// One-based source line, for a line entry.
+    pub line: Option<usize>,
+    pub bytes: usize,
+    pub instructions: usize,
+}
+
+/// Where a function body's ROM bytes go: per [`Construct`], and per line
+/// of the WAT it was written as, immediates counted with their
+/// instruction. Both lists are largest first.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SizeBreakdown {
+    pub body_bytes: usize,
+    pub constructs: Vec<SizeEntry>,
+    /// Empty without the source.
+    pub lines: Vec<SizeEntry>,
+}
+
+/// Break `body` down by construct and, given the WAT `source` of the
+/// module it is the first function of, by source line. A body that
+/// doesn't match the source gets no lines.
+pub fn size_breakdown(body: &[u8], source: Option<&str>) -> SizeBreakdown {
+    let insns = disassemble(body);
+    let mut constructs: Vec<SizeEntry> = Construct::ALL
+        .iter()
+        .map(|c| SizeEntry {
+            label: c.label().to_string(),
+            line: None,
+            bytes: 0,
+            instructions: 0,
+        })
+        .collect();
+    for insn in &insns {
+        let entry = &mut constructs[Construct::of(&insn.mnemonic) as usize];
+        entry.bytes += insn.len;
+        entry.instructions += 1;
+    }
+    constructs.retain(|e| e.instructions > 0);
+
+    let mut lines: Vec<SizeEntry> = Vec::new();
+    let map = source.and_then(|source| Some((source, SourceMap::from_wat(source)?)));
+    if let Some((source, map)) = map {
+        // Line -> (first instruction's source offset, bytes, instructions).
+        let mut by_line = std::collections::BTreeMap::<usize, (usize, usize, usize)>::new();
+        for insn in &insns {
+            if let Some(offset) = map.source_offset(insn.offset) {
+                let entry = by_line
+                    .entry(line_col(source, offset).0)
+                    .or_insert((offset, 0, 0));
+                entry.0 = entry.0.min(offset);
+                entry.1 += insn.len;
+                entry.2 += 1;
+            }
+        }
+        lines = by_line
+            .into_iter()
+            .map(|(line, (offset, bytes, instructions))| SizeEntry {
+                label: expression_at(source, offset),
+                line: Some(line),
+                bytes,
+                instructions,
+            })
+            .collect();
+    }
+    for list in [&mut constructs, &mut lines] {
+        list.sort_by(|a, b| b.bytes.cmp(&a.bytes).then_with(|| a.line.cmp(&b.line)));
+    }
+    SizeBreakdown {
+        body_bytes: body.len(),
+        constructs,
+        lines,
+    }
+}
+
+/// A [`SizeBreakdown`] as an indented block under `name`: bytes, share
+/// of the body, and instructions per construct, then the `top` source
+/// lines using the most bytes.
+pub fn size_breakdown_text(name: &str, size: &SizeBreakdown, top: usize) -> String {
+    let percent = |bytes: usize| 100.0 * bytes as f64 / size.body_bytes.max(1) as f64;
+    let mut out = format!("{}: {} bytes\n", name, size.body_bytes);
+    out.push_str(&format!(
+        "  {:<14}{:>8}{:>8}{:>8}\n",
+        "construct", "bytes", "%", "insns"
+    ));
+    for e in &size.constructs {
+        out.push_str(&format!(
+            "  {:<14}{:>8}{:>7.1}%{:>8}\n",
+            e.label,
+            e.bytes,
+            percent(e.bytes),
+            e.instructions
+        ));
+    }
+    if !size.lines.is_empty() {
+        out.push_str(&format!(
+            "  {:<14}{:>8}{:>8}{:>8}  expression\n",
+            "line", "bytes", "%", "insns"
+        ));
+        for e in size.lines.iter().take(top) {
+            out.push_str(&format!(
+                "  {:<14}{:>8}{:>7.1}%{:>8}  {}\n",
+                e.line.unwrap_or_default(),
+                e.bytes,
+                percent(e.bytes),
+                e.instructions,
+                e.label
+            ));
+        }
+        if size.lines.len() > top {
+            let rest = &size.lines[top..];
+            let bytes: usize = rest.iter().map(|e| e.bytes).sum();
+            out.push_str(&format!(
+                "  {:<14}{:>8}{:>7.1}%{:>8}\n",
+                format!("({} more)", rest.len()),
+                bytes,
+                percent(bytes),
+                rest.iter().map(|e| e.instructions).sum::<usize>()
+            ));
+        }
+    }
+    out
+}
+
+// ---------------------------------------------------------------------------
+// Parsed bodies
+// ---------------------------------------------------------------------------
+
+/// A function body decoded once, for running several analyses over it
+/// without each decoding it again: its disassembly, branch table, stats,
+/// and listing. Unlike [`disassemble`], the body has to decode.
+#[derive(Debug, Clone)]
+pub struct ParsedBody<'a> {
+    bytes: &'a [u8],
+    /// Each operator and its PC, in order.
+    ops: Vec<(usize, Operator<'a>)>,
+}
+
+impl<'a> ParsedBody<'a> {
+    pub fn new(bytes: &'a [u8]) -> Result<Self, Error> {
+        let binary_reader = wasmparser::BinaryReader::new(bytes, 0);
+        let mut reader = wasmparser::OperatorsReader::new(binary_reader);
+        let mut ops = Vec::new();
+        while !reader.eof() {
+            let (op, offset) = reader.read_with_offset()?;
+            ops.push((offset, op));
+        }
+        Ok(ParsedBody { bytes, ops })
+    }
+
+    pub fn bytes(&self) -> &'a [u8] {
+        self.bytes
+    }
+
+    /// Each operator with its PC and the bytes it takes, immediates
+    /// included.
+    pub fn operators(&self) -> impl Iterator<Item = (usize, usize, &Operator<'a>)> + '_ {
+        let ends = self
+            .ops
+            .iter()
+            .skip(1)
+            .map(|(offset, _)| *offset)
+            .chain([self.bytes.len()]);
+        self.ops
+            .iter()
+            .zip(ends)
+            .map(|((offset, op), end)| (*offset, end - offset, op))
+    }
+
+    /// The body's [`Insn`]s, as [`disassemble`] gives them.
+    pub fn insns(&self) -> Vec<Insn> {
+        self.operators()
+            .map(|(offset, len, op)| Insn {
+                offset,
+                len,
+                mnemonic: operator_name(op),
+                immediates: operator_immediates(op),
+            })
+            .collect()
+    }
+
+    /// See [`compute_branch_table`](crate::compute_branch_table).
+    pub fn branch_table(&self) -> Result<Vec<BranchEntry>, Error> {
+        branch_table_of(self.ops.iter().map(|(offset, op)| (*offset, op)))
+    }
+
+    /// The first instruction outside the core's subset, as
+    /// [`unsupported_instruction`](crate::unsupported_instruction) finds it.
+    pub fn unsupported(&self) -> Option<String> {
+        let spec = crate::HardwareSpec::default();
+        self.ops
+            .iter()
+            .find(|(_, op)| !spec.supports(op))
+            .map(|(_, op)| format_operator(op))
+    }
+
+    /// See [`disassembly_listing`].
+    pub fn listing(&self, branch_table: &[BranchEntry]) -> String {
+        listing_of(self.bytes, &self.insns(), branch_table)
+    }
+
+    /// See [`program_stats`].
+    pub fn stats(&self) -> Result<ProgramStats, Error> {
+        use wasmparser::BlockType;
+        let mut opcodes = std::collections::BTreeMap::new();
+        let mut branches = 0;
+        let mut max_block_depth = 0;
+        let mut max_stack_depth = 0;
+        // (stack height on entry, result count) of each open block; the
+        // function's own frame is the first.
+        let mut frames = vec![(0usize, 0usize)];
+        let mut height = 0usize;
+        for (_, op) in &self.ops {
+            let name = operator_name(op);
+            *opcodes.entry(name.clone()).or_default() += 1;
+            let frame_height = frames.last().map_or(0, |f| f.0);
+            match op {
+                Operator::Block { blockty }
+                | Operator::Loop { blockty }
+                | Operator::If { blockty } => {
+                    if matches!(op, Operator::If { .. }) {
+                        height = height.saturating_sub(1);
+                    }
+                    let results = usize::from(matches!(blockty, BlockType::Type(_)));
+                    frames.push((height, results));
+                    max_block_depth = max_block_depth.max(frames.len() - 1);
+                }
+                Operator::Else => height = frame_height,
+                Operator::End => {
+                    if let Some((entry, results)) = frames.pop() {
+                        height = entry + results;
+                    }
+                }
+                Operator::Br { .. } | Operator::BrTable { .. } => {
+                    branches += 1;
+                    height = frame_height;
+                }
+                Operator::BrIf { .. } => {
+                    branches += 1;
+                    height = height.saturating_sub(1);
+                }
+                Operator::Return | Operator::Unreachable => height = frame_height,
+                _ => {
+                    let effect = stack_effect(&name);
+                    height = height.saturating_add_signed(effect);
+                }
+            }
+            max_stack_depth = max_stack_depth.max(height);
+        }
+        Ok(ProgramStats {
+            body_bytes: self.bytes.len(),
+            instructions: self.ops.len(),
+            opcodes,
+            max_block_depth,
+            branches,
+            branch_entries: self.branch_table()?.len(),
+            max_stack_depth,
+            unsupported: self.unsupported(),
+        })
+    }
+}
+
+// ---------------------------------------------------------------------------
+// WASM binary parsing: extract function body bytes
+// ---------------------------------------------------------------------------
+
+/// One code entry of a module.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FunctionBody {
+    /// Function index, counting imported functions, as `call` and exports
+    /// name it.
+    pub index: u32,
+    /// Local declarations as (count, type) runs, parameters excluded.
+    pub locals: Vec<(u32, wasmparser::ValType)>,
+    /// Where the entry's locals and operators are in the module.
+    pub range: std::ops::Range<usize>,
+    /// Where the operators start in the module.
+    pub ops_offset: usize,
+    /// The operator bytes, ending in the function's `end`.
+    pub ops: Vec<u8>,
+}
+
+/// Every function with code in a WASM binary, in module order.
+pub fn extract_function_bodies(wasm_bytes: &[u8]) -> Result<Vec<FunctionBody>, Error> {
+    let mut index = 0;
+    let mut bodies = Vec::new();
+    for payload in wasmparser::Parser::new(0).parse_all(wasm_bytes) {
+        match payload? {
+            Payload::ImportSection(reader) => {
+                for import in reader.into_imports() {
+                    if matches!(import?.ty, wasmparser::TypeRef::Func(_)) {
+                        index += 1;
+                    }
+                }
+            }
+            Payload::CodeSectionEntry(body) => {
+                let locals = body
+                    .get_locals_reader()?
+                    .into_iter()
+                    .collect::<Result<_, _>>()?;
+                let range = body.range();
+                let ops_offset = body.get_operators_reader()?.original_position();
+                bodies.push(FunctionBody {
+                    index,
+                    locals,
+                    ops: wasm_bytes[ops_offset..range.end].to_vec(),
+                    range,
+                    ops_offset,
+                });
+                index += 1;
+            }
+            _ => {}
+        }
+    }
+    Ok(bodies)
+}
+
+/// Extract the raw operator bytes of the first function in a WASM binary.
+/// Returns bytes starting from the first operator (locals are skipped).
+/// Replaces trailing `end` (0x0B) with `return` (0x0F) for the hardware
+/// (see [`rewrite_for_hardware`]).
+pub fn extract_function_body(wasm_bytes: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut body = extract_function_body_raw(wasm_bytes)?;
+    rewrite_for_hardware(&mut body);
+    Ok(body)
+}
+
+/// [`extract_function_body`] without the rewrite: the body ends in the
+/// function's `end` as the module has it, for a core that halts on it.
+pub fn extract_function_body_raw(wasm_bytes: &[u8]) -> Result<Vec<u8>, Error> {
+    Ok(extract_function_body_ref(wasm_bytes)?.to_vec())
+}
+
+/// [`extract_function_body_raw`] borrowed from the module, for reading the
+/// body without copying it. Parsing stops at the first code entry.
+pub fn extract_function_body_ref(wasm_bytes: &[u8]) -> Result<&[u8], Error> {
+    for payload in wasmparser::Parser::new(0).parse_all(wasm_bytes) {
+        if let Payload::CodeSectionEntry(body) = payload? {
+            let ops_offset = body.get_operators_reader()?.original_position();
+            return Ok(&wasm_bytes[ops_offset..body.range().end]);
+        }
+    }
+    Err(Error::NoCodeSection)
+}
+
+/// [`extract_function_body_raw`] for the function exported as `export`
+/// rather than the first one.
+pub fn extract_export_body(wasm_bytes: &[u8], export: &str) -> Result<Vec<u8>, Error> {
+    Ok(extract_export_body_ref(wasm_bytes, export)?.to_vec())
+}
+
+/// [`extract_export_body`] borrowed from the module. Exports come before
+/// code, so parsing stops at the exported function's entry.
+pub fn extract_export_body_ref<'a>(wasm_bytes: &'a [u8], export: &str) -> Result<&'a [u8], Error> {
+    let mut imported = 0;
+    let mut index = None;
+    let mut next = 0;
+    let no_such_export = || Error::NoSuchExport {
+        name: export.to_string(),
+    };
+    for payload in wasmparser::Parser::new(0).parse_all(wasm_bytes) {
+        match payload? {
+            Payload::ImportSection(reader) => {
+                for import in reader.into_imports() {
+                    if matches!(import?.ty, wasmparser::TypeRef::Func(_)) {
+                        imported += 1;
+                    }
+                }
+                next = imported;
+            }
+            Payload::ExportSection(reader) => {
+                for e in reader {
+                    let e = e?;
+                    if e.name == export && e.kind == wasmparser::ExternalKind::Func {
+                        index = Some(e.index);
+                    }
+                }
+            }
+            Payload::CodeSectionEntry(body) => {
+                let index = index.ok_or_else(no_such_export)?;
+                if index < imported {
+                    return Err(Error::ImportedFunction {
+                        name: export.to_string(),
+                    });
+                }
+                if index == next {
+                    let ops_offset = body.get_operators_reader()?.original_position();
+                    return Ok(&wasm_bytes[ops_offset..body.range().end]);
+                }
+                next += 1;
+            }
+            _ => {}
+        }
+    }
+    match index {
+        None => Err(no_such_export()),
+        Some(index) if index < imported => Err(Error::ImportedFunction {
+            name: export.to_string(),
+        }),
+        Some(_) if next == imported => Err(Error::NoCodeSection),
+        Some(index) => Err(Error::ParseError(anyhow!(
+            "export {:?} is function {}, but the module defines only {}",
+            export,
+            index,
+            next
+        ))),
+    }
+}
+
+/// `body` as the core runs it (see [`rewrite_for_hardware`]), copied only
+/// if its final `end` has to become a `return`.
+pub fn hardware_body(body: &[u8]) -> std::borrow::Cow<'_, [u8]> {
+    match body.last() {
+        Some(0x0B) => {
+            let mut body = body.to_vec();
+            rewrite_for_hardware(&mut body);
+            std::borrow::Cow::Owned(body)
+        }
+        _ => std::borrow::Cow::Borrowed(body),
+    }
+}
+
+/// Make an extracted body's final `end` (0x0B) a `return` (0x0F). The
+/// default core halts on `return` and treats `end` as closing a block, so
+/// [`crate::compile()`] does this unless told not to (see
+/// [`crate::CompileOptions::rewrite_end`]); a core that halts on the
+/// function's `end` takes the body as extracted.
+pub fn rewrite_for_hardware(body: &mut [u8]) {
+    if let Some(last) = body.last_mut() {
+        if *last == 0x0B {
+            *last = 0x0F;
+        }
+    }
+}
+
+/// A run of bytes to preload into linear memory before execution, taken
+/// from an active data segment.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MemSegment {
+    /// Index of the memory the segment initializes. Always 0 for now:
+    /// the core has one data RAM, and [`memory_image`] only flattens that
+    /// one, but the field keeps the JSON forms stable for hardware with
+    /// more.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub memory: u32,
+    pub addr: u32,
+    pub data: Vec<u8>,
+}
+
+/// Memories a module declares, imported ones included. The core has one
+/// data RAM, so a module with more is rejected before compiling (see
+/// [`check_single_memory`]).
+pub fn memory_count(wasm_bytes: &[u8]) -> Result<u32, Error> {
+    let mut count = 0;
+    for payload in wasmparser::Parser::new(0).parse_all(wasm_bytes) {
+        match payload? {
+            Payload::ImportSection(reader) => {
+                for import in reader.into_imports() {
+                    if matches!(import?.ty, wasmparser::TypeRef::Memory(_)) {
+                        count += 1;
+                    }
+                }
+            }
+            Payload::MemorySection(reader) => count += reader.count(),
+            _ => {}
+        }
+    }
+    Ok(count)
+}
+
+/// Fail for a module declaring more than one memory (the multi-memory
+/// proposal), which the core's single data RAM can't hold.
+pub fn check_single_memory(wasm_bytes: &[u8]) -> Result<(), Error> {
+    match memory_count(wasm_bytes)? {
+        0 | 1 => Ok(()),
+        n => Err(ErrorCategory::Unsupported
+            .error(format!(
+                "module declares {} memories, but the core has a single data RAM (memory 0); \
+                 multi-memory modules are not supported",
+                n
+            ))
+            .into()),
+    }
+}
+
+/// Extract the active data segments of a WASM binary.
+///
+/// Only constant `i32.const` offsets are supported, since that is all the
+/// loader can preload through the `i_mem_load_*` port.
+pub fn extract_memory_init(wasm_bytes: &[u8]) -> Result<Vec<MemSegment>, Error> {
+    let parser = wasmparser::Parser::new(0);
+    let mut segments = Vec::new();
+
+    for payload in parser.parse_all(wasm_bytes) {
+        if let Payload::DataSection(reader) = payload? {
+            for data in reader {
+                let data = data?;
+                let wasmparser::DataKind::Active {
+                    memory_index,
+                    offset_expr,
+                } = data.kind
+                else {
+                    continue;
+                };
+                let addr = match offset_expr.get_operators_reader().read()? {
+                    Operator::I32Const { value } => value as u32,
+                    op => {
+                        return Err(
+                            anyhow!("unsupported data segment offset expression {:?}", op).into(),
+                        )
+                    }
+                };
+                segments.push(MemSegment {
+                    memory: memory_index,
+                    addr,
+                    data: data.data.to_vec(),
+                });
+            }
+        }
+    }
+
+    Ok(segments)
+}
+
+/// Size in bytes of the core's data RAM.
+pub const MEMORY_SIZE: usize = 4096;
+
+/// Flatten data segments into one memory image starting at address 0.
+/// Gaps between segments are zero; later segments overwrite earlier ones.
+/// A segment that ends past the [`MEMORY_SIZE`]-byte RAM, or that is for
+/// a memory other than 0, is an error.
+pub fn memory_image(segments: &[MemSegment]) -> Result<Vec<u8>, Error> {
+    if let Some(s) = segments.iter().find(|s| s.memory != 0) {
+        return Err(ErrorCategory::Unsupported
+            .error(format!(
+                "data segment at {} targets memory {}, only memory 0 is supported",
+                s.addr, s.memory
+            ))
+            .into());
+    }
+    if let Some(s) = segments
+        .iter()
+        .find(|s| s.addr as u64 + s.data.len() as u64 > MEMORY_SIZE as u64)
+    {
+        return Err(Error::CapacityExceeded(anyhow!(
+            "data segment at {} ends at {}, past the {}-byte RAM",
+            s.addr,
+            s.addr as u64 + s.data.len() as u64,
+            MEMORY_SIZE
+        )));
+    }
+    let mut image = vec![0u8; memory_image_len(segments)];
+    for s in segments {
+        let start = s.addr as usize;
+        image[start..start + s.data.len()].copy_from_slice(&s.data);
+    }
+    Ok(image)
+}
+
+/// Length of the [`memory_image`] of `segments`, without building it.
+pub(crate) fn memory_image_len(segments: &[MemSegment]) -> usize {
+    segments
+        .iter()
+        .map(|s| s.addr as usize + s.data.len())
+        .max()
+        .unwrap_or(0)
+}
+// ---------------------------------------------------------------------------
+// Compile WAT source to WASM bytes
+// ---------------------------------------------------------------------------
+
+/// Compile WAT text source to WASM binary bytes.
+pub fn compile_wat(source: &str) -> Result<Vec<u8>, Error> {
+    let wasm = wat::parse_str(source)?;
+    Ok(wasm.to_vec())
+}
+
+// ---------------------------------------------------------------------------
+// Wasm features
+// ---------------------------------------------------------------------------
+
+/// Parse a comma-separated list of wasm proposals into the features a
+/// module is validated against (see
+/// [`CompileOptions::features`](crate::CompileOptions::features)),
+/// starting from wasmparser's defaults. `wasm1`, `wasm2`, `wasm3`, `all`,
+/// `none`, and `default` replace the set; a proposal's name, e.g.
+/// `sign-extension` or `+sign-extension`, turns it on, and `-simd` turns
+/// it off. Names are wasmparser's, in lower case with dashes.
+pub fn parse_wasm_features(spec: &str) -> Result<wasmparser::WasmFeatures, Error> {
+    use wasmparser::WasmFeatures;
+    let mut features = WasmFeatures::default();
+    for item in spec.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+        let preset = match item {
+            "wasm1" => Some(WasmFeatures::WASM1),
+            "wasm2" => Some(WasmFeatures::WASM2),
+            "wasm3" => Some(WasmFeatures::WASM3),
+            "all" => Some(WasmFeatures::all()),
+            "none" => Some(WasmFeatures::empty()),
+            "default" => Some(WasmFeatures::default()),
+            _ => None,
+        };
+        if let Some(preset) = preset {
+            features = preset;
+            continue;
+        }
+        let (on, name) = match item.strip_prefix('-') {
+            Some(name) => (false, name),
+            None => (true, item.strip_prefix('+').unwrap_or(item)),
+        };
+        let flag =
+            WasmFeatures::from_name(&name.to_uppercase().replace('-', "_")).ok_or_else(|| {
+                Error::ParseError(anyhow!(
+                    "unknown wasm feature `{}`; expected one of {}",
+                    name,
+                    WasmFeatures::all()
+                        .iter_names()
+                        .map(|(name, _)| wasm_feature_name(name))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ))
+            })?;
+        features.set(flag, on);
+    }
+    Ok(features)
+}
+
+/// A wasmparser feature constant's name as [`parse_wasm_features`] takes
+/// it, e.g. `sign-extension` for `SIGN_EXTENSION`.
+pub fn wasm_feature_name(name: &str) -> String {
+    name.to_lowercase().replace('_', "-")
+}
+
+/// Why a module failed validation: it uses a proposal `features` leaves
+/// out. Carried as context on the validator's error, so a located error
+/// can say so too.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct DisabledProposal(pub String);
+
+impl std::fmt::Display for DisabledProposal {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "the module uses the `{}` proposal, which is disabled",
+            self.0
+        )
+    }
+}
+
+/// The proposal `wasm` was rejected for, if turning on one `features`
+/// leaves out gets validation past the offset `error` stopped at.
+pub(crate) fn disabled_proposal(
+    wasm: &[u8],
+    features: wasmparser::WasmFeatures,
+    error: &wasmparser::BinaryReaderError,
+) -> Option<DisabledProposal> {
+    wasmparser::WasmFeatures::all()
+        .difference(features)
+        .iter_names()
+        .find(|(_, flag)| {
+            match wasmparser::Validator::new_with_features(features | *flag).validate_all(wasm) {
+                Ok(_) => true,
+                Err(e) => e.offset() > error.offset(),
+            }
+        })
+        .map(|(name, _)| DisabledProposal(wasm_feature_name(name)))
+}
+
+// ---------------------------------------------------------------------------
+// Source diagnostics
+// ---------------------------------------------------------------------------
+
+/// Where each instruction of a WAT module's first function, the one
+/// [`extract_function_body`] takes, starts in the source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceMap {
+    /// (PC, source offset) per instruction, in PC order.
+    starts: Vec<(usize, usize)>,
+}
+
+impl SourceMap {
+    /// Map `source`, or `None` if it doesn't compile or has no function
+    /// with code. The final `end`, which has no instruction of its own in
+    /// the text, maps to the `func` keyword.
+    pub fn from_wat(source: &str) -> Option<SourceMap> {
+        use wast::core::{Func, FuncKind, ModuleField, ModuleKind};
+        let mut buf = wast::parser::ParseBuffer::new(source).ok()?;
+        buf.track_instr_spans(true);
+        let wast::Wat::Module(module) = wast::parser::parse::<wast::Wat>(&buf).ok()? else {
+            return None;
+        };
+        let ModuleKind::Text(fields) = &module.kind else {
+            return None;
+        };
+        let (func, spans) = fields.iter().find_map(|field| match field {
+            ModuleField::Func(Func {
+                span,
+                kind: FuncKind::Inline { expression, .. },
+                ..
+            }) => Some((span, expression.instr_spans.as_ref())),
+            _ => None,
+        })?;
+        let wasm = wat::parse_str(source).ok()?;
+        let body = extract_function_body_ref(&wasm).ok()?;
+        let starts = disassemble(body)
+            .into_iter()
+            .map(|insn| insn.offset)
+            .zip(spans?.iter().chain([func]).map(|s| s.offset()))
+            .collect();
+        Some(SourceMap { starts })
+    }
+
+    /// Source offset of the instruction at or around `pc`.
+    pub fn source_offset(&self, pc: usize) -> Option<usize> {
+        let i = self.starts.partition_point(|&(start, _)| start <= pc);
+        self.starts
+            .get(i.checked_sub(1)?)
+            .map(|&(_, offset)| offset)
+    }
+}
+
+impl SourceMap {
+    /// Every mapped instruction's place in `source`, read from `file`,
+    /// for writing out next to the artifacts (see [`PcSourceMap`]).
+    pub fn pc_map(&self, file: &str, source: &str) -> PcSourceMap {
+        let locations = self
+            .starts
+            .iter()
+            .map(|&(pc, offset)| {
+                let (line, col) = line_col(source, offset);
+                SourceLocation {
+                    pc,
+                    line,
+                    col,
+                    text: expression_at(source, offset),
+                }
+            })
+            .collect();
+        PcSourceMap {
+            file: file.to_string(),
+            locations,
+        }
+    }
+}
+
+/// The WAT an instruction was written as: the rest of its line, up to the
+/// parenthesis closing the expression it starts. The `end` of a folded
+/// block is its closing parenthesis.
+fn expression_at(source: &str, offset: usize) -> String {
+    let rest = source[offset..].lines().next().unwrap_or_default();
+    if rest.starts_with(')') {
+        return ")".to_string();
+    }
+    let mut depth = 0usize;
+    let end = rest
+        .char_indices()
+        .find(|&(_, c)| match c {
+            '(' => {
+                depth += 1;
+                false
+            }
+            ')' if depth == 0 => true,
+            ')' => {
+                depth -= 1;
+                false
+            }
+            _ => false,
+        })
+        .map_or(rest.len(), |(i, _)| i);
+    rest[..end].trim().to_string()
+}
+
+/// Where one instruction came from in the WAT.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceLocation {
+    pub pc: usize,
+    /// One-based line and column of the instruction.
+    pub line: usize,
+    pub col: usize,
+    /// The instruction as written, e.g. `i32.const 1`.
+    pub text: String,
+}
+
+/// A [`SourceMap`] resolved against its source, as `compile --source-map`
+/// writes it to `<name>.srcmap.json` so failures found later, away from
+/// the WAT, can still be reported against it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PcSourceMap {
+    /// The WAT file, as it was named when compiled.
+    pub file: String,
+    /// In PC order.
+    pub locations: Vec<SourceLocation>,
+}
+
+impl PcSourceMap {
+    /// The instruction at or around `pc`.
+    pub fn at(&self, pc: usize) -> Option<&SourceLocation> {
+        let i = self.locations.partition_point(|l| l.pc <= pc);
+        self.locations.get(i.checked_sub(1)?)
+    }
+
+    /// `add.wat:3:9: i32.add` for the instruction at `pc`.
+    pub fn describe(&self, pc: usize) -> Option<String> {
+        let l = self.at(pc)?;
+        Some(format!("{}:{}:{}: {}", self.file, l.line, l.col, l.text))
+    }
+
+    pub fn to_json(&self) -> String {
+        let locations: Vec<serde_json::Value> = self
+            .locations
+            .iter()
+            .map(|l| serde_json::json!({"pc": l.pc, "line": l.line, "col": l.col, "text": l.text}))
+            .collect();
+        let json = serde_json::json!({"file": self.file, "locations": locations});
+        serde_json::to_string_pretty(&json).expect("JSON values serialize") + "\n"
+    }
+
+    /// Read back what [`to_json`](Self::to_json) wrote.
+    pub fn from_json(text: &str) -> Result<PcSourceMap, Error> {
+        let json: serde_json::Value =
+            serde_json::from_str(text).map_err(|e| anyhow!("parsing the source map: {}", e))?;
+        let field = |v: &serde_json::Value, key: &str| -> Result<usize> {
+            v[key]
+                .as_u64()
+                .map(|n| n as usize)
+                .ok_or_else(|| anyhow!("source map location lacks {}", key))
+        };
+        let locations = json["locations"]
+            .as_array()
+            .ok_or_else(|| anyhow!("source map has no locations"))?
+            .iter()
+            .map(|l| {
+                Ok(SourceLocation {
+                    pc: field(l, "pc")?,
+                    line: field(l, "line")?,
+                    col: field(l, "col")?,
+                    text: l["text"].as_str().unwrap_or_default().to_string(),
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(PcSourceMap {
+            file: json["file"].as_str().unwrap_or_default().to_string(),
+            locations,
+        })
+    }
+}
+
+/// One-based line and column of byte `offset` in `source`.
+pub fn line_col(source: &str, offset: usize) -> (usize, usize) {
+    let (line, col) = wast::token::Span::from_offset(offset).linecol_in(source);
+    (line + 1, col + 1)
+}
+
+/// `message` with the source line at `offset` under it and the token
+/// there underlined, laid out like wat's own parse errors:
+///
+/// ```text
+/// br depth 3 exceeds block nesting at offset 4
+///  --> add.wat:3:9
+///   |
+/// 3 |         br 3
+///   |         ^^
+/// ```
+pub fn source_snippet(label: &str, source: &str, offset: usize, message: &str) -> String {
+    let (line, col) = line_col(source, offset);
+    let text = source.lines().nth(line - 1).unwrap_or_default();
+    let (before, token) = text.split_at((col - 1).min(text.len()));
+    let token_len = token
+        .find(|c: char| c.is_whitespace() || c == '(' || c == ')')
+        .unwrap_or(token.len())
+        .max(1);
+    // Keep tabs so the underline lines up however they are shown.
+    let indent: String = before
+        .chars()
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect();
+    let gutter = " ".repeat(line.to_string().len());
+    format!(
+        "{}\n{} --> {}:{}:{}\n{} |\n{} | {}\n{} | {}{}",
+        message,
+        gutter,
+        label,
+        line,
+        col,
+        gutter,
+        line,
+        text,
+        gutter,
+        indent,
+        "^".repeat(token_len)
+    )
+}
+
+/// Point a [`BodyError`] in `e`, or a validation error in the first
+/// function's code, at the WAT it came from, named `label`. Other errors,
+/// and ones the source can't be mapped for, pass through.
+pub(crate) fn locate_in_wat(e: Error, label: &str, source: &str) -> Error {
+    let Some(inner) = e.inner() else {
+        return e;
+    };
+    let located = if let Some(body_error) = inner.downcast_ref::<BodyError>() {
+        Some((body_error.offset, body_error.to_string()))
+    } else if let Some(invalid) = inner.downcast_ref::<wasmparser::BinaryReaderError>() {
+        wat::parse_str(source)
+            .ok()
+            .and_then(|wasm| extract_function_bodies(&wasm).ok())
+            .and_then(|bodies| bodies.into_iter().next())
+            .filter(|body| (body.ops_offset..body.range.end).contains(&invalid.offset()))
+            .map(|body| {
+                let message = match inner.downcast_ref::<DisabledProposal>() {
+                    Some(proposal) => format!("{}; {}", invalid.message(), proposal),
+                    None => invalid.message().to_string(),
+                };
+                (invalid.offset() - body.ops_offset, message)
+            })
+    } else {
+        None
+    };
+    let Some((pc, message)) = located else {
+        return e;
+    };
+    match SourceMap::from_wat(source).and_then(|map| map.source_offset(pc)) {
+        Some(offset) => Error::ParseError(anyhow!(source_snippet(label, source, offset, &message))),
+        None => e,
+    }
+}
+
+/// The memory immediate of a load or store.
+pub(crate) fn operator_memarg<'a>(op: &'a Operator) -> Option<&'a wasmparser::MemArg> {
+    match op {
+        Operator::I32Load { memarg }
+        | Operator::I32Load8S { memarg }
+        | Operator::I32Load8U { memarg }
+        | Operator::I32Load16S { memarg }
+        | Operator::I32Load16U { memarg }
+        | Operator::I32Store { memarg }
+        | Operator::I32Store8 { memarg }
+        | Operator::I32Store16 { memarg }
+        | Operator::I64Load { memarg }
+        | Operator::I64Load8S { memarg }
+        | Operator::I64Load8U { memarg }
+        | Operator::I64Load16S { memarg }
+        | Operator::I64Load16U { memarg }
+        | Operator::I64Load32S { memarg }
+        | Operator::I64Load32U { memarg }
+        | Operator::I64Store { memarg }
+        | Operator::I64Store8 { memarg }
+        | Operator::I64Store16 { memarg }
+        | Operator::I64Store32 { memarg }
+        | Operator::F32Load { memarg }
+        | Operator::F32Store { memarg }
+        | Operator::F64Load { memarg }
+        | Operator::F64Store { memarg } => Some(memarg),
+        _ => None,
+    }
+}
+
+#[cfg(all(test, feature = "refexec"))]
+mod tests {
+    use super::*;
+    use crate::compile_wat_source;
+
+    #[test]
+    fn test_memory_init() {
+        let wasm = wat::parse_str(
+            r#"(module
+                (memory 1)
+                (data (i32.const 4) "\01\02")
+                (data (i32.const 0) "\aa")
+                (func (export "main") (result i32) i32.const 0))"#,
+        )
+        .unwrap();
+        let segments = extract_memory_init(&wasm).unwrap();
+        assert_eq!(
+            segments,
+            vec![
+                MemSegment {
+                    memory: 0,
+                    addr: 4,
+                    data: vec![1, 2]
+                },
+                MemSegment {
+                    memory: 0,
+                    addr: 0,
+                    data: vec![0xAA]
+                },
+            ]
+        );
+        assert_eq!(memory_image(&segments).unwrap(), vec![0xAA, 0, 0, 0, 1, 2]);
+
+        let past_ram = [MemSegment {
+            memory: 0,
+            addr: 0xffff_fff0,
+            data: vec![1; 32],
+        }];
+        let err = memory_image(&past_ram).unwrap_err();
+        assert!(
+            err.to_string().contains("past the 4096-byte RAM"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_disassembly_listing() {
+        let info = compile_wat_source(
+            "b",
+            r#"(module (func (export "main") (result i32)
+                (block (br 0)) i32.const 300))"#,
+        )
+        .unwrap();
+        let listing = disassembly_listing(&info.body_bytes, &info.branch_table).unwrap();
+        assert_eq!(
+            listing,
+            "0000  02 40     block\n\
+             0002  0C 00     br 0  -> 0005\n\
+             0004  0B        end\n\
+             0005  41 AC 02  i32.const 300\n\
+             0008  0F        return\n"
+        );
+
+        let source =
+            "(module (func (export \"main\") (result i32)\n  (block (br 0))\n  i32.const 300))";
+        let info = compile_wat_source("b", source).unwrap();
+        let map = SourceMap::from_wat(source).unwrap().pc_map("b.wat", source);
+        let lst =
+            listing_file(&info.name, &info.body_bytes, &info.branch_table, Some(&map)).unwrap();
+        assert_eq!(
+            lst,
+            "; b: 9 bytes, 1 branch entries\n\
+             ; source: b.wat\n\
+             0000  02 40     block          ; 2:4 block (br 0)\n\
+             0002  0C 00     br 0  -> 0005  ; 2:11 br 0\n\
+             0004  0B        end            ; 2:16 )\n\
+             0005  41 AC 02  i32.const 300  ; 3:3 i32.const 300\n\
+             0008  0F        return         ; 1:10 func (export \"main\") (result i32)\n"
+        );
+        let bare = listing_file(&info.name, &info.body_bytes, &info.branch_table, None).unwrap();
+        assert!(bare.ends_with(&listing));
+    }
+
+    #[test]
+    fn test_program_stats() {
+        let wasm = compile_wat(
+            r#"(module (func (export "main") (result i32)
+                i32.const 1 i32.const 2 i32.const 3 i32.add i32.add
+                (if (result i32) (then (block (br 0)) i32.const 4) (else i32.const 5))))"#,
+        )
+        .unwrap();
+        let stats = program_stats(&extract_function_body(&wasm).unwrap()).unwrap();
+        assert_eq!(stats.instructions, 14);
+        assert_eq!(stats.opcodes["i32.const"], 5);
+        assert_eq!(stats.opcodes["i32.add"], 2);
+        assert_eq!(stats.max_block_depth, 2);
+        assert_eq!(stats.max_stack_depth, 3);
+        assert_eq!(stats.branches, 1);
+        assert_eq!(stats.branch_entries, 3);
+        assert_eq!(stats.unsupported, None);
+
+        let wasm =
+            compile_wat(r#"(module (func (export "main") (result i32) (local i32) local.get 0))"#)
+                .unwrap();
+        let stats = program_stats(&extract_function_body(&wasm).unwrap()).unwrap();
+        assert_eq!(stats.unsupported.as_deref(), Some("local.get 0"));
+        assert!(program_stats_text("l", &stats).contains("  unsupported   local.get 0\n"));
+    }
+
+    #[test]
+    fn test_size_breakdown() {
+        let source = r#"(module (memory 1) (global $g (mut i32) (i32.const 0))
+  (func (export "main") (result i32) (local i32)
+    i32.const 300
+    local.set 0
+    (i32.store offset=4 (i32.const 0) (local.get 0))
+    (i32.add (i32.load offset=4 (i32.const 0)) (global.get $g))))"#;
+        let wasm = compile_wat(source).unwrap();
+        let body = extract_function_body(&wasm).unwrap();
+        let size = size_breakdown(&body, Some(source));
+        assert_eq!(size.body_bytes, body.len());
+        let construct = |label: &str| {
+            let e = size.constructs.iter().find(|e| e.label == label).unwrap();
+            (e.bytes, e.instructions)
+        };
+        assert_eq!(construct("constants"), (7, 3));
+        assert_eq!(construct("memory ops"), (6, 2));
+        assert_eq!(construct("locals"), (6, 3));
+        assert_eq!(construct("arithmetic"), (1, 1));
+        assert_eq!(construct("control flow"), (1, 1));
+        assert_eq!(size.constructs[0].label, "constants");
+        assert!(size.constructs.iter().all(|e| e.label != "other"));
+
+        // Folded expressions count whole, operands included.
+        let line = |n: usize| {
+            let e = size.lines.iter().find(|e| e.line == Some(n)).unwrap();
+            (e.label.as_str(), e.bytes, e.instructions)
+        };
+        assert_eq!(size.lines[0].line, Some(6));
+        assert_eq!(
+            line(6),
+            (
+                "i32.add (i32.load offset=4 (i32.const 0)) (global.get $g)",
+                8,
+                4
+            )
+        );
+        assert_eq!(
+            line(5),
+            ("i32.store offset=4 (i32.const 0) (local.get 0)", 7, 3)
+        );
+        let total: usize = size.lines.iter().map(|e| e.bytes).sum();
+        assert_eq!(total, body.len());
+
+        let text = size_breakdown_text("s", &size, 1);
+        assert!(text.starts_with(&format!("s: {} bytes\n", body.len())));
+        assert!(text.contains("  constants            7"));
+        assert!(text.contains("  (4 more)"));
+        assert!(size_breakdown(&body, None).lines.is_empty());
+    }
+
+    #[test]
+    fn test_parsed_body() {
+        let info = compile_wat_source(
+            "p",
+            r#"(module (memory 1) (func (export "main") (result i32)
+                (block (br_if 0 (i32.const 1)))
+                (if (result i32) (i32.load offset=4 (i32.const 0))
+                  (then i32.const 300) (else i32.const -1))))"#,
+        )
+        .unwrap();
+        let body = ParsedBody::new(&info.body_bytes).unwrap();
+        assert_eq!(body.insns(), disassemble(&info.body_bytes));
+        assert_eq!(body.branch_table().unwrap(), info.branch_table);
+        assert_eq!(
+            body.listing(&info.branch_table),
+            disassembly_listing(&info.body_bytes, &info.branch_table).unwrap()
+        );
+        assert_eq!(body.stats().unwrap().max_block_depth, 1);
+        let lens: usize = body.operators().map(|(_, len, _)| len).sum();
+        assert_eq!(lens, info.body_bytes.len());
+
+        // Unlike disassembly, parsing wants a body that decodes.
+        assert!(ParsedBody::new(&[0x41, 0xFF]).is_err());
+    }
+
+    #[test]
+    fn test_source_diagnostics() {
+        let source = "(module\n  (func (export \"main\") (result i32)\n    (block\n      br 3)\n    i32.const 0))\n";
+        let error = compile_wat_source("b", source).unwrap_err();
+        assert_eq!(
+            format!("{:#}", error),
+            "unknown label: branch depth too large\n  --> b:4:7\n  |\n4 |       br 3)\n  |       ^^"
+        );
+        let map = SourceMap::from_wat(source).unwrap();
+        assert_eq!(
+            map.source_offset(0).map(|o| line_col(source, o)),
+            Some((3, 6))
+        );
+        assert_eq!(
+            map.source_offset(3).map(|o| line_col(source, o)),
+            Some((4, 7))
+        );
+        // The final end, now a return, points at the function.
+        let end = map.source_offset(7).unwrap();
+        assert_eq!(line_col(source, end), (2, 4));
+        assert!(SourceMap::from_wat("(module").is_none());
+
+        let pcs = map.pc_map("b.wat", source);
+        assert_eq!(pcs.describe(3).as_deref(), Some("b.wat:4:7: br 3"));
+        assert_eq!(pcs.describe(4).as_deref(), Some("b.wat:4:11: )"));
+        assert_eq!(pcs.at(0).unwrap().text, "block");
+        assert_eq!(PcSourceMap::from_json(&pcs.to_json()).unwrap(), pcs);
+        assert!(PcSourceMap::from_json("{\"locations\": [{\"pc\": 0}]}").is_err());
+    }
+
+    #[test]
+    fn test_disassemble() {
+        let wasm = compile_wat(
+            r#"(module (memory 1)
+                (func (export "main") (result i32)
+                  (block (result i32) i32.const -200 br 0)
+                  i32.load8_u offset=4))"#,
+        )
+        .unwrap();
+        let body = extract_function_body_raw(&wasm).unwrap();
+        assert_eq!(body.last(), Some(&0x0B));
+        assert_eq!(extract_function_body(&wasm).unwrap().last(), Some(&0x0F));
+        let body = extract_function_body(&wasm).unwrap();
+        let insns = disassemble(&body);
+        let text: Vec<String> = insns.iter().map(|i| i.to_string()).collect();
+        assert_eq!(
+            text,
+            [
+                "block (result i32)",
+                "i32.const -200",
+                "br 0",
+                "end",
+                "i32.load8_u offset=4",
+                "return"
+            ]
+        );
+        assert_eq!((insns[1].offset, insns[1].len), (2, 3));
+        assert_eq!(insns[4].immediates, ["offset=4"]);
+        assert_eq!(insns[1].bytes(&body), &body[2..5]);
+        assert_eq!(insns.iter().map(|i| i.len).sum::<usize>(), body.len());
+
+        let damaged = disassemble(&[0x41, 0xFF, 0xFF]);
+        assert_eq!(damaged[0].mnemonic, "??");
+        assert_eq!(damaged.len(), 3);
+        let resynced = disassemble(&[0xFF, 0x6A]);
+        assert_eq!(resynced[1].to_string(), "i32.add");
+    }
+
+    #[test]
+    fn test_function_bodies() {
+        let wasm = compile_wat(
+            r#"(module
+                (import "env" "f" (func))
+                (func (result i32) (local i32 i32) (local i64) i32.const 1)
+                (func (export "two") (result i32) i32.const 2))"#,
+        )
+        .unwrap();
+        let bodies = extract_function_bodies(&wasm).unwrap();
+        assert_eq!(bodies.len(), 2);
+        assert_eq!(bodies[0].index, 1);
+        assert_eq!(
+            bodies[0].locals,
+            [(2, wasmparser::ValType::I32), (1, wasmparser::ValType::I64)]
+        );
+        assert_eq!(bodies[0].ops, [0x41, 0x01, 0x0B]);
+        assert_eq!(
+            &wasm[bodies[0].ops_offset..bodies[0].range.end],
+            &bodies[0].ops[..]
+        );
+        assert_eq!(bodies[1].index, 2);
+        assert!(bodies[1].locals.is_empty());
+        assert_eq!(extract_function_body_raw(&wasm).unwrap(), bodies[0].ops);
+        assert_eq!(extract_export_body(&wasm, "two").unwrap(), bodies[1].ops);
+        assert!(extract_function_bodies(&compile_wat("(module)").unwrap())
+            .unwrap()
+            .is_empty());
+
+        // The borrowed variants point into the module.
+        let first = extract_function_body_ref(&wasm).unwrap();
+        assert_eq!(first, bodies[0].ops);
+        assert!(wasm.as_ptr_range().contains(&first.as_ptr()));
+        let two = extract_export_body_ref(&wasm, "two").unwrap();
+        assert_eq!(two.as_ptr(), wasm[bodies[1].ops_offset..].as_ptr());
+        assert!(matches!(
+            extract_export_body_ref(&wasm, "three"),
+            Err(Error::NoSuchExport { name }) if name == "three"
+        ));
+        assert!(matches!(
+            extract_function_body_ref(&compile_wat("(module)").unwrap()),
+            Err(Error::NoCodeSection)
+        ));
+        let imported =
+            compile_wat(r#"(module (import "env" "f" (func)) (export "f" (func 0)) (func))"#)
+                .unwrap();
+        let e = extract_export_body_ref(&imported, "f").unwrap_err();
+        assert!(matches!(&e, Error::ImportedFunction { name } if name == "f"));
+        assert_eq!(e.to_string(), "\"f\" is an imported function");
+        assert_eq!(e.category(), Some(ErrorCategory::Unsupported));
+
+        assert!(matches!(hardware_body(first), std::borrow::Cow::Owned(_)));
+        assert_eq!(*hardware_body(first), [0x41, 0x01, 0x0F]);
+        assert!(matches!(
+            hardware_body(&[0x41, 0x01, 0x0F]),
+            std::borrow::Cow::Borrowed(_)
+        ));
+    }
+}
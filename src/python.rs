@@ -0,0 +1,212 @@
+//! Python bindings, so cocotb testbenches and other Python tooling can
+//! compile and check programs in-process instead of running
+//! `wasm-compile` and parsing the files it writes. maturin builds them as
+//! the `wasm_ic` extension module with the `pyo3` feature (see
+//! `pyproject.toml`).
+//!
+//! `run` is the wasmtime golden run that tests take their expectations
+//! from, and `Program.run_model` the cycle-approximate model of the core
+//! (see [`crate::model`]). Failures raise `ValueError` carrying the whole
+//! error chain.
+
+use crate::{CompiledProgram, Error, ModelOutcome, SvhOptions};
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+
+fn py_err(e: Error) -> PyErr {
+    PyValueError::new_err(format!("{:#}", e))
+}
+
+/// A compiled test, as [`CompiledProgram`].
+#[pyclass(name = "Program", frozen)]
+struct PyProgram(CompiledProgram);
+
+#[pymethods]
+impl PyProgram {
+    #[getter]
+    fn name(&self) -> &str {
+        &self.0.name
+    }
+
+    /// The program ROM image.
+    #[getter]
+    fn body<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new(py, &self.0.body_bytes)
+    }
+
+    /// `(source_pc, target_pc)` pairs.
+    #[getter]
+    fn branch_table(&self) -> Vec<(u32, u32)> {
+        branch_pairs(&self.0.branch_table)
+    }
+
+    /// `(addr, data)` data segments.
+    #[getter]
+    fn mem_init<'py>(&self, py: Python<'py>) -> Vec<(u32, Bound<'py, PyBytes>)> {
+        self.0
+            .mem_init
+            .iter()
+            .map(|s| (s.addr, PyBytes::new(py, &s.data)))
+            .collect()
+    }
+
+    #[getter]
+    fn expected(&self) -> i32 {
+        self.0.expected
+    }
+
+    /// `(addr, value)` data RAM words the run leaves changed.
+    #[getter]
+    fn mem_expected(&self) -> Vec<(u32, u32)> {
+        self.0
+            .mem_expected
+            .iter()
+            .map(|c| (c.addr, c.value))
+            .collect()
+    }
+
+    #[getter]
+    fn expect_trap(&self) -> bool {
+        self.0.expect_trap
+    }
+
+    #[getter]
+    fn cycle_budget(&self) -> Option<u32> {
+        self.0.cycle_budget
+    }
+
+    /// Run the program on the model of the core for at most
+    /// `max_cycles`, by default its testbench cycle budget: `(outcome,
+    /// result, cycles, instructions)`, with `outcome` one of `"halted"`,
+    /// `"trapped"`, or `"timed_out"` and `result` only for `"halted"`.
+    #[pyo3(signature = (max_cycles = None))]
+    fn run_model(
+        &self,
+        max_cycles: Option<u64>,
+    ) -> PyResult<(&'static str, Option<i32>, u64, u64)> {
+        let max_cycles =
+            max_cycles.unwrap_or_else(|| SvhOptions::default().cycle_budget(&self.0).into());
+        let run = crate::run_model(&self.0, max_cycles).map_err(py_err)?;
+        let (outcome, result) = match run.outcome {
+            ModelOutcome::Halted(result) => ("halted", Some(result)),
+            ModelOutcome::Trapped => ("trapped", None),
+            ModelOutcome::TimedOut => ("timed_out", None),
+        };
+        Ok((outcome, result, run.cycles, run.instructions))
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "Program(name={:?}, body={} bytes, branches={}, expected={})",
+            self.0.name,
+            self.0.body_bytes.len(),
+            self.0.branch_table.len(),
+            self.0.expected
+        )
+    }
+}
+
+fn branch_pairs(entries: &[crate::BranchEntry]) -> Vec<(u32, u32)> {
+    entries.iter().map(|e| (e.source_pc, e.target_pc)).collect()
+}
+
+/// WAT text to a wasm binary.
+#[pyfunction]
+fn compile_wat<'py>(py: Python<'py>, source: &str) -> PyResult<Bound<'py, PyBytes>> {
+    let wasm = crate::compile_wat(source).map_err(py_err)?;
+    Ok(PyBytes::new(py, &wasm))
+}
+
+/// The operator bytes of a wasm binary's first function.
+#[pyfunction]
+fn extract_function_body<'py>(py: Python<'py>, wasm: &[u8]) -> PyResult<Bound<'py, PyBytes>> {
+    let body = crate::extract_function_body(wasm).map_err(py_err)?;
+    Ok(PyBytes::new(py, &body))
+}
+
+/// `(source_pc, target_pc)` pairs for a function body.
+#[pyfunction]
+fn compute_branch_table(body: &[u8]) -> PyResult<Vec<(u32, u32)>> {
+    let entries = crate::compute_branch_table(body).map_err(py_err)?;
+    Ok(branch_pairs(&entries))
+}
+
+/// A whole test from WAT source, as `wasm-compile` builds it.
+#[pyfunction]
+fn compile(name: &str, source: &str) -> PyResult<PyProgram> {
+    crate::compile_wat_source(name, source)
+        .map(PyProgram)
+        .map_err(py_err)
+}
+
+/// Run `export` of a wasm binary on wasmtime: its `i32` result and the
+/// final linear memory, if the module exports it as `memory`. A trap
+/// raises.
+#[pyfunction]
+#[pyo3(signature = (wasm, export = "main"))]
+fn run<'py>(
+    py: Python<'py>,
+    wasm: &[u8],
+    export: &str,
+) -> PyResult<(i32, Option<Bound<'py, PyBytes>>)> {
+    let (result, memory) = crate::run_export_with_wasmtime(wasm, export).map_err(py_err)?;
+    Ok((result, memory.map(|m| PyBytes::new(py, &m))))
+}
+
+#[pymodule]
+fn wasm_ic(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyProgram>()?;
+    m.add_function(wrap_pyfunction!(compile_wat, m)?)?;
+    m.add_function(wrap_pyfunction!(extract_function_body, m)?)?;
+    m.add_function(wrap_pyfunction!(compute_branch_table, m)?)?;
+    m.add_function(wrap_pyfunction!(compile, m)?)?;
+    m.add_function(wrap_pyfunction!(run, m)?)?;
+    m.add("__version__", env!("CARGO_PKG_VERSION"))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pyo3::types::PyDict;
+
+    #[test]
+    fn test_python_module() {
+        Python::initialize();
+        Python::attach(|py| {
+            let globals = PyDict::new(py);
+            globals
+                .set_item("wasm_ic", pyo3::wrap_pymodule!(wasm_ic)(py))
+                .unwrap();
+            py.run(
+                cr#"
+src = '(module (memory (export "memory") 1) (func (export "main") (result i32) (block (br 0)) i32.const 0 i32.const 7 i32.store8 i32.const 7))'
+wasm = wasm_ic.compile_wat(src)
+body = wasm_ic.extract_function_body(wasm)
+assert body[:2] == bytes([0x02, 0x40]), body
+assert wasm_ic.compute_branch_table(body) == [(2, 5)]
+result, memory = wasm_ic.run(wasm)
+assert (result, memory[0]) == (7, 7)
+
+p = wasm_ic.compile("t", src)
+assert (p.name, p.expected, p.expect_trap) == ("t", 7, False)
+assert p.body[-1] == 0x0F and p.branch_table == [(2, 5)]
+assert p.mem_expected == [(0, 7)]
+outcome, result, cycles, instructions = p.run_model()
+assert (outcome, result) == ("halted", 7), (outcome, result)
+assert 0 < instructions < cycles
+assert p.run_model(1)[:2] == ("timed_out", None)
+try:
+    wasm_ic.compile_wat("(module")
+    raise AssertionError("no error")
+except ValueError as e:
+    assert "expected" in str(e), e
+"#,
+                Some(&globals),
+                None,
+            )
+            .unwrap();
+        });
+    }
+}
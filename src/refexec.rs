@@ -0,0 +1,298 @@
+//! The reference execution on wasmtime that gives each test its expected
+//! result and final memory. The runs need the `refexec` feature, which a
+//! build without wasmtime (e.g. for `wasm32-unknown-unknown`) leaves out;
+//! comparing memory against a run's result doesn't.
+
+use crate::emit::ChecksumAlgo;
+use crate::parse::{memory_image, MemSegment, MEMORY_SIZE};
+use crate::{Entry, Error};
+#[cfg(feature = "refexec")]
+use anyhow::{Context, Result};
+
+#[cfg(feature = "refexec")]
+/// Run a WASM module with wasmtime, calling exported `main() -> i32`.
+pub fn run_with_wasmtime(wasm_bytes: &[u8]) -> Result<i32, Error> {
+    run_with_wasmtime_memory(wasm_bytes).map(|(result, _)| result)
+}
+
+#[cfg(feature = "refexec")]
+/// Like [`run_with_wasmtime`], but also return the final contents of the
+/// exported `memory`, if the module exports one.
+pub fn run_with_wasmtime_memory(wasm_bytes: &[u8]) -> Result<(i32, Option<Vec<u8>>), Error> {
+    run_export_with_wasmtime(wasm_bytes, "main")
+}
+
+#[cfg(feature = "refexec")]
+/// [`run_with_wasmtime_memory`] calling the `() -> i32` function exported
+/// as `export` on a fresh instance. Every failure, a trap included, is an
+/// [`Error::RefExecFailed`].
+pub fn run_export_with_wasmtime(
+    wasm_bytes: &[u8],
+    export: &str,
+) -> Result<(i32, Option<Vec<u8>>), Error> {
+    run_export(wasm_bytes, export, None).map_err(Error::RefExecFailed)
+}
+
+#[cfg(feature = "refexec")]
+static MODULE_CACHE: std::sync::RwLock<Option<std::path::PathBuf>> = std::sync::RwLock::new(None);
+
+#[cfg(feature = "refexec")]
+/// Keep the modules the reference runs compile in `dir`, keyed by the
+/// wasm they came from and the wasmtime build, so a later run of the
+/// same module loads it instead of compiling it again; `None` (the
+/// default) compiles every time. Applies to every run in the process.
+///
+/// Each `.cwasm` entry sits beside a `.wasm` copy of its source, and is
+/// only loaded when that copy matches the module byte for byte, so two
+/// modules whose checksums collide can't load each other's code. Entries
+/// are still loaded as native code without checks, so `dir` must not be
+/// writable by anyone who shouldn't run code as this user.
+pub fn set_module_cache(dir: Option<std::path::PathBuf>) {
+    *MODULE_CACHE.write().unwrap() = dir;
+}
+
+#[cfg(feature = "refexec")]
+/// `wasm_bytes` compiled for `engine`, through the
+/// [`set_module_cache`] directory if there is one. An entry that doesn't
+/// load, or whose source differs, is a miss, and one that can't be
+/// written only costs the next run a compile.
+fn load_module(engine: &wasmtime::Engine, wasm_bytes: &[u8]) -> Result<wasmtime::Module> {
+    use std::hash::{Hash, Hasher};
+
+    let Some(dir) = MODULE_CACHE.read().unwrap().clone() else {
+        return wasmtime::Module::new(engine, wasm_bytes);
+    };
+    let mut compat = std::collections::hash_map::DefaultHasher::new();
+    engine.precompile_compatibility_hash().hash(&mut compat);
+    let entry = dir.join(format!(
+        "{:08x}{:08x}-{:016x}.cwasm",
+        ChecksumAlgo::Crc32.checksum(wasm_bytes),
+        ChecksumAlgo::Crc32c.checksum(wasm_bytes),
+        compat.finish()
+    ));
+    let source = entry.with_extension("wasm");
+    match std::fs::read(&source) {
+        Ok(cached) if cached == wasm_bytes => {
+            // SAFETY: entries are only ever written below, by `serialize`,
+            // for the module whose bytes were just compared, and the
+            // directory is trusted (see `set_module_cache`).
+            match unsafe { wasmtime::Module::deserialize_file(engine, &entry) } {
+                Ok(module) => {
+                    log::debug!("reusing {}", entry.display());
+                    return Ok(module);
+                }
+                Err(e) => log::debug!("ignoring {}: {:#}", entry.display(), e),
+            }
+        }
+        Ok(_) => log::debug!("ignoring {}: built from another module", entry.display()),
+        Err(_) => {}
+    }
+    let module = wasmtime::Module::new(engine, wasm_bytes)?;
+    // Written aside and renamed, so parallel runs never load half an entry.
+    let tmp = format!("{}.tmp", std::process::id());
+    let partial = entry.with_extension(&tmp);
+    let partial_source = source.with_extension(format!("wasm.{}", tmp));
+    let written = module.serialize().and_then(|bytes| {
+        std::fs::create_dir_all(&dir)?;
+        std::fs::write(&partial, bytes)?;
+        std::fs::write(&partial_source, wasm_bytes)?;
+        std::fs::rename(&partial, &entry)?;
+        Ok(std::fs::rename(&partial_source, &source)?)
+    });
+    if let Err(e) = written {
+        log::warn!("not caching module in {}: {:#}", dir.display(), e);
+        let _ = std::fs::remove_file(&partial);
+        let _ = std::fs::remove_file(&partial_source);
+    }
+    Ok(module)
+}
+
+#[cfg(feature = "refexec")]
+fn run_export(
+    wasm_bytes: &[u8],
+    export: &str,
+    fuel: Option<u64>,
+) -> Result<(i32, Option<Vec<u8>>)> {
+    let mut config = wasmtime::Config::new();
+    config.consume_fuel(fuel.is_some());
+    let engine = wasmtime::Engine::new(&config)?;
+    let module = load_module(&engine, wasm_bytes)?;
+    let mut store = wasmtime::Store::new(&engine, ());
+    if let Some(fuel) = fuel {
+        store.set_fuel(fuel)?;
+    }
+    let instance = wasmtime::Instance::new(&mut store, &module, &[])?;
+
+    let main_fn = instance
+        .get_typed_func::<(), i32>(&mut store, export)
+        .with_context(|| {
+            format!(
+                "Could not find exported function '{}' with signature () -> i32",
+                export
+            )
+        })?;
+
+    let result = main_fn.call(&mut store, ())?;
+    let memory = instance
+        .get_memory(&mut store, "memory")
+        .map(|m| m.data(&store).to_vec());
+    Ok((result, memory))
+}
+
+/// The golden run of `entry` for [`crate::compile`], burning at most
+/// `fuel` if given.
+#[cfg(feature = "refexec")]
+pub(crate) fn run_entry(
+    wasm_bytes: &[u8],
+    entry: &Entry,
+    fuel: Option<u64>,
+) -> Result<(i32, Option<Vec<u8>>), Error> {
+    let export = match entry {
+        Entry::First => "main",
+        Entry::Export(export) => export,
+        Entry::Index(index) => {
+            return Err(Error::Other(anyhow::anyhow!(
+                "function {} has no golden run; run it through an export",
+                index
+            )))
+        }
+    };
+    run_export(wasm_bytes, export, fuel).map_err(Error::RefExecFailed)
+}
+
+#[cfg(not(feature = "refexec"))]
+pub(crate) fn run_entry(
+    _: &[u8],
+    _: &Entry,
+    _: Option<u64>,
+) -> Result<(i32, Option<Vec<u8>>), Error> {
+    Err(Error::Other(anyhow::anyhow!(
+        "built without the `refexec` feature, so there is no reference run; \
+         leave out `Pass::RefExec`"
+    )))
+}
+
+/// Whether a failed run trapped rather than failing to run at all;
+/// running out of fuel is the latter.
+#[cfg(feature = "refexec")]
+pub(crate) fn is_trap(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<wasmtime::Trap>()
+        .is_some_and(|&trap| trap != wasmtime::Trap::OutOfFuel)
+}
+
+#[cfg(not(feature = "refexec"))]
+pub(crate) fn is_trap(_: &anyhow::Error) -> bool {
+    false
+}
+
+/// The kind of trap a failed run ended in, as the spec tests'
+/// `assert_trap` messages name it: `unreachable`, `integer divide by
+/// zero`, `out of bounds memory access`, ...
+#[cfg(feature = "refexec")]
+pub(crate) fn trap_kind(e: &anyhow::Error) -> Option<String> {
+    e.downcast_ref::<wasmtime::Trap>().map(|trap| match trap {
+        wasmtime::Trap::UnreachableCodeReached => "unreachable".to_string(),
+        wasmtime::Trap::StackOverflow => "call stack exhausted".to_string(),
+        trap => {
+            let message = trap.to_string();
+            message.trim_start_matches("wasm trap: ").to_string()
+        }
+    })
+}
+
+#[cfg(not(feature = "refexec"))]
+pub(crate) fn trap_kind(_: &anyhow::Error) -> Option<String> {
+    None
+}
+
+/// An aligned 32-bit word of data RAM and the little-endian value it
+/// should hold after the program runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MemCheck {
+    /// Index of the memory the word is in; always 0, as for
+    /// [`MemSegment::memory`].
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub memory: u32,
+    pub addr: u32,
+    pub value: u32,
+}
+
+/// Compare the final memory of a run against the preloaded image and
+/// return every word within [`MEMORY_SIZE`] that the program changed.
+pub fn expected_memory(segments: &[MemSegment], final_mem: &[u8]) -> Result<Vec<MemCheck>, Error> {
+    let mut initial = memory_image(segments)?;
+    initial.resize(MEMORY_SIZE, 0);
+    let mut checks = Vec::new();
+    for (i, (before, after)) in initial
+        .chunks(4)
+        .zip(final_mem[..MEMORY_SIZE.min(final_mem.len())].chunks(4))
+        .enumerate()
+    {
+        if before != after {
+            let mut word = [0u8; 4];
+            word[..after.len()].copy_from_slice(after);
+            checks.push(MemCheck {
+                memory: 0,
+                addr: (i * 4) as u32,
+                value: u32::from_le_bytes(word),
+            });
+        }
+    }
+    Ok(checks)
+}
+
+#[cfg(all(test, feature = "refexec"))]
+mod tests {
+    use super::*;
+    use crate::compile_wat;
+    use std::fs;
+
+    #[test]
+    fn test_module_cache() {
+        let dir = std::env::temp_dir().join(format!("wasm-ic-modules-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let wasm = compile_wat(
+            r#"(module (func (export "main") (result i32) i32.const 6 i32.const 7 i32.mul))"#,
+        )
+        .unwrap();
+        let prefix = format!(
+            "{:08x}{:08x}-",
+            ChecksumAlgo::Crc32.checksum(&wasm),
+            ChecksumAlgo::Crc32c.checksum(&wasm)
+        );
+        let entry = || {
+            fs::read_dir(&dir)
+                .unwrap()
+                .map(|e| e.unwrap().path())
+                .find(|p| {
+                    p.extension().unwrap() == "cwasm"
+                        && p.file_name()
+                            .unwrap()
+                            .to_string_lossy()
+                            .starts_with(&prefix)
+                })
+        };
+
+        // Other tests' runs may land in the directory while it is set.
+        set_module_cache(Some(dir.clone()));
+        assert_eq!(run_with_wasmtime(&wasm).unwrap(), 42);
+        let cached = entry().expect("no cache entry written");
+        assert_eq!(cached.extension().unwrap(), "cwasm");
+        assert_eq!(run_with_wasmtime(&wasm).unwrap(), 42);
+
+        // A damaged entry is compiled again and replaced.
+        fs::write(&cached, b"not a module").unwrap();
+        assert_eq!(run_with_wasmtime(&wasm).unwrap(), 42);
+        assert_ne!(fs::read(&cached).unwrap(), b"not a module");
+
+        // An entry is only trusted for the exact module it was built from.
+        let source = cached.with_extension("wasm");
+        assert_eq!(fs::read(&source).unwrap(), wasm);
+        fs::write(&source, b"another module").unwrap();
+        assert_eq!(run_with_wasmtime(&wasm).unwrap(), 42);
+        assert_eq!(fs::read(&source).unwrap(), wasm);
+        set_module_cache(None);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
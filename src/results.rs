@@ -0,0 +1,290 @@
+//! The results file the generated tests write, and the JUnit, JSON,
+//! Markdown, and CSV reports made from it.
+
+use crate::Error;
+use anyhow::{anyhow, Context};
+
+// ---------------------------------------------------------------------------
+// Simulation results
+// ---------------------------------------------------------------------------
+
+/// One line of the results file the generated tests write through
+/// `wat_record`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SimResult {
+    pub name: String,
+    pub passed: bool,
+    pub cycles: u64,
+    /// Final stack top in decimal, `trap`, or `timeout`.
+    pub result: String,
+    /// Why the test failed; empty when it passed.
+    pub detail: String,
+}
+
+/// Parse a results file: tab-separated name, `pass`/`fail`, cycles,
+/// result, and detail, one test per line. Blank lines are skipped.
+pub fn parse_results(text: &str) -> Result<Vec<SimResult>, Error> {
+    let mut results = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let fields: Vec<&str> = line.splitn(5, '\t').collect();
+        if fields.len() < 4 {
+            return Err(Error::ParseError(anyhow!(
+                "line {}: expected at least 4 tab-separated fields",
+                i + 1
+            )));
+        }
+        let passed = match fields[1] {
+            "pass" => true,
+            "fail" => false,
+            other => {
+                return Err(Error::ParseError(anyhow!(
+                    "line {}: unknown status `{}`",
+                    i + 1,
+                    other
+                )))
+            }
+        };
+        let cycles = fields[2]
+            .parse()
+            .with_context(|| format!("line {}: bad cycle count `{}`", i + 1, fields[2]))?;
+        results.push(SimResult {
+            name: fields[0].to_string(),
+            passed,
+            cycles,
+            result: fields[3].to_string(),
+            detail: fields.get(4).unwrap_or(&"").to_string(),
+        });
+    }
+    Ok(results)
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// JUnit XML with one `<testsuite>` named `suite`, as read by most CI
+/// dashboards. Cycles and the final value go in each case's system-out.
+pub fn results_junit(results: &[SimResult], suite: &str) -> String {
+    let failures = results.iter().filter(|r| !r.passed).count();
+    let mut out = String::new();
+    out.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    out.push_str(&format!(
+        "<testsuite name=\"{}\" tests=\"{}\" failures=\"{}\">\n",
+        xml_escape(suite),
+        results.len(),
+        failures
+    ));
+    for r in results {
+        out.push_str(&format!(
+            "  <testcase name=\"{}\" classname=\"{}\">\n",
+            xml_escape(&r.name),
+            xml_escape(suite)
+        ));
+        if !r.passed {
+            out.push_str(&format!(
+                "    <failure message=\"{}\">got {}</failure>\n",
+                xml_escape(&r.detail),
+                xml_escape(&r.result)
+            ));
+        }
+        out.push_str(&format!(
+            "    <system-out>cycles={} result={}</system-out>\n",
+            r.cycles,
+            xml_escape(&r.result)
+        ));
+        out.push_str("  </testcase>\n");
+    }
+    out.push_str("</testsuite>\n");
+    out
+}
+
+/// The results as a JSON document with pass/fail totals.
+pub fn results_json(results: &[SimResult]) -> String {
+    let passed = results.iter().filter(|r| r.passed).count();
+    let doc = serde_json::json!({
+        "tests": results.len(),
+        "passed": passed,
+        "failed": results.len() - passed,
+        "results": results
+            .iter()
+            .map(|r| serde_json::json!({
+                "name": r.name,
+                "status": if r.passed { "pass" } else { "fail" },
+                "cycles": r.cycles,
+                "result": r.result,
+                "detail": r.detail,
+            }))
+            .collect::<Vec<_>>(),
+    });
+    let mut out = serde_json::to_string_pretty(&doc).expect("JSON values always serialize");
+    out.push('\n');
+    out
+}
+
+/// A regression run's result for one test, with the size of what was
+/// loaded; the sizes are `None` when the WAT did not compile.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegressionEntry {
+    pub result: SimResult,
+    pub rom_bytes: Option<usize>,
+    pub branch_entries: Option<usize>,
+}
+
+fn count_label(n: Option<usize>) -> String {
+    n.map_or(String::new(), |n| n.to_string())
+}
+
+/// A Markdown summary for pasting into PRs: a table of every test, the
+/// totals, and the reason for each failure.
+pub fn regression_markdown(entries: &[RegressionEntry]) -> String {
+    let cell = |s: &str| s.replace('|', "\\|").replace('\n', " ");
+    let mut out = String::new();
+    out.push_str("| Test | Status | Cycles | Result | ROM bytes | Branch entries |\n");
+    out.push_str("|------|--------|-------:|--------|----------:|---------------:|\n");
+    for e in entries {
+        let r = &e.result;
+        out.push_str(&format!(
+            "| {} | {} | {} | {} | {} | {} |\n",
+            cell(&r.name),
+            if r.passed { "pass" } else { "**FAIL**" },
+            r.cycles,
+            cell(&r.result),
+            count_label(e.rom_bytes),
+            count_label(e.branch_entries)
+        ));
+    }
+    let failed: Vec<&SimResult> = entries
+        .iter()
+        .map(|e| &e.result)
+        .filter(|r| !r.passed)
+        .collect();
+    out.push_str(&format!(
+        "\n{} passed, {} failed\n",
+        entries.len() - failed.len(),
+        failed.len()
+    ));
+    if !failed.is_empty() {
+        out.push_str("\n### Failures\n\n");
+        for r in failed {
+            out.push_str(&format!("- `{}`: {}\n", r.name, cell(&r.detail)));
+        }
+    }
+    out
+}
+
+/// Quote a CSV field if it needs it (RFC 4180).
+pub(crate) fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+/// One CSV row per test, with a header, for tracking results over time.
+pub fn regression_csv(entries: &[RegressionEntry]) -> String {
+    let mut out = String::from("test,status,cycles,result,rom_bytes,branch_entries,detail\n");
+    for e in entries {
+        let r = &e.result;
+        out.push_str(&format!(
+            "{},{},{},{},{},{},{}\n",
+            csv_field(&r.name),
+            if r.passed { "pass" } else { "fail" },
+            r.cycles,
+            csv_field(&r.result),
+            count_label(e.rom_bytes),
+            count_label(e.branch_entries),
+            csv_field(&r.detail)
+        ));
+    }
+    out
+}
+
+#[cfg(all(test, feature = "refexec"))]
+mod tests {
+    use super::*;
+    use crate::generate_svh;
+
+    #[test]
+    fn test_regression_reports() {
+        let entries = [
+            RegressionEntry {
+                result: SimResult {
+                    name: "add".to_string(),
+                    passed: true,
+                    cycles: 12,
+                    result: "30".to_string(),
+                    detail: String::new(),
+                },
+                rom_bytes: Some(6),
+                branch_entries: Some(0),
+            },
+            RegressionEntry {
+                result: SimResult {
+                    name: "bad".to_string(),
+                    passed: false,
+                    cycles: 0,
+                    result: "error".to_string(),
+                    detail: "compiling: expected \"i32\", got a|b".to_string(),
+                },
+                rom_bytes: None,
+                branch_entries: None,
+            },
+        ];
+        let md = regression_markdown(&entries);
+        let lines: Vec<&str> = md.lines().collect();
+        assert_eq!(lines[2], "| add | pass | 12 | 30 | 6 | 0 |");
+        assert_eq!(lines[3], "| bad | **FAIL** | 0 | error |  |  |");
+        assert_eq!(lines[5], "1 passed, 1 failed");
+        assert_eq!(
+            lines.last().unwrap(),
+            &"- `bad`: compiling: expected \"i32\", got a\\|b"
+        );
+
+        let csv = regression_csv(&entries);
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(
+            lines[0],
+            "test,status,cycles,result,rom_bytes,branch_entries,detail"
+        );
+        assert_eq!(lines[1], "add,pass,12,30,6,0,");
+        assert_eq!(
+            lines[2],
+            "bad,fail,0,error,,,\"compiling: expected \"\"i32\"\", got a|b\""
+        );
+    }
+
+    #[test]
+    fn test_parse_results() {
+        let text = "add\tpass\t12\t30\t\nsub\tfail\t9\t-1\texpected 1\n\n";
+        let results = parse_results(text).unwrap();
+        assert_eq!(
+            results[1],
+            SimResult {
+                name: "sub".to_string(),
+                passed: false,
+                cycles: 9,
+                result: "-1".to_string(),
+                detail: "expected 1".to_string(),
+            }
+        );
+        assert!(parse_results("add\tok\t1\t2\n").is_err());
+
+        let xml = results_junit(&results, "wat");
+        assert!(xml.contains("<testsuite name=\"wat\" tests=\"2\" failures=\"1\">\n"));
+        assert!(xml.contains("    <failure message=\"expected 1\">got -1</failure>\n"));
+        let json: serde_json::Value = serde_json::from_str(&results_json(&results)).unwrap();
+        assert_eq!(json["failed"], 1);
+        assert_eq!(json["results"][0]["cycles"], 12);
+
+        let svh = generate_svh(&[]);
+        assert!(svh.contains("path = \"wat_results.tsv\";\n"));
+        assert!(svh.contains("        wat_record(name, 1, \"\");\n"));
+    }
+}
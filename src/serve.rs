@@ -0,0 +1,207 @@
+//! The JSON-RPC compile server behind `wasm-compile serve`.
+
+use crate::emit::{test_json, ArtifactEmitter, BuiltinEmitter};
+use crate::{compile, ArtifactFormat, CompileOptions, EmitOptions, Error, Source};
+
+// ---------------------------------------------------------------------------
+// Compile server
+// ---------------------------------------------------------------------------
+
+/// Port `wasm-compile serve` listens on unless told otherwise.
+pub const SERVE_PORT: u16 = 7878;
+
+/// Fuel the golden run of a `compile` request may burn in wasmtime
+/// before the request fails as never halting.
+pub const SERVE_FUEL: u64 = 10_000_000;
+
+/// Seconds a `wasm-compile serve` client may sit between requests before
+/// the server hangs up, freeing its worker for the next client.
+pub const SERVE_IDLE_SECS: u64 = 60;
+
+/// Longest request line a `wasm-compile serve` client may send, in bytes.
+/// A longer one is answered with an invalid request error and the
+/// connection is closed.
+pub const SERVE_MAX_LINE: usize = 4 << 20;
+
+/// Answer one JSON-RPC 2.0 request line from a `wasm-compile serve`
+/// client with one response line (no newline). Methods:
+///
+/// - `compile`, params `{"wat": "...", "name": "main", "format": "hex"}`
+///   with `name` and `format` optional: the [`test_json`] document, and
+///   with a `format`, an `artifacts` object from file name to file text.
+/// - `version`: the crate version.
+///
+/// A notification (no `id`) gets no response, and a request that isn't
+/// a JSON object an invalid request error. The golden run of a
+/// `compile` fails once it burns [`SERVE_FUEL`], so a client's module
+/// can't tie up the server forever.
+pub fn handle_rpc(line: &str) -> Option<String> {
+    let (id, result) = match serde_json::from_str::<serde_json::Value>(line) {
+        Ok(request) if !request.is_object() => (
+            serde_json::Value::Null,
+            Err((-32600, "invalid request: not an object".to_string())),
+        ),
+        Ok(request) => {
+            let id = request.get("id")?.clone();
+            (id, rpc_call(&request))
+        }
+        Err(e) => (
+            serde_json::Value::Null,
+            Err((-32700, format!("parse error: {}", e))),
+        ),
+    };
+    Some(rpc_response(id, result))
+}
+
+/// The response line for a request the server can't read at all, such as
+/// one over [`SERVE_MAX_LINE`]: a JSON-RPC error with a null `id`.
+pub fn rpc_error(code: i64, message: &str) -> String {
+    rpc_response(serde_json::Value::Null, Err((code, message.to_string())))
+}
+
+fn rpc_response(
+    id: serde_json::Value,
+    result: std::result::Result<serde_json::Value, (i64, String)>,
+) -> String {
+    let response = match result {
+        Ok(result) => serde_json::json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err((code, message)) => serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "error": { "code": code, "message": message },
+        }),
+    };
+    response.to_string()
+}
+
+/// Dispatch a parsed request, failing with a JSON-RPC error code.
+fn rpc_call(request: &serde_json::Value) -> std::result::Result<serde_json::Value, (i64, String)> {
+    let method = request
+        .get("method")
+        .and_then(|m| m.as_str())
+        .ok_or((-32600, "invalid request: no method".to_string()))?;
+    let params = request.get("params").cloned().unwrap_or_default();
+    let param = |key: &str| -> std::result::Result<Option<&str>, (i64, String)> {
+        match params.get(key) {
+            None | Some(serde_json::Value::Null) => Ok(None),
+            Some(v) => v
+                .as_str()
+                .map(Some)
+                .ok_or((-32602, format!("{} must be a string", key))),
+        }
+    };
+    match method {
+        "version" => Ok(serde_json::json!(env!("CARGO_PKG_VERSION"))),
+        "compile" => {
+            let wat = param("wat")?.ok_or((-32602, "missing wat".to_string()))?;
+            let name = param("name")?.unwrap_or("main");
+            let format = match param("format")? {
+                Some(f) => Some(
+                    ArtifactFormat::from_name(f)
+                        .filter(|&f| f != ArtifactFormat::Bin)
+                        .ok_or((-32602, format!("unknown or binary format {:?}", f)))?,
+                ),
+                None => None,
+            };
+            let fail = |e: Error| (-32000, format!("{:#}", e));
+            let options = CompileOptions::new().name(name).fuel(SERVE_FUEL);
+            let info = compile(Source::Wat(wat), &options).map_err(fail)?;
+            let mut result = test_json(&info);
+            if let Some(format) = format {
+                let opts = EmitOptions {
+                    format,
+                    ..EmitOptions::default()
+                };
+                opts.check_fits(&info).map_err(fail)?;
+                let mut artifacts = serde_json::Map::new();
+                for &kind in format.kinds() {
+                    // Skip the empty memory images, as emit_artifacts does.
+                    let emitter = BuiltinEmitter::new(format, kind, opts.clone());
+                    if !emitter.applies(&info) {
+                        continue;
+                    }
+                    let bytes = emitter.emit(&info).map_err(fail)?;
+                    let text = String::from_utf8_lossy(&bytes).into_owned();
+                    artifacts.insert(emitter.extension().to_string(), text.into());
+                }
+                result["artifacts"] = artifacts.into();
+            }
+            Ok(result)
+        }
+        _ => Err((-32601, format!("unknown method {:?}", method))),
+    }
+}
+
+#[cfg(all(test, feature = "refexec"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_handle_rpc() {
+        let call = |request: serde_json::Value| -> serde_json::Value {
+            serde_json::from_str(&handle_rpc(&request.to_string()).unwrap()).unwrap()
+        };
+        let response = call(serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 7,
+            "method": "compile",
+            "params": {
+                "name": "two",
+                "wat": r#"(module (func (export "main") (result i32) i32.const 2))"#,
+                "format": "hex",
+            },
+        }));
+        assert_eq!(response["id"], 7);
+        let result = &response["result"];
+        assert_eq!(result["name"], "two");
+        assert_eq!(result["expected"], 2);
+        assert_eq!(result["expect_trap"], false);
+        assert_eq!(result["prog"][0], 0x41);
+        let artifacts = result["artifacts"].as_object().unwrap();
+        let files: Vec<&str> = artifacts.keys().map(|k| k.as_str()).collect();
+        assert_eq!(files, ["branch.hex", "expected.txt", "prog.hex"]);
+        assert_eq!(artifacts["expected.txt"], "2\n");
+
+        let error = |request: serde_json::Value| call(request)["error"]["code"].clone();
+        assert_eq!(
+            error(
+                serde_json::json!({ "id": 1, "method": "compile", "params": { "wat": "(module" } })
+            ),
+            -32000
+        );
+        assert_eq!(
+            error(serde_json::json!({ "id": 1, "method": "compile" })),
+            -32602
+        );
+        assert_eq!(
+            error(
+                serde_json::json!({ "id": 1, "method": "compile", "params": { "wat": "", "format": "bin" } })
+            ),
+            -32602
+        );
+        assert_eq!(
+            error(serde_json::json!({ "id": 1, "method": "run" })),
+            -32601
+        );
+        let spin = r#"(module (func (export "main") (result i32) (loop (br 0)) i32.const 0))"#;
+        assert_eq!(
+            error(serde_json::json!({ "id": 1, "method": "compile", "params": { "wat": spin } })),
+            -32000
+        );
+        assert_eq!(
+            handle_rpc(r#"{"jsonrpc": "2.0", "method": "version"}"#),
+            None
+        );
+        let garbage: serde_json::Value = serde_json::from_str(&handle_rpc("{").unwrap()).unwrap();
+        assert_eq!(garbage["error"]["code"], -32700);
+        assert_eq!(garbage["id"], serde_json::Value::Null);
+        for line in ["[1, 2]", "7", "\"compile\"", "null"] {
+            let reply: serde_json::Value =
+                serde_json::from_str(&handle_rpc(line).unwrap()).unwrap();
+            assert_eq!(reply["error"]["code"], -32600, "{}", line);
+            assert_eq!(reply["id"], serde_json::Value::Null);
+        }
+        let long: serde_json::Value = serde_json::from_str(&rpc_error(-32600, "too long")).unwrap();
+        assert_eq!(long["error"]["message"], "too long");
+    }
+}
@@ -0,0 +1,3006 @@
+//! Simulation harness for models of the core.
+//!
+//! The crate's own hardware tests and downstream testbenches drive the core
+//! the same way: feed the ROM byte at the program address around every
+//! clock edge, hold reset, write the branch table and data segments, pulse
+//! start, and wait for halt or trap. The functions here do that through the
+//! [`WasmCoreDut`] trait, which
+//! [`impl_wasm_core_dut!`](crate::impl_wasm_core_dut) implements for a
+//! marlin model with the `WasmCoreTb` port names:
+//!
+//! ```ignore
+//! #[veryl(src = "src/wasm_core_tb.veryl", name = "WasmCoreTb")]
+//! pub struct WasmCoreTb;
+//!
+//! wasm_ic::impl_wasm_core_dut!(WasmCoreTb);
+//!
+//! let info = wasm_ic::compile_wat_source("add", source)?;
+//! let mut dut = runtime.create_model::<WasmCoreTb>()?;
+//! let max_cycles = sim::Timeout::default().max_cycles(&info);
+//! sim::run_test(&mut dut, &info, max_cycles).check(&info)?;
+//! ```
+//!
+//! A core variant with other port names implements [`WasmCoreDut`] by hand,
+//! or, if its model reads and writes ports by name ([`NamedSignals`]), is
+//! wrapped in a [`PortMapped`] with the [`PortMap`] describing them.
+//! [`run_test_with_rom`] serves the program from a [`RomModel`] such as a
+//! [`Rom`] with read latency and random wait states instead, and
+//! [`run_test_with`] also takes the [`Loader`] for the load phase: the
+//! dedicated ports ([`PortLoader`]) or a memory-mapped bus ([`BusLoader`]),
+//! such as an AXI-lite slave driven by [`AxiLiteMaster`].
+//! [`run_latency_stress`] repeats a test under seeded random ROM and data
+//! memory latency to shake out handshake bugs, and [`run_control_stress`]
+//! under random reset and start timing, including spurious starts.
+//!
+//! [`run_test_checked`] runs and checks a test while dumping a waveform
+//! as set by [`WaveOptions`], keeping it only when the test fails unless
+//! asked otherwise. Build the marlin model with tracing enabled for the
+//! dump to have any content. [`Traced`] also feeds a [`TraceRecorder`],
+//! which samples chosen outputs every cycle into a trace that can be
+//! saved as JSONL and diffed against another run. A failing run's inputs
+//! can be saved as a [`StimulusLog`] too, replayed onto a fresh model,
+//! and bisected to the first cycle that goes wrong with
+//! [`bisect_replay`].
+
+use crate::emit::{bus_transactions, BusMap};
+use crate::{CompiledProgram, Port, PortMap};
+use anyhow::{anyhow, bail, Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Cycles any test may run after start before it counts as timed out;
+/// [`Timeout`] adds more for each program byte.
+pub const DEFAULT_MAX_CYCLES: u32 = 200;
+
+/// Reset cycles held before releasing `i_rst`.
+pub const RESET_CYCLES: u32 = 4;
+
+/// Samples shown either side of a trace divergence by [`run_test_checked`].
+pub const DIVERGENCE_CONTEXT: usize = 3;
+
+/// The core's state when a run stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SimOutcome {
+    pub halted: bool,
+    pub trapped: bool,
+    pub stack_top: i32,
+    pub pc: u32,
+    /// Cycles from start until halt or trap, or the budget on timeout.
+    pub cycles: u32,
+    /// The first `mem_expected` word the data RAM disagreed with after
+    /// halting; `None` if all matched or the DUT cannot read memory back.
+    pub mem_mismatch: Option<MemMismatch>,
+}
+
+/// A data RAM word read back after a run that differs from the golden
+/// run's.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemMismatch {
+    pub addr: u32,
+    pub expected: u32,
+    pub actual: u32,
+}
+
+impl SimOutcome {
+    /// The final value as in the SV results file: the stack top in
+    /// decimal, `trap`, or `timeout`.
+    pub fn result(&self) -> String {
+        if self.trapped {
+            "trap".to_string()
+        } else if !self.halted {
+            "timeout".to_string()
+        } else {
+            self.stack_top.to_string()
+        }
+    }
+
+    /// Compare against the golden result: the core must halt with the
+    /// expected stack top and data RAM, or trap without halting when the
+    /// golden run trapped, within the test's cycle budget if it has one.
+    pub fn check(&self, info: &CompiledProgram) -> Result<()> {
+        if info.expect_trap {
+            if !self.trapped {
+                return Err(anyhow!("{}: did not trap, pc={}", info.name, self.pc));
+            }
+            if self.halted {
+                return Err(anyhow!("{}: halted, expected trap", info.name));
+            }
+        } else {
+            if self.trapped {
+                return Err(anyhow!("{}: trapped, pc={}", info.name, self.pc));
+            }
+            if !self.halted {
+                return Err(anyhow!("{}: timed out, pc={}", info.name, self.pc));
+            }
+            if self.stack_top != info.expected {
+                return Err(anyhow!(
+                    "{}: got {} expected {}",
+                    info.name,
+                    self.stack_top,
+                    info.expected
+                ));
+            }
+            if let Some(m) = self.mem_mismatch {
+                return Err(anyhow!(
+                    "{}: memory at 0x{:08X} is 0x{:08X}, expected 0x{:08X}",
+                    info.name,
+                    m.addr,
+                    m.actual,
+                    m.expected
+                ));
+            }
+        }
+        if let Some(budget) = info.cycle_budget {
+            if self.cycles > budget {
+                return Err(anyhow!(
+                    "{}: took {} cycles, budget is {}",
+                    info.name,
+                    self.cycles,
+                    budget
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Why [`run_test_checked`] failed a test that ran to completion, with
+/// the state it stopped in for reports.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TestFailure {
+    pub outcome: SimOutcome,
+    pub reason: String,
+}
+
+impl std::fmt::Display for TestFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.reason)
+    }
+}
+
+impl std::error::Error for TestFailure {}
+
+/// The ports the harness drives. Setters only stage values; they reach
+/// the model on the next [`eval`](WasmCoreDut::eval).
+pub trait WasmCoreDut {
+    fn set_clk(&mut self, high: bool);
+    /// `true` holds the core in reset, whatever the port's polarity.
+    fn set_reset(&mut self, asserted: bool);
+    fn set_start(&mut self, start: bool);
+    /// Byte address the core is fetching from.
+    fn prog_addr(&self) -> u32;
+    fn set_prog_data(&mut self, byte: u8);
+    /// Whether the byte on the program data port is valid. Cores that
+    /// assume a combinational ROM have no such port and can only be
+    /// driven by a zero-latency [`Rom`].
+    fn set_prog_valid(&mut self, _valid: bool) {}
+    /// Whether the data memory answers this cycle. Cores whose data RAM
+    /// always answers in one cycle have no such port.
+    fn set_data_ready(&mut self, _ready: bool) {}
+    /// Branch table write port; the entry is written on a clock edge while
+    /// `en` is set.
+    fn set_branch_write(&mut self, en: bool, source_pc: u32, target_pc: u32);
+    /// Data RAM load port, one byte per clock edge while `en` is set.
+    fn set_mem_load(&mut self, en: bool, addr: u32, data: u8);
+    /// The little-endian data RAM word at `addr`, read back after a run
+    /// to check it against `mem_expected`. `None` if the model has no way
+    /// to read its RAM, which skips the check.
+    fn read_mem_word(&mut self, _addr: u32) -> Option<u32> {
+        None
+    }
+    fn halted(&self) -> bool;
+    fn trapped(&self) -> bool;
+    fn stack_top(&self) -> i32;
+    fn pc(&self) -> u32;
+    fn eval(&mut self);
+    /// Start a waveform dump to `path`, returning a function that dumps
+    /// the current signal values at a timestamp. `None` if the model
+    /// cannot trace.
+    fn open_wave<'w>(&mut self, _path: &Path) -> Option<Box<dyn FnMut(u64) + 'w>>
+    where
+        Self: 'w,
+    {
+        None
+    }
+}
+
+/// Program memory as seen from the core's fetch port.
+pub trait RomModel {
+    /// The byte at `addr`, or `None` while the read is still in flight.
+    /// Called several times per cycle as the address settles.
+    fn read(&mut self, addr: u32) -> Option<u8>;
+    /// Advance one clock cycle.
+    fn clock(&mut self);
+    /// Whether the data memory answers in the coming cycle; the default
+    /// never stalls.
+    fn data_ready(&mut self) -> bool {
+        true
+    }
+}
+
+/// Read timing of a [`Rom`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct RomTiming {
+    /// Cycles from a new address until its data is valid; 0 is a
+    /// combinational read.
+    pub latency: u32,
+    /// Up to this many extra cycles are added to each read at random.
+    pub max_wait_states: u32,
+    /// Chance in percent that the data memory stalls in a given cycle,
+    /// for cores with a [`set_data_ready`](WasmCoreDut::set_data_ready)
+    /// port.
+    pub data_stall_percent: u32,
+    /// Seed for the wait states and stalls, so a failing run can be
+    /// replayed.
+    pub seed: u64,
+}
+
+/// A [`RomModel`] over the program bytes; addresses past the end read 0.
+/// It also stalls the data memory as its timing asks.
+#[derive(Debug, Clone)]
+pub struct Rom {
+    data: Vec<u8>,
+    timing: RomTiming,
+    rng: u64,
+    /// Address being read and the cycles until its data is valid.
+    pending: Option<(u32, u32)>,
+}
+
+impl Rom {
+    pub fn new(data: &[u8], timing: RomTiming) -> Self {
+        Rom {
+            data: data.to_vec(),
+            timing,
+            // xorshift gets stuck at zero.
+            rng: timing.seed | 1,
+            pending: None,
+        }
+    }
+
+    fn random(&mut self) -> u64 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        self.rng
+    }
+
+    fn wait_states(&mut self) -> u32 {
+        if self.timing.max_wait_states == 0 {
+            return 0;
+        }
+        (self.random() % (self.timing.max_wait_states as u64 + 1)) as u32
+    }
+}
+
+impl RomModel for Rom {
+    fn read(&mut self, addr: u32) -> Option<u8> {
+        let left = match self.pending {
+            Some((a, left)) if a == addr => left,
+            _ => {
+                let left = self.timing.latency + self.wait_states();
+                self.pending = Some((addr, left));
+                left
+            }
+        };
+        (left == 0).then(|| self.data.get(addr as usize).copied().unwrap_or(0))
+    }
+
+    fn clock(&mut self) {
+        if let Some((_, left)) = self.pending.as_mut() {
+            *left = left.saturating_sub(1);
+        }
+    }
+
+    fn data_ready(&mut self) -> bool {
+        self.timing.data_stall_percent == 0
+            || self.random() % 100 >= self.timing.data_stall_percent as u64
+    }
+}
+
+/// Drive the fetch port from `rom` for the address the core requests.
+/// While a read is in flight the previous byte stays on the port.
+fn drive_rom<D: WasmCoreDut, R: RomModel>(dut: &mut D, rom: &mut R) {
+    match rom.read(dut.prog_addr()) {
+        Some(byte) => {
+            dut.set_prog_data(byte);
+            dut.set_prog_valid(true);
+        }
+        None => dut.set_prog_valid(false),
+    }
+}
+
+/// One clock cycle, with the fetch port driven before and after each edge
+/// since the address can change combinationally.
+pub fn tick<D: WasmCoreDut, R: RomModel>(dut: &mut D, rom: &mut R) {
+    dut.set_data_ready(rom.data_ready());
+    drive_rom(dut, rom);
+    dut.set_clk(false);
+    dut.eval();
+    drive_rom(dut, rom);
+    dut.set_clk(true);
+    dut.eval();
+    rom.clock();
+    drive_rom(dut, rom);
+    dut.eval();
+}
+
+/// Hold reset with every load port idle, then release it.
+pub fn reset<D: WasmCoreDut, R: RomModel>(dut: &mut D, rom: &mut R) {
+    reset_for(dut, rom, RESET_CYCLES);
+}
+
+fn reset_for<D: WasmCoreDut, R: RomModel>(dut: &mut D, rom: &mut R, cycles: u32) {
+    dut.set_reset(true);
+    dut.set_start(false);
+    dut.set_branch_write(false, 0, 0);
+    dut.set_mem_load(false, 0, 0);
+    for _ in 0..cycles {
+        tick(dut, rom);
+    }
+    dut.set_reset(false);
+    tick(dut, rom);
+}
+
+/// Write the branch table and data segments, one per cycle.
+pub fn load<D: WasmCoreDut, R: RomModel>(dut: &mut D, rom: &mut R, info: &CompiledProgram) {
+    for entry in &info.branch_table {
+        dut.set_branch_write(true, entry.source_pc, entry.target_pc);
+        tick(dut, rom);
+    }
+    dut.set_branch_write(false, 0, 0);
+    for seg in &info.mem_init {
+        for (i, &b) in seg.data.iter().enumerate() {
+            dut.set_mem_load(true, seg.addr + i as u32, b);
+            tick(dut, rom);
+        }
+    }
+    dut.set_mem_load(false, 0, 0);
+}
+
+/// How a test's program, branch table, and data segments get into the
+/// core between reset and start.
+pub trait Loader<D: WasmCoreDut> {
+    fn load<R: RomModel>(&mut self, dut: &mut D, rom: &mut R, info: &CompiledProgram)
+        -> Result<()>;
+}
+
+/// Loads through the dedicated `i_bt_wr_*` and `i_mem_load_*` ports, as
+/// [`load`] does; the program stays in the external ROM.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PortLoader;
+
+impl<D: WasmCoreDut> Loader<D> for PortLoader {
+    fn load<R: RomModel>(
+        &mut self,
+        dut: &mut D,
+        rom: &mut R,
+        info: &CompiledProgram,
+    ) -> Result<()> {
+        load(dut, rom, info);
+        Ok(())
+    }
+}
+
+/// Issues writes on a memory-mapped bus, advancing the clock as the
+/// protocol needs.
+pub trait BusMaster<D: WasmCoreDut> {
+    fn write<R: RomModel>(&mut self, dut: &mut D, rom: &mut R, addr: u32, data: u32) -> Result<()>;
+}
+
+/// A single-cycle write strobe: address and data with an enable, one write
+/// per clock edge and no response.
+pub trait BusPort {
+    fn set_bus_write(&mut self, en: bool, addr: u32, data: u32);
+}
+
+/// [`BusMaster`] for a core with a [`BusPort`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StrobeBus;
+
+impl<D: WasmCoreDut + BusPort> BusMaster<D> for StrobeBus {
+    fn write<R: RomModel>(&mut self, dut: &mut D, rom: &mut R, addr: u32, data: u32) -> Result<()> {
+        dut.set_bus_write(true, addr, data);
+        tick(dut, rom);
+        dut.set_bus_write(false, 0, 0);
+        Ok(())
+    }
+}
+
+/// Loads the program, branch table, and data segments through `bus`
+/// according to `map`, issuing the same writes, in the same order, as the
+/// bus.txt artifact ([`bus_transactions`]). The map's `start_addr` is
+/// left out: [`run_test_with`] starts the core itself.
+#[derive(Debug, Clone, Default)]
+pub struct BusLoader<B> {
+    pub map: BusMap,
+    pub bus: B,
+}
+
+impl<D: WasmCoreDut, B: BusMaster<D>> Loader<D> for BusLoader<B> {
+    fn load<R: RomModel>(
+        &mut self,
+        dut: &mut D,
+        rom: &mut R,
+        info: &CompiledProgram,
+    ) -> Result<()> {
+        let map = BusMap {
+            start_addr: None,
+            ..self.map
+        };
+        for w in bus_transactions(info, &map)? {
+            self.bus
+                .write(dut, rom, w.addr, w.data)
+                .with_context(|| format!("writing {:#010X} to {:#010X}", w.data, w.addr))?;
+        }
+        Ok(())
+    }
+}
+
+/// Reset, load, start, and run until halt, trap, or `max_cycles`, reading
+/// the program from a combinational ROM.
+pub fn run_test<D: WasmCoreDut>(
+    dut: &mut D,
+    info: &CompiledProgram,
+    max_cycles: u32,
+) -> SimOutcome {
+    let mut rom = Rom::new(&info.body_bytes, RomTiming::default());
+    run_test_with_rom(dut, &mut rom, info, max_cycles)
+}
+
+/// [`run_test`] with the program served by `rom`, to exercise the fetch
+/// handshake with slow or irregular program memory.
+pub fn run_test_with_rom<D: WasmCoreDut, R: RomModel>(
+    dut: &mut D,
+    rom: &mut R,
+    info: &CompiledProgram,
+    max_cycles: u32,
+) -> SimOutcome {
+    reset(dut, rom);
+    load(dut, rom, info);
+    start_and_run(dut, rom, info, max_cycles)
+}
+
+/// [`run_test_with_rom`] loading through `loader`, so the same test can
+/// check each of the core's load paths.
+pub fn run_test_with<D: WasmCoreDut, R: RomModel, L: Loader<D>>(
+    dut: &mut D,
+    rom: &mut R,
+    loader: &mut L,
+    info: &CompiledProgram,
+    max_cycles: u32,
+) -> Result<SimOutcome> {
+    reset(dut, rom);
+    loader.load(dut, rom, info)?;
+    Ok(start_and_run(dut, rom, info, max_cycles))
+}
+
+/// How long a run may take before it counts as timed out, scaled with
+/// the program so larger programs aren't cut off: `base_cycles +
+/// cycles_per_byte * len`, like
+/// [`SvhOptions::cycle_budget`](crate::SvhOptions::cycle_budget) but with
+/// more slack, since this only catches hangs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timeout {
+    pub base_cycles: u32,
+    pub cycles_per_byte: u32,
+}
+
+impl Default for Timeout {
+    fn default() -> Self {
+        Timeout {
+            base_cycles: DEFAULT_MAX_CYCLES,
+            cycles_per_byte: 16,
+        }
+    }
+}
+
+impl Timeout {
+    /// The `max_cycles` for a test on a combinational ROM. A declared
+    /// cycle budget is always overrun by at least one cycle, so overruns
+    /// report their count instead of timing out.
+    pub fn max_cycles(&self, info: &CompiledProgram) -> u32 {
+        self.max_cycles_with(info, &RomTiming::default())
+    }
+
+    /// [`max_cycles`](Self::max_cycles) under `timing`: every byte may
+    /// wait out the full latency and wait states, and data memory stalls
+    /// stretch the whole run.
+    pub fn max_cycles_with(&self, info: &CompiledProgram, timing: &RomTiming) -> u32 {
+        let per_byte = self
+            .cycles_per_byte
+            .saturating_mul(1 + timing.latency + timing.max_wait_states);
+        let cycles = self
+            .base_cycles
+            .saturating_add(per_byte.saturating_mul(info.body_bytes.len() as u32));
+        // A stall rate near 100% would never finish; cap the slowdown at 10x.
+        let ready = 100 - timing.data_stall_percent.min(90);
+        let cycles = (cycles as u64 * 100).div_ceil(ready as u64);
+        let cycles = u32::try_from(cycles).unwrap_or(u32::MAX);
+        cycles.max(info.cycle_budget.map_or(0, |b| b.saturating_add(1)))
+    }
+}
+
+/// Seeded latency stress: each run draws its own ROM latency, wait
+/// states, and data memory stall rate, up to the maxima here, from
+/// `seed` and the run number.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LatencyStress {
+    pub seed: u64,
+    pub runs: u32,
+    pub max_latency: u32,
+    pub max_wait_states: u32,
+    pub max_data_stall_percent: u32,
+}
+
+impl Default for LatencyStress {
+    fn default() -> Self {
+        LatencyStress {
+            seed: 1,
+            runs: 16,
+            max_latency: 3,
+            max_wait_states: 4,
+            max_data_stall_percent: 50,
+        }
+    }
+}
+
+/// splitmix64 over a seed and run number, so neighbouring runs get
+/// unrelated timings.
+struct RunRng(u64);
+
+impl RunRng {
+    fn new(seed: u64, run: u32) -> Self {
+        RunRng(seed ^ (run as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15))
+    }
+
+    /// A value in `0..=bound`.
+    fn next(&mut self, bound: u32) -> u32 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+        (z % (bound as u64 + 1)) as u32
+    }
+}
+
+impl LatencyStress {
+    /// The timing of run `run`; the same seed always gives the same runs.
+    pub fn timing(&self, run: u32) -> RomTiming {
+        let mut rng = RunRng::new(self.seed, run);
+        RomTiming {
+            latency: rng.next(self.max_latency),
+            max_wait_states: rng.next(self.max_wait_states),
+            data_stall_percent: rng.next(self.max_data_stall_percent),
+            seed: rng.next(u32::MAX) as u64,
+        }
+    }
+}
+
+/// Run a test `stress.runs` times under randomized memory latency,
+/// stopping at the first failing run with its timing in the error so it
+/// can be replayed through [`run_test_with`]. Cycle budgets are not
+/// checked, since stalls make any run slower; each run gets `timeout`
+/// scaled to its timing instead.
+pub fn run_latency_stress<D: WasmCoreDut, L: Loader<D>>(
+    dut: &mut D,
+    loader: &mut L,
+    info: &CompiledProgram,
+    timeout: &Timeout,
+    stress: &LatencyStress,
+) -> Result<Vec<SimOutcome>> {
+    let info = CompiledProgram {
+        cycle_budget: None,
+        ..info.clone()
+    };
+    let mut outcomes = Vec::new();
+    for run in 0..stress.runs {
+        let timing = stress.timing(run);
+        let mut rom = Rom::new(&info.body_bytes, timing);
+        let max_cycles = timeout.max_cycles_with(&info, &timing);
+        let outcome = run_test_with(dut, &mut rom, loader, &info, max_cycles)
+            .and_then(|outcome| outcome.check(&info).map(|()| outcome))
+            .with_context(|| format!("stress run {} with {:?}", run, timing))?;
+        outcomes.push(outcome);
+    }
+    Ok(outcomes)
+}
+
+/// How the harness sequences reset and start.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ControlTiming {
+    /// Cycles reset is held; at least 1.
+    pub reset_cycles: u32,
+    /// Idle cycles between loading and the start pulse.
+    pub start_delay: u32,
+    /// Cycles the start pulse is held; at least 1.
+    pub start_width: u32,
+    /// Cycles after the start pulse began at which start is pulsed again
+    /// for one cycle while the program runs; the core must ignore them.
+    pub spurious_starts: Vec<u32>,
+}
+
+impl Default for ControlTiming {
+    fn default() -> Self {
+        ControlTiming {
+            reset_cycles: RESET_CYCLES,
+            start_delay: 0,
+            start_width: 1,
+            spurious_starts: Vec::new(),
+        }
+    }
+}
+
+/// Seeded reset and start stress: each run draws a [`ControlTiming`] up
+/// to the maxima here from `seed` and the run number.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ControlStress {
+    pub seed: u64,
+    pub runs: u32,
+    pub max_reset_cycles: u32,
+    pub max_start_delay: u32,
+    pub max_start_width: u32,
+    pub max_spurious_starts: u32,
+}
+
+impl Default for ControlStress {
+    fn default() -> Self {
+        ControlStress {
+            seed: 1,
+            runs: 16,
+            max_reset_cycles: 8,
+            max_start_delay: 8,
+            max_start_width: 3,
+            max_spurious_starts: 3,
+        }
+    }
+}
+
+impl ControlStress {
+    /// The timing of run `run`; spurious starts fall within 16 cycles
+    /// after the start pulse.
+    pub fn timing(&self, run: u32) -> ControlTiming {
+        let mut rng = RunRng::new(self.seed, run);
+        let start_width = 1 + rng.next(self.max_start_width.saturating_sub(1));
+        let mut spurious_starts: Vec<u32> = (0..rng.next(self.max_spurious_starts))
+            .map(|_| start_width + rng.next(15))
+            .collect();
+        spurious_starts.sort_unstable();
+        spurious_starts.dedup();
+        ControlTiming {
+            reset_cycles: 1 + rng.next(self.max_reset_cycles.saturating_sub(1)),
+            start_delay: rng.next(self.max_start_delay),
+            start_width,
+            spurious_starts,
+        }
+    }
+}
+
+/// [`run_test_with`] with reset and start sequenced by `timing`. Cycles
+/// are counted from the first cycle of the start pulse, so they match
+/// [`run_test_with`] whatever the timing.
+pub fn run_test_with_control<D: WasmCoreDut, R: RomModel, L: Loader<D>>(
+    dut: &mut D,
+    rom: &mut R,
+    loader: &mut L,
+    info: &CompiledProgram,
+    max_cycles: u32,
+    timing: &ControlTiming,
+) -> Result<SimOutcome> {
+    reset_for(dut, rom, timing.reset_cycles);
+    loader.load(dut, rom, info)?;
+    for _ in 0..timing.start_delay {
+        tick(dut, rom);
+    }
+    Ok(start_and_run_with(
+        dut,
+        rom,
+        info,
+        max_cycles,
+        timing.start_width,
+        &timing.spurious_starts,
+    ))
+}
+
+/// Run a test with the default control timing, then `stress.runs` more
+/// times with randomized reset and start timing, failing unless every
+/// run ends in exactly the same state after the same number of cycles.
+/// Returns the baseline outcome, which must also pass.
+pub fn run_control_stress<D: WasmCoreDut, L: Loader<D>>(
+    dut: &mut D,
+    loader: &mut L,
+    info: &CompiledProgram,
+    max_cycles: u32,
+    stress: &ControlStress,
+) -> Result<SimOutcome> {
+    let mut run = |timing: &ControlTiming| {
+        let mut rom = Rom::new(&info.body_bytes, RomTiming::default());
+        run_test_with_control(dut, &mut rom, loader, info, max_cycles, timing)
+    };
+    let baseline = run(&ControlTiming::default())?;
+    baseline.check(info)?;
+    for n in 0..stress.runs {
+        let timing = stress.timing(n);
+        let outcome = run(&timing).with_context(|| format!("stress run {}", n))?;
+        if outcome != baseline {
+            return Err(anyhow!(
+                "{}: stress run {} with {:?} ended with {:?}, expected {:?}",
+                info.name,
+                n,
+                timing,
+                outcome,
+                baseline
+            ));
+        }
+    }
+    Ok(baseline)
+}
+
+/// Waveform dumping for [`run_test_checked`].
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct WaveOptions {
+    /// Where to write the VCD; `None` dumps nothing.
+    pub path: Option<PathBuf>,
+    /// First clock cycle to dump, counted from the start of reset.
+    pub start_cycle: u64,
+    /// Cycle at which dumping stops; `None` dumps to the end of the run.
+    pub stop_cycle: Option<u64>,
+    /// Keep the waveform of a passing test too; by default it is
+    /// deleted and only failing tests leave one behind.
+    pub keep_passing: bool,
+    /// Where to save the stimulus of a failing run; `None` saves nothing.
+    pub stimulus: Option<PathBuf>,
+}
+
+impl WaveOptions {
+    /// Dump the whole run of a test to `<dir>/<name>.vcd`, saving its
+    /// stimulus to `<dir>/<name>.stim` if it fails.
+    pub fn in_dir(dir: impl AsRef<Path>, name: &str) -> Self {
+        WaveOptions {
+            path: Some(dir.as_ref().join(format!("{}.vcd", name))),
+            stimulus: Some(dir.as_ref().join(format!("{}.stim", name))),
+            ..Default::default()
+        }
+    }
+
+    fn in_window(&self, cycle: u64) -> bool {
+        cycle >= self.start_cycle && self.stop_cycle.is_none_or(|stop| cycle < stop)
+    }
+}
+
+/// A DUT output the [`TraceRecorder`] can sample.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Signal {
+    Pc,
+    StackTop,
+    Halted,
+    Trap,
+}
+
+impl Signal {
+    pub const ALL: [Signal; 4] = [Signal::Pc, Signal::StackTop, Signal::Halted, Signal::Trap];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Signal::Pc => "pc",
+            Signal::StackTop => "stack_top",
+            Signal::Halted => "halted",
+            Signal::Trap => "trap",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Result<Signal> {
+        Signal::ALL
+            .into_iter()
+            .find(|s| s.name() == name)
+            .ok_or_else(|| anyhow!("unknown signal {:?}", name))
+    }
+
+    fn is_bool(self) -> bool {
+        matches!(self, Signal::Halted | Signal::Trap)
+    }
+
+    fn sample<D: WasmCoreDut>(self, dut: &D) -> i64 {
+        match self {
+            Signal::Pc => dut.pc() as i64,
+            Signal::StackTop => dut.stack_top() as i64,
+            Signal::Halted => dut.halted() as i64,
+            Signal::Trap => dut.trapped() as i64,
+        }
+    }
+}
+
+/// The recorded signals at one clock cycle, in the recorder's signal
+/// order; flags are 0 or 1.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceSample {
+    pub cycle: u64,
+    pub values: Vec<i64>,
+}
+
+/// Where two traces first disagree. A `None` value means that trace
+/// ended before `cycle`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceDiff {
+    /// Sample index in both traces.
+    pub index: usize,
+    pub cycle: u64,
+    pub signal: Signal,
+    pub left: Option<i64>,
+    pub right: Option<i64>,
+}
+
+/// Samples a set of DUT outputs once per clock cycle, just after the
+/// rising edge, while attached to a [`Traced`] DUT. Cycles are counted
+/// from the start of reset, like the [`WaveOptions`] window.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceRecorder {
+    signals: Vec<Signal>,
+    samples: Vec<TraceSample>,
+}
+
+impl Default for TraceRecorder {
+    fn default() -> Self {
+        TraceRecorder::new(&Signal::ALL)
+    }
+}
+
+impl TraceRecorder {
+    pub fn new(signals: &[Signal]) -> Self {
+        TraceRecorder {
+            signals: signals.to_vec(),
+            samples: Vec::new(),
+        }
+    }
+
+    pub fn signals(&self) -> &[Signal] {
+        &self.signals
+    }
+
+    pub fn samples(&self) -> &[TraceSample] {
+        &self.samples
+    }
+
+    fn record<D: WasmCoreDut>(&mut self, cycle: u64, dut: &D) {
+        let values = self.signals.iter().map(|s| s.sample(dut)).collect();
+        self.samples.push(TraceSample { cycle, values });
+    }
+
+    /// One JSON object per sample, e.g.
+    /// `{"cycle":7,"halted":false,"pc":3,"stack_top":2,"trap":false}`.
+    pub fn to_jsonl(&self) -> String {
+        let mut out = String::new();
+        for sample in &self.samples {
+            let mut obj = serde_json::Map::new();
+            obj.insert("cycle".into(), sample.cycle.into());
+            for (&signal, &v) in self.signals.iter().zip(&sample.values) {
+                let value = if signal.is_bool() {
+                    (v != 0).into()
+                } else {
+                    v.into()
+                };
+                obj.insert(signal.name().into(), value);
+            }
+            out.push_str(&serde_json::Value::Object(obj).to_string());
+            out.push('\n');
+        }
+        out
+    }
+
+    pub fn write_jsonl(&self, path: &Path) -> Result<()> {
+        fs::write(path, self.to_jsonl()).with_context(|| format!("writing {}", path.display()))
+    }
+
+    /// Read back a trace written by [`to_jsonl`](Self::to_jsonl). The
+    /// signals are those of the first line, in [`Signal::ALL`] order.
+    pub fn parse_jsonl(text: &str) -> Result<Self> {
+        let mut recorder = TraceRecorder::new(&[]);
+        for (i, line) in text
+            .lines()
+            .enumerate()
+            .filter(|(_, l)| !l.trim().is_empty())
+        {
+            let context = || format!("line {}", i + 1);
+            let value: serde_json::Value = serde_json::from_str(line).with_context(context)?;
+            let obj = value
+                .as_object()
+                .ok_or_else(|| anyhow!("expected an object"))
+                .with_context(context)?;
+            if recorder.samples.is_empty() {
+                for key in obj.keys().filter(|k| *k != "cycle") {
+                    Signal::from_name(key).with_context(context)?;
+                }
+                recorder.signals = Signal::ALL
+                    .into_iter()
+                    .filter(|s| obj.contains_key(s.name()))
+                    .collect();
+            }
+            let field = |key: &str| {
+                match obj.get(key) {
+                    Some(serde_json::Value::Bool(b)) => Some(*b as i64),
+                    Some(v) => v.as_i64(),
+                    None => None,
+                }
+                .ok_or_else(|| anyhow!("missing or invalid {:?}", key))
+                .with_context(context)
+            };
+            let cycle = field("cycle")? as u64;
+            let values = recorder
+                .signals
+                .iter()
+                .map(|s| field(s.name()))
+                .collect::<Result<_>>()?;
+            recorder.samples.push(TraceSample { cycle, values });
+        }
+        Ok(recorder)
+    }
+
+    /// Signals recorded by both traces, with their index in each.
+    fn shared_signals(&self, other: &TraceRecorder) -> Vec<(Signal, usize, usize)> {
+        self.signals
+            .iter()
+            .enumerate()
+            .filter_map(|(i, s)| Some((*s, i, other.signals.iter().position(|o| o == s)?)))
+            .collect()
+    }
+
+    /// The first cycle at which a signal recorded by both traces differs,
+    /// or at which one trace has ended and the other has not.
+    pub fn first_difference(&self, other: &TraceRecorder) -> Option<TraceDiff> {
+        let shared = self.shared_signals(other);
+        let &(first, fi, fj) = shared.first()?;
+        let len = self.samples.len().max(other.samples.len());
+        for n in 0..len {
+            let (a, b) = (self.samples.get(n), other.samples.get(n));
+            if let (Some(a), Some(b)) = (a, b) {
+                for &(signal, i, j) in &shared {
+                    if a.values[i] != b.values[j] {
+                        return Some(TraceDiff {
+                            index: n,
+                            cycle: a.cycle,
+                            signal,
+                            left: Some(a.values[i]),
+                            right: Some(b.values[j]),
+                        });
+                    }
+                }
+            } else {
+                return Some(TraceDiff {
+                    index: n,
+                    cycle: a.or(b)?.cycle,
+                    signal: first,
+                    left: a.map(|a| a.values[fi]),
+                    right: b.map(|b| b.values[fj]),
+                });
+            }
+        }
+        None
+    }
+
+    /// Compare this trace of the DUT against a `golden` one and report the
+    /// first divergence with `context` samples either side of it.
+    pub fn divergence(&self, golden: &TraceRecorder, context: usize) -> Option<Divergence> {
+        let diff = self.first_difference(golden)?;
+        let shared = self.shared_signals(golden);
+        let len = self.samples.len().max(golden.samples.len());
+        let rows: Vec<DivergenceRow> = (diff.index.saturating_sub(context)
+            ..(diff.index + context + 1).min(len))
+            .map(|n| {
+                let (a, b) = (self.samples.get(n), golden.samples.get(n));
+                DivergenceRow {
+                    index: n,
+                    cycle: a.or(b).map_or(0, |s| s.cycle),
+                    values: shared
+                        .iter()
+                        .map(|&(signal, i, j)| {
+                            (signal, a.map(|a| a.values[i]), b.map(|b| b.values[j]))
+                        })
+                        .collect(),
+                }
+            })
+            .collect();
+        let pc = rows
+            .iter()
+            .find(|r| r.index == diff.index)
+            .and_then(|r| r.values.iter().find(|v| v.0 == Signal::Pc))
+            .and_then(|&(_, actual, expected)| actual.or(expected));
+        Some(Divergence { diff, pc, rows })
+    }
+}
+
+/// One sample of a [`Divergence`] report: each shared signal's actual
+/// and expected value, `None` where that trace has ended.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DivergenceRow {
+    pub index: usize,
+    pub cycle: u64,
+    pub values: Vec<(Signal, Option<i64>, Option<i64>)>,
+}
+
+/// Where a DUT trace first leaves the golden trace, from
+/// [`TraceRecorder::divergence`]. `diff.left` is the DUT's value and
+/// `diff.right` the golden one.
+///
+/// Displays as a summary line, then one line per sample with
+/// `expected/actual` wherever the traces disagree and `>` marking the
+/// divergence.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    pub diff: TraceDiff,
+    /// The PC at the divergence, if either trace recorded it.
+    pub pc: Option<i64>,
+    pub rows: Vec<DivergenceRow>,
+}
+
+impl std::fmt::Display for Divergence {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let value = |v: Option<i64>| v.map_or("-".to_string(), |v| v.to_string());
+        let d = &self.diff;
+        write!(f, "first divergence at cycle {}", d.cycle)?;
+        if let Some(pc) = self.pc {
+            write!(f, ", pc {}", pc)?;
+        }
+        match (d.left, d.right) {
+            (Some(_), None) => write!(f, ": golden trace ended")?,
+            (None, Some(_)) => write!(f, ": DUT trace ended")?,
+            _ => write!(
+                f,
+                ": {} expected {}, got {}",
+                d.signal.name(),
+                value(d.right),
+                value(d.left)
+            )?,
+        }
+        for row in &self.rows {
+            let marker = if row.index == d.index { '>' } else { ' ' };
+            write!(f, "\n{} {:>8}", marker, row.cycle)?;
+            for &(signal, actual, expected) in &row.values {
+                if actual == expected {
+                    write!(f, "  {}={}", signal.name(), value(actual))?;
+                } else {
+                    write!(
+                        f,
+                        "  {}={}/{}",
+                        signal.name(),
+                        value(expected),
+                        value(actual)
+                    )?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A [`WasmCoreDut`] that dumps the wrapped model's signals after every
+/// eval inside the [`WaveOptions`] cycle window, and feeds a
+/// [`TraceRecorder`] if one is attached.
+pub struct Traced<'a, D> {
+    dut: &'a mut D,
+    wave: Option<Box<dyn FnMut(u64) + 'a>>,
+    opts: &'a WaveOptions,
+    recorder: Option<&'a mut TraceRecorder>,
+    cycle: u64,
+    /// Last cycle handed to the recorder.
+    recorded: u64,
+    time: u64,
+}
+
+impl<'a, D: WasmCoreDut> Traced<'a, D> {
+    /// Wrap `dut`, opening the waveform if `opts` has a path.
+    pub fn new(dut: &'a mut D, opts: &'a WaveOptions) -> Result<Self> {
+        let wave = match &opts.path {
+            Some(path) => {
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent)
+                        .with_context(|| format!("creating {}", parent.display()))?;
+                }
+                dut.open_wave(path)
+            }
+            None => None,
+        };
+        Ok(Traced {
+            dut,
+            wave,
+            opts,
+            recorder: None,
+            cycle: 0,
+            recorded: 0,
+            time: 0,
+        })
+    }
+
+    /// Also sample the DUT into `recorder` every cycle.
+    pub fn with_recorder(mut self, recorder: &'a mut TraceRecorder) -> Self {
+        self.recorder = Some(recorder);
+        self
+    }
+}
+
+impl<D: WasmCoreDut> WasmCoreDut for Traced<'_, D> {
+    fn set_clk(&mut self, high: bool) {
+        self.cycle += high as u64;
+        self.dut.set_clk(high);
+    }
+    fn set_reset(&mut self, asserted: bool) {
+        self.dut.set_reset(asserted);
+    }
+    fn set_start(&mut self, start: bool) {
+        self.dut.set_start(start);
+    }
+    fn prog_addr(&self) -> u32 {
+        self.dut.prog_addr()
+    }
+    fn set_prog_data(&mut self, byte: u8) {
+        self.dut.set_prog_data(byte);
+    }
+    fn set_prog_valid(&mut self, valid: bool) {
+        self.dut.set_prog_valid(valid);
+    }
+    fn set_data_ready(&mut self, ready: bool) {
+        self.dut.set_data_ready(ready);
+    }
+    fn set_branch_write(&mut self, en: bool, source_pc: u32, target_pc: u32) {
+        self.dut.set_branch_write(en, source_pc, target_pc);
+    }
+    fn set_mem_load(&mut self, en: bool, addr: u32, data: u8) {
+        self.dut.set_mem_load(en, addr, data);
+    }
+    fn read_mem_word(&mut self, addr: u32) -> Option<u32> {
+        self.dut.read_mem_word(addr)
+    }
+    fn halted(&self) -> bool {
+        self.dut.halted()
+    }
+    fn trapped(&self) -> bool {
+        self.dut.trapped()
+    }
+    fn stack_top(&self) -> i32 {
+        self.dut.stack_top()
+    }
+    fn pc(&self) -> u32 {
+        self.dut.pc()
+    }
+    fn eval(&mut self) {
+        self.dut.eval();
+        self.time += 1;
+        if let Some(dump) = self.wave.as_mut() {
+            if self.opts.in_window(self.cycle) {
+                dump(self.time);
+            }
+        }
+        if self.cycle > self.recorded {
+            self.recorded = self.cycle;
+            if let Some(recorder) = self.recorder.as_mut() {
+                recorder.record(self.cycle, &*self.dut);
+            }
+        }
+    }
+}
+
+impl<D: BusPort> BusPort for Traced<'_, D> {
+    fn set_bus_write(&mut self, en: bool, addr: u32, data: u32) {
+        self.dut.set_bus_write(en, addr, data);
+    }
+}
+
+impl<D: AxiLitePort> AxiLitePort for Traced<'_, D> {
+    fn set_aw(&mut self, valid: bool, addr: u32) {
+        self.dut.set_aw(valid, addr);
+    }
+    fn aw_ready(&self) -> bool {
+        self.dut.aw_ready()
+    }
+    fn set_w(&mut self, valid: bool, data: u32, strb: u8) {
+        self.dut.set_w(valid, data, strb);
+    }
+    fn w_ready(&self) -> bool {
+        self.dut.w_ready()
+    }
+    fn set_b_ready(&mut self, ready: bool) {
+        self.dut.set_b_ready(ready);
+    }
+    fn b_valid(&self) -> bool {
+        self.dut.b_valid()
+    }
+    fn b_resp(&self) -> u8 {
+        self.dut.b_resp()
+    }
+    fn set_ar(&mut self, valid: bool, addr: u32) {
+        self.dut.set_ar(valid, addr);
+    }
+    fn ar_ready(&self) -> bool {
+        self.dut.ar_ready()
+    }
+    fn set_r_ready(&mut self, ready: bool) {
+        self.dut.set_r_ready(ready);
+    }
+    fn r_valid(&self) -> bool {
+        self.dut.r_valid()
+    }
+    fn r_data(&self) -> u32 {
+        self.dut.r_data()
+    }
+    fn r_resp(&self) -> u8 {
+        self.dut.r_resp()
+    }
+}
+
+/// One input change the harness made to the DUT.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stimulus {
+    Clk(bool),
+    Reset(bool),
+    Start(bool),
+    ProgData(u8),
+    ProgValid(bool),
+    DataReady(bool),
+    BranchWrite {
+        en: bool,
+        source_pc: u32,
+        target_pc: u32,
+    },
+    MemLoad {
+        en: bool,
+        addr: u32,
+        data: u8,
+    },
+    BusWrite {
+        en: bool,
+        addr: u32,
+        data: u32,
+    },
+    Aw {
+        valid: bool,
+        addr: u32,
+    },
+    W {
+        valid: bool,
+        data: u32,
+        strb: u8,
+    },
+    BReady(bool),
+    Ar {
+        valid: bool,
+        addr: u32,
+    },
+    RReady(bool),
+    Eval,
+}
+
+impl std::fmt::Display for Stimulus {
+    /// One line of a stimulus file: the port, then its values in decimal,
+    /// with `0`/`1` for booleans.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let b = |v: bool| v as u8;
+        match *self {
+            Stimulus::Clk(v) => write!(f, "clk {}", b(v)),
+            Stimulus::Reset(v) => write!(f, "rst {}", b(v)),
+            Stimulus::Start(v) => write!(f, "start {}", b(v)),
+            Stimulus::ProgData(v) => write!(f, "prog_data {}", v),
+            Stimulus::ProgValid(v) => write!(f, "prog_valid {}", b(v)),
+            Stimulus::DataReady(v) => write!(f, "data_ready {}", b(v)),
+            Stimulus::BranchWrite {
+                en,
+                source_pc,
+                target_pc,
+            } => write!(f, "branch_write {} {} {}", b(en), source_pc, target_pc),
+            Stimulus::MemLoad { en, addr, data } => {
+                write!(f, "mem_load {} {} {}", b(en), addr, data)
+            }
+            Stimulus::BusWrite { en, addr, data } => {
+                write!(f, "bus_write {} {} {}", b(en), addr, data)
+            }
+            Stimulus::Aw { valid, addr } => write!(f, "aw {} {}", b(valid), addr),
+            Stimulus::W { valid, data, strb } => write!(f, "w {} {} {}", b(valid), data, strb),
+            Stimulus::BReady(v) => write!(f, "b_ready {}", b(v)),
+            Stimulus::Ar { valid, addr } => write!(f, "ar {} {}", b(valid), addr),
+            Stimulus::RReady(v) => write!(f, "r_ready {}", b(v)),
+            Stimulus::Eval => write!(f, "eval"),
+        }
+    }
+}
+
+impl std::str::FromStr for Stimulus {
+    type Err = anyhow::Error;
+
+    fn from_str(line: &str) -> Result<Self> {
+        let mut words = line.split_whitespace();
+        let port = words.next().ok_or_else(|| anyhow!("empty stimulus"))?;
+        let args: Vec<u64> = words
+            .map(|w| w.parse().with_context(|| format!("invalid value {:?}", w)))
+            .collect::<Result<_>>()?;
+        let arity = match port {
+            "eval" => 0,
+            "branch_write" | "mem_load" | "bus_write" | "w" => 3,
+            "aw" | "ar" => 2,
+            _ => 1,
+        };
+        if args.len() != arity {
+            bail!("{} takes {} values, got {}", port, arity, args.len());
+        }
+        let bit = |i: usize| match args[i] {
+            0 => Ok(false),
+            1 => Ok(true),
+            v => Err(anyhow!("{} is not 0 or 1", v)),
+        };
+        let int = |i: usize, max: u64| {
+            u32::try_from(args[i])
+                .ok()
+                .filter(|&v| v as u64 <= max)
+                .ok_or_else(|| anyhow!("{} is out of range", args[i]))
+        };
+        Ok(match port {
+            "clk" => Stimulus::Clk(bit(0)?),
+            "rst" => Stimulus::Reset(bit(0)?),
+            "start" => Stimulus::Start(bit(0)?),
+            "prog_data" => Stimulus::ProgData(int(0, 0xFF)? as u8),
+            "prog_valid" => Stimulus::ProgValid(bit(0)?),
+            "data_ready" => Stimulus::DataReady(bit(0)?),
+            "branch_write" => Stimulus::BranchWrite {
+                en: bit(0)?,
+                source_pc: int(1, u32::MAX as u64)?,
+                target_pc: int(2, u32::MAX as u64)?,
+            },
+            "mem_load" => Stimulus::MemLoad {
+                en: bit(0)?,
+                addr: int(1, u32::MAX as u64)?,
+                data: int(2, 0xFF)? as u8,
+            },
+            "bus_write" => Stimulus::BusWrite {
+                en: bit(0)?,
+                addr: int(1, u32::MAX as u64)?,
+                data: int(2, u32::MAX as u64)?,
+            },
+            "aw" => Stimulus::Aw {
+                valid: bit(0)?,
+                addr: int(1, u32::MAX as u64)?,
+            },
+            "w" => Stimulus::W {
+                valid: bit(0)?,
+                data: int(1, u32::MAX as u64)?,
+                strb: int(2, 0xFF)? as u8,
+            },
+            "b_ready" => Stimulus::BReady(bit(0)?),
+            "ar" => Stimulus::Ar {
+                valid: bit(0)?,
+                addr: int(1, u32::MAX as u64)?,
+            },
+            "r_ready" => Stimulus::RReady(bit(0)?),
+            "eval" => Stimulus::Eval,
+            _ => bail!("unknown port {:?}", port),
+        })
+    }
+}
+
+impl Stimulus {
+    /// Drive this change into `dut`. Bus and AXI-lite events need ports
+    /// a plain [`WasmCoreDut`] doesn't have, so they are an error.
+    pub fn apply<D: WasmCoreDut>(self, dut: &mut D) -> Result<()> {
+        match self {
+            Stimulus::Clk(v) => dut.set_clk(v),
+            Stimulus::Reset(v) => dut.set_reset(v),
+            Stimulus::Start(v) => dut.set_start(v),
+            Stimulus::ProgData(v) => dut.set_prog_data(v),
+            Stimulus::ProgValid(v) => dut.set_prog_valid(v),
+            Stimulus::DataReady(v) => dut.set_data_ready(v),
+            Stimulus::BranchWrite {
+                en,
+                source_pc,
+                target_pc,
+            } => dut.set_branch_write(en, source_pc, target_pc),
+            Stimulus::MemLoad { en, addr, data } => dut.set_mem_load(en, addr, data),
+            Stimulus::Eval => dut.eval(),
+            _ => bail!("cannot replay {:?} without a bus port", self),
+        }
+        Ok(())
+    }
+}
+
+/// Every input change of a run in order, as recorded by [`Recording`].
+/// The ROM's latency and wait states and the start timing are all in
+/// the events, so replaying them reproduces a run exactly whatever seeds
+/// produced it; the seeds themselves go in the comments.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct StimulusLog {
+    /// Free-form notes such as the test name and timing, saved as `#`
+    /// lines.
+    pub comments: Vec<String>,
+    pub events: Vec<Stimulus>,
+}
+
+impl StimulusLog {
+    /// Clock cycles in the log, counted by rising edges.
+    pub fn cycles(&self) -> u64 {
+        self.events
+            .iter()
+            .filter(|e| **e == Stimulus::Clk(true))
+            .count() as u64
+    }
+
+    /// The log as text: the comments as `#` lines, then one event per
+    /// line.
+    pub fn to_text(&self) -> String {
+        let mut out = String::new();
+        for comment in &self.comments {
+            out.push_str(&format!("# {}\n", comment));
+        }
+        for event in &self.events {
+            out.push_str(&format!("{}\n", event));
+        }
+        out
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        fs::write(path, self.to_text()).with_context(|| format!("writing {}", path.display()))
+    }
+
+    /// Parse the output of [`to_text`](Self::to_text).
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut log = StimulusLog::default();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if let Some(comment) = line.strip_prefix('#') {
+                log.comments.push(comment.trim().to_string());
+            } else if !line.is_empty() {
+                log.events
+                    .push(line.parse().with_context(|| format!("line {}", i + 1))?);
+            }
+        }
+        Ok(log)
+    }
+
+    /// Apply the events to a fresh `dut` through the end of cycle
+    /// `cycles`, stopping before the next rising edge; `None` replays
+    /// the whole log.
+    pub fn replay<D: WasmCoreDut>(&self, dut: &mut D, cycles: Option<u64>) -> Result<()> {
+        let mut cycle = 0;
+        for (i, &event) in self.events.iter().enumerate() {
+            if event == Stimulus::Clk(true) {
+                cycle += 1;
+                if cycles.is_some_and(|c| cycle > c) {
+                    break;
+                }
+            }
+            event.apply(dut).with_context(|| format!("event {}", i))?;
+        }
+        Ok(())
+    }
+}
+
+/// Find the first cycle after which `failed` holds by replaying `log`
+/// onto fresh models from `new_dut`, bisecting on the number of cycles
+/// replayed. Assumes that once the run has gone wrong it stays wrong,
+/// as with a trap or a bad stack top latched before halt. `None` if
+/// even the whole log doesn't reproduce the failure.
+pub fn bisect_replay<D, F, P>(
+    log: &StimulusLog,
+    mut new_dut: F,
+    mut failed: P,
+) -> Result<Option<u64>>
+where
+    D: WasmCoreDut,
+    F: FnMut() -> Result<D>,
+    P: FnMut(&D) -> bool,
+{
+    let mut fails_at = |cycles: u64| -> Result<bool> {
+        let mut dut = new_dut()?;
+        log.replay(&mut dut, Some(cycles))?;
+        Ok(failed(&dut))
+    };
+    let (mut lo, mut hi) = (0, log.cycles());
+    if !fails_at(hi)? {
+        return Ok(None);
+    }
+    // Invariant: the failure shows after `hi` cycles but not after `lo`,
+    // unless `lo` is 0 and it shows from the outset.
+    if fails_at(lo)? {
+        return Ok(Some(lo));
+    }
+    while hi - lo > 1 {
+        let mid = lo + (hi - lo) / 2;
+        if fails_at(mid)? {
+            hi = mid;
+        } else {
+            lo = mid;
+        }
+    }
+    Ok(Some(hi))
+}
+
+/// A [`WasmCoreDut`] that appends every input change to a
+/// [`StimulusLog`] before passing it on to the wrapped model.
+pub struct Recording<'a, D> {
+    dut: &'a mut D,
+    log: &'a mut StimulusLog,
+}
+
+impl<'a, D> Recording<'a, D> {
+    pub fn new(dut: &'a mut D, log: &'a mut StimulusLog) -> Self {
+        Recording { dut, log }
+    }
+}
+
+impl<D: WasmCoreDut> WasmCoreDut for Recording<'_, D> {
+    fn set_clk(&mut self, high: bool) {
+        self.log.events.push(Stimulus::Clk(high));
+        self.dut.set_clk(high);
+    }
+    fn set_reset(&mut self, asserted: bool) {
+        self.log.events.push(Stimulus::Reset(asserted));
+        self.dut.set_reset(asserted);
+    }
+    fn set_start(&mut self, start: bool) {
+        self.log.events.push(Stimulus::Start(start));
+        self.dut.set_start(start);
+    }
+    fn prog_addr(&self) -> u32 {
+        self.dut.prog_addr()
+    }
+    fn set_prog_data(&mut self, byte: u8) {
+        self.log.events.push(Stimulus::ProgData(byte));
+        self.dut.set_prog_data(byte);
+    }
+    fn set_prog_valid(&mut self, valid: bool) {
+        self.log.events.push(Stimulus::ProgValid(valid));
+        self.dut.set_prog_valid(valid);
+    }
+    fn set_data_ready(&mut self, ready: bool) {
+        self.log.events.push(Stimulus::DataReady(ready));
+        self.dut.set_data_ready(ready);
+    }
+    fn set_branch_write(&mut self, en: bool, source_pc: u32, target_pc: u32) {
+        self.log.events.push(Stimulus::BranchWrite {
+            en,
+            source_pc,
+            target_pc,
+        });
+        self.dut.set_branch_write(en, source_pc, target_pc);
+    }
+    fn set_mem_load(&mut self, en: bool, addr: u32, data: u8) {
+        self.log.events.push(Stimulus::MemLoad { en, addr, data });
+        self.dut.set_mem_load(en, addr, data);
+    }
+    fn read_mem_word(&mut self, addr: u32) -> Option<u32> {
+        self.dut.read_mem_word(addr)
+    }
+    fn halted(&self) -> bool {
+        self.dut.halted()
+    }
+    fn trapped(&self) -> bool {
+        self.dut.trapped()
+    }
+    fn stack_top(&self) -> i32 {
+        self.dut.stack_top()
+    }
+    fn pc(&self) -> u32 {
+        self.dut.pc()
+    }
+    fn eval(&mut self) {
+        self.log.events.push(Stimulus::Eval);
+        self.dut.eval();
+    }
+    fn open_wave<'w>(&mut self, path: &Path) -> Option<Box<dyn FnMut(u64) + 'w>>
+    where
+        Self: 'w,
+    {
+        self.dut.open_wave(path)
+    }
+}
+
+impl<D: BusPort> BusPort for Recording<'_, D> {
+    fn set_bus_write(&mut self, en: bool, addr: u32, data: u32) {
+        self.log.events.push(Stimulus::BusWrite { en, addr, data });
+        self.dut.set_bus_write(en, addr, data);
+    }
+}
+
+impl<D: AxiLitePort> AxiLitePort for Recording<'_, D> {
+    fn set_aw(&mut self, valid: bool, addr: u32) {
+        self.log.events.push(Stimulus::Aw { valid, addr });
+        self.dut.set_aw(valid, addr);
+    }
+    fn aw_ready(&self) -> bool {
+        self.dut.aw_ready()
+    }
+    fn set_w(&mut self, valid: bool, data: u32, strb: u8) {
+        self.log.events.push(Stimulus::W { valid, data, strb });
+        self.dut.set_w(valid, data, strb);
+    }
+    fn w_ready(&self) -> bool {
+        self.dut.w_ready()
+    }
+    fn set_b_ready(&mut self, ready: bool) {
+        self.log.events.push(Stimulus::BReady(ready));
+        self.dut.set_b_ready(ready);
+    }
+    fn b_valid(&self) -> bool {
+        self.dut.b_valid()
+    }
+    fn b_resp(&self) -> u8 {
+        self.dut.b_resp()
+    }
+    fn set_ar(&mut self, valid: bool, addr: u32) {
+        self.log.events.push(Stimulus::Ar { valid, addr });
+        self.dut.set_ar(valid, addr);
+    }
+    fn ar_ready(&self) -> bool {
+        self.dut.ar_ready()
+    }
+    fn set_r_ready(&mut self, ready: bool) {
+        self.log.events.push(Stimulus::RReady(ready));
+        self.dut.set_r_ready(ready);
+    }
+    fn r_valid(&self) -> bool {
+        self.dut.r_valid()
+    }
+    fn r_data(&self) -> u32 {
+        self.dut.r_data()
+    }
+    fn r_resp(&self) -> u8 {
+        self.dut.r_resp()
+    }
+}
+
+/// [`run_test_with`] and [`SimOutcome::check`] in one, dumping a waveform
+/// as set by `wave`. A failure's error names the waveform it left; a
+/// passing test's waveform is deleted unless `wave.keep_passing` is set.
+/// A failing run's stimulus is also saved if `wave.stimulus` is set, for
+/// [`StimulusLog::replay`] and [`bisect_replay`].
+///
+/// With a `golden` trace, the run is recorded with the same signals and
+/// fails at the first divergence from it, reported with the samples
+/// around it, before the final state is checked. Either failure is a
+/// [`TestFailure`] in the error chain.
+pub fn run_test_checked<D, R, L>(
+    dut: &mut D,
+    rom: &mut R,
+    loader: &mut L,
+    info: &CompiledProgram,
+    max_cycles: u32,
+    wave: &WaveOptions,
+    golden: Option<&TraceRecorder>,
+) -> Result<SimOutcome>
+where
+    D: WasmCoreDut,
+    R: RomModel,
+    L: for<'a, 'b> Loader<Traced<'a, Recording<'b, D>>>,
+{
+    let mut recorder = golden.map(|g| TraceRecorder::new(g.signals()));
+    let mut stimulus = StimulusLog {
+        comments: vec![format!("test {} max_cycles {}", info.name, max_cycles)],
+        events: Vec::new(),
+    };
+    let result = {
+        let mut recording = Recording::new(dut, &mut stimulus);
+        let mut traced = Traced::new(&mut recording, wave)?;
+        if let Some(recorder) = recorder.as_mut() {
+            traced = traced.with_recorder(recorder);
+        }
+        run_test_with(&mut traced, rom, loader, info, max_cycles)
+    }
+    .and_then(|outcome| {
+        let reason = match (&recorder, golden) {
+            (Some(actual), Some(golden)) => actual
+                .divergence(golden, DIVERGENCE_CONTEXT)
+                .map(|d| format!("{}: {}", info.name, d)),
+            _ => None,
+        };
+        match reason.or_else(|| outcome.check(info).err().map(|e| e.to_string())) {
+            Some(reason) => Err(TestFailure { outcome, reason }.into()),
+            None => Ok(outcome),
+        }
+    });
+    let result = match (result, &wave.stimulus) {
+        (Err(e), Some(path)) => {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)
+                    .with_context(|| format!("creating {}", parent.display()))?;
+            }
+            stimulus.write(path)?;
+            Err(e.context(format!("stimulus in {}", path.display())))
+        }
+        (result, _) => result,
+    };
+    // The dump is closed by now, so the file is complete.
+    let Some(path) = wave.path.as_deref().filter(|p| p.exists()) else {
+        return result;
+    };
+    match result {
+        Ok(outcome) => {
+            if !wave.keep_passing {
+                fs::remove_file(path).with_context(|| format!("removing {}", path.display()))?;
+            }
+            Ok(outcome)
+        }
+        Err(e) => Err(e.context(format!("waveform in {}", path.display()))),
+    }
+}
+
+fn start_and_run<D: WasmCoreDut, R: RomModel>(
+    dut: &mut D,
+    rom: &mut R,
+    info: &CompiledProgram,
+    max_cycles: u32,
+) -> SimOutcome {
+    start_and_run_with(dut, rom, info, max_cycles, 1, &[])
+}
+
+/// Hold start for `start_width` cycles, pulse it again at each of
+/// `spurious_starts`, and run until halt, trap, or `max_cycles`; then
+/// read back the data RAM words `info` expects if the core halted.
+fn start_and_run_with<D: WasmCoreDut, R: RomModel>(
+    dut: &mut D,
+    rom: &mut R,
+    info: &CompiledProgram,
+    max_cycles: u32,
+    start_width: u32,
+    spurious_starts: &[u32],
+) -> SimOutcome {
+    dut.set_start(true);
+    tick(dut, rom);
+
+    let mut cycles = max_cycles;
+    for c in 1..=max_cycles {
+        dut.set_start(c < start_width || spurious_starts.contains(&c));
+        tick(dut, rom);
+        if dut.halted() || dut.trapped() {
+            cycles = c;
+            break;
+        }
+    }
+    dut.set_start(false);
+    let mut mem_mismatch = None;
+    if dut.halted() && !dut.trapped() {
+        for check in &info.mem_expected {
+            let Some(actual) = dut.read_mem_word(check.addr) else {
+                break;
+            };
+            if actual != check.value {
+                mem_mismatch = Some(MemMismatch {
+                    addr: check.addr,
+                    expected: check.value,
+                    actual,
+                });
+                break;
+            }
+        }
+    }
+    SimOutcome {
+        halted: dut.halted(),
+        trapped: dut.trapped(),
+        stack_top: dut.stack_top(),
+        pc: dut.pc(),
+        cycles,
+        mem_mismatch,
+    }
+}
+
+/// The AXI-lite slave channels of a core that has one, driven by
+/// [`AxiLiteMaster`].
+pub trait AxiLitePort {
+    fn set_aw(&mut self, valid: bool, addr: u32);
+    fn aw_ready(&self) -> bool;
+    fn set_w(&mut self, valid: bool, data: u32, strb: u8);
+    fn w_ready(&self) -> bool;
+    fn set_b_ready(&mut self, ready: bool);
+    fn b_valid(&self) -> bool;
+    fn b_resp(&self) -> u8;
+    fn set_ar(&mut self, valid: bool, addr: u32);
+    fn ar_ready(&self) -> bool;
+    fn set_r_ready(&mut self, ready: bool);
+    fn r_valid(&self) -> bool;
+    fn r_data(&self) -> u32;
+    fn r_resp(&self) -> u8;
+}
+
+/// AXI-lite master bus functional model. Each transfer holds its valid
+/// signals until the matching ready, and fails on a slave protocol
+/// violation (a response before its address and data handshakes), an
+/// error response, or no response within `timeout` cycles.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AxiLiteMaster {
+    pub timeout: u32,
+    /// Write strobes for every write.
+    pub strb: u8,
+}
+
+impl Default for AxiLiteMaster {
+    fn default() -> Self {
+        AxiLiteMaster {
+            timeout: 64,
+            strb: 0xF,
+        }
+    }
+}
+
+/// Check an AXI-lite `BRESP`/`RRESP`.
+fn axi_resp(resp: u8, op: &str, addr: u32) -> Result<()> {
+    match resp {
+        0 => Ok(()),
+        1 => Err(anyhow!(
+            "{} 0x{:08X}: EXOKAY is not allowed on AXI-lite",
+            op,
+            addr
+        )),
+        2 => Err(anyhow!("{} 0x{:08X}: slave error (SLVERR)", op, addr)),
+        3 => Err(anyhow!("{} 0x{:08X}: decode error (DECERR)", op, addr)),
+        _ => Err(anyhow!("{} 0x{:08X}: invalid response {}", op, addr, resp)),
+    }
+}
+
+impl AxiLiteMaster {
+    /// Read the word at `addr`.
+    pub fn read<D: WasmCoreDut + AxiLitePort, R: RomModel>(
+        &mut self,
+        dut: &mut D,
+        rom: &mut R,
+        addr: u32,
+    ) -> Result<u32> {
+        dut.set_ar(true, addr);
+        dut.set_r_ready(true);
+        let result = self.read_inner(dut, rom, addr);
+        dut.set_ar(false, 0);
+        dut.set_r_ready(false);
+        result
+    }
+
+    fn read_inner<D: WasmCoreDut + AxiLitePort, R: RomModel>(
+        &mut self,
+        dut: &mut D,
+        rom: &mut R,
+        addr: u32,
+    ) -> Result<u32> {
+        let mut ar_done = false;
+        for _ in 0..self.timeout {
+            // Sample the slave just before the edge.
+            dut.eval();
+            let r_valid = dut.r_valid();
+            if r_valid && !ar_done {
+                return Err(anyhow!(
+                    "read 0x{:08X}: RVALID before the AR handshake",
+                    addr
+                ));
+            }
+            let ar_handshake = !ar_done && dut.ar_ready();
+            let (data, resp) = (dut.r_data(), dut.r_resp());
+            tick(dut, rom);
+            if ar_handshake {
+                ar_done = true;
+                dut.set_ar(false, 0);
+            }
+            if r_valid {
+                axi_resp(resp, "read", addr)?;
+                return Ok(data);
+            }
+        }
+        Err(anyhow!(
+            "read 0x{:08X}: no response within {} cycles",
+            addr,
+            self.timeout
+        ))
+    }
+
+    fn write_inner<D: WasmCoreDut + AxiLitePort, R: RomModel>(
+        &mut self,
+        dut: &mut D,
+        rom: &mut R,
+        addr: u32,
+    ) -> Result<()> {
+        let (mut aw_done, mut w_done) = (false, false);
+        for _ in 0..self.timeout {
+            dut.eval();
+            let b_valid = dut.b_valid();
+            if b_valid && !(aw_done && w_done) {
+                return Err(anyhow!(
+                    "write 0x{:08X}: BVALID before the AW and W handshakes",
+                    addr
+                ));
+            }
+            let aw_handshake = !aw_done && dut.aw_ready();
+            let w_handshake = !w_done && dut.w_ready();
+            let resp = dut.b_resp();
+            tick(dut, rom);
+            if aw_handshake {
+                aw_done = true;
+                dut.set_aw(false, 0);
+            }
+            if w_handshake {
+                w_done = true;
+                dut.set_w(false, 0, 0);
+            }
+            if b_valid {
+                return axi_resp(resp, "write", addr);
+            }
+        }
+        Err(anyhow!(
+            "write 0x{:08X}: no response within {} cycles",
+            addr,
+            self.timeout
+        ))
+    }
+}
+
+impl<D: WasmCoreDut + AxiLitePort> BusMaster<D> for AxiLiteMaster {
+    fn write<R: RomModel>(&mut self, dut: &mut D, rom: &mut R, addr: u32, data: u32) -> Result<()> {
+        dut.set_aw(true, addr);
+        dut.set_w(true, data, self.strb);
+        dut.set_b_ready(true);
+        let result = self.write_inner(dut, rom, addr);
+        dut.set_aw(false, 0);
+        dut.set_w(false, 0, 0);
+        dut.set_b_ready(false);
+        result
+    }
+}
+
+/// A model whose ports are read and written by name, as raw values.
+pub trait NamedSignals {
+    fn set(&mut self, name: &str, value: u64);
+    fn get(&self, name: &str) -> u64;
+    fn eval(&mut self);
+}
+
+/// [`WasmCoreDut`] for a [`NamedSignals`] model with the ports of `ports`:
+/// each role on its port's name, control ports driven and read at their
+/// active levels, values cut to the ports' widths, and the stack top
+/// sign-extended from its width.
+#[derive(Debug, Clone)]
+pub struct PortMapped<M> {
+    pub model: M,
+    pub ports: PortMap,
+}
+
+impl<M: NamedSignals> PortMapped<M> {
+    pub fn new(model: M, ports: PortMap) -> Self {
+        PortMapped { model, ports }
+    }
+
+    fn drive(&mut self, port: fn(&PortMap) -> &Port, value: u64) {
+        let port = port(&self.ports);
+        self.model.set(&port.name, value & port.mask());
+    }
+
+    fn control(&mut self, port: fn(&PortMap) -> &Port, asserted: bool) {
+        let bit = port(&self.ports).bit(asserted);
+        self.drive(port, bit);
+    }
+
+    fn read(&self, port: fn(&PortMap) -> &Port) -> u64 {
+        let port = port(&self.ports);
+        self.model.get(&port.name) & port.mask()
+    }
+
+    fn asserted(&self, port: fn(&PortMap) -> &Port) -> bool {
+        self.read(port) == port(&self.ports).bit(true)
+    }
+}
+
+impl<M: NamedSignals> WasmCoreDut for PortMapped<M> {
+    fn set_clk(&mut self, high: bool) {
+        self.drive(|p| &p.clk, high as u64);
+    }
+    fn set_reset(&mut self, asserted: bool) {
+        self.control(|p| &p.rst, asserted);
+    }
+    fn set_start(&mut self, start: bool) {
+        self.control(|p| &p.start, start);
+    }
+    fn prog_addr(&self) -> u32 {
+        self.read(|p| &p.prog_addr) as u32
+    }
+    fn set_prog_data(&mut self, byte: u8) {
+        self.drive(|p| &p.prog_data, byte as u64);
+    }
+    fn set_branch_write(&mut self, en: bool, source_pc: u32, target_pc: u32) {
+        self.control(|p| &p.bt_wr_en, en);
+        self.drive(|p| &p.bt_wr_addr, source_pc as u64);
+        self.drive(|p| &p.bt_wr_data, target_pc as u64);
+    }
+    fn set_mem_load(&mut self, en: bool, addr: u32, data: u8) {
+        self.control(|p| &p.mem_load_en, en);
+        self.drive(|p| &p.mem_load_addr, addr as u64);
+        self.drive(|p| &p.mem_load_data, data as u64);
+    }
+    fn halted(&self) -> bool {
+        self.asserted(|p| &p.halted)
+    }
+    fn trapped(&self) -> bool {
+        self.asserted(|p| &p.trap)
+    }
+    fn stack_top(&self) -> i32 {
+        let shift = 64 - self.ports.stack_top.width;
+        ((self.read(|p| &p.stack_top) << shift) as i64 >> shift) as i32
+    }
+    fn pc(&self) -> u32 {
+        self.read(|p| &p.pc) as u32
+    }
+    fn eval(&mut self) {
+        self.model.eval();
+    }
+}
+
+/// Implement [`WasmCoreDut`] for a marlin model with the `WasmCoreTb`
+/// ports. Veryl's `reset` type is active-low: `i_rst = 0` asserts reset.
+/// Waveforms are VCD, and are only written if the model was created with
+/// tracing enabled.
+#[macro_export]
+macro_rules! impl_wasm_core_dut {
+    ($model:ident) => {
+        impl $crate::sim::WasmCoreDut for $model<'_> {
+            fn set_clk(&mut self, high: bool) {
+                self.i_clk = high as _;
+            }
+            fn set_reset(&mut self, asserted: bool) {
+                self.i_rst = !asserted as _;
+            }
+            fn set_start(&mut self, start: bool) {
+                self.i_start = start as _;
+            }
+            fn prog_addr(&self) -> u32 {
+                self.o_prog_addr as u32
+            }
+            fn set_prog_data(&mut self, byte: u8) {
+                self.i_prog_data = byte as _;
+            }
+            fn set_branch_write(&mut self, en: bool, source_pc: u32, target_pc: u32) {
+                self.i_bt_wr_en = en as _;
+                self.i_bt_wr_addr = source_pc as _;
+                self.i_bt_wr_data = target_pc as _;
+            }
+            fn set_mem_load(&mut self, en: bool, addr: u32, data: u8) {
+                self.i_mem_load_en = en as _;
+                self.i_mem_load_addr = addr as _;
+                self.i_mem_load_data = data as _;
+            }
+            fn halted(&self) -> bool {
+                self.o_halted != 0
+            }
+            fn trapped(&self) -> bool {
+                self.o_trap != 0
+            }
+            fn stack_top(&self) -> i32 {
+                self.o_stack_top as i32
+            }
+            fn pc(&self) -> u32 {
+                self.o_pc as u32
+            }
+            fn eval(&mut self) {
+                $model::eval(self)
+            }
+            fn open_wave<'w>(&mut self, path: &std::path::Path) -> Option<Box<dyn FnMut(u64) + 'w>>
+            where
+                Self: 'w,
+            {
+                let mut vcd = self.open_vcd(path);
+                Some(Box::new(move |time| vcd.dump(time)))
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MemCheck;
+
+    /// Stand-in core with active-high ports: records loads, then halts
+    /// after three data-ready cycles from start with the ROM byte at
+    /// address 0 on top.
+    #[derive(Default)]
+    struct FakeCore {
+        clk: bool,
+        rst: bool,
+        start: bool,
+        data: u8,
+        bt_en: bool,
+        bt: (u32, u32),
+        branches: Vec<(u32, u32)>,
+        mem_en: bool,
+        mem: (u32, u8),
+        mem_writes: Vec<(u32, u8)>,
+        bus_en: bool,
+        bus: (u32, u32),
+        bus_writes: Vec<(u32, u32)>,
+        running: Option<u32>,
+        halted: bool,
+        data_ready: bool,
+        /// Start while running restarts the program, a control bug.
+        restarts: bool,
+    }
+
+    impl WasmCoreDut for FakeCore {
+        fn set_clk(&mut self, high: bool) {
+            self.clk = high;
+        }
+        fn set_reset(&mut self, asserted: bool) {
+            self.rst = asserted;
+        }
+        fn set_start(&mut self, start: bool) {
+            self.start = start;
+        }
+        fn prog_addr(&self) -> u32 {
+            0
+        }
+        fn set_prog_data(&mut self, byte: u8) {
+            self.data = byte;
+        }
+        fn set_data_ready(&mut self, ready: bool) {
+            self.data_ready = ready;
+        }
+        fn set_branch_write(&mut self, en: bool, source_pc: u32, target_pc: u32) {
+            self.bt_en = en;
+            self.bt = (source_pc, target_pc);
+        }
+        fn set_mem_load(&mut self, en: bool, addr: u32, data: u8) {
+            self.mem_en = en;
+            self.mem = (addr, data);
+        }
+        fn read_mem_word(&mut self, addr: u32) -> Option<u32> {
+            let byte = |a: u32| {
+                let last = self.mem_writes.iter().rev().find(|w| w.0 == a);
+                last.map_or(0, |w| w.1 as u32)
+            };
+            Some((0..4).fold(0, |word, i| word | byte(addr + i) << (8 * i)))
+        }
+        fn halted(&self) -> bool {
+            self.halted
+        }
+        fn trapped(&self) -> bool {
+            false
+        }
+        fn stack_top(&self) -> i32 {
+            self.data as i32
+        }
+        fn pc(&self) -> u32 {
+            0
+        }
+        fn open_wave<'w>(&mut self, path: &Path) -> Option<Box<dyn FnMut(u64) + 'w>> {
+            use std::io::Write;
+            let mut file = fs::File::create(path).ok()?;
+            Some(Box::new(move |time| writeln!(file, "{}", time).unwrap()))
+        }
+        fn eval(&mut self) {
+            // Act once per rising edge: the second eval of a tick.
+            if !self.clk {
+                return;
+            }
+            self.clk = false;
+            if self.rst {
+                self.running = None;
+                self.halted = false;
+                return;
+            }
+            if self.bt_en {
+                self.branches.push(self.bt);
+            }
+            if self.mem_en {
+                self.mem_writes.push(self.mem);
+            }
+            if self.bus_en {
+                self.bus_writes.push(self.bus);
+            }
+            if self.start && (self.running.is_none() || self.restarts) {
+                self.running = Some(0);
+            } else if let Some(n) = self.running.as_mut().filter(|_| self.data_ready) {
+                *n += 1;
+                self.halted = *n == 3;
+            }
+        }
+    }
+
+    #[test]
+    fn test_rom_timing() {
+        let mut comb = Rom::new(&[7, 8], RomTiming::default());
+        assert_eq!(comb.read(1), Some(8));
+        assert_eq!(comb.read(5), Some(0));
+
+        let mut slow = Rom::new(
+            &[7, 8],
+            RomTiming {
+                latency: 2,
+                ..Default::default()
+            },
+        );
+        assert_eq!(slow.read(0), None);
+        slow.clock();
+        assert_eq!(slow.read(0), None);
+        slow.clock();
+        assert_eq!(slow.read(0), Some(7));
+        // A new address restarts the read.
+        assert_eq!(slow.read(1), None);
+
+        let timing = RomTiming {
+            latency: 1,
+            max_wait_states: 3,
+            seed: 42,
+            ..Default::default()
+        };
+        let delays = |rom: &mut Rom| {
+            (0..16)
+                .map(|addr| {
+                    let mut n = 0;
+                    while rom.read(addr).is_none() {
+                        rom.clock();
+                        n += 1;
+                    }
+                    n
+                })
+                .collect::<Vec<u32>>()
+        };
+        let first = delays(&mut Rom::new(&[0; 16], timing));
+        assert!(first.iter().all(|&n| (1..=4).contains(&n)));
+        assert!(first.iter().any(|&n| n > 1));
+        assert_eq!(first, delays(&mut Rom::new(&[0; 16], timing)));
+    }
+
+    impl BusPort for FakeCore {
+        fn set_bus_write(&mut self, en: bool, addr: u32, data: u32) {
+            self.bus_en = en;
+            self.bus = (addr, data);
+        }
+    }
+
+    #[test]
+    fn test_bus_loader() {
+        let info = crate::compile_wat_source(
+            "br",
+            r#"(module
+                (memory (export "memory") 1)
+                (data (i32.const 8) "\2a")
+                (func (export "main") (result i32)
+                    (block
+                        i32.const 1
+                        br_if 0)
+                    i32.const 5))"#,
+        )
+        .unwrap();
+        let mut dut = FakeCore::default();
+        let mut rom = Rom::new(&info.body_bytes, RomTiming::default());
+        let mut loader = BusLoader {
+            map: BusMap::default(),
+            bus: StrobeBus,
+        };
+        let outcome = run_test_with(&mut dut, &mut rom, &mut loader, &info, 10).unwrap();
+        assert!(outcome.halted);
+
+        // The same writes as bus.txt: little-endian program words, the
+        // target of the branch at pc 4 at its slot, then the memory image.
+        let stream: Vec<(u32, u32)> = bus_transactions(&info, &BusMap::default())
+            .unwrap()
+            .iter()
+            .map(|w| (w.addr, w.data))
+            .collect();
+        assert_eq!(dut.bus_writes, stream);
+        let prog_words = info.body_bytes.len().div_ceil(4);
+        let first = u32::from_le_bytes(info.body_bytes[..4].try_into().unwrap());
+        assert_eq!(dut.bus_writes[0], (0, first));
+        assert_eq!(
+            dut.bus_writes[prog_words..],
+            [
+                (0x1_0010, 7),
+                (0x2_0000, 0),
+                (0x2_0004, 0),
+                (0x2_0008, 0x2A)
+            ]
+        );
+        assert!(dut.branches.is_empty() && dut.mem_writes.is_empty());
+    }
+
+    /// AXI-lite register file that raises AWREADY after `aw_delay` cycles
+    /// of AWVALID and answers every write with `resp`.
+    #[derive(Default)]
+    struct FakeAxi {
+        clk: bool,
+        aw: (bool, u32),
+        w: (bool, u32),
+        b_ready: bool,
+        ar: (bool, u32),
+        r_ready: bool,
+        aw_delay: u32,
+        aw_seen: u32,
+        aw_addr: Option<u32>,
+        w_data: Option<u32>,
+        b_valid: bool,
+        resp: u8,
+        /// Raise BVALID as soon as AWVALID is seen.
+        early_b: bool,
+        r_valid: bool,
+        r_data: u32,
+        writes: Vec<(u32, u32)>,
+    }
+
+    impl WasmCoreDut for FakeAxi {
+        fn set_clk(&mut self, high: bool) {
+            self.clk = high;
+        }
+        fn set_reset(&mut self, _asserted: bool) {}
+        fn set_start(&mut self, _start: bool) {}
+        fn prog_addr(&self) -> u32 {
+            0
+        }
+        fn set_prog_data(&mut self, _byte: u8) {}
+        fn set_branch_write(&mut self, _en: bool, _source_pc: u32, _target_pc: u32) {}
+        fn set_mem_load(&mut self, _en: bool, _addr: u32, _data: u8) {}
+        fn halted(&self) -> bool {
+            false
+        }
+        fn trapped(&self) -> bool {
+            false
+        }
+        fn stack_top(&self) -> i32 {
+            0
+        }
+        fn pc(&self) -> u32 {
+            0
+        }
+        fn eval(&mut self) {
+            if !self.clk {
+                return;
+            }
+            self.clk = false;
+            let aw_handshake = self.aw.0 && self.aw_ready();
+            let w_handshake = self.w.0 && self.w_ready();
+            let b_handshake = self.b_valid && self.b_ready;
+            let ar_handshake = self.ar.0 && self.ar_ready();
+            let r_handshake = self.r_valid && self.r_ready;
+            if aw_handshake {
+                self.aw_addr = Some(self.aw.1);
+                self.aw_seen = 0;
+            } else if self.aw.0 {
+                self.aw_seen += 1;
+                self.b_valid |= self.early_b;
+            }
+            if w_handshake {
+                self.w_data = Some(self.w.1);
+            }
+            if b_handshake {
+                self.b_valid = false;
+            } else if let (Some(addr), Some(data)) = (self.aw_addr, self.w_data) {
+                self.writes.push((addr, data));
+                self.aw_addr = None;
+                self.w_data = None;
+                self.b_valid = true;
+            }
+            if r_handshake {
+                self.r_valid = false;
+            }
+            if ar_handshake {
+                let addr = self.ar.1;
+                self.r_data = self
+                    .writes
+                    .iter()
+                    .rev()
+                    .find(|w| w.0 == addr)
+                    .map_or(0, |w| w.1);
+                self.r_valid = true;
+            }
+        }
+    }
+
+    impl AxiLitePort for FakeAxi {
+        fn set_aw(&mut self, valid: bool, addr: u32) {
+            self.aw = (valid, addr);
+        }
+        fn aw_ready(&self) -> bool {
+            self.aw_seen >= self.aw_delay && self.aw_addr.is_none()
+        }
+        fn set_w(&mut self, valid: bool, data: u32, _strb: u8) {
+            self.w = (valid, data);
+        }
+        fn w_ready(&self) -> bool {
+            self.w_data.is_none()
+        }
+        fn set_b_ready(&mut self, ready: bool) {
+            self.b_ready = ready;
+        }
+        fn b_valid(&self) -> bool {
+            self.b_valid
+        }
+        fn b_resp(&self) -> u8 {
+            self.resp
+        }
+        fn set_ar(&mut self, valid: bool, addr: u32) {
+            self.ar = (valid, addr);
+        }
+        fn ar_ready(&self) -> bool {
+            !self.r_valid
+        }
+        fn set_r_ready(&mut self, ready: bool) {
+            self.r_ready = ready;
+        }
+        fn r_valid(&self) -> bool {
+            self.r_valid
+        }
+        fn r_data(&self) -> u32 {
+            self.r_data
+        }
+        fn r_resp(&self) -> u8 {
+            0
+        }
+    }
+
+    #[test]
+    fn test_axi_lite_master() {
+        let info = crate::compile_wat_source(
+            "br",
+            r#"(module
+                (func (export "main") (result i32)
+                    (block
+                        i32.const 1
+                        br_if 0)
+                    i32.const 5))"#,
+        )
+        .unwrap();
+        let mut rom = Rom::new(&info.body_bytes, RomTiming::default());
+        let mut dut = FakeAxi {
+            aw_delay: 2,
+            ..Default::default()
+        };
+        let mut loader = BusLoader {
+            map: BusMap::default(),
+            bus: AxiLiteMaster::default(),
+        };
+        loader.load(&mut dut, &mut rom, &info).unwrap();
+        let prog_words = info.body_bytes.len().div_ceil(4);
+        assert_eq!(dut.writes.len(), prog_words + 1);
+        assert_eq!(dut.writes[prog_words..], [(0x1_0010, 7)]);
+        let mut bfm = AxiLiteMaster::default();
+        assert_eq!(bfm.read(&mut dut, &mut rom, 0x1_0010).unwrap(), 7);
+
+        let mut dut = FakeAxi {
+            resp: 2,
+            ..Default::default()
+        };
+        let err = bfm.write(&mut dut, &mut rom, 0x10, 1).unwrap_err();
+        assert_eq!(err.to_string(), "write 0x00000010: slave error (SLVERR)");
+        assert!(!dut.aw.0 && !dut.w.0 && !dut.b_ready);
+
+        let mut dut = FakeAxi {
+            aw_delay: 3,
+            early_b: true,
+            ..Default::default()
+        };
+        let err = bfm.write(&mut dut, &mut rom, 0x10, 1).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("BVALID before the AW and W handshakes"));
+
+        let mut dut = FakeAxi {
+            aw_delay: 100,
+            ..Default::default()
+        };
+        let err = bfm.write(&mut dut, &mut rom, 0x10, 1).unwrap_err();
+        assert!(err.to_string().contains("no response within 64 cycles"));
+    }
+
+    #[test]
+    fn test_run_test() {
+        let info = crate::compile_wat_source(
+            "br",
+            r#"(module
+                (memory (export "memory") 1)
+                (data (i32.const 8) "\2a\2b")
+                (func (export "main") (result i32)
+                    (block
+                        i32.const 1
+                        br_if 0)
+                    i32.const 5))"#,
+        )
+        .unwrap();
+        let mut dut = FakeCore::default();
+        let outcome = run_test(&mut dut, &info, 10);
+        assert_eq!(dut.branches, [(4, 7)]);
+        assert_eq!(dut.mem_writes, [(8, 0x2A), (9, 0x2B)]);
+        assert!(outcome.halted);
+        assert_eq!(outcome.cycles, 3);
+        assert_eq!(outcome.stack_top, info.body_bytes[0] as i32);
+        assert_eq!(outcome.mem_mismatch, None);
+
+        // The fake core never stores, so only the loaded words read back.
+        let mut info = info;
+        info.mem_expected = vec![
+            MemCheck {
+                memory: 0,
+                addr: 8,
+                value: 0x2B2A,
+            },
+            MemCheck {
+                memory: 0,
+                addr: 12,
+                value: 1,
+            },
+        ];
+        let outcome = run_test(&mut FakeCore::default(), &info, 10);
+        assert_eq!(
+            outcome.mem_mismatch,
+            Some(MemMismatch {
+                addr: 12,
+                expected: 1,
+                actual: 0
+            })
+        );
+    }
+
+    /// [`FakeCore`] behind named ports, with an active-low reset and
+    /// halt.
+    struct NamedFake(FakeCore);
+
+    impl NamedSignals for NamedFake {
+        fn set(&mut self, name: &str, value: u64) {
+            let core = &mut self.0;
+            match name {
+                "clock" => core.set_clk(value != 0),
+                "reset_n" => core.set_reset(value == 0),
+                "go" => core.set_start(value != 0),
+                "rom_data" => core.set_prog_data(value as u8),
+                "bt_en" => core.bt_en = value != 0,
+                "bt_src" => core.bt.0 = value as u32,
+                "bt_dst" => core.bt.1 = value as u32,
+                "ld_en" => core.mem_en = value != 0,
+                "ld_addr" => core.mem.0 = value as u32,
+                "ld_data" => core.mem.1 = value as u8,
+                _ => panic!("no port `{}`", name),
+            }
+        }
+        fn get(&self, name: &str) -> u64 {
+            let core = &self.0;
+            match name {
+                "rom_addr" => core.prog_addr() as u64,
+                "done_n" => !core.halted() as u64,
+                "o_trap" => core.trapped() as u64,
+                // Garbage above the port's width.
+                "result" => 0xFF00 | core.data as u64,
+                "o_pc" => core.pc() as u64,
+                _ => panic!("no port `{}`", name),
+            }
+        }
+        fn eval(&mut self) {
+            WasmCoreDut::eval(&mut self.0);
+        }
+    }
+
+    #[test]
+    fn test_port_mapped() {
+        let ports = PortMap::from_toml(
+            r#"
+            clk = "clock"
+            rst = { name = "reset_n" }
+            start = "go"
+            prog_addr = "rom_addr"
+            prog_data = "rom_data"
+            bt_wr_en = "bt_en"
+            bt_wr_addr = "bt_src"
+            bt_wr_data = "bt_dst"
+            mem_load_en = "ld_en"
+            mem_load_addr = "ld_addr"
+            mem_load_data = "ld_data"
+            halted = { name = "done_n", active_low = true }
+            stack_top = { name = "result", width = 8 }
+            "#,
+        )
+        .unwrap();
+        let info = crate::compile_wat_source(
+            "br",
+            r#"(module
+                (memory 1)
+                (data (i32.const 8) "\2a")
+                (func (export "main") (result i32)
+                    (block
+                        i32.const 1
+                        br_if 0)
+                    i32.const 5))"#,
+        )
+        .unwrap();
+        // The map has no data-ready port; the fake's RAM always answers.
+        let core = FakeCore {
+            data_ready: true,
+            ..Default::default()
+        };
+        let mut dut = PortMapped::new(NamedFake(core), ports);
+        let outcome = run_test(&mut dut, &info, 10);
+        assert_eq!(dut.model.0.branches, [(4, 7)]);
+        assert_eq!(dut.model.0.mem_writes, [(8, 0x2A)]);
+        assert!(outcome.halted);
+        assert_eq!(outcome.cycles, 3);
+        assert_eq!(outcome.stack_top, info.body_bytes[0] as i32);
+
+        // Narrow results are signed.
+        dut.model.0.data = 0xFE;
+        assert_eq!(dut.stack_top(), -2);
+    }
+
+    #[test]
+    fn test_run_test_checked() {
+        let dir = std::env::temp_dir().join(format!("wasm-ic-waves-{}", std::process::id()));
+        let mut info = crate::compile_wat_source(
+            "two",
+            r#"(module (func (export "main") (result i32) i32.const 2))"#,
+        )
+        .unwrap();
+        // FakeCore returns the first program byte, the i32.const opcode.
+        info.expected = info.body_bytes[0] as i32;
+        let mut rom = Rom::new(&info.body_bytes, RomTiming::default());
+
+        let wave = WaveOptions::in_dir(&dir, "two");
+        let path = wave.path.clone().unwrap();
+        run_test_checked(
+            &mut FakeCore::default(),
+            &mut rom,
+            &mut PortLoader,
+            &info,
+            10,
+            &wave,
+            None,
+        )
+        .unwrap();
+        assert!(!path.exists());
+
+        // Cycles 6 and 7 are the start pulse and the first cycle after it.
+        let wave = WaveOptions {
+            start_cycle: 6,
+            stop_cycle: Some(8),
+            keep_passing: true,
+            ..wave
+        };
+        run_test_checked(
+            &mut FakeCore::default(),
+            &mut rom,
+            &mut PortLoader,
+            &info,
+            10,
+            &wave,
+            None,
+        )
+        .unwrap();
+        let times: Vec<u64> = fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|l| l.parse().unwrap())
+            .collect();
+        assert_eq!(times, [17, 18, 19, 20, 21, 22]);
+
+        info.expected += 1;
+        let err = run_test_checked(
+            &mut FakeCore::default(),
+            &mut rom,
+            &mut PortLoader,
+            &info,
+            10,
+            &WaveOptions::in_dir(&dir, "two"),
+            None,
+        )
+        .unwrap_err();
+        assert_eq!(err.to_string(), format!("waveform in {}", path.display()));
+        assert!(format!("{:#}", err).contains("two: got 65 expected 66"));
+        let failure = err.downcast_ref::<TestFailure>().unwrap();
+        assert_eq!(failure.outcome.result(), "65");
+        assert!(path.exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_stimulus_replay() {
+        let dir = std::env::temp_dir().join(format!("wasm-ic-stim-{}", std::process::id()));
+        let mut info = crate::compile_wat_source(
+            "two",
+            r#"(module (func (export "main") (result i32) i32.const 2))"#,
+        )
+        .unwrap();
+        info.expected = 0;
+        info.branch_table = vec![crate::BranchEntry {
+            source_pc: 4,
+            target_pc: 9,
+        }];
+        let timing = RomTiming {
+            data_stall_percent: 30,
+            seed: 7,
+            ..RomTiming::default()
+        };
+        let mut rom = Rom::new(&info.body_bytes, timing);
+        let wave = WaveOptions {
+            stimulus: Some(dir.join("two.stim")),
+            ..WaveOptions::default()
+        };
+        let mut dut = FakeCore::default();
+        let err = run_test_checked(&mut dut, &mut rom, &mut PortLoader, &info, 20, &wave, None)
+            .unwrap_err();
+        assert!(err.to_string().starts_with("stimulus in "));
+        let outcome = err.downcast_ref::<TestFailure>().unwrap().outcome;
+
+        let text = fs::read_to_string(dir.join("two.stim")).unwrap();
+        assert!(text.starts_with("# test two max_cycles 20\nrst 1\n"));
+        let log = StimulusLog::parse(&text).unwrap();
+        assert_eq!(log.to_text(), text);
+        assert!(log.events.contains(&Stimulus::BranchWrite {
+            en: true,
+            source_pc: 4,
+            target_pc: 9
+        }));
+        let mut replayed = FakeCore::default();
+        log.replay(&mut replayed, None).unwrap();
+        assert_eq!(replayed.branches, dut.branches);
+        assert!(replayed.halted);
+        assert_eq!(replayed.stack_top(), outcome.stack_top);
+
+        // The first cycle ending halted, found without knowing which
+        // cycles the seeded stalls hit.
+        let halt = bisect_replay(&log, || Ok(FakeCore::default()), |d| d.halted)
+            .unwrap()
+            .unwrap();
+        assert!(halt > RESET_CYCLES as u64 + 3 && halt <= log.cycles());
+        let mut dut = FakeCore::default();
+        log.replay(&mut dut, Some(halt - 1)).unwrap();
+        assert!(!dut.halted);
+        assert_eq!(
+            bisect_replay(&log, || Ok(FakeCore::default()), |d| d.trapped()).unwrap(),
+            None
+        );
+
+        assert!(StimulusLog::parse("clk 2").is_err());
+        assert!(StimulusLog::parse("mem_load 1 4").is_err());
+        assert!("bus_write 1 4 5"
+            .parse::<Stimulus>()
+            .unwrap()
+            .apply(&mut FakeCore::default())
+            .is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_trace_recorder() {
+        let info = crate::compile_wat_source(
+            "two",
+            r#"(module (func (export "main") (result i32) i32.const 2))"#,
+        )
+        .unwrap();
+        let mut rom = Rom::new(&info.body_bytes, RomTiming::default());
+        let mut dut = FakeCore::default();
+        let mut recorder = TraceRecorder::new(&[Signal::StackTop, Signal::Halted]);
+        let wave = WaveOptions::default();
+        let mut traced = Traced::new(&mut dut, &wave)
+            .unwrap()
+            .with_recorder(&mut recorder);
+        run_test_with(&mut traced, &mut rom, &mut PortLoader, &info, 10).unwrap();
+        drop(traced);
+
+        // Five reset cycles, the start pulse, and three to halt.
+        let samples = recorder.samples();
+        assert_eq!(samples.len(), 9);
+        assert_eq!(samples[0].cycle, 1);
+        assert_eq!(samples[8].values, [0x41, 1]);
+        assert_eq!(samples[7].values, [0x41, 0]);
+        let jsonl = recorder.to_jsonl();
+        assert_eq!(
+            jsonl.lines().last().unwrap(),
+            r#"{"cycle":9,"halted":true,"stack_top":65}"#
+        );
+        let parsed = TraceRecorder::parse_jsonl(&jsonl).unwrap();
+        assert_eq!(parsed.signals(), [Signal::StackTop, Signal::Halted]);
+        assert_eq!(parsed.samples(), recorder.samples());
+        assert_eq!(recorder.first_difference(&parsed), None);
+
+        let mut other = TraceRecorder::new(&Signal::ALL);
+        other.samples = recorder
+            .samples()
+            .iter()
+            .map(|s| TraceSample {
+                cycle: s.cycle,
+                values: vec![0, s.values[0], (s.cycle >= 8) as i64, 0],
+            })
+            .collect();
+        let diff = recorder.first_difference(&other).unwrap();
+        assert_eq!((diff.cycle, diff.signal), (8, Signal::Halted));
+        assert_eq!((diff.left, diff.right), (Some(0), Some(1)));
+        other.samples.truncate(3);
+        let diff = recorder.first_difference(&other).unwrap();
+        assert_eq!((diff.cycle, diff.left, diff.right), (4, Some(0x41), None));
+        assert!(TraceRecorder::parse_jsonl(r#"{"cycle":1,"sp":3}"#).is_err());
+    }
+
+    #[test]
+    fn test_trace_divergence() {
+        let mut info = crate::compile_wat_source(
+            "two",
+            r#"(module (func (export "main") (result i32) i32.const 2))"#,
+        )
+        .unwrap();
+        info.expected = info.body_bytes[0] as i32;
+        let mut rom = Rom::new(&info.body_bytes, RomTiming::default());
+        let wave = WaveOptions::default();
+        let mut golden = TraceRecorder::new(&[Signal::Pc, Signal::Halted]);
+        let mut dut = FakeCore::default();
+        let mut traced = Traced::new(&mut dut, &wave)
+            .unwrap()
+            .with_recorder(&mut golden);
+        run_test_with(&mut traced, &mut rom, &mut PortLoader, &info, 10).unwrap();
+        drop(traced);
+        let run = |golden: &TraceRecorder| {
+            run_test_checked(
+                &mut FakeCore::default(),
+                &mut rom.clone(),
+                &mut PortLoader,
+                &info,
+                10,
+                &wave,
+                Some(golden),
+            )
+        };
+        assert!(run(&golden).is_ok());
+
+        // The golden model halts a cycle earlier than the fake core.
+        golden.samples[7].values[1] = 1;
+        let err = run(&golden).unwrap_err().to_string();
+        let lines: Vec<&str> = err.lines().collect();
+        assert_eq!(
+            lines[0],
+            "two: first divergence at cycle 8, pc 0: halted expected 1, got 0"
+        );
+        assert_eq!(lines[1], "         5  pc=0  halted=0");
+        assert_eq!(lines[4], ">        8  pc=0  halted=1/0");
+        assert_eq!(lines.len(), 6);
+
+        golden.samples.truncate(7);
+        let err = run(&golden).unwrap_err().to_string();
+        assert!(err.starts_with("two: first divergence at cycle 8, pc 0: golden trace ended"));
+        assert!(err.ends_with(">        8  pc=-/0  halted=-/0\n         9  pc=-/0  halted=-/1"));
+    }
+
+    #[test]
+    fn test_timeout() {
+        let mut info = crate::compile_wat_source(
+            "two",
+            r#"(module (func (export "main") (result i32) i32.const 2))"#,
+        )
+        .unwrap();
+        let len = info.body_bytes.len() as u32;
+        let timeout = Timeout::default();
+        assert_eq!(timeout.max_cycles(&info), DEFAULT_MAX_CYCLES + 16 * len);
+        let timing = RomTiming {
+            latency: 1,
+            max_wait_states: 2,
+            ..RomTiming::default()
+        };
+        assert_eq!(
+            timeout.max_cycles_with(&info, &timing),
+            DEFAULT_MAX_CYCLES + 64 * len
+        );
+        let stalls = RomTiming {
+            data_stall_percent: 50,
+            ..RomTiming::default()
+        };
+        assert_eq!(
+            timeout.max_cycles_with(&info, &stalls),
+            2 * timeout.max_cycles(&info)
+        );
+
+        info.cycle_budget = Some(1000);
+        assert_eq!(timeout.max_cycles(&info), 1001);
+    }
+
+    #[test]
+    fn test_latency_stress() {
+        let stress = LatencyStress::default();
+        assert_eq!(stress.timing(3), stress.timing(3));
+        let timings: Vec<RomTiming> = (0..stress.runs).map(|run| stress.timing(run)).collect();
+        assert!(timings
+            .iter()
+            .all(|t| t.latency <= 3 && t.max_wait_states <= 4 && t.data_stall_percent <= 50));
+        assert!(timings.iter().any(|t| t.data_stall_percent > 10));
+        assert_ne!(timings[0], timings[1]);
+
+        let mut info = crate::compile_wat_source(
+            "two",
+            r#"(module (func (export "main") (result i32) i32.const 2))"#,
+        )
+        .unwrap();
+        info.expected = info.body_bytes[0] as i32;
+        info.cycle_budget = Some(3);
+        let mut dut = FakeCore::default();
+        let timeout = Timeout::default();
+        let outcomes =
+            run_latency_stress(&mut dut, &mut PortLoader, &info, &timeout, &stress).unwrap();
+        assert_eq!(outcomes.len(), 16);
+        // Data stalls slow the fake core down.
+        assert!(outcomes.iter().any(|o| o.cycles > 3));
+
+        let timeout = Timeout {
+            base_cycles: 3,
+            cycles_per_byte: 0,
+        };
+        let err = run_latency_stress(
+            &mut FakeCore::default(),
+            &mut PortLoader,
+            &info,
+            &timeout,
+            &stress,
+        )
+        .unwrap_err();
+        assert!(err.to_string().starts_with("stress run "));
+        assert!(format!("{:#}", err).contains("timed out"));
+    }
+
+    #[test]
+    fn test_control_stress() {
+        let stress = ControlStress::default();
+        assert_eq!(stress.timing(5), stress.timing(5));
+        let timings: Vec<ControlTiming> = (0..stress.runs).map(|n| stress.timing(n)).collect();
+        assert!(timings.iter().all(|t| (1..=8).contains(&t.reset_cycles)
+            && t.start_delay <= 8
+            && (1..=3).contains(&t.start_width)
+            && t.spurious_starts.iter().all(|&c| c >= t.start_width)));
+        assert!(timings.iter().any(|t| !t.spurious_starts.is_empty()));
+
+        let mut info = crate::compile_wat_source(
+            "two",
+            r#"(module (func (export "main") (result i32) i32.const 2))"#,
+        )
+        .unwrap();
+        info.expected = info.body_bytes[0] as i32;
+        let mut dut = FakeCore::default();
+        let outcome = run_control_stress(&mut dut, &mut PortLoader, &info, 20, &stress).unwrap();
+        assert_eq!(outcome.cycles, 3);
+
+        let mut dut = FakeCore {
+            restarts: true,
+            ..Default::default()
+        };
+        let err = run_control_stress(&mut dut, &mut PortLoader, &info, 20, &stress).unwrap_err();
+        assert!(err.to_string().starts_with("two: stress run "));
+    }
+
+    #[test]
+    fn test_check() {
+        let mut info = crate::compile_wat_source(
+            "add",
+            r#"(module (func (export "main") (result i32) i32.const 2))"#,
+        )
+        .unwrap();
+        let halted = SimOutcome {
+            halted: true,
+            trapped: false,
+            stack_top: 2,
+            pc: 3,
+            cycles: 5,
+            mem_mismatch: None,
+        };
+        assert!(halted.check(&info).is_ok());
+        let wrong = SimOutcome {
+            stack_top: 3,
+            ..halted
+        };
+        assert_eq!(
+            wrong.check(&info).unwrap_err().to_string(),
+            "add: got 3 expected 2"
+        );
+
+        info.cycle_budget = Some(4);
+        assert_eq!(
+            halted.check(&info).unwrap_err().to_string(),
+            "add: took 5 cycles, budget is 4"
+        );
+        info.cycle_budget = None;
+
+        let bad_mem = SimOutcome {
+            mem_mismatch: Some(MemMismatch {
+                addr: 8,
+                expected: 7,
+                actual: 0,
+            }),
+            ..halted
+        };
+        assert_eq!(
+            bad_mem.check(&info).unwrap_err().to_string(),
+            "add: memory at 0x00000008 is 0x00000000, expected 0x00000007"
+        );
+
+        info.expect_trap = true;
+        assert!(halted.check(&info).is_err());
+        let trapped = SimOutcome {
+            halted: false,
+            trapped: true,
+            ..halted
+        };
+        assert!(trapped.check(&info).is_ok());
+    }
+}
@@ -0,0 +1,270 @@
+//! Importing `.wast` scripts from the WebAssembly spec testsuite.
+
+use crate::wast::parse_wast_checks;
+use crate::{unsupported_instruction, Error};
+use anyhow::{anyhow, Result};
+
+// ---------------------------------------------------------------------------
+// Spec testsuite import
+// ---------------------------------------------------------------------------
+
+/// A spec testsuite script cut down to the checks the core can run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpecImport {
+    /// The curated script: every module with a kept check, followed by
+    /// its kept checks. Each keeps its original line, so tests are named
+    /// as they would be in the full script. Empty if nothing is kept.
+    pub wast: String,
+    pub kept: usize,
+    pub excluded: Vec<SpecExclusion>,
+}
+
+/// A check left out of an import, by the line it starts on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpecExclusion {
+    pub line: usize,
+    pub reason: String,
+}
+
+/// Filter a spec testsuite `.wast` script to the checks that compile with
+/// [`compile_wast_source`](crate::compile_wast_source) and whose invoked function stays within
+/// [`unsupported_instruction`]'s subset. Errors if the script itself
+/// doesn't parse.
+pub fn import_spec_script(name: &str, source: &str) -> Result<SpecImport, Error> {
+    let parsed = parse_wast_checks(name, source)?;
+    let mut kept = Vec::new();
+    let mut excluded = Vec::new();
+    for check in &parsed.checks {
+        let reason = match &check.test {
+            Err(e) => e.root_cause().to_string(),
+            Ok(info) => match unsupported_instruction(&info.body_bytes)? {
+                Some(op) => format!("uses unsupported instruction {}", op),
+                None => {
+                    kept.push(check);
+                    continue;
+                }
+            },
+        };
+        excluded.push(SpecExclusion {
+            line: check.line,
+            reason: reason.replace('\n', " "),
+        });
+    }
+
+    let mut extents = Vec::new();
+    let mut last_module = None;
+    for check in &kept {
+        if last_module != Some(check.module) {
+            last_module = Some(check.module);
+            extents.push(directive_extent(source, parsed.modules[check.module])?);
+        }
+        extents.push(directive_extent(source, check.offset)?);
+    }
+    // Directives are copied in source order, padded with newlines so each
+    // starts on the line it did in the original.
+    extents.sort();
+    extents.dedup();
+    let mut wast = String::new();
+    let mut line = 0;
+    for (start, end) in extents {
+        let start_line = source[..start].matches('\n').count();
+        while line < start_line {
+            wast.push('\n');
+            line += 1;
+        }
+        wast.push_str(&source[start..end]);
+        wast.push('\n');
+        line += source[start..end].matches('\n').count() + 1;
+    }
+    Ok(SpecImport {
+        wast,
+        kept: kept.len(),
+        excluded,
+    })
+}
+
+/// The byte range of the parenthesized directive whose keyword is at
+/// `keyword`, from its `(` to its matching `)`, skipping strings and
+/// comments.
+fn directive_extent(source: &str, keyword: usize) -> Result<(usize, usize)> {
+    let start = source[..keyword]
+        .rfind('(')
+        .ok_or_else(|| anyhow!("no `(` before offset {}", keyword))?;
+    let bytes = source.as_bytes();
+    let mut depth = 0usize;
+    let mut i = start;
+    while i < bytes.len() {
+        match (bytes[i], bytes.get(i + 1)) {
+            (b'"', _) => {
+                i += 1;
+                while i < bytes.len() && bytes[i] != b'"' {
+                    i += if bytes[i] == b'\\' { 2 } else { 1 };
+                }
+            }
+            (b';', Some(b';')) => {
+                while i < bytes.len() && bytes[i] != b'\n' {
+                    i += 1;
+                }
+            }
+            (b'(', Some(b';')) => {
+                let mut nesting = 0usize;
+                while i + 1 < bytes.len() {
+                    match (bytes[i], bytes[i + 1]) {
+                        (b'(', b';') => nesting += 1,
+                        (b';', b')') => nesting -= 1,
+                        _ => {
+                            i += 1;
+                            continue;
+                        }
+                    }
+                    i += 2;
+                    if nesting == 0 {
+                        break;
+                    }
+                }
+                continue;
+            }
+            (b'(', _) => depth += 1,
+            (b')', _) => {
+                depth -= 1;
+                if depth == 0 {
+                    return Ok((start, i + 1));
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    Err(anyhow!("unbalanced parentheses after offset {}", start))
+}
+
+/// An exclusion reason with quoted names and numbers blanked out, so
+/// reasons that differ only in the export or value they mention group
+/// together.
+fn reason_shape(reason: &str) -> String {
+    let mut unquoted = String::new();
+    for (i, part) in reason.split('"').enumerate() {
+        unquoted.push_str(if i % 2 == 0 { part } else { "\"_\"" });
+    }
+    unquoted
+        .split(' ')
+        .map(|word| {
+            let number = word.trim_end_matches([',', '.', ':']);
+            if number.parse::<i64>().is_ok() {
+                word.replace(number, "N")
+            } else {
+                word.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Markdown report of a spec import: a count of each exclusion reason,
+/// then every script with its kept and excluded checks.
+pub fn spec_import_report(imports: &[(String, Result<SpecImport>)]) -> String {
+    let mut out = String::from("# Spec testsuite import\n\n");
+    let kept: usize = imports
+        .iter()
+        .filter_map(|(_, i)| i.as_ref().ok())
+        .map(|i| i.kept)
+        .sum();
+    let failed = imports.iter().filter(|(_, i)| i.is_err()).count();
+    let mut reasons = std::collections::BTreeMap::<String, usize>::new();
+    for import in imports.iter().filter_map(|(_, i)| i.as_ref().ok()) {
+        for exclusion in &import.excluded {
+            *reasons.entry(reason_shape(&exclusion.reason)).or_default() += 1;
+        }
+    }
+    let excluded: usize = reasons.values().sum();
+    out.push_str(&format!(
+        "{} scripts ({} not imported), {} checks kept, {} excluded.\n\n",
+        imports.len(),
+        failed,
+        kept,
+        excluded
+    ));
+    if !reasons.is_empty() {
+        let mut reasons: Vec<_> = reasons.into_iter().collect();
+        reasons.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        out.push_str("| Reason | Checks |\n|---|---:|\n");
+        for (reason, count) in reasons {
+            out.push_str(&format!("| {} | {} |\n", reason.replace('|', "\\|"), count));
+        }
+        out.push('\n');
+    }
+    for (name, import) in imports {
+        out.push_str(&format!("## {}\n\n", name));
+        match import {
+            Ok(import) => {
+                out.push_str(&format!(
+                    "{} kept, {} excluded.\n",
+                    import.kept,
+                    import.excluded.len()
+                ));
+                if !import.excluded.is_empty() {
+                    out.push('\n');
+                }
+                for exclusion in &import.excluded {
+                    out.push_str(&format!(
+                        "- line {}: {}\n",
+                        exclusion.line, exclusion.reason
+                    ));
+                }
+            }
+            Err(e) => out.push_str(&format!("Not imported:\n\n```text\n{:#}\n```\n", e)),
+        }
+        out.push('\n');
+    }
+    out
+}
+
+#[cfg(all(test, feature = "refexec"))]
+mod tests {
+    use super::*;
+    use crate::compile_wast_source;
+
+    #[test]
+    fn test_import_spec_script() {
+        let script = r#"(module
+  (func (export "two") (result i32) i32.const 2)
+  (func (export "id") (param i32) (result i32) local.get 0)
+  (func (export "wide") (result i32) i64.const 1 i32.wrap_i64))
+(assert_return (invoke "two") (i32.const 2)) ;; a (paren) "in ; text"
+(assert_return (invoke "id" (i32.const 1)) (i32.const 1))
+(assert_return (invoke "wide") (i32.const 1))
+(module (func (export "three") (result i32) (local i32) local.get 0))
+(assert_return (invoke "three") (i32.const 0))
+(module $m (; (unbalanced ;) (func (export "four") (result i32) i32.const 4))
+(assert_return (invoke $m "four") (i32.const 4))
+"#;
+        let import = import_spec_script("s", script).unwrap();
+        assert_eq!(import.kept, 2);
+        let excluded: Vec<_> = import
+            .excluded
+            .iter()
+            .map(|e| (e.line, e.reason.as_str()))
+            .collect();
+        assert_eq!(
+            excluded,
+            [
+                (
+                    6,
+                    "invoke \"id\" passes arguments, which the core cannot take"
+                ),
+                (7, "uses unsupported instruction i64.const 1"),
+                (9, "uses unsupported instruction local.get 0"),
+            ]
+        );
+        let tests = compile_wast_source("s", &import.wast).unwrap();
+        let names: Vec<&str> = tests.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(names, ["s_line5", "s_line11"]);
+        assert!(!import.wast.contains("three"));
+        assert!(import.wast.contains("(; (unbalanced ;)"));
+
+        let report = spec_import_report(&[("s".to_string(), Ok(import))]);
+        assert!(report.contains("2 checks kept, 3 excluded"));
+        assert!(report.contains("| uses unsupported instruction i64.const N | 1 |"));
+        assert!(report.contains("- line 9: uses unsupported instruction local.get 0"));
+    }
+}
@@ -0,0 +1,331 @@
+//! Reading VCD dumps from the core and reconstructing what it executed.
+
+use crate::{disassemble, Error};
+use anyhow::{anyhow, Context};
+
+// ---------------------------------------------------------------------------
+// VCD analysis
+// ---------------------------------------------------------------------------
+
+/// A variable declared in a VCD header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VcdVar {
+    /// Identifier code used in the value changes.
+    pub id: String,
+    /// Dotted scope path and reference, e.g. `TOP.WasmCoreTb.o_pc`.
+    pub name: String,
+    pub width: u32,
+}
+
+/// The variables and value changes of a VCD file. Values with `x` or `z`
+/// bits are `None`; real and string values are not kept.
+#[derive(Debug, Clone, Default)]
+pub struct VcdDump {
+    pub vars: Vec<VcdVar>,
+    changes: std::collections::HashMap<String, Vec<(u64, Option<u64>)>>,
+}
+
+/// Parse a VCD file as written by Verilator or an event simulator.
+pub fn parse_vcd(text: &str) -> Result<VcdDump, Error> {
+    let mut dump = VcdDump::default();
+    let mut scopes: Vec<String> = Vec::new();
+    let mut tokens = text.split_whitespace();
+    let mut time = 0u64;
+    let mut header = true;
+    let skip_block = |tokens: &mut std::str::SplitWhitespace| {
+        for t in tokens.by_ref() {
+            if t == "$end" {
+                return;
+            }
+        }
+    };
+    while let Some(tok) = tokens.next() {
+        if header {
+            match tok {
+                "$scope" => {
+                    let _kind = tokens.next();
+                    let name = tokens.next().ok_or_else(|| anyhow!("truncated $scope"))?;
+                    scopes.push(name.to_string());
+                    skip_block(&mut tokens);
+                }
+                "$upscope" => {
+                    scopes.pop();
+                    skip_block(&mut tokens);
+                }
+                "$var" => {
+                    let fields: Vec<&str> = tokens.by_ref().take_while(|t| *t != "$end").collect();
+                    if fields.len() < 4 {
+                        return Err(Error::ParseError(anyhow!(
+                            "malformed $var: {}",
+                            fields.join(" ")
+                        )));
+                    }
+                    let width = fields[1]
+                        .parse()
+                        .with_context(|| format!("bad $var width `{}`", fields[1]))?;
+                    let mut path = scopes.clone();
+                    path.push(fields[3].to_string());
+                    dump.vars.push(VcdVar {
+                        id: fields[2].to_string(),
+                        name: path.join("."),
+                        width,
+                    });
+                }
+                "$enddefinitions" => {
+                    header = false;
+                    skip_block(&mut tokens);
+                }
+                _ if tok.starts_with('$') => skip_block(&mut tokens),
+                _ => {
+                    return Err(Error::ParseError(anyhow!(
+                        "unexpected `{}` in the VCD header",
+                        tok
+                    )))
+                }
+            }
+            continue;
+        }
+        let (id, value) = match tok.as_bytes()[0] {
+            b'#' => {
+                time = tok[1..]
+                    .parse()
+                    .with_context(|| format!("bad timestamp `{}`", tok))?;
+                continue;
+            }
+            // $dumpvars and friends wrap ordinary value changes.
+            b'$' => continue,
+            b'b' | b'B' => {
+                let id = tokens
+                    .next()
+                    .ok_or_else(|| anyhow!("missing identifier after `{}`", tok))?;
+                let bits = &tok[1..];
+                let value = if bits.bytes().all(|b| b == b'0' || b == b'1') {
+                    Some(
+                        u64::from_str_radix(&bits[bits.len().saturating_sub(64)..], 2)
+                            .map_err(|e| Error::ParseError(e.into()))?,
+                    )
+                } else {
+                    None
+                };
+                (id, value)
+            }
+            b'r' | b'R' | b's' | b'S' => {
+                tokens.next();
+                continue;
+            }
+            b'0' | b'1' => (&tok[1..], Some((tok.as_bytes()[0] - b'0') as u64)),
+            b'x' | b'X' | b'z' | b'Z' => (&tok[1..], None),
+            _ => {
+                return Err(Error::ParseError(anyhow!(
+                    "unexpected `{}` at time {}",
+                    tok,
+                    time
+                )))
+            }
+        };
+        dump.changes
+            .entry(id.to_string())
+            .or_default()
+            .push((time, value));
+    }
+    if header {
+        return Err(Error::ParseError(anyhow!(
+            "no $enddefinitions; not a VCD file?"
+        )));
+    }
+    Ok(dump)
+}
+
+impl VcdDump {
+    /// The variable called `name`, given in full or as a trailing part of
+    /// its path; the shallowest match wins, so `o_pc` finds the top-level
+    /// port rather than a same-named signal deeper in the design.
+    pub fn find(&self, name: &str) -> Result<&VcdVar, Error> {
+        let suffix = format!(".{}", name);
+        self.vars
+            .iter()
+            .filter(|v| v.name == name || v.name.ends_with(&suffix))
+            .min_by_key(|v| v.name.matches('.').count())
+            .ok_or_else(|| anyhow!("no signal named `{}` in the VCD", name).into())
+    }
+
+    /// Value changes of `var` in time order.
+    pub fn changes(&self, var: &VcdVar) -> &[(u64, Option<u64>)] {
+        self.changes.get(&var.id).map_or(&[], |c| c.as_slice())
+    }
+
+    /// The value of `var` at `time`, after any change at that time.
+    pub fn value_at(&self, var: &VcdVar, time: u64) -> Option<u64> {
+        let changes = self.changes(var);
+        let n = changes.partition_point(|&(t, _)| t <= time);
+        n.checked_sub(1).and_then(|i| changes[i].1)
+    }
+}
+
+/// Names of the signals [`reconstruct_execution`] reads.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VcdSignalNames {
+    pub clock: String,
+    pub pc: String,
+    pub stack_top: String,
+    /// Only cycles where this signal is high count; `None` uses them all.
+    pub running: Option<String>,
+}
+
+impl Default for VcdSignalNames {
+    fn default() -> Self {
+        VcdSignalNames {
+            clock: "i_clk".to_string(),
+            pc: "o_pc".to_string(),
+            stack_top: "o_stack_top".to_string(),
+            running: Some("o_running".to_string()),
+        }
+    }
+}
+
+/// An instruction the core reached, as seen on the PC.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExecStep {
+    /// Rising clock edges since the start of the dump.
+    pub cycle: u64,
+    pub time: u64,
+    pub pc: u32,
+    pub instr: String,
+    /// Stack top when the PC reached the instruction; `None` if unknown.
+    pub stack_top: Option<i32>,
+}
+
+/// Rebuild the executed instruction sequence from a dump. The PC is
+/// sampled at every rising clock edge, and a step is recorded each time
+/// it moves to the start of an instruction of `body_bytes`; cycles spent
+/// on immediates or waiting on the same instruction add nothing.
+pub fn reconstruct_execution(
+    vcd: &VcdDump,
+    body_bytes: &[u8],
+    names: &VcdSignalNames,
+) -> Result<Vec<ExecStep>, Error> {
+    let clock = vcd.find(&names.clock)?;
+    let pc = vcd.find(&names.pc)?;
+    let stack_top = vcd.find(&names.stack_top)?;
+    let running = names.running.as_deref().map(|n| vcd.find(n)).transpose()?;
+    let instrs: std::collections::HashMap<usize, String> = disassemble(body_bytes)
+        .into_iter()
+        .map(|insn| (insn.offset, insn.to_string()))
+        .collect();
+
+    let mut steps = Vec::new();
+    let mut last_pc = None;
+    let mut prev_clk = None;
+    let mut cycle = 0;
+    for &(time, clk) in vcd.changes(clock) {
+        let rising = clk == Some(1) && prev_clk != Some(1);
+        prev_clk = clk;
+        if !rising {
+            continue;
+        }
+        cycle += 1;
+        if running.is_some_and(|r| vcd.value_at(r, time) != Some(1)) {
+            last_pc = None;
+            continue;
+        }
+        let Some(pc_value) = vcd.value_at(pc, time) else {
+            continue;
+        };
+        if last_pc == Some(pc_value) {
+            continue;
+        }
+        last_pc = Some(pc_value);
+        if let Some(instr) = instrs.get(&(pc_value as usize)) {
+            steps.push(ExecStep {
+                cycle,
+                time,
+                pc: pc_value as u32,
+                instr: instr.clone(),
+                stack_top: vcd.value_at(stack_top, time).map(|v| v as i32),
+            });
+        }
+    }
+    Ok(steps)
+}
+
+/// One line per step: cycle, time, PC, instruction, and stack top.
+pub fn format_execution(steps: &[ExecStep]) -> String {
+    let mut out = format!(
+        "{:>8} {:>10} {:>6}  {:<28} {}\n",
+        "cycle", "time", "pc", "instruction", "stack_top"
+    );
+    for s in steps {
+        let top = s.stack_top.map_or("x".to_string(), |v| v.to_string());
+        out.push_str(&format!(
+            "{:>8} {:>10} {:>6}  {:<28} {}\n",
+            s.cycle, s.time, s.pc, s.instr, top
+        ));
+    }
+    out
+}
+
+#[cfg(all(test, feature = "refexec"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_analyze_vcd() {
+        // i32.const 2; i32.const 3; i32.add; return
+        let body = [0x41, 0x02, 0x41, 0x03, 0x6A, 0x0F];
+        let mut vcd = String::from(
+            "$date today $end\n$timescale 1ps $end\n\
+             $scope module TOP $end\n\
+             $var wire 1 ! i_clk $end\n\
+             $var wire 32 \" o_pc [31:0] $end\n\
+             $scope module core $end\n\
+             $var wire 32 # o_pc [31:0] $end\n\
+             $upscope $end\n\
+             $var wire 32 $ o_stack_top [31:0] $end\n\
+             $var wire 1 % o_running $end\n\
+             $upscope $end\n$enddefinitions $end\n\
+             #0\n$dumpvars\n0!\nb0 \"\nb111 #\nbx $\n0%\n$end\n",
+        );
+        // Running from the second edge; the PC steps through every byte
+        // and sits on the add for two cycles.
+        let pcs = [0, 0, 1, 2, 3, 4, 4, 5];
+        for (i, pc) in pcs.iter().enumerate() {
+            let t = 10 * (i as u64 + 1);
+            vcd.push_str(&format!("#{}\n1!\nb{:b} \"\n", t, pc));
+            if i == 1 {
+                vcd.push_str("1%\n");
+            }
+            if *pc == 2 {
+                vcd.push_str("b10 $\n");
+            }
+            vcd.push_str(&format!("#{}\n0!\n", t + 5));
+        }
+
+        let dump = parse_vcd(&vcd).unwrap();
+        assert_eq!(dump.find("o_pc").unwrap().name, "TOP.o_pc");
+        assert_eq!(dump.find("core.o_pc").unwrap().id, "#");
+        assert!(dump.find("o_missing").is_err());
+        let pc = dump.find("o_pc").unwrap();
+        assert_eq!(dump.value_at(pc, 44), Some(2));
+        assert_eq!(dump.value_at(dump.find("o_stack_top").unwrap(), 0), None);
+
+        let steps = reconstruct_execution(&dump, &body, &VcdSignalNames::default()).unwrap();
+        let summary: Vec<(u64, u32, &str, Option<i32>)> = steps
+            .iter()
+            .map(|s| (s.cycle, s.pc, s.instr.as_str(), s.stack_top))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (2, 0, "i32.const 2", None),
+                (4, 2, "i32.const 3", Some(2)),
+                (6, 4, "i32.add", Some(2)),
+                (8, 5, "return", Some(2)),
+            ]
+        );
+        let listing = format_execution(&steps);
+        assert_eq!(listing.lines().count(), 5);
+        assert!(listing.lines().nth(3).unwrap().contains("i32.add"));
+
+        assert!(parse_vcd("#0\n1!").is_err());
+    }
+}
@@ -0,0 +1,211 @@
+//! Turning `.wast` `assert_return` and `assert_trap` directives into tests.
+
+use crate::emit::ChecksumAlgo;
+use crate::{compile_wasm_export, CompiledProgram, Error, ErrorCategory};
+use anyhow::{anyhow, Context, Result};
+
+// ---------------------------------------------------------------------------
+// WAST scripts
+// ---------------------------------------------------------------------------
+
+/// Compile a `.wast` script into one test per `assert_return` or
+/// `assert_trap` on an invoke, named `<name>_line<N>` after the line the
+/// assertion starts on.
+///
+/// Each test runs the invoked export on a fresh instance of the module
+/// the assertion refers to, and the script's expectation must agree with
+/// wasmtime's run of that, so assertions relying on state left by earlier
+/// invokes are an error. The core cannot be passed arguments, so invokes
+/// must take none, and only a single `i32` result can be checked. Other
+/// directives are ignored.
+pub fn compile_wast_source(name: &str, source: &str) -> Result<Vec<CompiledProgram>, Error> {
+    parse_wast_checks(name, source)?
+        .checks
+        .into_iter()
+        .map(|check| check.test.map_err(Error::from))
+        .collect()
+}
+
+/// The modules and checks of a `.wast` script, located in its source.
+pub(crate) struct WastChecks {
+    /// Source offset of each module directive's keyword.
+    pub(crate) modules: Vec<usize>,
+    pub(crate) checks: Vec<WastCheck>,
+}
+
+/// One `assert_return` or `assert_trap` on an invoke, and the test it
+/// compiles to or why it can't run on the core.
+pub(crate) struct WastCheck {
+    pub(crate) line: usize,
+    /// Source offset of the assertion's keyword.
+    pub(crate) offset: usize,
+    /// Index into [`WastChecks::modules`] of the module it invokes.
+    pub(crate) module: usize,
+    pub(crate) test: Result<CompiledProgram>,
+}
+
+pub(crate) fn parse_wast_checks(name: &str, source: &str) -> Result<WastChecks> {
+    use wast::{WastDirective, WastExecute, WastRet};
+    let located = |mut e: wast::Error| {
+        e.set_text(source);
+        ErrorCategory::Parse.error(e)
+    };
+    let buf = wast::parser::ParseBuffer::new(source).map_err(located)?;
+    let script = wast::parser::parse::<wast::Wast>(&buf).map_err(located)?;
+
+    let mut modules = Vec::new();
+    let mut wasm = Vec::new();
+    let mut named = std::collections::HashMap::new();
+    let mut checks = Vec::new();
+    for directive in script.directives {
+        let (span, invoke, expected) = match directive {
+            WastDirective::Module(mut module) => {
+                let bytes = module.encode().map_err(located)?;
+                if let Some(id) = module.name() {
+                    named.insert(id.name(), wasm.len());
+                }
+                modules.push(module.span().offset());
+                wasm.push(bytes);
+                continue;
+            }
+            WastDirective::AssertReturn {
+                span,
+                exec: WastExecute::Invoke(invoke),
+                results,
+            } => {
+                let expected = match results.as_slice() {
+                    [WastRet::Core(wast::core::WastRetCore::I32(v))] => Some(*v),
+                    _ => None,
+                };
+                (span, invoke, Some(expected))
+            }
+            WastDirective::AssertTrap {
+                span,
+                exec: WastExecute::Invoke(invoke),
+                ..
+            } => (span, invoke, None),
+            _ => continue,
+        };
+        let line = span.linecol_in(source).0 + 1;
+        let context = || format!("{} line {}", name, line);
+        let module = match invoke.module {
+            Some(id) => named.get(id.name()).copied(),
+            None => wasm.len().checked_sub(1),
+        }
+        .ok_or_else(|| anyhow!("no module to invoke {:?} on", invoke.name))
+        .with_context(context)?;
+        let test = check_wast_invoke(
+            &format!("{}_line{}", name, line),
+            &wasm[module],
+            &invoke,
+            expected,
+        )
+        .map(|test| CompiledProgram {
+            input_hash: Some(ChecksumAlgo::Crc32.checksum(source.as_bytes())),
+            ..test
+        })
+        .with_context(context);
+        checks.push(WastCheck {
+            line,
+            offset: span.offset(),
+            module,
+            test,
+        });
+    }
+    Ok(WastChecks { modules, checks })
+}
+
+/// The test for one invoke, if a fresh instance gives what the script
+/// expects: `Some(Some(v))` for an `i32` result `v`, `Some(None)` for
+/// any other results, and `None` for a trap.
+fn check_wast_invoke(
+    name: &str,
+    wasm: &[u8],
+    invoke: &wast::WastInvoke,
+    expected: Option<Option<i32>>,
+) -> Result<CompiledProgram> {
+    if !invoke.args.is_empty() {
+        return Err(ErrorCategory::Unsupported.error(format!(
+            "invoke {:?} passes arguments, which the core cannot take",
+            invoke.name
+        )));
+    }
+    let info = compile_wasm_export(name, wasm, invoke.name)?;
+    let got = if info.expect_trap {
+        "a trap".to_string()
+    } else {
+        info.expected.to_string()
+    };
+    match expected {
+        Some(Some(value)) if info.expect_trap || info.expected != value => {
+            Err(ErrorCategory::Mismatch.error(format!(
+                "the script expects {} but a fresh instance gives {}",
+                value, got
+            )))
+        }
+        Some(None) => {
+            Err(ErrorCategory::Unsupported
+                .error("only a single i32 result can be checked on the core"))
+        }
+        None if !info.expect_trap => Err(ErrorCategory::Mismatch.error(format!(
+            "the script expects a trap but a fresh instance gives {}",
+            got
+        ))),
+        _ => Ok(info),
+    }
+}
+
+#[cfg(all(test, feature = "refexec"))]
+mod tests {
+    use super::*;
+    use crate::MemCheck;
+
+    #[test]
+    fn test_compile_wast() {
+        let script = r#"(module
+  (memory (export "memory") 1)
+  (func (export "two") (result i32) i32.const 2)
+  (func (export "store") (result i32)
+    i32.const 8 i32.const 5 i32.store
+    i32.const 1)
+  (func (export "boom") (result i32) unreachable))
+(assert_return (invoke "two") (i32.const 2))
+(assert_return (invoke "store") (i32.const 1))
+(assert_invalid (module (func (result i32))) "type mismatch")
+(assert_trap (invoke "boom") "unreachable")
+(module $m (func (export "two") (result i32) i32.const 3))
+(assert_return (invoke $m "two") (i32.const 3))
+"#;
+        let tests = compile_wast_source("s", script).unwrap();
+        let names: Vec<&str> = tests.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(names, ["s_line8", "s_line9", "s_line11", "s_line13"]);
+        assert_eq!(tests[0].body_bytes, [0x41, 0x02, 0x0F]);
+        assert_eq!(
+            tests[1].mem_expected,
+            [MemCheck {
+                memory: 0,
+                addr: 8,
+                value: 5
+            }]
+        );
+        assert!(tests[2].expect_trap);
+        assert_eq!(tests[3].expected, 3);
+
+        let module = r#"(module (func (export "id") (param i32) (result i32) local.get 0)
+  (func (export "two") (result i32) i32.const 2))"#;
+        let err = |asserts: &str| {
+            format!(
+                "{:#}",
+                compile_wast_source("s", &format!("{}\n{}", module, asserts)).unwrap_err()
+            )
+        };
+        assert!(err(r#"(assert_return (invoke "two") (i32.const 4))"#)
+            .contains("s line 3: the script expects 4 but a fresh instance gives 2"));
+        assert!(
+            err(r#"(assert_return (invoke "id" (i32.const 1)) (i32.const 1))"#)
+                .contains("passes arguments")
+        );
+        assert!(err(r#"(assert_trap (invoke "two") "unreachable")"#).contains("expects a trap"));
+        assert!(err(r#"(assert_return (invoke "two") (i64.const 2))"#).contains("single i32"));
+    }
+}
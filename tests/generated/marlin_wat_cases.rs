@@ -0,0 +1,55 @@
+// Auto-generated by wasm-compile gen-marlin. Do not edit.
+
+#[test]
+fn test_wat_add() -> Result<(), Whatever> {
+    let runtime = VerylRuntime::new(VerylRuntimeOptions {
+        call_veryl_build: true,
+        ..Default::default()
+    })?;
+    run_wat_test(&runtime, "add", include_str!("../wat/add.wat"))
+}
+
+#[test]
+fn test_wat_branch() -> Result<(), Whatever> {
+    let runtime = VerylRuntime::new(VerylRuntimeOptions {
+        call_veryl_build: true,
+        ..Default::default()
+    })?;
+    run_wat_test(&runtime, "branch", include_str!("../wat/branch.wat"))
+}
+
+#[test]
+fn test_wat_expr() -> Result<(), Whatever> {
+    let runtime = VerylRuntime::new(VerylRuntimeOptions {
+        call_veryl_build: true,
+        ..Default::default()
+    })?;
+    run_wat_test(&runtime, "expr", include_str!("../wat/expr.wat"))
+}
+
+#[test]
+fn test_wat_if_else() -> Result<(), Whatever> {
+    let runtime = VerylRuntime::new(VerylRuntimeOptions {
+        call_veryl_build: true,
+        ..Default::default()
+    })?;
+    run_wat_test(&runtime, "if_else", include_str!("../wat/if_else.wat"))
+}
+
+#[test]
+fn test_wat_memory() -> Result<(), Whatever> {
+    let runtime = VerylRuntime::new(VerylRuntimeOptions {
+        call_veryl_build: true,
+        ..Default::default()
+    })?;
+    run_wat_test(&runtime, "memory", include_str!("../wat/memory.wat"))
+}
+
+#[test]
+fn test_wat_trap() -> Result<(), Whatever> {
+    let runtime = VerylRuntime::new(VerylRuntimeOptions {
+        call_veryl_build: true,
+        ..Default::default()
+    })?;
+    run_wat_test(&runtime, "trap", include_str!("../wat/trap.wat"))
+}
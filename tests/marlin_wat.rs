@@ -1,128 +1,57 @@
+use marlin::verilator::VerilatedModelConfig;
 use marlin::veryl::prelude::*;
 use snafu::Whatever;
-use wasm_ic::{compile_wat, compute_branch_table, extract_function_body, run_with_wasmtime};
+use std::path::Path;
+use wasm_ic::sim::{self, PortLoader, Rom, RomTiming, Timeout, TraceRecorder, WaveOptions};
+use wasm_ic::{compile_wat_source, impl_wasm_core_dut, wat_test};
 
 #[veryl(src = "src/wasm_core_tb.veryl", name = "WasmCoreTb")]
 pub struct WasmCoreTb;
 
-fn tick(dut: &mut WasmCoreTb, prog: &[u8]) {
-    // Provide ROM data for the current address (combinational read)
-    let addr = dut.o_prog_addr as usize;
-    dut.i_prog_data = if addr < prog.len() { prog[addr] } else { 0 };
-    dut.i_clk = 0;
-    dut.eval();
-    // Re-provide ROM data in case address changed combinationally
-    let addr = dut.o_prog_addr as usize;
-    dut.i_prog_data = if addr < prog.len() { prog[addr] } else { 0 };
-    dut.i_clk = 1;
-    dut.eval();
-    // After posedge: update data for the new address so it's ready
-    let addr = dut.o_prog_addr as usize;
-    dut.i_prog_data = if addr < prog.len() { prog[addr] } else { 0 };
-    dut.eval();
-}
-
-fn do_reset(dut: &mut WasmCoreTb, prog: &[u8]) {
-    // Veryl `reset` type is active-low: i_rst=0 asserts reset, i_rst=1 deasserts
-    dut.i_rst = 0;
-    dut.i_start = 0;
-    dut.i_bt_wr_en = 0;
-    dut.i_bt_wr_addr = 0;
-    dut.i_bt_wr_data = 0;
-    dut.i_mem_load_en = 0;
-    dut.i_mem_load_addr = 0;
-    dut.i_mem_load_data = 0;
-    for _ in 0..4 {
-        tick(dut, prog);
-    }
-    dut.i_rst = 1;
-    tick(dut, prog);
-}
+impl_wasm_core_dut!(WasmCoreTb);
 
 fn run_wat_test(runtime: &VerylRuntime, name: &str, wat_source: &str) -> Result<(), Whatever> {
-    let wasm = compile_wat(wat_source).expect("WAT compile failed");
-    let body = extract_function_body(&wasm).expect("body extraction failed");
-    let branches = compute_branch_table(&body).expect("branch table failed");
-    let expected = run_with_wasmtime(&wasm).expect("wasmtime failed");
-
-    let mut dut = runtime.create_model::<WasmCoreTb>()?;
-
-    do_reset(&mut dut, &body);
-
-    // Write branch table entries
-    for entry in &branches {
-        dut.i_bt_wr_en = 1;
-        dut.i_bt_wr_addr = entry.source_pc;
-        dut.i_bt_wr_data = entry.target_pc;
-        tick(&mut dut, &body);
-    }
-    dut.i_bt_wr_en = 0;
-
-    // Start execution
-    dut.i_start = 1;
-    tick(&mut dut, &body);
-    dut.i_start = 0;
-
-    // Run until halted or trap (max 200 cycles)
-    for _ in 0..200 {
-        tick(&mut dut, &body);
-        if dut.o_halted != 0 || dut.o_trap != 0 {
-            break;
-        }
-    }
-
-    assert_eq!(dut.o_trap, 0, "{name}: trapped");
-    assert_ne!(dut.o_halted, 0, "{name}: timed out, pc={}", dut.o_pc);
-    assert_eq!(
-        dut.o_stack_top as i32, expected,
-        "{name}: got {} expected {}",
-        dut.o_stack_top as i32, expected
-    );
-
-    Ok(())
-}
-
-#[test]
-fn test_wat_add() -> Result<(), Whatever> {
-    let runtime = VerylRuntime::new(VerylRuntimeOptions {
-        call_veryl_build: true,
+    let info = compile_wat_source(name, wat_source).expect("WAT compile failed");
+    let mut dut = runtime.create_model_with_config::<WasmCoreTb>(&VerilatedModelConfig {
+        enable_tracing: true,
         ..Default::default()
     })?;
-    run_wat_test(&runtime, "add", include_str!("wat/add.wat"))
-}
-
-#[test]
-fn test_wat_expr() -> Result<(), Whatever> {
-    let runtime = VerylRuntime::new(VerylRuntimeOptions {
-        call_veryl_build: true,
-        ..Default::default()
-    })?;
-    run_wat_test(&runtime, "expr", include_str!("wat/expr.wat"))
-}
-
-#[test]
-fn test_wat_branch() -> Result<(), Whatever> {
-    let runtime = VerylRuntime::new(VerylRuntimeOptions {
-        call_veryl_build: true,
-        ..Default::default()
-    })?;
-    run_wat_test(&runtime, "branch", include_str!("wat/branch.wat"))
+    let max_cycles = Timeout::default().max_cycles(&info);
+    let mut rom = Rom::new(&info.body_bytes, RomTiming::default());
+    // Failing tests leave their waveform and stimulus in
+    // target/waves/<name>.vcd and <name>.stim.
+    let wave = WaveOptions::in_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/target/waves"), name);
+    // A golden trace next to the WAT file is checked cycle by cycle.
+    let golden_path = Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/wat"))
+        .join(format!("{name}.trace.jsonl"));
+    let golden = golden_path.exists().then(|| {
+        let text = std::fs::read_to_string(&golden_path).expect("reading golden trace");
+        TraceRecorder::parse_jsonl(&text).expect("parsing golden trace")
+    });
+    match sim::run_test_checked(
+        &mut dut,
+        &mut rom,
+        &mut PortLoader,
+        &info,
+        max_cycles,
+        &wave,
+        golden.as_ref(),
+    ) {
+        Ok(outcome) => println!("{name}: {} cycles", outcome.cycles),
+        Err(e) => panic!("{e:#}"),
+    }
+    Ok(())
 }
 
-#[test]
-fn test_wat_if_else() -> Result<(), Whatever> {
-    let runtime = VerylRuntime::new(VerylRuntimeOptions {
-        call_veryl_build: true,
-        ..Default::default()
-    })?;
-    run_wat_test(&runtime, "if_else", include_str!("wat/if_else.wat"))
-}
+// One test per tests/wat file; regenerate with
+// `wasm-compile gen-marlin --wat-dir tests/wat --output tests/generated/marlin_wat_cases.rs`.
+include!("generated/marlin_wat_cases.rs");
 
-#[test]
-fn test_wat_memory() -> Result<(), Whatever> {
-    let runtime = VerylRuntime::new(VerylRuntimeOptions {
-        call_veryl_build: true,
-        ..Default::default()
-    })?;
-    run_wat_test(&runtime, "memory", include_str!("wat/memory.wat"))
-}
+wat_test!(
+    test_wat_inline_sub,
+    r#"(module
+        (func (export "main") (result i32)
+            i32.const 50
+            i32.const 8
+            i32.sub))"#
+);
@@ -0,0 +1,50 @@
+//! Time `compute_branch_table` on a body of a few megabytes mixing the
+//! shapes the corpus uses: nested blocks branched out of, loops branched
+//! back to, and `if`/`else`. Run with `cargo bench -p wasm-ic-branch`.
+
+use std::time::{Duration, Instant};
+use wasm_ic_branch::compute_branch_table;
+
+/// Times each run of the body is compiled; the fastest counts.
+const RUNS: usize = 10;
+
+/// A body of `n` constructs, each a few bytes, ending in `end`.
+fn large_body(n: usize) -> Vec<u8> {
+    let mut body = Vec::new();
+    for i in 0..n {
+        body.extend_from_slice(match i % 3 {
+            // block block (br_if 1 (i32.const 0)) br 0 end end
+            0 => &[
+                0x02, 0x40, 0x02, 0x40, 0x41, 0x00, 0x0D, 0x01, 0x0C, 0x00, 0x0B, 0x0B,
+            ][..],
+            // loop (br_if 0 (i32.const 0)) end
+            1 => &[0x03, 0x40, 0x41, 0x00, 0x0D, 0x00, 0x0B],
+            // (if (i32.const 1) (then (br_if 0 (i32.const 0))) (else nop))
+            _ => &[
+                0x41, 0x01, 0x04, 0x40, 0x41, 0x00, 0x0D, 0x00, 0x05, 0x01, 0x0B,
+            ],
+        });
+    }
+    body.push(0x0B);
+    body
+}
+
+fn main() {
+    let body = large_body(300_000);
+    let mut fastest = Duration::MAX;
+    let mut entries = 0;
+    for _ in 0..RUNS {
+        let started = Instant::now();
+        entries = std::hint::black_box(compute_branch_table(&body))
+            .expect("the body is well formed")
+            .len();
+        fastest = fastest.min(started.elapsed());
+    }
+    println!(
+        "compute_branch_table: {} bytes, {} entries in {:.1} ms ({:.0} MB/s)",
+        body.len(),
+        entries,
+        fastest.as_secs_f64() * 1000.0,
+        body.len() as f64 / fastest.as_secs_f64() / 1e6
+    );
+}
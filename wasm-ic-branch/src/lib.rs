@@ -0,0 +1,342 @@
+//! The branch table: for each branch in a body, the PC it goes to, which
+//! the core reads instead of scanning for the matching `end`.
+//!
+//! Builds with `no_std` and `alloc`, so loader firmware can compute the
+//! table on the target; `wasm_ic::branch` wraps it for the compiler.
+
+#![no_std]
+
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use wasmparser::{BinaryReaderError, Operator};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BlockKind {
+    Block,
+    Loop,
+    If,
+}
+
+#[derive(Debug)]
+struct BlockInfo {
+    kind: BlockKind,
+    start_offset: usize,
+    body_offset: usize,
+    else_offset: Option<usize>,
+    /// The last entry branching out of the block before its `end` is
+    /// known. Each such entry holds the index of the one before it in
+    /// place of its target, [`UNPATCHED`] ending the chain, until the
+    /// `end` patches them all.
+    pending: Option<usize>,
+}
+
+/// Ends a [`BlockInfo::pending`] chain.
+const UNPATCHED: u32 = u32::MAX;
+
+impl BlockInfo {
+    fn new(kind: BlockKind, offset: usize) -> Self {
+        BlockInfo {
+            kind,
+            start_offset: offset,
+            body_offset: offset + 2,
+            else_offset: None,
+            pending: None,
+        }
+    }
+}
+
+/// A single branch table entry: source_pc -> target_pc
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BranchEntry {
+    pub source_pc: u32,
+    pub target_pc: u32,
+}
+
+/// One instruction of a body, as far as the branch table cares.
+#[derive(Debug)]
+pub struct InstrRecord {
+    /// Offset of the opcode in the body, i.e. its PC.
+    pub offset: usize,
+    pub kind: InstrKind,
+}
+
+#[derive(Debug)]
+pub enum InstrKind {
+    Block,
+    Loop,
+    If,
+    Else,
+    End,
+    Br(u32),
+    BrIf(u32),
+    Return,
+    Other,
+}
+
+/// Compute branch table entries from raw function body bytes.
+///
+/// `body_bytes` is the raw bytecode of the function body (operators only,
+/// no locals prefix). Offsets are relative to the start of body_bytes,
+/// which corresponds to PC=0 in the hardware.
+pub fn compute_branch_table(body_bytes: &[u8]) -> Result<Vec<BranchEntry>, BranchError> {
+    let binary_reader = wasmparser::BinaryReader::new(body_bytes, 0);
+    let mut reader = wasmparser::OperatorsReader::new(binary_reader);
+    let mut table = TableBuilder::default();
+    // Decoding goes on past a body error, so a body that also doesn't
+    // decode reports that instead.
+    while !reader.eof() {
+        let (op, offset) = reader.read_with_offset()?;
+        table.push(offset, &op);
+    }
+    table.finish().map_err(BranchError::Body)
+}
+
+/// [`compute_branch_table`] for a body already decoded into its operators
+/// and their offsets, in order.
+pub fn branch_table_of<'a, 'op: 'a>(
+    ops: impl IntoIterator<Item = (usize, &'a Operator<'op>)>,
+) -> Result<Vec<BranchEntry>, BodyError> {
+    let mut table = TableBuilder::default();
+    for (offset, op) in ops {
+        table.push(offset, op);
+    }
+    table.finish()
+}
+
+/// The branch table built in one pass over the operators, patching each
+/// branch out of a block when the block's `end` comes.
+#[derive(Default)]
+struct TableBuilder {
+    entries: Vec<BranchEntry>,
+    stack: Vec<BlockInfo>,
+    /// The first branch deeper than its nesting.
+    too_deep: Option<BodyError>,
+}
+
+impl TableBuilder {
+    fn push(&mut self, offset: usize, op: &Operator) {
+        let entries = &mut self.entries;
+        let stack = &mut self.stack;
+        match *op {
+            Operator::Block { .. } => stack.push(BlockInfo::new(BlockKind::Block, offset)),
+            Operator::Loop { .. } => stack.push(BlockInfo::new(BlockKind::Loop, offset)),
+            Operator::If { .. } => stack.push(BlockInfo::new(BlockKind::If, offset)),
+            Operator::Else => {
+                if let Some(info) = stack.last_mut() {
+                    if info.kind == BlockKind::If {
+                        info.else_offset = Some(offset);
+                        entries.push(BranchEntry {
+                            source_pc: info.start_offset as u32,
+                            target_pc: (offset + 1) as u32,
+                        });
+                    }
+                }
+            }
+            Operator::End => {
+                let end_plus_one = (offset + 1) as u32;
+                if let Some(info) = stack.pop() {
+                    let mut next = info.pending;
+                    while let Some(i) = next {
+                        let link = entries[i].target_pc;
+                        entries[i].target_pc = end_plus_one;
+                        next = (link != UNPATCHED).then_some(link as usize);
+                    }
+                    if info.kind == BlockKind::If {
+                        entries.push(BranchEntry {
+                            source_pc: info.else_offset.unwrap_or(info.start_offset) as u32,
+                            target_pc: end_plus_one,
+                        });
+                    }
+                }
+            }
+            Operator::Br { relative_depth } | Operator::BrIf { relative_depth } => {
+                if self.too_deep.is_some() {
+                    return;
+                }
+                let Some(target_idx) = stack.len().checked_sub(1 + relative_depth as usize) else {
+                    self.too_deep = Some(BodyError {
+                        offset,
+                        message: format!("br depth {} exceeds block nesting", relative_depth),
+                    });
+                    return;
+                };
+                let target = &mut stack[target_idx];
+                let target_pc = match target.kind {
+                    BlockKind::Loop => target.body_offset as u32,
+                    BlockKind::Block | BlockKind::If => {
+                        let link = target.pending.map_or(UNPATCHED, |i| i as u32);
+                        target.pending = Some(entries.len());
+                        link
+                    }
+                };
+                entries.push(BranchEntry {
+                    source_pc: offset as u32,
+                    target_pc,
+                });
+            }
+            _ => {}
+        }
+    }
+
+    fn finish(self) -> Result<Vec<BranchEntry>, BodyError> {
+        let entries = self.entries;
+        // A branch out of a block that never ends has no target. The first
+        // such branch and the first too-deep one each fail; report
+        // whichever comes first.
+        let unended = self
+            .stack
+            .iter()
+            .filter_map(|info| {
+                let mut first = None;
+                let mut next = info.pending;
+                while let Some(i) = next {
+                    first = Some(entries[i].source_pc as usize);
+                    let link = entries[i].target_pc;
+                    next = (link != UNPATCHED).then_some(link as usize);
+                }
+                Some((first?, info.start_offset))
+            })
+            .min();
+        match (unended, self.too_deep) {
+            (Some((pc, start_offset)), deep) if deep.as_ref().is_none_or(|d| pc < d.offset) => {
+                Err(BodyError {
+                    offset: start_offset,
+                    message: "no end found for block".to_string(),
+                })
+            }
+            (_, Some(deep)) => Err(deep),
+            _ => Ok(entries),
+        }
+    }
+}
+
+/// Parse bytecode into instruction records with offsets.
+pub fn collect_instructions(body_bytes: &[u8]) -> Result<Vec<InstrRecord>, BinaryReaderError> {
+    let mut records = Vec::new();
+    let binary_reader = wasmparser::BinaryReader::new(body_bytes, 0);
+    let mut reader = wasmparser::OperatorsReader::new(binary_reader);
+
+    while !reader.eof() {
+        let (op, offset) = reader.read_with_offset()?;
+
+        let kind = match op {
+            Operator::Block { .. } => InstrKind::Block,
+            Operator::Loop { .. } => InstrKind::Loop,
+            Operator::If { .. } => InstrKind::If,
+            Operator::Else => InstrKind::Else,
+            Operator::End => InstrKind::End,
+            Operator::Br { relative_depth } => InstrKind::Br(relative_depth),
+            Operator::BrIf { relative_depth } => InstrKind::BrIf(relative_depth),
+            Operator::Return => InstrKind::Return,
+            _ => InstrKind::Other,
+        };
+
+        records.push(InstrRecord { offset, kind });
+    }
+
+    Ok(records)
+}
+
+/// A problem with one instruction of an extracted body, which a WAT
+/// compile points at in the source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BodyError {
+    /// Offset of the instruction in the body, i.e. its PC.
+    pub offset: usize,
+    pub message: String,
+}
+
+impl core::fmt::Display for BodyError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "{} at offset {}", self.message, self.offset)
+    }
+}
+
+impl core::error::Error for BodyError {}
+
+/// Why [`compute_branch_table`] failed: the body doesn't decode, or its
+/// branches don't resolve.
+#[derive(Debug)]
+pub enum BranchError {
+    Decode(BinaryReaderError),
+    Body(BodyError),
+}
+
+impl From<BinaryReaderError> for BranchError {
+    fn from(e: BinaryReaderError) -> Self {
+        BranchError::Decode(e)
+    }
+}
+
+impl core::fmt::Display for BranchError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            BranchError::Decode(e) => write!(f, "{}", e),
+            BranchError::Body(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl core::error::Error for BranchError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_branch_table() {
+        // block; i32.const 1; br_if 0; end; loop; br 0; end; return
+        let body = [
+            0x02, 0x40, 0x41, 0x01, 0x0D, 0x00, 0x0B, 0x03, 0x40, 0x0C, 0x00, 0x0B, 0x0F,
+        ];
+        let entries = compute_branch_table(&body).unwrap();
+        assert_eq!(
+            entries,
+            [
+                BranchEntry {
+                    source_pc: 4,
+                    target_pc: 7
+                },
+                BranchEntry {
+                    source_pc: 9,
+                    target_pc: 9
+                },
+            ]
+        );
+        assert!(matches!(
+            compute_branch_table(&[0x0C, 0x00, 0x0F]),
+            Err(BranchError::Body(BodyError { offset: 0, .. }))
+        ));
+        // if; br 0; else; end: the br waits for the end to learn its target.
+        assert_eq!(
+            compute_branch_table(&[0x04, 0x40, 0x0C, 0x00, 0x05, 0x0B, 0x0F]).unwrap(),
+            [
+                BranchEntry {
+                    source_pc: 2,
+                    target_pc: 6
+                },
+                BranchEntry {
+                    source_pc: 0,
+                    target_pc: 5
+                },
+                BranchEntry {
+                    source_pc: 4,
+                    target_pc: 6
+                },
+            ]
+        );
+        // The branch out of the unended block comes before the too-deep one.
+        assert!(matches!(
+            compute_branch_table(&[0x02, 0x40, 0x0C, 0x00, 0x0C, 0x05]),
+            Err(BranchError::Body(BodyError { offset: 0, .. }))
+        ));
+        assert!(matches!(
+            compute_branch_table(&[0x41]),
+            Err(BranchError::Decode(_))
+        ));
+    }
+}
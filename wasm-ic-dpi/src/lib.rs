@@ -0,0 +1,290 @@
+//! DPI-C functions exposing the WAT compiler to SystemVerilog testbenches,
+//! so a test can compile its program at simulation time instead of reading
+//! pre-generated files. Built as a shared library; the matching imports
+//! are `wasm_ic::DPI_IMPORTS`, written by `wasm-compile gen-dpi`.
+//!
+//! Compiled tests live in a table indexed by the handle a compile returns;
+//! freeing one makes its slot, and so its handle, free for the next compile.
+//! Strings handed back stay valid until the next call that returns a
+//! string on the same thread.
+
+use std::cell::RefCell;
+use std::ffi::{c_char, CStr, CString};
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard};
+use wasm_ic::{compile_wat_file, compile_wat_source, CompiledProgram, Error, SvhOptions};
+
+static TESTS: Mutex<Vec<Option<Compiled>>> = Mutex::new(Vec::new());
+
+thread_local! {
+    static LAST_ERROR: RefCell<CString> = RefCell::new(CString::default());
+    static RETURNED: RefCell<CString> = RefCell::new(CString::default());
+}
+
+/// The table of compiled tests. A panic while it was held can't have left
+/// it half-updated, so a poisoned lock is taken over rather than failing
+/// every later call.
+fn tests() -> MutexGuard<'static, Vec<Option<Compiled>>> {
+    TESTS.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// A compiled test with its data RAM image flattened to (addr, byte).
+struct Compiled {
+    info: CompiledProgram,
+    mem: Vec<(u32, u8)>,
+}
+
+fn set_error(message: String) {
+    LAST_ERROR.with(|e| *e.borrow_mut() = to_cstring(message));
+}
+
+fn to_cstring(s: String) -> CString {
+    CString::new(s.replace('\0', " ")).unwrap_or_default()
+}
+
+/// Hand `s` to C, keeping it alive until the next call.
+fn return_str(s: String) -> *const c_char {
+    RETURNED.with(|r| {
+        *r.borrow_mut() = to_cstring(s);
+        r.borrow().as_ptr()
+    })
+}
+
+/// Store a compile result in the first free slot, returning its handle or
+/// -1.
+fn register(result: Result<CompiledProgram, Error>) -> i32 {
+    match result {
+        Ok(info) => {
+            let mem = info
+                .mem_init
+                .iter()
+                .flat_map(|seg| {
+                    seg.data
+                        .iter()
+                        .enumerate()
+                        .map(move |(i, &b)| (seg.addr + i as u32, b))
+                })
+                .collect();
+            let mut tests = tests();
+            let slot = match tests.iter().position(Option::is_none) {
+                Some(slot) => slot,
+                None => {
+                    tests.push(None);
+                    tests.len() - 1
+                }
+            };
+            match i32::try_from(slot) {
+                Ok(handle) => {
+                    tests[slot] = Some(Compiled { info, mem });
+                    handle
+                }
+                Err(_) => {
+                    tests.pop();
+                    set_error("too many compiled tests; free some".to_string());
+                    -1
+                }
+            }
+        }
+        Err(e) => {
+            set_error(format!("{:#}", e));
+            -1
+        }
+    }
+}
+
+/// Look up a handle, giving `default` for a bad one.
+fn with_test<T>(handle: i32, default: T, f: impl FnOnce(&Compiled) -> Option<T>) -> T {
+    let tests = tests();
+    usize::try_from(handle)
+        .ok()
+        .and_then(|h| tests.get(h))
+        .and_then(|t| t.as_ref())
+        .and_then(f)
+        .unwrap_or(default)
+}
+
+fn index(i: i32) -> usize {
+    usize::try_from(i).unwrap_or(usize::MAX)
+}
+
+unsafe fn read_str(s: *const c_char) -> Option<String> {
+    (!s.is_null()).then(|| CStr::from_ptr(s).to_string_lossy().into_owned())
+}
+
+/// # Safety
+///
+/// `path` must be null or a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn wasm_ic_compile(path: *const c_char) -> i32 {
+    match read_str(path) {
+        Some(path) => register(compile_wat_file(Path::new(&path))),
+        None => register(Err(Error::Other(anyhow::anyhow!("null path")))),
+    }
+}
+
+/// # Safety
+///
+/// `name` and `wat` must each be null or a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn wasm_ic_compile_source(name: *const c_char, wat: *const c_char) -> i32 {
+    match (read_str(name), read_str(wat)) {
+        (Some(name), Some(wat)) => register(compile_wat_source(&name, &wat)),
+        _ => register(Err(Error::Other(anyhow::anyhow!("null name or source")))),
+    }
+}
+
+/// Why the last compile on this thread failed.
+#[no_mangle]
+pub extern "C" fn wasm_ic_last_error() -> *const c_char {
+    LAST_ERROR.with(|e| e.borrow().as_ptr())
+}
+
+/// Drop a compiled test; a later compile may reuse its handle.
+#[no_mangle]
+pub extern "C" fn wasm_ic_free(handle: i32) {
+    if let Ok(h) = usize::try_from(handle) {
+        if let Some(test) = tests().get_mut(h) {
+            *test = None;
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn wasm_ic_name(handle: i32) -> *const c_char {
+    return_str(with_test(handle, String::new(), |t| {
+        Some(t.info.name.clone())
+    }))
+}
+
+#[no_mangle]
+pub extern "C" fn wasm_ic_body_len(handle: i32) -> i32 {
+    with_test(handle, 0, |t| Some(t.info.body_bytes.len() as i32))
+}
+
+#[no_mangle]
+pub extern "C" fn wasm_ic_body_byte(handle: i32, i: i32) -> u8 {
+    with_test(handle, 0, |t| t.info.body_bytes.get(index(i)).copied())
+}
+
+#[no_mangle]
+pub extern "C" fn wasm_ic_branch_count(handle: i32) -> i32 {
+    with_test(handle, 0, |t| Some(t.info.branch_table.len() as i32))
+}
+
+#[no_mangle]
+pub extern "C" fn wasm_ic_branch_source(handle: i32, i: i32) -> u32 {
+    with_test(handle, 0, |t| {
+        t.info.branch_table.get(index(i)).map(|e| e.source_pc)
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn wasm_ic_branch_target(handle: i32, i: i32) -> u32 {
+    with_test(handle, 0, |t| {
+        t.info.branch_table.get(index(i)).map(|e| e.target_pc)
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn wasm_ic_mem_len(handle: i32) -> i32 {
+    with_test(handle, 0, |t| Some(t.mem.len() as i32))
+}
+
+#[no_mangle]
+pub extern "C" fn wasm_ic_mem_addr(handle: i32, i: i32) -> u32 {
+    with_test(handle, 0, |t| t.mem.get(index(i)).map(|m| m.0))
+}
+
+#[no_mangle]
+pub extern "C" fn wasm_ic_mem_data(handle: i32, i: i32) -> u8 {
+    with_test(handle, 0, |t| t.mem.get(index(i)).map(|m| m.1))
+}
+
+#[no_mangle]
+pub extern "C" fn wasm_ic_expected(handle: i32) -> i32 {
+    with_test(handle, 0, |t| Some(t.info.expected))
+}
+
+#[no_mangle]
+pub extern "C" fn wasm_ic_expect_trap(handle: i32) -> u8 {
+    with_test(handle, 0, |t| Some(t.info.expect_trap as u8))
+}
+
+/// The test's cycle budget as `gen-tests` computes it.
+#[no_mangle]
+pub extern "C" fn wasm_ic_cycle_budget(handle: i32) -> u32 {
+    with_test(handle, 0, |t| {
+        Some(SvhOptions::default().cycle_budget(&t.info))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dpi_compile() {
+        let name = CString::new("mem").unwrap();
+        let wat = CString::new(
+            r#"(module (memory 1) (data (i32.const 8) "\2a\00")
+                (func (export "main") (result i32) i32.const 8 i32.load))"#,
+        )
+        .unwrap();
+        let h = unsafe { wasm_ic_compile_source(name.as_ptr(), wat.as_ptr()) };
+        assert!(h >= 0);
+        let got = unsafe { CStr::from_ptr(wasm_ic_name(h)) };
+        assert_eq!(got.to_str().unwrap(), "mem");
+        assert_eq!(wasm_ic_expected(h), 42);
+        assert_eq!(wasm_ic_expect_trap(h), 0);
+        let body: Vec<u8> = (0..wasm_ic_body_len(h))
+            .map(|i| wasm_ic_body_byte(h, i))
+            .collect();
+        assert_eq!(body[0], 0x41);
+        assert_eq!(wasm_ic_mem_len(h), 2);
+        assert_eq!((wasm_ic_mem_addr(h, 0), wasm_ic_mem_data(h, 0)), (8, 0x2a));
+        assert_eq!(wasm_ic_mem_data(h, 2), 0);
+        assert!(wasm_ic_cycle_budget(h) > 0);
+
+        wasm_ic_free(h);
+        assert_eq!(wasm_ic_body_len(h), 0);
+        assert_eq!(wasm_ic_expected(-1), 0);
+
+        // The freed slot is the next one handed out.
+        let again = unsafe { wasm_ic_compile_source(name.as_ptr(), wat.as_ptr()) };
+        assert_eq!(again, h);
+        assert_eq!(wasm_ic_expected(again), 42);
+        wasm_ic_free(again);
+
+        // A panic while the table was locked doesn't take the library down.
+        let _ = std::thread::spawn(|| {
+            let _held = tests();
+            panic!("poison the table");
+        })
+        .join();
+        assert!(TESTS.is_poisoned());
+        assert_eq!(wasm_ic_body_len(h), 0);
+
+        let bad = CString::new("(module").unwrap();
+        assert_eq!(
+            unsafe { wasm_ic_compile_source(name.as_ptr(), bad.as_ptr()) },
+            -1
+        );
+        let error = unsafe { CStr::from_ptr(wasm_ic_last_error()) };
+        assert!(!error.to_bytes().is_empty());
+
+        let source = include_str!("lib.rs");
+        let exported = source
+            .lines()
+            .filter_map(|l| l.split("extern \"C\" fn ").nth(1))
+            .filter_map(|l| l.split('(').next())
+            .collect::<Vec<_>>();
+        assert_eq!(exported.len(), 16);
+        for name in exported {
+            assert!(
+                wasm_ic::DPI_IMPORTS.contains(&format!(" {}(", name)),
+                "{} is not imported",
+                name
+            );
+        }
+    }
+}
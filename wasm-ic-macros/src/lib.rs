@@ -0,0 +1,49 @@
+//! Procedural macros for wasm-ic, re-exported from the main crate.
+
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::parse::{Parse, ParseStream};
+use syn::{parse_macro_input, Ident, LitStr, Token};
+
+struct WatTestInput {
+    name: Ident,
+    source: LitStr,
+}
+
+impl Parse for WatTestInput {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let name = input.parse()?;
+        input.parse::<Token![,]>()?;
+        let source = input.parse()?;
+        input.parse::<Option<Token![,]>>()?;
+        Ok(WatTestInput { name, source })
+    }
+}
+
+/// Expand `wat_test!(name, r#"(module ...)"#)` into a marlin `#[test]`
+/// that runs the inline WAT on the core through the `run_wat_test`
+/// harness in scope, like the generated corpus tests.
+///
+/// The WAT is compiled at macro expansion time, so a malformed module is
+/// a compile error at the call site rather than a failing test.
+#[proc_macro]
+pub fn wat_test(input: TokenStream) -> TokenStream {
+    let WatTestInput { name, source } = parse_macro_input!(input as WatTestInput);
+    if let Err(e) = wat::parse_str(source.value()) {
+        return syn::Error::new(source.span(), format!("invalid WAT: {}", e))
+            .to_compile_error()
+            .into();
+    }
+    let label = name.to_string();
+    quote! {
+        #[test]
+        fn #name() -> Result<(), Whatever> {
+            let runtime = VerylRuntime::new(VerylRuntimeOptions {
+                call_veryl_build: true,
+                ..Default::default()
+            })?;
+            run_wat_test(&runtime, #label, #source)
+        }
+    }
+    .into()
+}
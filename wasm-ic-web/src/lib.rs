@@ -0,0 +1,115 @@
+//! wasm-bindgen API behind a browser playground: paste WAT, see the
+//! program image, branch table, and disassembly the compiler makes of it.
+//! Built for `wasm32-unknown-unknown` (`wasm-pack build wasm-ic-web
+//! --target web`) without the wasmtime reference run, so a compiled
+//! program has no expected result.
+
+use wasm_bindgen::prelude::*;
+use wasm_ic::emit::{render_artifact, ArtifactKind};
+use wasm_ic::{compile, disassemble, CompileOptions, CompiledProgram, EmitOptions, Pass, Source};
+
+/// A program compiled from pasted WAT.
+#[wasm_bindgen]
+pub struct Compiled {
+    info: CompiledProgram,
+}
+
+/// Compile `wat` as `wasm-compile` would, minus the reference run.
+#[wasm_bindgen]
+pub fn compile_wat(wat: &str) -> Result<Compiled, JsError> {
+    compile_playground(wat).map_err(|e| JsError::new(&format!("{:#}", e)))
+}
+
+fn compile_playground(wat: &str) -> Result<Compiled, wasm_ic::Error> {
+    let options = CompileOptions::new()
+        .name("playground")
+        .passes(&[Pass::Memory, Pass::CycleBudget]);
+    let info = compile(Source::Wat(wat), &options)?;
+    Ok(Compiled { info })
+}
+
+#[wasm_bindgen]
+impl Compiled {
+    /// prog.hex as `$readmemh` reads it.
+    pub fn prog_hex(&self) -> Result<String, JsError> {
+        self.hex(ArtifactKind::Prog)
+    }
+
+    /// branch.hex: `source target` PCs per line.
+    pub fn branch_hex(&self) -> Result<String, JsError> {
+        self.hex(ArtifactKind::Branch)
+    }
+
+    /// mem_init.hex, the data segments flattened.
+    pub fn mem_hex(&self) -> Result<String, JsError> {
+        self.hex(ArtifactKind::Mem)
+    }
+
+    /// One line per instruction: PC, bytes, and WAT.
+    pub fn disassembly(&self) -> String {
+        let body = &self.info.body_bytes;
+        disassemble(body)
+            .iter()
+            .map(|insn| {
+                let bytes: Vec<String> = insn
+                    .bytes(body)
+                    .iter()
+                    .map(|b| format!("{:02X}", b))
+                    .collect();
+                format!("{:04X}  {:<15} {}\n", insn.offset, bytes.join(" "), insn)
+            })
+            .collect()
+    }
+
+    pub fn body_len(&self) -> usize {
+        self.info.body_bytes.len()
+    }
+
+    /// Source and target PCs, interleaved.
+    pub fn branch_table(&self) -> Vec<u32> {
+        self.info
+            .branch_table
+            .iter()
+            .flat_map(|e| [e.source_pc, e.target_pc])
+            .collect()
+    }
+
+    fn hex(&self, kind: ArtifactKind) -> Result<String, JsError> {
+        let text = render_artifact(&self.info, kind, &EmitOptions::default())
+            .map_err(|e| JsError::new(&format!("{:#}", e)))?;
+        Ok(String::from_utf8(text).expect("hex artifacts are text"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_playground_compile() {
+        let compiled = compile_playground(
+            r#"(module (memory 1) (data (i32.const 0) "\2a")
+                (func (export "main") (result i32)
+                  (block (br 0)) i32.const 0 i32.load8_u))"#,
+        )
+        .unwrap();
+        assert_eq!(compiled.branch_table(), [2, 5]);
+        assert!(compiled
+            .prog_hex()
+            .unwrap()
+            .contains("\n02\n40\n0C\n00\n0B\n41\n00\n2D\n00\n00\n0F\n"));
+        assert!(compiled
+            .branch_hex()
+            .unwrap()
+            .ends_with("\n00000002 00000005\n"));
+        assert!(compiled.mem_hex().unwrap().ends_with("\n2A\n"));
+        let listing = compiled.disassembly();
+        assert!(
+            listing.starts_with("0000  02 40           block\n"),
+            "{}",
+            listing
+        );
+        assert_eq!(listing.lines().count(), 6);
+        assert!(compile_playground("(module").is_err());
+    }
+}